@@ -3,6 +3,7 @@ use std::cmp;
 use image::{Rgb, ImageBuffer, RgbImage, imageops};
 
 mod model;
+mod output;
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 800;
@@ -43,7 +44,8 @@ fn line(mut x0: u32, mut y0: u32, mut x1: u32, mut y1: u32, image: &mut RgbImage
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let output = output::Output::from_args(&mut args).unwrap();
     let model = model::file_to_model(if args.len() == 2 { &args[1] } else { "obj/african_head.obj" }).unwrap();
 
     let mut image: RgbImage = ImageBuffer::new(WIDTH, HEIGHT);
@@ -62,5 +64,5 @@ fn main() {
 
     // (0,0) is the bottom left
     imageops::flip_vertical_in_place(&mut image);
-    image.save("output.tga").unwrap();
+    output.save(&image).unwrap();
 }
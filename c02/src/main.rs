@@ -2,6 +2,7 @@ use image::{Rgb, ImageBuffer, RgbImage, imageops};
 use cgmath::{Vector3, Vector2, dot};
 
 mod model;
+mod output;
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 800;
@@ -40,7 +41,8 @@ fn triangle(pts: &[Vector2<f32>; 3], image: &mut RgbImage, color: Rgb<u8>) {
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let output = output::Output::from_args(&mut args).unwrap();
     let model = model::file_to_model(if args.len() == 2 { &args[1] } else { "obj/african_head.obj" }).unwrap();
 
     let mut image: RgbImage = ImageBuffer::new(WIDTH, HEIGHT);
@@ -64,5 +66,5 @@ fn main() {
 
     // (0,0) is the bottom left
     imageops::flip_vertical_in_place(&mut image);
-    image.save("output.tga").unwrap();
+    output.save(&image).unwrap();
 }
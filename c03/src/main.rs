@@ -4,6 +4,7 @@ use image::io::Reader as ImageReader;
 use image::{imageops, ImageBuffer, RgbImage};
 
 mod model;
+mod output;
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 800;
@@ -84,7 +85,8 @@ fn triangle(
 }
 
 fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let output = output::Output::from_args(&mut args)?;
     let model = model::file_to_model(if args.len() == 2 {
         &args[1]
     } else {
@@ -142,7 +144,7 @@ fn main() -> Result<()> {
 
     // (0,0) is the bottom left
     imageops::flip_vertical_in_place(&mut image);
-    image.save("output.tga")?;
+    output.save(&image)?;
 
     Ok(())
 }
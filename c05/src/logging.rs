@@ -0,0 +1,20 @@
+use anyhow::{Context, Result};
+
+// Takes --log error|warn|info|debug|trace out of args and sends events up to that level to
+// stderr, warn when it isn't given. debug says which faces were skipped and why.
+pub fn from_args(args: &mut Vec<String>) -> Result<()> {
+    let mut level = tracing::Level::WARN;
+    if let Some(i) = args.iter().position(|arg| arg == "--log") {
+        level = args
+            .get(i + 1)
+            .context("--log expects error, warn, info, debug or trace")?
+            .parse()
+            .context("--log expects error, warn, info, debug or trace")?;
+        args.drain(i..i + 2);
+    }
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+    Ok(())
+}
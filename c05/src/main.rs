@@ -3,7 +3,9 @@ use cgmath::{dot, InnerSpace, Matrix, Matrix4, SquareMatrix, Vector2, Vector3, V
 use image::io::Reader as ImageReader;
 use image::{imageops, ImageBuffer, RgbImage, GrayImage, Luma};
 
+mod logging;
 mod model;
+mod output;
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 800;
@@ -97,7 +99,12 @@ fn triangle(
     for i in 0..3 {
         for j in 0..2 {
             if pts[i][j].is_sign_negative() {
-                print!("Triangle outside bounds of canvas\n");
+                tracing::debug!(
+                    vertex = i,
+                    x = pts[i].x,
+                    y = pts[i].y,
+                    "triangle outside bounds of canvas, skipped"
+                );
                 return;
             }
             bboxmin[j] = bboxmin[j].clamp(0, pts[i][j] as u32);
@@ -116,7 +123,7 @@ fn triangle(
                 continue;
             }
             p.z = pts[0].z * bc_screen[0] + pts[1].z * bc_screen[1] + pts[2].z * bc_screen[2];
-            print!("p.z = {}\n", p.z);
+            tracing::trace!(x = p.x, y = p.y, z = p.z, "fragment");
             if zbuffer.get_pixel(p.x as u32, p.y as u32)[0] < p.z as u8 {
                 zbuffer.put_pixel(p.x as u32, p.y as u32, Luma { 0: [p.z as u8] });
 
@@ -141,7 +148,9 @@ fn triangle(
 }
 
 fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let output = output::Output::from_args(&mut args)?;
+    logging::from_args(&mut args)?;
     let model = model::file_to_model(if args.len() == 2 {
         &args[1]
     } else {
@@ -178,7 +187,8 @@ fn main() -> Result<()> {
     let verts = model.get_verts();
     let norms = model.get_norms();
     let uvs = model.get_uvs();
-    for face in model.get_faces() {
+    for (i, face) in model.get_faces().iter().enumerate() {
+        let _face = tracing::debug_span!("face", index = i).entered();
         let mut screen_coords: [Vector3<f32>; 3] = [Vector3 {
             x: 0.0,
             y: 0.0,
@@ -216,7 +226,7 @@ fn main() -> Result<()> {
 
     // (0,0) is the bottom left
     imageops::flip_vertical_in_place(&mut image);
-    image.save("output.tga")?;
+    output.save(&image)?;
 
     imageops::flip_vertical_in_place(&mut zbuffer);
     zbuffer.save("debug.tga")?;
@@ -1,8 +1,10 @@
+mod logging;
 mod model;
+mod output;
 mod our_gl;
 mod shaders;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use cgmath::{InnerSpace, Vector3, Vector4};
 use image::io::Reader as ImageReader;
 use image::{imageops, GrayImage, ImageBuffer, RgbImage};
@@ -33,7 +35,18 @@ const LIGHT_DIR: Vector3<f32> = Vector3 {
 };
 
 fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let output = output::Output::from_args(&mut args)?;
+    logging::from_args(&mut args)?;
+    // --zbuffer <path> also saves the depth buffer there
+    let zbuffer_path = match args.iter().position(|arg| arg == "--zbuffer") {
+        Some(i) => {
+            let path = args.get(i + 1).context("--zbuffer expects a path")?.clone();
+            args.drain(i..i + 2);
+            Some(path)
+        }
+        None => None,
+    };
     let path = if args.len() == 2 {
         &args[1]
     } else {
@@ -84,6 +97,7 @@ fn main() -> Result<()> {
     );
 
     for i in 0..model.get_faces().len() {
+        let _face = tracing::debug_span!("face", index = i).entered();
         let mut screen_coords: [Vector4<f32>; 3] = [Vector4 {
             x: 0.0,
             y: 0.0,
@@ -98,10 +112,12 @@ fn main() -> Result<()> {
 
     // (0,0) is the bottom left
     imageops::flip_vertical_in_place(&mut image);
-    image.save("output.tga")?;
+    output.save(&image)?;
 
-    imageops::flip_vertical_in_place(&mut zbuffer);
-    zbuffer.save("debug.tga")?;
+    if let Some(path) = zbuffer_path {
+        imageops::flip_vertical_in_place(&mut zbuffer);
+        zbuffer.save(path)?;
+    }
 
     Ok(())
 }
@@ -95,7 +95,12 @@ pub fn triangle<T: Shader>(
     for i in 0..3 {
         for j in 0..2 {
             if pts[i][j].is_sign_negative() {
-                print!("Triangle outside bounds of canvas\n");
+                tracing::debug!(
+                    vertex = i,
+                    x = pts[i].x,
+                    y = pts[i].y,
+                    "triangle outside bounds of canvas, skipped"
+                );
                 return;
             }
             bboxmin[j] = bboxmin[j].min((pts[i][j] / pts[i].w) as i32);
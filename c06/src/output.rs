@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::{ImageFormat, RgbImage};
+use std::fs::File;
+use std::io::BufWriter;
+
+// from 1 to 100, for jpeg when --quality isn't given
+const JPEG_QUALITY: u8 = 90;
+
+// Where the frame is saved. The format follows the extension unless --format gives one,
+// --quality only matters for jpeg.
+pub struct Output {
+    pub path: String,
+    pub format: Option<ImageFormat>,
+    pub quality: u8,
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output {
+            path: String::from("output.tga"),
+            format: None,
+            quality: JPEG_QUALITY,
+        }
+    }
+}
+
+pub fn parse_format(name: &str) -> Result<ImageFormat> {
+    match name.to_ascii_lowercase().as_str() {
+        "tga" => Ok(ImageFormat::Tga),
+        "png" => Ok(ImageFormat::Png),
+        "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+        "bmp" => Ok(ImageFormat::Bmp),
+        _ => Err(anyhow!(
+            "unknown image format '{}', expected tga, png, jpeg or bmp",
+            name
+        )),
+    }
+}
+
+impl Output {
+    // --output, --format and --quality with their values
+    pub fn set(&mut self, flag: &str, value: &str) -> Result<()> {
+        match flag {
+            "--output" => self.path = String::from(value),
+            "--format" => self.format = Some(parse_format(value)?),
+            "--quality" => {
+                self.quality = value.parse()?;
+                if !(1..=100).contains(&self.quality) {
+                    return Err(anyhow!("--quality expects 1 to 100"));
+                }
+            }
+            _ => return Err(anyhow!("'{}' isn't an output flag", flag)),
+        }
+        Ok(())
+    }
+
+    // takes the output flags out of args, leaving everything else in order
+    pub fn from_args(args: &mut Vec<String>) -> Result<Output> {
+        let mut output = Output::default();
+        let mut i = 0;
+        while i < args.len() {
+            if !matches!(args[i].as_str(), "--output" | "--format" | "--quality") {
+                i += 1;
+                continue;
+            }
+            let value = args
+                .get(i + 1)
+                .with_context(|| format!("{} expects a value", args[i]))?
+                .clone();
+            output.set(&args[i], &value)?;
+            args.drain(i..i + 2);
+        }
+        Ok(output)
+    }
+
+    pub fn format(&self) -> Result<ImageFormat> {
+        match self.format {
+            Some(format) => Ok(format),
+            None => parse_format(
+                std::path::Path::new(&self.path)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .with_context(|| format!("{} has no extension, give --format", self.path))?,
+            ),
+        }
+    }
+
+    pub fn save(&self, image: &RgbImage) -> Result<()> {
+        let format = self.format()?;
+        if format == ImageFormat::Jpeg {
+            let mut file = BufWriter::new(File::create(&self.path)?);
+            JpegEncoder::new_with_quality(&mut file, self.quality).encode_image(image)?;
+        } else {
+            image.save_with_format(&self.path, format)?;
+        }
+        Ok(())
+    }
+}
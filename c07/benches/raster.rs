@@ -0,0 +1,114 @@
+use cgmath::{InnerSpace, Matrix4, Vector3, Vector4};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use image::{GrayImage, Rgb, RgbImage};
+use tinyrenderer::mesh;
+use tinyrenderer::model::Model;
+use tinyrenderer::our_gl::{self, Framebuffer, Instance, Shader, Uniforms};
+
+const SIZE: u32 = 512;
+
+// Lambert against a light from the camera, cheap enough that the time goes to the rasterizer.
+struct FlatShader {
+    varying_norm: [Vector3<f32>; 3],
+}
+
+impl Shader for FlatShader {
+    fn vertex(
+        &mut self,
+        model: &Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &Uniforms,
+        instance: &Instance,
+    ) -> Vector4<f32> {
+        let v = model.get_faces()[iface][nthvert];
+        self.varying_norm[nthvert] = instance.normal(model.get_norms()[v]);
+        uniforms.mat * instance.position(model.get_verts()[v]).extend(1.0)
+    }
+
+    fn fragment(&self, bar: Vector3<f32>, _uniforms: &Uniforms, color: &mut Rgb<u8>) -> bool {
+        let n = (self.varying_norm[0] * bar.x
+            + self.varying_norm[1] * bar.y
+            + self.varying_norm[2] * bar.z)
+            .normalize();
+        let intensity = n.dot(Vector3::new(1.0, 1.0, 3.0).normalize()).max(0.0);
+        *color = Rgb([(255.0 * intensity) as u8; 3]);
+        true
+    }
+}
+
+// the sphere filling the middle of a size x size target
+fn camera(size: u32) -> Matrix4<f32> {
+    let eye = Vector3::new(1.0, 1.0, 3.0);
+    let size = size as f32;
+    our_gl::viewport(size / 8.0, size / 8.0, size * 3.0 / 4.0, size * 3.0 / 4.0)
+        * our_gl::projection(-1.0 / eye.magnitude())
+        * our_gl::lookat(
+            eye,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        )
+}
+
+fn frame(size: u32) -> Framebuffer {
+    Framebuffer::new(RgbImage::new(size, size), GrayImage::new(size, size))
+}
+
+fn draw(model: &Model, mat: Matrix4<f32>, frame: &mut Framebuffer) {
+    let (width, height) = frame.dimensions();
+    let mut shader = FlatShader {
+        varying_norm: [Vector3::new(0.0, 0.0, 0.0); 3],
+    };
+    let uniforms = Uniforms::screen(mat);
+    let instance = Instance::default();
+    for i in our_gl::visible_faces(model, mat, width, height) {
+        let screen_coords = [0, 1, 2].map(|j| shader.vertex(model, i, j, &uniforms, &instance));
+        our_gl::triangle(&screen_coords, &shader, &uniforms, frame);
+    }
+}
+
+// throughput in triangles, finer spheres at the same size
+fn triangles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("triangles");
+    for subdivisions in 1..=5 {
+        let model = mesh::sphere(subdivisions).unwrap();
+        let mat = camera(SIZE);
+        group.throughput(Throughput::Elements(model.triangle_count() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(subdivisions),
+            &model,
+            |b, model| {
+                b.iter_batched_ref(
+                    || frame(SIZE),
+                    |frame| draw(model, mat, frame),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+// throughput in pixels covered, the same sphere on bigger targets
+fn pixels(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pixels");
+    let model = mesh::sphere(3).unwrap();
+    for size in [256, 512, 1024, 2048] {
+        let mat = camera(size);
+        let mut covered = frame(size);
+        draw(&model, mat, &mut covered);
+        let covered = covered.depth.pixels().filter(|depth| depth[0] > 0).count();
+        group.throughput(Throughput::Elements(covered as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter_batched_ref(
+                || frame(size),
+                |frame| draw(&model, mat, frame),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, triangles, pixels);
+criterion_main!(benches);
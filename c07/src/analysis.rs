@@ -0,0 +1,171 @@
+use super::our_gl::DepthImage;
+use super::texture::{linear_to_srgb, srgb_to_linear};
+use image::{GrayImage, ImageBuffer, Luma, Rgb, RgbImage};
+
+// exposure is measured in stops from middle grey
+const MIDDLE_GREY: f32 = 0.18;
+
+// False colour zones by the exposure they reach up to, one stop wide around middle grey.
+// The first takes everything darker down to black and the last starts just short of white
+// (2.47 stops) where highlights clip. Colours run from purple through grey to red.
+pub const ZONES: [(f32, Rgb<u8>); 9] = [
+    (-4.5, Rgb([64, 0, 160])),
+    (-3.5, Rgb([0, 64, 224])),
+    (-2.5, Rgb([0, 160, 224])),
+    (-1.5, Rgb([0, 160, 96])),
+    (-0.5, Rgb([96, 96, 96])),
+    (0.5, Rgb([128, 128, 128])),
+    (1.5, Rgb([240, 160, 192])),
+    (2.4, Rgb([240, 224, 0])),
+    (f32::INFINITY, Rgb([255, 0, 0])),
+];
+
+// False colour for how many times a pixel was shaded: black where nothing was drawn, blue
+// for once and through green and yellow to red, which takes the last count and every one
+// above it.
+pub const OVERDRAW: [Rgb<u8>; 8] = [
+    Rgb([0, 0, 0]),
+    Rgb([0, 32, 160]),
+    Rgb([0, 128, 224]),
+    Rgb([0, 192, 96]),
+    Rgb([160, 224, 0]),
+    Rgb([255, 224, 0]),
+    Rgb([255, 128, 0]),
+    Rgb([255, 0, 0]),
+];
+
+// Perceptually ordered colour ramps from 0 to 1, as polynomial fits of matplotlib's viridis
+// and Google's turbo with the lowest power first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Colormap {
+    Viridis,
+    Turbo,
+}
+
+const VIRIDIS: [[f32; 3]; 7] = [
+    [0.277727, 0.00540734, 0.334100],
+    [0.105093, 1.40461, 1.38459],
+    [-0.330862, 0.214848, 0.0950952],
+    [-4.63423, -5.79910, -19.3324],
+    [6.22827, 14.1799, 56.6906],
+    [4.77638, -13.7451, -65.3530],
+    [-5.43546, 4.64585, 26.3124],
+];
+
+const TURBO: [[f32; 3]; 6] = [
+    [0.135721, 0.0914026, 0.106673],
+    [4.61539, 2.19419, 12.6419],
+    [-42.6603, 4.84297, -60.5820],
+    [132.131, -14.1850, 110.363],
+    [-152.942, 4.27730, -89.9031],
+    [59.2864, 2.82957, 27.3482],
+];
+
+impl Colormap {
+    pub fn color(&self, t: f32) -> Rgb<u8> {
+        let coefficients: &[[f32; 3]] = match self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Turbo => &TURBO,
+        };
+        let t = t.clamp(0.0, 1.0);
+        let mut rgb = [0.0; 3];
+        for row in coefficients.iter().rev() {
+            for (c, coefficient) in rgb.iter_mut().zip(row) {
+                *c = *c * t + coefficient;
+            }
+        }
+        Rgb(rgb.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+    }
+}
+
+impl std::str::FromStr for Colormap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Colormap> {
+        match s {
+            "viridis" => Ok(Colormap::Viridis),
+            "turbo" => Ok(Colormap::Turbo),
+            _ => Err(anyhow::anyhow!("unknown colormap '{}'", s)),
+        }
+    }
+}
+
+// Colours the covered pixels of a depth buffer (those with a depth) by where their depth,
+// after linear, falls between the nearest and farthest of them. Nearest is the top of the
+// colormap, the rest stays black. Gives the range it was spread over.
+pub fn depth_view(
+    depth: &DepthImage,
+    colormap: Colormap,
+    linear: impl Fn(f32) -> f32,
+) -> (RgbImage, (f32, f32)) {
+    let covered = || depth.pixels().filter(|d| d[0] > 0.0).map(|d| linear(d[0]));
+    let min = covered().fold(f32::INFINITY, f32::min);
+    let max = covered().fold(f32::NEG_INFINITY, f32::max);
+    let out = ImageBuffer::from_fn(depth.width(), depth.height(), |x, y| {
+        let d = depth.get_pixel(x, y)[0];
+        if d > 0.0 {
+            colormap.color((linear(d) - min) / (max - min).max(f32::EPSILON))
+        } else {
+            Rgb([0, 0, 0])
+        }
+    });
+    (out, (min, max))
+}
+
+// relative luminance of an sRGB encoded pixel, in linear light
+pub fn linear_luminance(pixel: &Rgb<u8>) -> f32 {
+    0.2126 * srgb_to_linear(pixel[0])
+        + 0.7152 * srgb_to_linear(pixel[1])
+        + 0.0722 * srgb_to_linear(pixel[2])
+}
+
+// the image with its colour taken away, grey encoded like the image so it reads the same
+pub fn luminance(image: &RgbImage) -> GrayImage {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        Luma([linear_to_srgb(linear_luminance(image.get_pixel(x, y)))])
+    })
+}
+
+// how many stops a linear luminance is above middle grey
+pub fn stops(luminance: f32) -> f32 {
+    (luminance / MIDDLE_GREY).log2()
+}
+
+// which of ZONES a luminance falls in
+pub fn zone(luminance: f32) -> usize {
+    let stops = stops(luminance);
+    ZONES
+        .iter()
+        .position(|&(upper, _)| stops < upper)
+        .unwrap_or(ZONES.len() - 1)
+}
+
+// Paints every pixel the colour of its exposure zone and counts how many covered pixels
+// (those with a depth) fall in each zone. Images must not be flipped yet so they line up
+// with the zbuffer.
+pub fn zones(image: &RgbImage, zbuffer: &GrayImage) -> (RgbImage, [usize; ZONES.len()]) {
+    let mut counts = [0; ZONES.len()];
+    let out = ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let zone = zone(linear_luminance(image.get_pixel(x, y)));
+        if zbuffer.get_pixel(x, y)[0] > 0 {
+            counts[zone] += 1;
+        }
+        ZONES[zone].1
+    });
+    (out, counts)
+}
+
+// Paints the fragments counted at every pixel with OVERDRAW.
+pub fn overdraw(counts: &ImageBuffer<Luma<u16>, Vec<u16>>) -> RgbImage {
+    ImageBuffer::from_fn(counts.width(), counts.height(), |x, y| {
+        OVERDRAW[(counts.get_pixel(x, y)[0] as usize).min(OVERDRAW.len() - 1)]
+    })
+}
+
+// stops from middle grey a zone starts at, the first one reaches down to black
+pub fn zone_floor(zone: usize) -> f32 {
+    match zone {
+        0 => f32::NEG_INFINITY,
+        _ => ZONES[zone - 1].0,
+    }
+}
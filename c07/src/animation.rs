@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Result};
+use cgmath::Vector3;
+use image::io::Reader as ImageReader;
+use image::RgbImage;
+
+// Vertex animation texture: row f holds frame f, column v the offset of vertex v.
+// Each channel maps 0..=255 to -scale..=scale so mid grey is no movement.
+pub struct VertexAnimation {
+    image: RgbImage,
+    scale: f32,
+}
+
+impl VertexAnimation {
+    pub fn load(filename: &str, scale: f32) -> Result<VertexAnimation> {
+        let image = ImageReader::open(filename)?.decode()?.to_rgb8();
+        if image.width() == 0 || image.height() == 0 {
+            return Err(anyhow!("vertex animation {} is empty", filename));
+        }
+        Ok(VertexAnimation { image, scale })
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.image.height()
+    }
+
+    // frames wrap around so playback can loop, vertices past the last column don't move
+    pub fn offset(&self, vertex: usize, frame: u32) -> Vector3<f32> {
+        if vertex >= self.image.width() as usize {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        let texel = self.image.get_pixel(vertex as u32, frame % self.frames());
+        let decode = |c: u8| (c as f32 / 127.5 - 1.0) * self.scale;
+        Vector3::new(decode(texel[0]), decode(texel[1]), decode(texel[2]))
+    }
+}
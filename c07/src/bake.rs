@@ -0,0 +1,152 @@
+use cgmath::{Matrix4, Vector3, Vector4};
+use image::{imageops, GrayImage, Luma, RgbImage};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::f32::consts::PI;
+
+use super::light::{Light, Source};
+use super::model::Model;
+use super::our_gl::{self, DepthImage, DepthOffset, Framebuffer, Instance, Shader, Uniforms};
+use super::renderer::{self, Progress};
+use super::shaders::OcclusionBakeShader;
+use super::texture::Maps;
+
+// side of the depth buffers the model is seen through from each direction
+const DEPTH_SIZE: u32 = 1024;
+// texels around the unwrapped faces filled from their edges, so sampling next to a seam
+// doesn't pick up the unoccluded background
+const PADDING: u32 = 4;
+// the same directions every run
+const SEED: u64 = 0;
+
+// Ambient occlusion of the model's objects baked into textures in their uv space, the
+// tutorial's way. The model is seen from samples random directions over the upper
+// hemisphere and every texel counts how many of them it was in sight from, 255 for all of
+// them. sizes holds the width and height of each object's texture. The images are the
+// right way up to be saved, texels no face covers are left white.
+pub fn ambient_occlusion(
+    model: &Model,
+    maps: &[Maps],
+    sizes: &[(u32, u32)],
+    samples: usize,
+    progress: Progress,
+) -> Vec<GrayImage> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let instance = Instance::default();
+    let mut seen: Vec<Vec<u32>> = sizes
+        .iter()
+        .map(|&(width, height)| vec![0; (width * height) as usize])
+        .collect();
+    let mut covered: Vec<Vec<bool>> = sizes
+        .iter()
+        .map(|&(width, height)| vec![false; (width * height) as usize])
+        .collect();
+    for sample in 0..samples {
+        progress(sample, samples);
+        // uniform over the hemisphere
+        let y: f32 = rng.gen();
+        let phi = rng.gen_range(0.0..2.0 * PI);
+        let r = (1.0 - y * y).sqrt();
+        let direction = Vector3::new(phi.cos() * r, y, phi.sin() * r);
+        let up = if y > 0.99 {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_y()
+        };
+        let mut depth_frame = Framebuffer::new(
+            RgbImage::new(DEPTH_SIZE, DEPTH_SIZE),
+            DepthImage::new(DEPTH_SIZE, DEPTH_SIZE),
+        );
+        let camera = renderer::shadow_pass(
+            model,
+            maps,
+            &[instance],
+            &Light::white(Source::Directional { dir: direction }),
+            up,
+            &mut depth_frame,
+            DepthOffset {
+                bias: renderer::SHADOW_BIAS,
+                slope_bias: renderer::SHADOW_SLOPE_BIAS,
+            },
+            None,
+            &|_, _| {},
+        );
+
+        let mut shader = OcclusionBakeShader::new(&depth_frame.depth);
+        for (object, &(width, height)) in sizes.iter().enumerate() {
+            let uniforms = Uniforms {
+                shadow: camera.transform(),
+                ..Uniforms::screen(Matrix4::from_nonuniform_scale(
+                    width as f32,
+                    height as f32,
+                    1.0,
+                ))
+            };
+            let mut frame =
+                Framebuffer::new(RgbImage::new(width, height), GrayImage::new(width, height));
+            // however the faces are wound in uv space, none of them are culled
+            for i in (0..model.get_faces().len()).filter(|&i| model.get_object(i) == object) {
+                let screen_coords: [Vector4<f32>; 3] =
+                    [0, 1, 2].map(|j| shader.vertex(model, i, j, &uniforms, &instance));
+                our_gl::triangle(&screen_coords, &shader, &uniforms, &mut frame);
+            }
+            for (k, (color, depth)) in frame.color.pixels().zip(frame.depth.pixels()).enumerate() {
+                if depth[0] > 0 {
+                    covered[object][k] = true;
+                    seen[object][k] += (color[0] / 255) as u32;
+                }
+            }
+        }
+    }
+    progress(samples, samples);
+
+    sizes
+        .iter()
+        .zip(seen.iter().zip(&covered))
+        .map(|(&(width, height), (seen, covered))| {
+            let mut occlusion = GrayImage::from_fn(width, height, |x, y| {
+                let k = (y * width + x) as usize;
+                Luma([(255 * seen[k] / samples.max(1) as u32) as u8])
+            });
+            pad(&mut occlusion, covered.clone(), PADDING);
+            // (0,0) is the bottom left like the framebuffer and loaded textures
+            imageops::flip_vertical(&occlusion)
+        })
+        .collect()
+}
+
+// grows the covered texels of image outwards a texel at a time, the rest go white
+fn pad(image: &mut GrayImage, mut covered: Vec<bool>, texels: u32) {
+    let (width, height) = image.dimensions();
+    for _ in 0..texels {
+        let mut grown = covered.clone();
+        for y in 0..height {
+            for x in 0..width {
+                if covered[(y * width + x) as usize] {
+                    continue;
+                }
+                let (mut sum, mut count) = (0u32, 0u32);
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                        continue;
+                    }
+                    if covered[(ny as u32 * width + nx as u32) as usize] {
+                        sum += image.get_pixel(nx as u32, ny as u32)[0] as u32;
+                        count += 1;
+                    }
+                }
+                if let Some(mean) = sum.checked_div(count) {
+                    image.put_pixel(x, y, Luma([mean as u8]));
+                    grown[(y * width + x) as usize] = true;
+                }
+            }
+        }
+        covered = grown;
+    }
+    for (texel, covered) in image.pixels_mut().zip(&covered) {
+        if !covered {
+            *texel = Luma([255]);
+        }
+    }
+}
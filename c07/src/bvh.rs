@@ -0,0 +1,417 @@
+use cgmath::{dot, ElementWise, InnerSpace, Matrix4, SquareMatrix, Vector2, Vector3};
+use std::ops::Range;
+
+use super::model;
+use super::our_gl::Instance;
+
+// triangles a leaf holds before it is split
+const LEAF_SIZE: usize = 4;
+const EPSILON: f32 = 1e-5;
+
+// Möller–Trumbore, distance along dir to the triangle if it is hit and the barycentric
+// weights of its second and third corners there
+pub fn intersect(
+    origin: Vector3<f32>,
+    dir: Vector3<f32>,
+    tri: [Vector3<f32>; 3],
+) -> Option<(f32, Vector2<f32>)> {
+    let e1 = tri[1] - tri[0];
+    let e2 = tri[2] - tri[0];
+    let h = dir.cross(e2);
+    let a = dot(e1, h);
+    if a.abs() < EPSILON {
+        return None;
+    }
+    let s = origin - tri[0];
+    let u = dot(s, h) / a;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let v = dot(dir, q) / a;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = dot(e2, q) / a;
+    if t > EPSILON {
+        Some((t, Vector2::new(u, v)))
+    } else {
+        None
+    }
+}
+
+// Flat triangles of a coarse mesh shade as if curved, so a shadow ray from the flat one near
+// the terminator catches its neighbours. This is where to start it instead, the point at bc
+// on the curved surface the corners' normals describe, pushed out from each corner's tangent
+// plane (Hanika, Hacking the Shadow Terminator).
+pub fn curved_position(
+    corners: [Vector3<f32>; 3],
+    normals: [Vector3<f32>; 3],
+    bc: Vector3<f32>,
+) -> Vector3<f32> {
+    let flat = corners[0] * bc[0] + corners[1] * bc[1] + corners[2] * bc[2];
+    (0..3)
+        .map(|j| {
+            let n = normals[j].normalize();
+            (flat - n * dot(flat - corners[j], n).min(0.0)) * bc[j]
+        })
+        .sum()
+}
+
+// slab test, whether the ray enters the box before limit
+fn hits_box(
+    origin: Vector3<f32>,
+    inv_dir: Vector3<f32>,
+    (min, max): (Vector3<f32>, Vector3<f32>),
+    limit: f32,
+) -> bool {
+    let t0 = (min - origin).mul_element_wise(inv_dir);
+    let t1 = (max - origin).mul_element_wise(inv_dir);
+    let near = t0.x.min(t1.x).max(t0.y.min(t1.y)).max(t0.z.min(t1.z));
+    let far = t0.x.max(t1.x).min(t0.y.max(t1.y)).min(t0.z.max(t1.z));
+    near <= far && far >= 0.0 && near <= limit
+}
+
+// the box around everything in items
+fn bounds<T: Bounded>(items: &[T]) -> (Vector3<f32>, Vector3<f32>) {
+    let inf = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    items
+        .iter()
+        .map(Bounded::bounds)
+        .fold((inf, -inf), |(min, max), (lo, hi)| {
+            (
+                Vector3::new(min.x.min(lo.x), min.y.min(lo.y), min.z.min(lo.z)),
+                Vector3::new(max.x.max(hi.x), max.y.max(hi.y), max.z.max(hi.z)),
+            )
+        })
+}
+
+// what a hierarchy can be built over
+trait Bounded {
+    fn bounds(&self) -> (Vector3<f32>, Vector3<f32>);
+    fn centroid(&self) -> Vector3<f32>;
+}
+
+#[derive(Clone)]
+struct Tri {
+    corners: [Vector3<f32>; 3],
+    uvs: [Vector2<f32>; 3],
+    object: usize,
+    face: usize,
+}
+
+impl Bounded for Tri {
+    fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        bounds(&self.corners.map(Point))
+    }
+
+    fn centroid(&self) -> Vector3<f32> {
+        (self.corners[0] + self.corners[1] + self.corners[2]) / 3.0
+    }
+}
+
+struct Point(Vector3<f32>);
+
+impl Bounded for Point {
+    fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        (self.0, self.0)
+    }
+
+    fn centroid(&self) -> Vector3<f32> {
+        self.0
+    }
+}
+
+// an instance of the mesh, bounds are in world space
+#[derive(Clone)]
+struct Placed {
+    // world to the mesh's own space, none when they are the same
+    to_mesh: Option<Matrix4<f32>>,
+    bounds: (Vector3<f32>, Vector3<f32>),
+    index: usize,
+}
+
+impl Bounded for Placed {
+    fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        self.bounds
+    }
+
+    fn centroid(&self) -> Vector3<f32> {
+        (self.bounds.0 + self.bounds.1) / 2.0
+    }
+}
+
+impl Placed {
+    // the ray in the mesh's space, dir isn't normalized so distances along it stay the same
+    fn ray(&self, origin: Vector3<f32>, dir: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        match self.to_mesh {
+            Some(m) => (
+                (m * origin.extend(1.0)).truncate(),
+                (m * dir.extend(0.0)).truncate(),
+            ),
+            None => (origin, dir),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Node {
+    bounds: (Vector3<f32>, Vector3<f32>),
+    // a leaf's items are items[start..start + count], an inner node's children are
+    // nodes[start] and nodes[start + 1]
+    start: usize,
+    count: usize,
+}
+
+// the nodes of a hierarchy over items, which are reordered so every leaf's are together
+fn build<T: Bounded>(items: &mut [T], leaf_size: usize) -> Vec<Node> {
+    let mut nodes = vec![Node {
+        bounds: bounds(items),
+        start: 0,
+        count: items.len(),
+    }];
+    split(&mut nodes, items, 0, leaf_size);
+    nodes
+}
+
+// halves the node's items at the median of their centroids along the widest axis
+fn split<T: Bounded>(nodes: &mut Vec<Node>, items: &mut [T], index: usize, leaf_size: usize) {
+    let Node { start, count, .. } = nodes[index];
+    if count <= leaf_size {
+        return;
+    }
+    let slice = &mut items[start..start + count];
+    let (min, max) = slice.iter().fold(
+        (slice[0].centroid(), slice[0].centroid()),
+        |(min, max), item| {
+            let c = item.centroid();
+            (
+                Vector3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z)),
+                Vector3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z)),
+            )
+        },
+    );
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let half = count / 2;
+    slice.select_nth_unstable_by(half, |a, b| {
+        a.centroid()[axis].total_cmp(&b.centroid()[axis])
+    });
+
+    let first = nodes.len();
+    for (start, count) in [(start, half), (start + half, count - half)] {
+        nodes.push(Node {
+            bounds: bounds(&items[start..start + count]),
+            start,
+            count,
+        });
+    }
+    nodes[index] = Node {
+        start: first,
+        count: 0,
+        ..nodes[index]
+    };
+    split(nodes, items, first, leaf_size);
+    split(nodes, items, first + 1, leaf_size);
+}
+
+// The leaves of nodes whose boxes the ray enters before limit, nearest the root first. leaf
+// is given the range of their items and can bring limit in, it stops the walk by giving
+// true. Whether it was stopped.
+fn walk(
+    nodes: &[Node],
+    origin: Vector3<f32>,
+    dir: Vector3<f32>,
+    mut limit: f32,
+    mut leaf: impl FnMut(Range<usize>, &mut f32) -> bool,
+) -> bool {
+    let inv_dir = Vector3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+    let mut stack = vec![0];
+    while let Some(index) = stack.pop() {
+        let node = &nodes[index];
+        if !hits_box(origin, inv_dir, node.bounds, limit) {
+            continue;
+        }
+        if node.count == 0 {
+            stack.extend([node.start, node.start + 1]);
+            continue;
+        }
+        if leaf(node.start..node.start + node.count, &mut limit) {
+            return true;
+        }
+    }
+    false
+}
+
+// where a ray first meets the scene, bar holds the barycentric weights of the face's second
+// and third corners
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub t: f32,
+    pub bar: Vector2<f32>,
+    pub face: usize,
+    pub instance: usize,
+}
+
+// A hierarchy over the model's triangles in its own space, built once however many times
+// it is placed, under one over the boxes of its instances in world space. Rays are taken
+// into the model's space for each instance they reach, so casting rays against the scene
+// without rasterizing it stays logarithmic in both the triangles and the instances.
+#[derive(Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    tris: Vec<Tri>,
+    instance_nodes: Vec<Node>,
+    instances: Vec<Placed>,
+}
+
+impl Bvh {
+    pub fn new(model: &model::Model, instances: &[Instance]) -> Bvh {
+        let mut tris: Vec<Tri> = model
+            .get_faces()
+            .iter()
+            .enumerate()
+            .map(|(iface, face)| Tri {
+                corners: [face[0], face[1], face[2]].map(|v| model.get_verts()[v]),
+                uvs: [face[0], face[1], face[2]].map(|v| model.get_uvs()[v]),
+                object: model.get_object(iface),
+                face: iface,
+            })
+            .collect();
+        let nodes = build(&mut tris, LEAF_SIZE);
+        let (min, max) = nodes[0].bounds;
+        let mut instances: Vec<Placed> = instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| {
+                if instance.transform == Matrix4::identity() || tris.is_empty() {
+                    return Placed {
+                        to_mesh: None,
+                        bounds: (min, max),
+                        index,
+                    };
+                }
+                // the box of the mesh's box placed
+                let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|k| {
+                    let corner = Vector3::new(
+                        if k & 1 == 0 { min.x } else { max.x },
+                        if k & 2 == 0 { min.y } else { max.y },
+                        if k & 4 == 0 { min.z } else { max.z },
+                    );
+                    Point(instance.position(corner))
+                });
+                Placed {
+                    to_mesh: instance.transform.invert(),
+                    bounds: bounds(&corners),
+                    index,
+                }
+            })
+            .collect();
+        let instance_nodes = build(&mut instances, 1);
+        Bvh {
+            nodes,
+            tris,
+            instance_nodes,
+            instances,
+        }
+    }
+
+    // the instances whose boxes the ray enters before limit, see walk()
+    fn walk_instances(
+        &self,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+        limit: f32,
+        mut instance: impl FnMut(&Placed, &mut f32) -> bool,
+    ) -> bool {
+        if self.tris.is_empty() || self.instances.is_empty() {
+            return false;
+        }
+        walk(&self.instance_nodes, origin, dir, limit, |range, limit| {
+            self.instances[range]
+                .iter()
+                .any(|placed| instance(placed, limit))
+        })
+    }
+
+    // Whether anything lies between origin and limit along dir. opaque(object, uv) decides
+    // whether a hit blocks the ray, so cutouts can let it through.
+    pub fn occluded<F: Fn(usize, Vector2<f32>) -> bool>(
+        &self,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+        limit: f32,
+        opaque: F,
+    ) -> bool {
+        self.walk_instances(origin, dir, limit, |placed, _| {
+            let (origin, dir) = placed.ray(origin, dir);
+            walk(&self.nodes, origin, dir, limit, |range, _| {
+                self.tris[range].iter().any(|tri| {
+                    intersect(origin, dir, tri.corners).is_some_and(|(t, bar)| {
+                        let uv = tri.uvs[0] * (1.0 - bar.x - bar.y)
+                            + tri.uvs[1] * bar.x
+                            + tri.uvs[2] * bar.y;
+                        t < limit && opaque(tri.object, uv)
+                    })
+                })
+            })
+        })
+    }
+
+    // the nearest triangle along dir from origin
+    pub fn closest(&self, origin: Vector3<f32>, dir: Vector3<f32>) -> Option<Hit> {
+        self.closest_where(origin, dir, |_, _| true)
+    }
+
+    // the nearest triangle along dir from origin that opaque(object, uv) keeps, see
+    // occluded()
+    pub fn closest_where<F: Fn(usize, Vector2<f32>) -> bool>(
+        &self,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+        opaque: F,
+    ) -> Option<Hit> {
+        let mut nearest: Option<Hit> = None;
+        self.walk_instances(origin, dir, f32::INFINITY, |placed, limit| {
+            let (origin, dir) = placed.ray(origin, dir);
+            walk(&self.nodes, origin, dir, *limit, |range, limit| {
+                for tri in &self.tris[range] {
+                    let Some((t, bar)) = intersect(origin, dir, tri.corners) else {
+                        continue;
+                    };
+                    if t >= *limit {
+                        continue;
+                    }
+                    let uv = tri.uvs[0] * (1.0 - bar.x - bar.y)
+                        + tri.uvs[1] * bar.x
+                        + tri.uvs[2] * bar.y;
+                    if opaque(tri.object, uv) {
+                        *limit = t;
+                        nearest = Some(Hit {
+                            t,
+                            bar,
+                            face: tri.face,
+                            instance: placed.index,
+                        });
+                    }
+                }
+                false
+            });
+            *limit = nearest.map_or(f32::INFINITY, |hit| hit.t);
+            false
+        });
+        nearest
+    }
+
+    // how far off a surface a ray should start not to hit it again, relative to the scene
+    pub fn epsilon(&self) -> f32 {
+        let (min, max) = self.instance_nodes[0].bounds;
+        (max - min).magnitude() * 1e-4
+    }
+}
@@ -0,0 +1,390 @@
+use anyhow::{anyhow, Context, Result};
+use cgmath::{dot, InnerSpace, Matrix4, Transform, Vector3, Vector4};
+use std::fmt;
+
+use super::cubemap;
+use super::light::parse_vector;
+use super::our_gl;
+
+// vertical field of view of new cameras, degrees
+pub const DEFAULT_FOV: f32 = 60.0;
+
+// the closest fit_depth() lets near get to the eye, as a part of the scene's radius
+const NEAREST: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    // chapter 4's single coefficient of -1 / distance from the eye to the target, one unit
+    // around the target fills the viewport. Kept for comparison with the lessons.
+    Simple,
+    // no foreshortening, size units either side of the target fill the viewport vertically
+    Orthographic { size: f32 },
+    // vertical field of view in degrees, the depth range is spread between near and far
+    Perspective { fov: f32, near: f32, far: f32 },
+}
+
+impl Projection {
+    // The frustum new cameras distance away from their target get. The depth buffer only
+    // has 8 bits and perspective spends most of them near the eye, so rather than reaching
+    // from the eye to the horizon near and far hug the models around the target.
+    pub fn perspective(distance: f32) -> Projection {
+        Projection::Perspective {
+            fov: DEFAULT_FOV,
+            near: distance / 2.0,
+            far: distance * 2.0,
+        }
+    }
+}
+
+// the rectangle of the frame the unit cube is mapped onto, (0,0) is the bottom left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    // all of a width x height frame
+    pub fn full(width: u32, height: u32) -> Viewport {
+        Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: width as f32,
+            height: height as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub eye: Vector3<f32>,
+    pub target: Vector3<f32>,
+    pub up: Vector3<f32>,
+    pub projection: Projection,
+    pub viewport: Viewport,
+}
+
+impl Camera {
+    // the default perspective projection over all of a width x height frame
+    pub fn new(
+        eye: Vector3<f32>,
+        target: Vector3<f32>,
+        up: Vector3<f32>,
+        width: u32,
+        height: u32,
+    ) -> Camera {
+        Camera {
+            eye,
+            target,
+            up,
+            projection: Projection::perspective((eye - target).magnitude()),
+            viewport: Viewport::full(width, height),
+        }
+    }
+
+    // Looks at a sphere from the direction the camera already looks in, from just far
+    // enough away that all of it fits in the viewport. Near and far hug the sphere.
+    pub fn frame(&mut self, center: Vector3<f32>, radius: f32) {
+        let aspect = self.viewport.width / self.viewport.height;
+        let direction = (self.eye - self.target).normalize();
+        let direction = if direction.x.is_finite() {
+            direction
+        } else {
+            Vector3::new(0.0, 0.0, 1.0)
+        };
+        let distance = match self.projection {
+            Projection::Perspective { fov, .. } => {
+                // the narrower of the vertical and horizontal field of view
+                let tan = (fov.to_radians() / 2.0).tan() * aspect.min(1.0);
+                radius / tan.atan().sin()
+            }
+            _ => radius * 2.0,
+        };
+        self.target = center;
+        self.eye = center + direction * distance;
+        self.projection = match self.projection {
+            Projection::Simple => Projection::Simple,
+            Projection::Orthographic { .. } => Projection::Orthographic {
+                size: radius / aspect.min(1.0),
+            },
+            Projection::Perspective { fov, .. } => Projection::Perspective {
+                fov,
+                near: distance - radius,
+                far: distance + radius,
+            },
+        };
+    }
+
+    // Fits near and far of a perspective camera around a sphere without moving it. The eye
+    // can be inside the sphere, near then stops at a small part of it.
+    pub fn fit_depth(&mut self, center: Vector3<f32>, radius: f32) {
+        if let Projection::Perspective { fov, .. } = self.projection {
+            let distance = (self.eye - center).magnitude();
+            self.projection = Projection::Perspective {
+                fov,
+                near: (distance - radius).max(radius * NEAREST),
+                far: distance + radius,
+            };
+        }
+    }
+
+    // Turns the camera about its eye to look out of one of the faces of a cube map, one
+    // whose -Z is where the camera looked and +Y its up. The six make up the whole sphere
+    // around the eye with a square viewport. Near and far are kept if it had them.
+    pub fn cube_face(&mut self, face: usize) {
+        let distance = (self.eye - self.target).magnitude();
+        let back = (self.eye - self.target).normalize();
+        let right = self.up.cross(back).normalize();
+        let up = back.cross(right);
+        let world = |v: Vector3<f32>| right * v.x + up * v.y + back * v.z;
+        let (forward, face_up) = cubemap::face_axes(face);
+        self.target = self.eye + world(forward) * distance;
+        self.up = world(face_up);
+        let (near, far) = match self.projection {
+            Projection::Perspective { near, far, .. } => (near, far),
+            _ => (distance / 2.0, distance * 2.0),
+        };
+        self.projection = Projection::Perspective {
+            fov: 90.0,
+            near,
+            far,
+        };
+    }
+
+    // how far the top of the viewport is above the target, in world units
+    pub fn half_height(&self) -> f32 {
+        match self.projection {
+            Projection::Simple => 1.0,
+            Projection::Orthographic { size } => size,
+            Projection::Perspective { fov, .. } => {
+                (self.eye - self.target).magnitude() * (fov.to_radians() / 2.0).tan()
+            }
+        }
+    }
+
+    // about how many pixels across the middle of a sphere is from its centre, e.g. to pick a
+    // level of detail by
+    pub fn projected_radius(&self, center: Vector3<f32>, radius: f32) -> f32 {
+        let half_height = match self.projection {
+            Projection::Perspective { fov, .. } => {
+                (center - self.eye).magnitude() * (fov.to_radians() / 2.0).tan()
+            }
+            _ => self.half_height(),
+        };
+        radius * self.viewport.height / 2.0 / half_height
+    }
+
+    // Pixels across per unit of the depth buffer for surfaces around the target, to compare
+    // depths with distances on screen. Perspective depth isn't linear so this only holds
+    // near the target.
+    pub fn depth_scale(&self) -> f32 {
+        let distance = (self.eye - self.target).magnitude();
+        let pixels_per_unit = self.viewport.height / 2.0 / self.half_height();
+        let depth_per_unit = our_gl::DEPTH / 2.0
+            * match self.projection {
+                Projection::Simple => 1.0,
+                Projection::Orthographic { .. } => 1.0 / distance,
+                // the slope of the depth row of perspective() at the target
+                Projection::Perspective { near, far, .. } => {
+                    2.0 * far * near / ((far - near) * distance * distance)
+                }
+            };
+        pixels_per_unit / depth_per_unit
+    }
+
+    // What a depth buffer value would be had the projection spread near to far evenly over
+    // it, nearer still larger. Perspective spends most of the buffer near the eye, the other
+    // projections are left as they are.
+    pub fn linear_depth(&self, depth: f32) -> f32 {
+        match self.projection {
+            Projection::Perspective { near, far, .. } => {
+                // inverting the depth row of perspective() and the viewport
+                let ndc = depth / our_gl::DEPTH * 2.0 - 1.0;
+                let distance = 2.0 * far * near / ((far - near) * ndc + far + near);
+                our_gl::DEPTH * (far - distance) / (far - near)
+            }
+            _ => depth,
+        }
+    }
+
+    pub fn model_view(&self) -> Matrix4<f32> {
+        our_gl::lookat(self.eye, self.target, self.up)
+    }
+
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        match self.projection {
+            Projection::Simple => our_gl::projection(-1.0 / (self.eye - self.target).magnitude()),
+            // the depth range reaches from the eye to as far behind the target
+            Projection::Orthographic { size } => our_gl::orthographic(
+                size * self.viewport.width / self.viewport.height,
+                size,
+                (self.eye - self.target).magnitude(),
+            ),
+            Projection::Perspective { fov, near, far } => {
+                // lookat() puts the target at the origin rather than the eye
+                let distance = (self.eye - self.target).magnitude();
+                our_gl::perspective(
+                    fov.to_radians(),
+                    self.viewport.width / self.viewport.height,
+                    near,
+                    far,
+                ) * Matrix4::from_translation(Vector3::new(0.0, 0.0, -distance))
+            }
+        }
+    }
+
+    pub fn viewport_matrix(&self) -> Matrix4<f32> {
+        our_gl::viewport(
+            self.viewport.x,
+            self.viewport.y,
+            self.viewport.width,
+            self.viewport.height,
+        )
+    }
+
+    // world to clip space
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.projection_matrix() * self.model_view()
+    }
+
+    // What the shaders light in, view_projection() about the target rather than the origin.
+    // They only take directions through it, but its inverse transpose carries translation
+    // into the normals, so this keeps the lighting the same wherever the scene is.
+    pub fn shading_matrix(&self) -> Matrix4<f32> {
+        self.view_projection() * Matrix4::from_translation(self.target)
+    }
+
+    // world to the screen
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.viewport_matrix() * self.projection_matrix() * self.model_view()
+    }
+
+    // what the shaders see of this camera, the shadow matrix is left to the caller
+    pub fn uniforms(&self) -> our_gl::Uniforms {
+        our_gl::Uniforms {
+            viewport: self.viewport_matrix(),
+            ..our_gl::Uniforms::new(
+                self.view_projection(),
+                self.model_view(),
+                self.shading_matrix(),
+                self.eye,
+            )
+        }
+    }
+
+    // the ray through screen point (x, y), see primary_ray()
+    pub fn ray(&self, x: f32, y: f32) -> (Vector3<f32>, Vector3<f32>) {
+        let to_world = self
+            .transform()
+            .inverse_transform()
+            .expect("the camera transform has no inverse");
+        let forward = (self.target - self.eye).normalize();
+        primary_ray(to_world, self.eye, forward, x, y)
+    }
+
+    // takes points on this camera's screen to where other sees them, e.g. for shadow lookups
+    pub fn screen_to(&self, other: &Camera) -> Matrix4<f32> {
+        other.transform()
+            * self
+                .transform()
+                .inverse_transform()
+                .expect("the camera transform has no inverse")
+    }
+
+    // Overrides the fields named in spec, a ';' separated list of
+    //   eye=x,y,z  target=x,y,z  up=x,y,z  viewport=x,y,width,height
+    //   projection=simple|orthographic[:size]|perspective:fov:near:far
+    // which is also what the camera displays as.
+    pub fn apply(&mut self, spec: &str) -> Result<()> {
+        for field in spec.split(';').map(str::trim).filter(|f| !f.is_empty()) {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| anyhow!("camera field '{}' should be key=value", field))?;
+            match key.trim() {
+                "eye" => self.eye = parse_vector(value)?,
+                "target" => self.target = parse_vector(value)?,
+                "up" => self.up = parse_vector(value)?,
+                "viewport" => {
+                    let v = value
+                        .split(',')
+                        .map(|c| c.trim().parse::<f32>())
+                        .collect::<Result<Vec<f32>, _>>()
+                        .with_context(|| format!("'{}' is not a viewport", value))?;
+                    if v.len() != 4 {
+                        return Err(anyhow!("viewport '{}' should be x,y,width,height", value));
+                    }
+                    self.viewport = Viewport {
+                        x: v[0],
+                        y: v[1],
+                        width: v[2],
+                        height: v[3],
+                    };
+                }
+                "projection" => {
+                    let parts: Vec<&str> = value.trim().split(':').collect();
+                    self.projection = match parts[..] {
+                        ["simple"] => Projection::Simple,
+                        ["orthographic"] => Projection::Orthographic { size: 1.0 },
+                        ["orthographic", size] => Projection::Orthographic {
+                            size: size.parse()?,
+                        },
+                        ["perspective", fov, near, far] => Projection::Perspective {
+                            fov: fov.parse()?,
+                            near: near.parse()?,
+                            far: far.parse()?,
+                        },
+                        _ => return Err(anyhow!("malformed projection '{}'", value)),
+                    };
+                }
+                _ => return Err(anyhow!("unknown camera field '{}'", key)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Camera {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let v = |v: Vector3<f32>| format!("{},{},{}", v.x, v.y, v.z);
+        write!(
+            f,
+            "eye={};target={};up={};",
+            v(self.eye),
+            v(self.target),
+            v(self.up)
+        )?;
+        match self.projection {
+            Projection::Simple => write!(f, "projection=simple;")?,
+            Projection::Orthographic { size } => write!(f, "projection=orthographic:{};", size)?,
+            Projection::Perspective { fov, near, far } => {
+                write!(f, "projection=perspective:{}:{}:{};", fov, near, far)?
+            }
+        }
+        write!(
+            f,
+            "viewport={},{},{},{}",
+            self.viewport.x, self.viewport.y, self.viewport.width, self.viewport.height
+        )
+    }
+}
+
+// The ray through screen point (x, y), found by taking two depths of it back to the world.
+// It starts level with the eye, which is the eye itself in perspective.
+pub fn primary_ray(
+    to_world: Matrix4<f32>,
+    eye: Vector3<f32>,
+    forward: Vector3<f32>,
+    x: f32,
+    y: f32,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let unproject = |z: f32| {
+        let p = to_world * Vector4::new(x, y, z, 1.0);
+        p.truncate() / p.w
+    };
+    let (a, b) = (unproject(0.0), unproject(our_gl::DEPTH));
+    let dir = (b - a).normalize();
+    let dir = if dot(dir, forward) < 0.0 { -dir } else { dir };
+    (a + dir * dot(eye - a, dir), dir)
+}
@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Context, Result};
+use cgmath::{InnerSpace, Vector3};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+
+use super::camera::{Camera, Projection, DEFAULT_FOV};
+use super::light::parse_vector;
+
+// Where the camera is at a moment of a flythrough
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f32, // seconds
+    pub eye: Vector3<f32>,
+    pub target: Vector3<f32>,
+    pub up: Vector3<f32>,
+    pub fov: f32, // vertical, degrees
+}
+
+impl Keyframe {
+    // Moves camera here with a perspective projection. Near and far are kept, they
+    // depend on the scene rather than the path, see Camera::fit_depth().
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.up = self.up;
+        let (near, far) = match camera.projection {
+            Projection::Perspective { near, far, .. } => (near, far),
+            _ => {
+                let distance = (self.eye - self.target).magnitude();
+                (distance / 2.0, distance * 2.0)
+            }
+        };
+        camera.projection = Projection::Perspective {
+            fov: self.fov,
+            near,
+            far,
+        };
+    }
+}
+
+// Keyframes in a text file, one a line as a time in seconds and ';' separated fields
+//   eye=x,y,z  target=x,y,z  up=x,y,z  fov=degrees
+// Fields left out are the previous keyframe's, the first needs eye and target. Lines
+// starting with # are comments.
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    keys: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(filename: &str) -> Result<CameraPath> {
+        let text =
+            fs::read_to_string(filename).with_context(|| format!("Could not read {}", filename))?;
+        CameraPath::parse(&text).with_context(|| format!("Could not read {}", filename))
+    }
+
+    pub fn parse(text: &str) -> Result<CameraPath> {
+        let mut keys: Vec<Keyframe> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = || format!("camera path line {}", number + 1);
+            let (time, fields) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let time: f32 = time
+                .parse()
+                .with_context(|| format!("{} should start with a time", at()))?;
+            if keys.last().is_some_and(|k| time <= k.time) {
+                return Err(anyhow!("{} goes back in time", at()));
+            }
+            let (mut eye, mut target) = (None, None);
+            let mut key = keys.last().copied().unwrap_or(Keyframe {
+                time,
+                eye: Vector3::new(0.0, 0.0, 0.0),
+                target: Vector3::new(0.0, 0.0, 0.0),
+                up: Vector3::new(0.0, 1.0, 0.0),
+                fov: DEFAULT_FOV,
+            });
+            key.time = time;
+            for field in fields.split(';').map(str::trim).filter(|f| !f.is_empty()) {
+                let (name, value) = field
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("{}: '{}' should be key=value", at(), field))?;
+                match name.trim() {
+                    "eye" => eye = Some(parse_vector(value)?),
+                    "target" => target = Some(parse_vector(value)?),
+                    "up" => key.up = parse_vector(value)?,
+                    "fov" => key.fov = value.trim().parse()?,
+                    _ => return Err(anyhow!("{}: unknown field '{}'", at(), name)),
+                }
+            }
+            if keys.is_empty() && (eye.is_none() || target.is_none()) {
+                return Err(anyhow!("{} is the first, it needs eye and target", at()));
+            }
+            key.eye = eye.unwrap_or(key.eye);
+            key.target = target.unwrap_or(key.target);
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Err(anyhow!("camera path has no keyframes"));
+        }
+        Ok(CameraPath { keys })
+    }
+
+    // seconds from the first keyframe to the last
+    pub fn duration(&self) -> f32 {
+        self.keys[self.keys.len() - 1].time - self.keys[0].time
+    }
+
+    // The camera seconds after the first keyframe, on a Catmull-Rom spline through all of
+    // them. The ends are held before and after the path.
+    pub fn at(&self, seconds: f32) -> Keyframe {
+        let time = self.keys[0].time + seconds;
+        let last = self.keys.len() - 1;
+        let k = self.keys.partition_point(|key| key.time <= time);
+        if k == 0 {
+            return self.keys[0];
+        }
+        if k > last {
+            return self.keys[last];
+        }
+        let (a, b) = (k - 1, k);
+        let dt = self.keys[b].time - self.keys[a].time;
+        let s = (time - self.keys[a].time) / dt;
+        let spline = |get: fn(&Keyframe) -> Vector3<f32>| {
+            // the slope at a key is the one between its neighbours, the ends only have one
+            let slope = |i: usize| {
+                let (before, after) = (i.saturating_sub(1), (i + 1).min(last));
+                (get(&self.keys[after]) - get(&self.keys[before]))
+                    / (self.keys[after].time - self.keys[before].time)
+            };
+            let (s2, s3) = (s * s, s * s * s);
+            get(&self.keys[a]) * (2.0 * s3 - 3.0 * s2 + 1.0)
+                + slope(a) * dt * (s3 - 2.0 * s2 + s)
+                + get(&self.keys[b]) * (-2.0 * s3 + 3.0 * s2)
+                + slope(b) * dt * (s3 - s2)
+        };
+        let up = spline(|k| k.up);
+        Keyframe {
+            time,
+            eye: spline(|k| k.eye),
+            target: spline(|k| k.target),
+            up: if up.magnitude2().is_normal() {
+                up.normalize()
+            } else {
+                self.keys[a].up
+            },
+            fov: spline(|k| Vector3::new(k.fov, 0.0, 0.0)).x,
+        }
+    }
+}
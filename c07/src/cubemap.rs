@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Result};
+use cgmath::Vector3;
+use image::io::Reader as ImageReader;
+use image::{imageops, Rgb, RgbImage};
+use std::f32::consts::PI;
+use std::path::Path;
+
+// order of the faces, also the suffixes of the six file layout
+pub const FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+// the direction each face looks in and its up, as it is stored below
+const FACE_AXES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+];
+
+// the face named px, nx, py, ny, pz or nz
+pub fn face(name: &str) -> Result<usize> {
+    FACES.iter().position(|&face| face == name).ok_or_else(|| {
+        anyhow!(
+            "unknown cube face '{}', expected {}",
+            name,
+            FACES.join(", ")
+        )
+    })
+}
+
+// where a face looks and which way is up on it, to render it with a 90 degree camera
+pub fn face_axes(face: usize) -> (Vector3<f32>, Vector3<f32>) {
+    let (forward, up) = FACE_AXES[face];
+    (forward.into(), up.into())
+}
+
+// where each face sits in a horizontal cross, in face sized (column, row) steps:
+//         +Y
+//     -X  -Z  +X  +Z
+//         -Y
+// -Z is the front, matching the camera looking down -z
+const CROSS: [(u32, u32); 6] = [(2, 1), (0, 1), (1, 0), (1, 2), (3, 1), (1, 1)];
+
+// Every face is stored as seen from inside the cube with (0,0) at the top left.
+// The side faces have +Y up, the top and bottom faces have their edge shared with -Z at
+// the bottom and top respectively.
+pub struct CubeMap {
+    faces: Vec<RgbImage>,
+}
+
+impl CubeMap {
+    // a single image is read as a cross, anything else as the prefix of
+    // <path>_px.tga, <path>_nx.tga, ... <path>_nz.tga
+    pub fn load(path: &str) -> Result<CubeMap> {
+        if Path::new(path).is_file() {
+            return CubeMap::from_cross(ImageReader::open(path)?.decode()?.to_rgb8());
+        }
+        let faces = FACES
+            .iter()
+            .map(|face| -> Result<RgbImage> {
+                Ok(ImageReader::open(format!("{}_{}.tga", path, face))?
+                    .decode()?
+                    .to_rgb8())
+            })
+            .collect::<Result<Vec<RgbImage>>>()?;
+        if faces
+            .iter()
+            .any(|face| face.dimensions() != faces[0].dimensions())
+        {
+            return Err(anyhow!("cube map faces of {} differ in size", path));
+        }
+        Ok(CubeMap { faces })
+    }
+
+    fn from_cross(mut cross: RgbImage) -> Result<CubeMap> {
+        let size = cross.width() / 4;
+        if size == 0 || cross.width() != size * 4 || cross.height() != size * 3 {
+            return Err(anyhow!(
+                "a cross cube map should be 4:3, got {}x{}",
+                cross.width(),
+                cross.height()
+            ));
+        }
+        let faces = CROSS
+            .iter()
+            .map(|&(col, row)| {
+                imageops::crop(&mut cross, col * size, row * size, size, size).to_image()
+            })
+            .collect();
+        Ok(CubeMap { faces })
+    }
+
+    // the faces laid out in a cross as a single image is read, black around them
+    pub fn to_cross(&self) -> RgbImage {
+        let size = self.faces[0].width();
+        let mut cross = RgbImage::new(size * 4, size * 3);
+        for (face, &(col, row)) in self.faces.iter().zip(CROSS.iter()) {
+            imageops::replace(&mut cross, face, col * size, row * size);
+        }
+        cross
+    }
+
+    // nearest texel in direction dir, which doesn't need to be normalized
+    pub fn sample(&self, dir: Vector3<f32>) -> Rgb<u8> {
+        let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+        // (face, right, up) with right and up in [-1, 1]
+        let (face, s, t) = if ax >= ay && ax >= az {
+            if dir.x > 0.0 {
+                (0, dir.z / ax, dir.y / ax)
+            } else {
+                (1, -dir.z / ax, dir.y / ax)
+            }
+        } else if ay >= az {
+            if dir.y > 0.0 {
+                (2, dir.x / ay, dir.z / ay)
+            } else {
+                (3, dir.x / ay, -dir.z / ay)
+            }
+        } else if dir.z > 0.0 {
+            (4, -dir.x / az, dir.y / az)
+        } else {
+            (5, dir.x / az, dir.y / az)
+        };
+        let image = &self.faces[face];
+        let (width, height) = image.dimensions();
+        let x = ((s + 1.0) / 2.0 * width as f32) as u32;
+        let y = ((1.0 - t) / 2.0 * height as f32) as u32;
+        *image.get_pixel(x.min(width - 1), y.min(height - 1))
+    }
+
+    // The whole sphere around the cube's centre as an equirectangular panorama, -Z in the
+    // middle and +Y at the top. Longitude runs across the width and latitude down the height.
+    pub fn equirectangular(&self, width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let longitude = ((x as f32 + 0.5) / width as f32 - 0.5) * 2.0 * PI;
+            let latitude = (0.5 - (y as f32 + 0.5) / height as f32) * PI;
+            self.sample(Vector3::new(
+                longitude.sin() * latitude.cos(),
+                latitude.sin(),
+                -longitude.cos() * latitude.cos(),
+            ))
+        })
+    }
+}
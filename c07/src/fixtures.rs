@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use cgmath::{Vector2, Vector3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::panic;
+
+use super::model::{self, Model};
+
+// Random obj text for hardening the loader. Valid fixtures are small meshes with every
+// index in range, malformed ones are valid fixtures with some lines mangled the ways real
+// world files go wrong.
+pub fn valid_obj(rng: &mut StdRng) -> String {
+    let verts = rng.gen_range(3..40);
+    let uvs = rng.gen_range(0..40);
+    let mut obj = String::new();
+    for _ in 0..verts {
+        obj += &format!("v {} {} {}\n", coord(rng), coord(rng), coord(rng));
+    }
+    for _ in 0..uvs {
+        obj += &format!("vt {} {}\n", rng.gen::<f32>(), rng.gen::<f32>());
+    }
+    let norms = rng.gen_bool(0.5);
+    if norms {
+        for _ in 0..verts {
+            obj += &format!("vn {} {} {}\n", coord(rng), coord(rng), coord(rng));
+        }
+    }
+    for _ in 0..rng.gen_range(1..60) {
+        // sub-meshes come and go, names can be repeated
+        if rng.gen_bool(0.1) {
+            obj += &format!("g part{}\n", rng.gen_range(0..3));
+        }
+        obj += "f";
+        for _ in 0..rng.gen_range(3..6) {
+            // negative indices count back from the end
+            let v = if rng.gen_bool(0.8) {
+                rng.gen_range(1..=verts) as i64
+            } else {
+                -(rng.gen_range(1..=verts) as i64)
+            };
+            obj += &if uvs > 0 {
+                format!(" {}/{}", v, rng.gen_range(1..=uvs))
+            } else if norms && rng.gen_bool(0.5) {
+                format!(" {}//{}", v, v)
+            } else {
+                format!(" {}", v)
+            };
+        }
+        obj += "\n";
+    }
+    if rng.gen_bool(0.3) {
+        obj += "# a comment\ns off\ng group\no object\n";
+    }
+    obj
+}
+
+fn coord(rng: &mut StdRng) -> f32 {
+    match rng.gen_range(0..20) {
+        0 => 0.0,
+        1 => -0.0,
+        2 => f32::MAX,
+        3 => f32::MIN_POSITIVE,
+        _ => rng.gen_range(-10.0..10.0),
+    }
+}
+
+pub fn malformed_obj(rng: &mut StdRng) -> String {
+    let mut lines: Vec<String> = valid_obj(rng).lines().map(String::from).collect();
+    for _ in 0..rng.gen_range(1..6) {
+        let i = rng.gen_range(0..lines.len());
+        let line = &mut lines[i];
+        match rng.gen_range(0..10) {
+            // cut short
+            0 => {
+                let mut cut = rng.gen_range(0..=line.len());
+                while !line.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                line.truncate(cut);
+            }
+            1 => *line += " garbage",
+            2 => *line = line.replacen(' ', " x", 1),
+            3 => *line = String::from("f 0 0 0"),
+            4 => *line = String::from("f 1 99999999 2"),
+            5 => *line = String::from("f -99999999 1 2"),
+            6 => *line = String::from("f 1/2/3/4 //"),
+            7 => *line = String::from("v nan inf -inf"),
+            8 => *line = String::from("f 18446744073709551616 1 1"),
+            _ => *line = String::from("usemtl no such material"),
+        }
+    }
+    lines.join("\n")
+}
+
+// bit for bit, so NaNs and signed zeros have to survive too
+fn same_model(a: &Model, b: &Model) -> bool {
+    let v3 = |v: &Vector3<f32>| [v.x, v.y, v.z].map(f32::to_bits);
+    let v2 = |v: &Vector2<f32>| [v.x, v.y].map(f32::to_bits);
+    a.get_verts()
+        .iter()
+        .map(v3)
+        .eq(b.get_verts().iter().map(v3))
+        && a.get_uvs().iter().map(v2).eq(b.get_uvs().iter().map(v2))
+        && a.get_norms()
+            .iter()
+            .map(v3)
+            .eq(b.get_norms().iter().map(v3))
+        && a.get_faces() == b.get_faces()
+        && (0..a.get_faces().len())
+            .all(|i| a.group_names()[a.get_group(i)] == b.group_names()[b.get_group(i)])
+}
+
+// fixed so failures can be reproduced
+pub const SEED: u64 = 2790;
+
+fn round_trips(model: &Model) -> Result<bool> {
+    let mut obj = Vec::new();
+    model.write_obj(&mut obj)?;
+    let (reread, skipped) = model::obj_to_model(&String::from_utf8(obj)?, "", false)?;
+    Ok(skipped == 0 && same_model(model, &reread))
+}
+
+// a valid fixture has to load strictly and read back bit for bit from write_obj()
+pub fn check_valid(obj: &str) -> Result<()> {
+    let (model, _) = model::obj_to_model(obj, "", false)?;
+    if !round_trips(&model)? {
+        return Err(anyhow!("valid fixture didn't round trip"));
+    }
+    Ok(())
+}
+
+// a malformed one can fail strictly, but in tolerant mode has to load and round trip like
+// a valid one
+pub fn check_malformed(obj: &str) -> Result<()> {
+    let _ = model::obj_to_model(obj, "", false);
+    let (model, _) = model::obj_to_model(obj, "", true)?;
+    if !round_trips(&model)? {
+        return Err(anyhow!("tolerant load didn't round trip"));
+    }
+    Ok(())
+}
+
+// Runs both checks over iterations random fixtures of each kind, catching the loader's
+// panics so every failure is listed.
+pub fn check_loader(iterations: usize, seed: u64) -> Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    // the default hook would print every caught panic
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut failures = Vec::new();
+    for i in 0..iterations {
+        let valid = valid_obj(&mut rng);
+        let malformed = malformed_obj(&mut rng);
+        let result = panic::catch_unwind(|| -> Result<()> {
+            check_valid(&valid)?;
+            check_malformed(&malformed)
+        });
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => failures.push(format!("fixture {}: {}", i, e)),
+            Err(_) => failures.push(format!("fixture {}: the loader panicked", i)),
+        }
+    }
+    panic::set_hook(hook);
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} of {} fixtures failed (seed {}):\n{}",
+            failures.len(),
+            iterations,
+            seed,
+            failures.join("\n")
+        ))
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Result};
+use cgmath::{InnerSpace, Vector4};
+
+use super::model;
+use super::our_gl::{GeometryShader, Instance, Primitive, Uniforms};
+
+// the geometry stages --geometry picks between
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    // every triangle pushed out along its face normal by this much, in scene units
+    Explode(f32),
+    // every triangle scaled about its centre by this much, gaps open up between them
+    Shrink(f32),
+}
+
+impl std::str::FromStr for Effect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Effect> {
+        let (name, amount) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("geometry '{}' should be name=amount", s))?;
+        let amount = amount.parse()?;
+        match name {
+            "explode" => Ok(Effect::Explode(amount)),
+            "shrink" => Ok(Effect::Shrink(amount)),
+            _ => Err(anyhow!("unknown geometry '{}'", name)),
+        }
+    }
+}
+
+// Both move the corners in clip space, which is linear in the scene's, so the vertex stage's
+// own positions are kept and only added to.
+impl GeometryShader for Effect {
+    fn geometry(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        instance: &Instance,
+        uniforms: &Uniforms,
+        pts: &[Vector4<f32>; 3],
+        emit: &mut dyn FnMut(Primitive),
+    ) {
+        match *self {
+            Effect::Explode(distance) => {
+                let face = &model.get_faces()[iface];
+                let [a, b, c] = [0, 1, 2].map(|j| instance.position(model.get_verts()[face[j]]));
+                let n = (b - a).cross(c - a);
+                if !n.magnitude2().is_normal() {
+                    emit(Primitive::new(*pts)); // no direction to push it in
+                    return;
+                }
+                let offset = uniforms.mat * (n.normalize() * distance).extend(0.0);
+                emit(Primitive::new(pts.map(|pt| pt + offset)));
+            }
+            Effect::Shrink(factor) => {
+                let center = (pts[0] + pts[1] + pts[2]) / 3.0;
+                emit(Primitive::new(
+                    pts.map(|pt| center + (pt - center) * factor),
+                ));
+            }
+        }
+    }
+}
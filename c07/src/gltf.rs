@@ -0,0 +1,851 @@
+use super::material::Material;
+use super::model::{self, Model};
+use super::texture::ImageSource;
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::Context;
+use anyhow::{anyhow, Result};
+use cgmath::{InnerSpace, Matrix, Matrix4, Quaternion, SquareMatrix, Vector2, Vector3, Vector4};
+use serde_json::Value;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+// glTF 2.0, as a .gltf file with its buffers in files next to it or in data uris, or as a
+// .glb. Only what the renderer draws is read: the triangles of the default scene's meshes
+// with their normals, uvs, joints and weights, the nodes, skins and animations that pose
+// them, and the base colour and normal maps of their materials.
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON: u32 = 0x4e4f_534a;
+const GLB_BIN: u32 = 0x004e_4942;
+const TRIANGLES: u64 = 4;
+
+// a node's transform relative to its parent
+#[derive(Debug, Clone, Copy)]
+enum Local {
+    Matrix(Matrix4<f32>),
+    Trs {
+        translation: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+    },
+}
+
+impl Local {
+    fn matrix(&self) -> Matrix4<f32> {
+        match *self {
+            Local::Matrix(m) => m,
+            Local::Trs {
+                translation,
+                rotation,
+                scale,
+            } => {
+                Matrix4::from_translation(translation)
+                    * Matrix4::from(rotation)
+                    * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline, // every keyframe is an in tangent, the value and an out tangent
+}
+
+#[derive(Debug)]
+struct Channel {
+    node: usize,
+    property: Property,
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    values: Vec<f32>, // flattened, 3 or 4 components per value
+}
+
+#[derive(Debug)]
+struct Animation {
+    name: String,
+    channels: Vec<Channel>,
+}
+
+// The node hierarchy of a file and what moves it, for posing the model read with it
+#[derive(Debug)]
+pub struct Rig {
+    locals: Vec<Local>,
+    parents: Vec<Option<usize>>,
+    // the joints of every skin one after the other, each with its inverse bind matrix
+    joints: Vec<(usize, Matrix4<f32>)>,
+    animations: Vec<Animation>,
+}
+
+impl Rig {
+    // The animation called name, or the first one. Fails listing what there is when
+    // there's no such animation.
+    pub fn animation(&self, name: Option<&str>) -> Result<usize> {
+        if self.animations.is_empty() {
+            return Err(anyhow!("the model has no animations"));
+        }
+        let Some(name) = name else {
+            return Ok(0);
+        };
+        self.animations
+            .iter()
+            .position(|a| a.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.animations.iter().map(|a| a.name.as_str()).collect();
+                anyhow!(
+                    "no animation called '{}', the model has {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+
+    // What Model::skin() takes for the joints at time seconds into the animation, or in
+    // their rest pose without one. Before the first keyframe or after the last the
+    // joints hold still, as glTF players do.
+    pub fn joint_matrices(&self, animation: Option<usize>, time: f32) -> Vec<Matrix4<f32>> {
+        let mut locals = self.locals.clone();
+        if let Some(animation) = animation.and_then(|a| self.animations.get(a)) {
+            for channel in &animation.channels {
+                let value = channel.sample(time);
+                let (mut translation, mut rotation, mut scale) = match locals[channel.node] {
+                    Local::Trs {
+                        translation,
+                        rotation,
+                        scale,
+                    } => (translation, rotation, scale),
+                    // animated nodes can't have a matrix, the file is wrong but the
+                    // channel is still the best guess
+                    Local::Matrix(_) => (
+                        Vector3::new(0.0, 0.0, 0.0),
+                        Quaternion::new(1.0, 0.0, 0.0, 0.0),
+                        Vector3::new(1.0, 1.0, 1.0),
+                    ),
+                };
+                match channel.property {
+                    Property::Translation => {
+                        translation = Vector3::new(value[0], value[1], value[2])
+                    }
+                    Property::Scale => scale = Vector3::new(value[0], value[1], value[2]),
+                    Property::Rotation => {
+                        rotation =
+                            Quaternion::new(value[3], value[0], value[1], value[2]).normalize()
+                    }
+                }
+                locals[channel.node] = Local::Trs {
+                    translation,
+                    rotation,
+                    scale,
+                };
+            }
+        }
+        let globals = globals(&locals, &self.parents);
+        self.joints
+            .iter()
+            .map(|&(node, inverse_bind)| globals[node] * inverse_bind)
+            .collect()
+    }
+}
+
+impl Channel {
+    fn sample(&self, time: f32) -> Vec<f32> {
+        let n = match self.property {
+            Property::Rotation => 4,
+            _ => 3,
+        };
+        // keyframes hold 3 values with cubic splines, the middle one is the value
+        let stride = match self.interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        let value = |k: usize, part: usize| &self.values[(k * stride + part) * n..][..n];
+        let middle = stride / 2;
+        let last = self.times.len() - 1;
+        let k = self.times.partition_point(|&t| t <= time).saturating_sub(1);
+        if time <= self.times[0] || k >= last {
+            return value(if time <= self.times[0] { 0 } else { last }, middle).to_vec();
+        }
+        let dt = self.times[k + 1] - self.times[k];
+        let s = if dt > 0.0 {
+            (time - self.times[k]) / dt
+        } else {
+            0.0
+        };
+        match self.interpolation {
+            Interpolation::Step => value(k, 0).to_vec(),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let q = |v: &[f32]| Quaternion::new(v[3], v[0], v[1], v[2]);
+                let (a, b) = (q(value(k, 0)), q(value(k + 1, 0)));
+                // the short way round
+                let b = if a.dot(b) < 0.0 { -b } else { b };
+                let r = a.slerp(b, s);
+                vec![r.v.x, r.v.y, r.v.z, r.s]
+            }
+            Interpolation::Linear => value(k, 0)
+                .iter()
+                .zip(value(k + 1, 0))
+                .map(|(a, b)| a + (b - a) * s)
+                .collect(),
+            Interpolation::CubicSpline => {
+                let (s2, s3) = (s * s, s * s * s);
+                let (p0, m0) = (value(k, 1), value(k, 2));
+                let (p1, m1) = (value(k + 1, 1), value(k + 1, 0));
+                (0..n)
+                    .map(|i| {
+                        (2.0 * s3 - 3.0 * s2 + 1.0) * p0[i]
+                            + (s3 - 2.0 * s2 + s) * dt * m0[i]
+                            + (-2.0 * s3 + 3.0 * s2) * p1[i]
+                            + (s3 - s2) * dt * m1[i]
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// every node's transform in the scene, parents are worked out before their children
+fn globals(locals: &[Local], parents: &[Option<usize>]) -> Vec<Matrix4<f32>> {
+    let mut globals: Vec<Option<Matrix4<f32>>> = vec![None; locals.len()];
+    for node in 0..locals.len() {
+        let mut chain = vec![node];
+        while let Some(parent) = parents[*chain.last().unwrap()] {
+            if globals[parent].is_some() || chain.contains(&parent) {
+                break;
+            }
+            chain.push(parent);
+        }
+        for &n in chain.iter().rev() {
+            if globals[n].is_none() {
+                let parent = parents[n]
+                    .and_then(|p| globals[p])
+                    .unwrap_or_else(Matrix4::identity);
+                globals[n] = Some(parent * locals[n].matrix());
+            }
+        }
+    }
+    globals.into_iter().map(Option::unwrap).collect()
+}
+
+fn column_major(m: [f32; 16]) -> Matrix4<f32> {
+    Matrix4::new(
+        m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11], m[12], m[13],
+        m[14], m[15],
+    )
+}
+
+fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value> {
+    value
+        .get(key)
+        .ok_or_else(|| anyhow!("glTF '{}' missing", key))
+}
+
+fn index(value: &Value, key: &str) -> Result<usize> {
+    field(value, key)?
+        .as_u64()
+        .map(|i| i as usize)
+        .ok_or_else(|| anyhow!("glTF '{}' is not an index", key))
+}
+
+// the elements of an array property, none when it's left out
+fn list<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .map_or(&[], Vec::as_slice)
+}
+
+fn floats<const N: usize>(value: &Value, key: &str, default: [f32; N]) -> Result<[f32; N]> {
+    let Some(array) = value.get(key) else {
+        return Ok(default);
+    };
+    let array = array
+        .as_array()
+        .filter(|a| a.len() == N)
+        .ok_or_else(|| anyhow!("glTF '{}' should be {} numbers", key, N))?;
+    let mut out = default;
+    for (out, v) in out.iter_mut().zip(array) {
+        *out = v
+            .as_f64()
+            .ok_or_else(|| anyhow!("glTF '{}' should be {} numbers", key, N))?
+            as f32;
+    }
+    Ok(out)
+}
+
+struct Document {
+    json: Value,
+    buffers: Vec<Vec<u8>>,
+    images: Vec<Option<ImageSource>>, // None where they couldn't be read
+}
+
+impl Document {
+    // the elements of an accessor, each as components f64s since that holds any of the
+    // integer types exactly, normalized integers are mapped onto [0, 1] or [-1, 1]
+    fn accessor(&self, i: usize) -> Result<(Vec<f64>, usize)> {
+        let accessor = list(&self.json, "accessors")
+            .get(i)
+            .ok_or_else(|| anyhow!("glTF accessor {} out of range", i))?;
+        if accessor.get("sparse").is_some() {
+            return Err(anyhow!("glTF sparse accessors aren't supported"));
+        }
+        let count = index(accessor, "count")?;
+        let components = match field(accessor, "type")?.as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT4") => 16,
+            _ => return Err(anyhow!("glTF accessor {} has a type that isn't read", i)),
+        };
+        let component_type = index(accessor, "componentType")?;
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(anyhow!("glTF accessor {} component type unknown", i)),
+        };
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        // without a buffer view every element is zero
+        let Ok(view) = index(accessor, "bufferView") else {
+            return Ok((vec![0.0; count * components], components));
+        };
+        let view = list(&self.json, "bufferViews")
+            .get(view)
+            .ok_or_else(|| anyhow!("glTF buffer view {} out of range", view))?;
+        let buffer = self
+            .buffers
+            .get(index(view, "buffer")?)
+            .ok_or_else(|| anyhow!("glTF buffer out of range"))?;
+        let offset = view.get("byteOffset").and_then(Value::as_u64).unwrap_or(0) as usize
+            + accessor
+                .get("byteOffset")
+                .and_then(Value::as_u64)
+                .unwrap_or(0) as usize;
+        let stride = view
+            .get("byteStride")
+            .and_then(Value::as_u64)
+            .map_or(components * size, |s| s as usize);
+        let end = offset + stride * count.saturating_sub(1) + components * size;
+        if count > 0 && end > buffer.len() {
+            return Err(anyhow!("glTF accessor {} runs past its buffer", i));
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let at = offset + element * stride + component * size;
+                let bytes = &buffer[at..at + size];
+                let value = match component_type {
+                    5120 => bytes[0] as i8 as f64,
+                    5121 => bytes[0] as f64,
+                    5122 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    5123 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    5125 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                };
+                values.push(match (normalized, component_type) {
+                    (true, 5120) => (value / 127.0).max(-1.0),
+                    (true, 5121) => value / 255.0,
+                    (true, 5122) => (value / 32767.0).max(-1.0),
+                    (true, 5123) => value / 65535.0,
+                    _ => value,
+                });
+            }
+        }
+        Ok((values, components))
+    }
+
+    fn vectors<const N: usize>(&self, i: usize) -> Result<Vec<[f32; N]>> {
+        let (values, components) = self.accessor(i)?;
+        if components != N {
+            return Err(anyhow!("glTF accessor {} should have {} components", i, N));
+        }
+        Ok(values
+            .chunks(N)
+            .map(|c| std::array::from_fn(|k| c[k] as f32))
+            .collect())
+    }
+
+    fn indices(&self, i: usize) -> Result<Vec<usize>> {
+        Ok(self
+            .accessor(i)?
+            .0
+            .into_iter()
+            .map(|v| v as usize)
+            .collect())
+    }
+}
+
+// Reads a .gltf or .glb file and the buffers it refers to
+#[cfg(not(target_arch = "wasm32"))]
+pub fn file_to_model(filename: &str) -> Result<(Model, Rig)> {
+    let bytes = fs::read(filename).with_context(|| format!("Could not read {}", filename))?;
+    // buffers in other files are relative to this one
+    bytes_to_model(&bytes, |uri| {
+        let path = Path::new(filename).with_file_name(uri);
+        fs::read(&path).with_context(|| format!("Could not read {}", path.display()))
+    })
+}
+
+// Reads the contents of a .gltf or .glb, load gives the buffers in other files by uri
+pub fn bytes_to_model<F: Fn(&str) -> Result<Vec<u8>>>(
+    bytes: &[u8],
+    load: F,
+) -> Result<(Model, Rig)> {
+    let (json, mut binary) = if bytes.starts_with(GLB_MAGIC) {
+        split_glb(bytes)?
+    } else {
+        (bytes.to_vec(), None)
+    };
+    let json: Value = serde_json::from_slice(&json)?;
+    let mut buffers = Vec::new();
+    for buffer in list(&json, "buffers") {
+        let data = match buffer.get("uri").and_then(Value::as_str) {
+            Some(uri) if uri.starts_with("data:") => {
+                let (_, data) = uri
+                    .split_once(";base64,")
+                    .ok_or_else(|| anyhow!("glTF data uri isn't base64"))?;
+                base64(data)?
+            }
+            Some(uri) => load(uri)?,
+            // the first buffer of a .glb can be its binary chunk
+            None => binary
+                .take()
+                .ok_or_else(|| anyhow!("glTF buffer without a uri or binary chunk"))?,
+        };
+        buffers.push(data);
+    }
+    // all of them are read into memory, files next to this one are named like buffers
+    let mut images = Vec::new();
+    for (i, image) in list(&json, "images").iter().enumerate() {
+        let data = match (
+            image.get("uri").and_then(Value::as_str),
+            index(image, "bufferView"),
+        ) {
+            (Some(uri), _) if uri.starts_with("data:") => uri
+                .split_once(";base64,")
+                .ok_or_else(|| anyhow!("glTF data uri isn't base64"))
+                .and_then(|(_, data)| base64(data)),
+            (Some(uri), _) => load(uri),
+            (None, Ok(view)) => buffer_view(&json, &buffers, view),
+            (None, Err(e)) => Err(e),
+        };
+        images.push(match data {
+            Ok(data) => Some(ImageSource::Embedded(data)),
+            Err(e) => {
+                tracing::warn!(image = i, error = %e, "glTF image not read");
+                None
+            }
+        });
+    }
+    gltf_to_model(Document {
+        json,
+        buffers,
+        images,
+    })
+}
+
+// the bytes of a buffer view, for images kept in a buffer
+fn buffer_view(json: &Value, buffers: &[Vec<u8>], view: usize) -> Result<Vec<u8>> {
+    let view = list(json, "bufferViews")
+        .get(view)
+        .ok_or_else(|| anyhow!("glTF buffer view {} out of range", view))?;
+    let buffer = buffers
+        .get(index(view, "buffer")?)
+        .ok_or_else(|| anyhow!("glTF buffer out of range"))?;
+    let offset = view.get("byteOffset").and_then(Value::as_u64).unwrap_or(0) as usize;
+    buffer
+        .get(offset..offset + index(view, "byteLength")?)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow!("glTF buffer view runs past its buffer"))
+}
+
+// What the shaders use of a glTF material: the base colour, its texture and the normal
+// map. Metallic roughness is left out, highlights are those of non-metals.
+fn material(document: &Document, i: usize, material: &Value) -> Result<Material> {
+    let name = material
+        .get("name")
+        .and_then(Value::as_str)
+        .map_or_else(|| format!("material {}", i), str::to_string);
+    let mut m = Material::new(&name);
+    m.ks = Vector3::new(0.04, 0.04, 0.04);
+    // the image of a texture info such as baseColorTexture
+    let texture = |info: Option<&Value>| -> Result<Option<ImageSource>> {
+        let Some(info) = info else {
+            return Ok(None);
+        };
+        let texture = list(&document.json, "textures")
+            .get(index(info, "index")?)
+            .ok_or_else(|| anyhow!("glTF material {} texture out of range", i))?;
+        Ok(index(texture, "source")
+            .ok()
+            .and_then(|source| document.images.get(source).cloned().flatten()))
+    };
+    if let Some(pbr) = material.get("pbrMetallicRoughness") {
+        let [r, g, b, a] = floats(pbr, "baseColorFactor", [1.0; 4])?;
+        m.kd = Vector3::new(r, g, b);
+        // opaque materials ignore alpha
+        if material.get("alphaMode").and_then(Value::as_str) == Some("BLEND") {
+            m.d = a;
+        }
+        m.map_kd = texture(pbr.get("baseColorTexture"))?;
+    }
+    m.map_bump = texture(material.get("normalTexture"))?;
+    Ok(m)
+}
+
+// the json and binary chunks of a .glb
+fn split_glb(bytes: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    let word = |at: usize| -> Result<u32> {
+        bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| anyhow!("glb file cut short"))
+    };
+    if word(4)? != 2 {
+        return Err(anyhow!("only glTF 2 glb files are read"));
+    }
+    let (mut json, mut binary) = (None, None);
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let (length, kind) = (word(at)? as usize, word(at + 4)?);
+        let chunk = bytes
+            .get(at + 8..at + 8 + length)
+            .ok_or_else(|| anyhow!("glb chunk cut short"))?
+            .to_vec();
+        match kind {
+            GLB_JSON => json = Some(chunk),
+            GLB_BIN if binary.is_none() => binary = Some(chunk),
+            _ => {}
+        }
+        at += 8 + length;
+    }
+    Ok((json.ok_or_else(|| anyhow!("glb without json"))?, binary))
+}
+
+fn base64(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes().filter(|&c| c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(anyhow!("glTF data uri isn't valid base64")),
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+fn gltf_to_model(document: Document) -> Result<(Model, Rig)> {
+    let json = &document.json;
+    let nodes = list(json, "nodes");
+    let mut locals = Vec::with_capacity(nodes.len());
+    let mut parents = vec![None; nodes.len()];
+    for (i, node) in nodes.iter().enumerate() {
+        locals.push(match node.get("matrix") {
+            Some(_) => {
+                let m = floats::<16>(node, "matrix", [0.0; 16])?;
+                Local::Matrix(column_major(m))
+            }
+            None => {
+                let [x, y, z, w] = floats(node, "rotation", [0.0, 0.0, 0.0, 1.0])?;
+                Local::Trs {
+                    translation: floats(node, "translation", [0.0; 3])?.into(),
+                    rotation: Quaternion::new(w, x, y, z),
+                    scale: floats(node, "scale", [1.0; 3])?.into(),
+                }
+            }
+        });
+        for child in list(node, "children") {
+            let child = child
+                .as_u64()
+                .map(|c| c as usize)
+                .filter(|&c| c < nodes.len())
+                .ok_or_else(|| anyhow!("glTF node {} has a child out of range", i))?;
+            parents[child] = Some(i);
+        }
+    }
+    let globals = globals(&locals, &parents);
+
+    // the joints of each skin start where the ones before it end
+    let mut joints = Vec::new();
+    let mut skin_offsets = Vec::new();
+    for skin in list(json, "skins") {
+        skin_offsets.push(joints.len());
+        let inverse_binds = match index(skin, "inverseBindMatrices") {
+            Ok(accessor) => document
+                .vectors::<16>(accessor)?
+                .into_iter()
+                .map(column_major)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        for (j, joint) in list(skin, "joints").iter().enumerate() {
+            let node = joint
+                .as_u64()
+                .map(|n| n as usize)
+                .filter(|&n| n < nodes.len())
+                .ok_or_else(|| anyhow!("glTF skin joint out of range"))?;
+            let inverse_bind = inverse_binds
+                .get(j)
+                .copied()
+                .unwrap_or_else(Matrix4::identity);
+            joints.push((node, inverse_bind));
+        }
+    }
+
+    // the default scene's nodes and everything under them, or every node without one
+    let scenes = list(json, "scenes");
+    let scene = json
+        .get("scene")
+        .and_then(Value::as_u64)
+        .map_or(0, |s| s as usize);
+    let mut drawn: Vec<usize> = match scenes.get(scene) {
+        Some(scene) => list(scene, "nodes")
+            .iter()
+            .filter_map(|n| n.as_u64().map(|n| n as usize))
+            .filter(|&n| n < nodes.len())
+            .collect(),
+        None => (0..nodes.len()).filter(|&n| parents[n].is_none()).collect(),
+    };
+    let mut at = 0;
+    while at < drawn.len() {
+        for child in list(&nodes[drawn[at]], "children") {
+            let child = child.as_u64().map_or(0, |c| c as usize);
+            if !drawn.contains(&child) {
+                drawn.push(child);
+            }
+        }
+        at += 1;
+    }
+
+    let mut verts = Vec::new();
+    let mut norms = Vec::new();
+    let mut uvs = Vec::new();
+    let mut vertex_joints = Vec::new();
+    let mut weights = Vec::new();
+    let mut faces = Vec::new();
+    let mut face_materials = Vec::new();
+    let mut has_normals = true;
+    for &n in &drawn {
+        let node = &nodes[n];
+        let Ok(mesh) = index(node, "mesh") else {
+            continue;
+        };
+        let mesh = list(json, "meshes")
+            .get(mesh)
+            .ok_or_else(|| anyhow!("glTF mesh {} out of range", mesh))?;
+        // a skinned mesh is placed by its joints, not its node
+        let skin = index(node, "skin")
+            .ok()
+            .map(|s| {
+                skin_offsets
+                    .get(s)
+                    .copied()
+                    .ok_or_else(|| anyhow!("glTF skin {} out of range", s))
+            })
+            .transpose()?;
+        let transform = match skin {
+            Some(_) => Matrix4::identity(),
+            None => globals[n],
+        };
+        let normal_matrix = transform.invert().unwrap_or(transform).transpose();
+
+        for primitive in list(mesh, "primitives") {
+            let mode = primitive
+                .get("mode")
+                .and_then(Value::as_u64)
+                .unwrap_or(TRIANGLES);
+            if mode != TRIANGLES {
+                tracing::debug!(node = n, mode, "skipped a primitive that isn't triangles");
+                continue;
+            }
+            let attributes = field(primitive, "attributes")?;
+            let positions = document.vectors::<3>(index(attributes, "POSITION")?)?;
+            let count = positions.len();
+            let first = verts.len();
+            verts.extend(
+                positions
+                    .into_iter()
+                    .map(|p| (transform * Vector4::new(p[0], p[1], p[2], 1.0)).truncate()),
+            );
+            match index(attributes, "NORMAL") {
+                Ok(accessor) => {
+                    norms.extend(document.vectors::<3>(accessor)?.into_iter().map(|n| {
+                        (normal_matrix * Vector4::new(n[0], n[1], n[2], 0.0))
+                            .truncate()
+                            .normalize()
+                    }))
+                }
+                Err(_) => has_normals = false,
+            }
+            // glTF puts v = 0 at the top of the image, obj at the bottom
+            match index(attributes, "TEXCOORD_0") {
+                Ok(accessor) => uvs.extend(
+                    document
+                        .vectors::<2>(accessor)?
+                        .into_iter()
+                        .map(|[u, v]| Vector2::new(u, 1.0 - v)),
+                ),
+                Err(_) => uvs.resize(first + count, Vector2::new(0.0, 0.0)),
+            }
+            match (
+                skin,
+                index(attributes, "JOINTS_0"),
+                index(attributes, "WEIGHTS_0"),
+            ) {
+                (Some(offset), Ok(j), Ok(w)) => {
+                    vertex_joints.extend(
+                        document
+                            .vectors::<4>(j)?
+                            .into_iter()
+                            .map(|j| j.map(|j| j as usize + offset)),
+                    );
+                    weights.extend(document.vectors::<4>(w)?);
+                }
+                _ => {
+                    vertex_joints.resize(first + count, [0; 4]);
+                    weights.resize(first + count, [0.0; 4]);
+                }
+            }
+            if verts.len() != uvs.len() || verts.len() != weights.len() {
+                return Err(anyhow!("glTF attributes of node {} differ in count", n));
+            }
+
+            let indices = match index(primitive, "indices") {
+                Ok(accessor) => document.indices(accessor)?,
+                Err(_) => (0..count).collect(),
+            };
+            if indices.iter().any(|&i| i >= count) {
+                return Err(anyhow!("glTF index out of range in node {}", n));
+            }
+            faces.extend(
+                indices
+                    .chunks_exact(3)
+                    .map(|t| t.iter().map(|&i| first + i).collect()),
+            );
+            let material = index(primitive, "material").ok();
+            face_materials.resize(faces.len(), material);
+        }
+    }
+    if faces.is_empty() {
+        return Err(anyhow!("glTF scene has no triangles"));
+    }
+    if weights.iter().all(|w| w.iter().all(|&w| w == 0.0)) {
+        vertex_joints.clear();
+        weights.clear();
+    }
+
+    let mut animations = Vec::new();
+    for (i, animation) in list(json, "animations").iter().enumerate() {
+        let samplers = list(animation, "samplers");
+        let mut channels = Vec::new();
+        for channel in list(animation, "channels") {
+            let target = field(channel, "target")?;
+            let property = match field(target, "path")?.as_str() {
+                Some("translation") => Property::Translation,
+                Some("rotation") => Property::Rotation,
+                Some("scale") => Property::Scale,
+                // morph target weights aren't drawn
+                _ => continue,
+            };
+            let Ok(node) = index(target, "node") else {
+                continue;
+            };
+            if node >= nodes.len() {
+                return Err(anyhow!("glTF animation {} targets a node out of range", i));
+            }
+            let sampler = samplers
+                .get(index(channel, "sampler")?)
+                .ok_or_else(|| anyhow!("glTF animation {} sampler out of range", i))?;
+            let interpolation = match sampler.get("interpolation").and_then(Value::as_str) {
+                Some("STEP") => Interpolation::Step,
+                Some("CUBICSPLINE") => Interpolation::CubicSpline,
+                _ => Interpolation::Linear,
+            };
+            let times: Vec<f32> = document
+                .accessor(index(sampler, "input")?)?
+                .0
+                .into_iter()
+                .map(|t| t as f32)
+                .collect();
+            let (values, components) = document.accessor(index(sampler, "output")?)?;
+            let n = if property == Property::Rotation { 4 } else { 3 };
+            let per_key = if interpolation == Interpolation::CubicSpline {
+                3
+            } else {
+                1
+            };
+            if times.is_empty() || components != n || values.len() != times.len() * n * per_key {
+                return Err(anyhow!("glTF animation {} has a malformed sampler", i));
+            }
+            channels.push(Channel {
+                node,
+                property,
+                interpolation,
+                times,
+                values: values.into_iter().map(|v| v as f32).collect(),
+            });
+        }
+        animations.push(Animation {
+            name: animation
+                .get("name")
+                .and_then(Value::as_str)
+                .map_or_else(|| i.to_string(), str::to_string),
+            channels,
+        });
+    }
+
+    let materials = list(json, "materials")
+        .iter()
+        .enumerate()
+        .map(|(i, m)| material(&document, i, m))
+        .collect::<Result<Vec<Material>>>()?;
+    if face_materials
+        .iter()
+        .any(|m| m.is_some_and(|m| m >= materials.len()))
+    {
+        return Err(anyhow!("glTF primitive material out of range"));
+    }
+    let mut model = model::indexed(
+        verts,
+        has_normals.then_some(norms),
+        uvs,
+        vertex_joints,
+        weights,
+        faces,
+    );
+    model.set_materials(materials, face_materials);
+    Ok((
+        model,
+        Rig {
+            locals,
+            parents,
+            joints,
+            animations,
+        },
+    ))
+}
@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Result};
+use image::{GrayImage, ImageBuffer, Pixel, RgbImage};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use super::model::Model;
+use super::our_gl::{DepthImage, Framebuffer, Instance};
+use super::pool::BufferPool;
+use super::renderer::{Progress, Scene, Shadow};
+use super::texture::Maps;
+
+// something a pass leaves for the passes after it, under a name
+pub enum Resource {
+    Frame(Box<Framebuffer>), // colour and depth with any attachments
+    Depth(DepthImage),
+    Gray(GrayImage),
+    Color(RgbImage),
+    Shadow(Shadow),
+}
+
+// the resources written so far while a graph runs
+#[derive(Default)]
+pub struct Resources {
+    named: HashMap<&'static str, Resource>,
+}
+
+impl Resources {
+    pub fn insert(&mut self, name: &'static str, resource: Resource) {
+        self.named.insert(name, resource);
+    }
+
+    pub fn take(&mut self, name: &str) -> Result<Resource> {
+        self.named
+            .remove(name)
+            .ok_or_else(|| anyhow!("no resource '{}'", name))
+    }
+
+    pub fn frame(&mut self, name: &str) -> Result<&mut Framebuffer> {
+        match self.named.get_mut(name) {
+            Some(Resource::Frame(frame)) => Ok(frame),
+            _ => Err(anyhow!("resource '{}' is not a frame", name)),
+        }
+    }
+
+    pub fn take_frame(&mut self, name: &str) -> Result<Framebuffer> {
+        match self.take(name)? {
+            Resource::Frame(frame) => Ok(*frame),
+            _ => Err(anyhow!("resource '{}' is not a frame", name)),
+        }
+    }
+
+    pub fn depth(&self, name: &str) -> Result<&DepthImage> {
+        match self.named.get(name) {
+            Some(Resource::Depth(depth)) => Ok(depth),
+            _ => Err(anyhow!("resource '{}' is not a depth buffer", name)),
+        }
+    }
+
+    pub fn take_depth(&mut self, name: &str) -> Result<DepthImage> {
+        match self.take(name)? {
+            Resource::Depth(depth) => Ok(depth),
+            _ => Err(anyhow!("resource '{}' is not a depth buffer", name)),
+        }
+    }
+
+    pub fn gray(&self, name: &str) -> Result<&GrayImage> {
+        match self.named.get(name) {
+            Some(Resource::Gray(gray)) => Ok(gray),
+            _ => Err(anyhow!("resource '{}' is not a grey image", name)),
+        }
+    }
+
+    pub fn take_color(&mut self, name: &str) -> Result<RgbImage> {
+        match self.take(name)? {
+            Resource::Color(color) => Ok(color),
+            _ => Err(anyhow!("resource '{}' is not a colour image", name)),
+        }
+    }
+
+    pub fn shadow(&self, name: &str) -> Result<&Shadow> {
+        match self.named.get(name) {
+            Some(Resource::Shadow(shadow)) => Ok(shadow),
+            _ => Err(anyhow!("resource '{}' is not a shadow", name)),
+        }
+    }
+}
+
+// what every pass of a frame draws, the scene's model at the levels of detail picked for its
+// instances, see renderer::instance_lods(), and the pool its buffers come from
+pub struct Context<'a> {
+    pub scene: &'a Scene,
+    pub model: &'a Model,
+    pub maps: &'a [Maps],
+    pub instances: &'a [Instance],
+    pub pool: &'a Mutex<BufferPool>,
+}
+
+impl Context<'_> {
+    // see BufferPool::acquire()
+    pub fn acquire<P: Pixel<Subpixel = u8> + 'static>(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageBuffer<P, Vec<u8>>> {
+        self.pool.lock().unwrap().acquire(width, height)
+    }
+
+    pub fn release<P: Pixel<Subpixel = u8> + 'static>(&self, image: ImageBuffer<P, Vec<u8>>) {
+        self.pool.lock().unwrap().release(image);
+    }
+}
+
+// One step of drawing a frame. It reads the resources named by inputs, which earlier passes
+// have to write, and writes those named by outputs. A pass that changes a resource in place
+// names it as both.
+pub trait Pass: Sync {
+    fn name(&self) -> &'static str;
+    fn inputs(&self) -> Vec<&'static str>;
+    fn outputs(&self) -> Vec<&'static str>;
+    fn run(&self, context: &Context, resources: &mut Resources, progress: Progress) -> Result<()>;
+}
+
+// passes run in the order they were added
+#[derive(Default)]
+pub struct Graph<'a> {
+    passes: Vec<Box<dyn Pass + 'a>>,
+}
+
+impl<'a> Graph<'a> {
+    pub fn add(&mut self, pass: Box<dyn Pass + 'a>) {
+        self.passes.push(pass);
+    }
+
+    // that every input is an output of a pass before it
+    pub fn check(&self) -> Result<()> {
+        let mut written = HashSet::new();
+        for pass in &self.passes {
+            if let Some(missing) = pass.inputs().into_iter().find(|i| !written.contains(i)) {
+                return Err(anyhow!(
+                    "the {} pass reads '{}', which no pass before it writes",
+                    pass.name(),
+                    missing
+                ));
+            }
+            written.extend(pass.outputs());
+        }
+        Ok(())
+    }
+
+    // Every pass, each told its own progress by name. Passes next to one another that share
+    // no resource run at once, each on a thread of its own with what it reads moved into
+    // resources of its own. Gives what they leave behind.
+    pub fn run(
+        &self,
+        context: &Context,
+        progress: &(dyn Fn(&'static str, usize, usize) + Sync),
+    ) -> Result<Resources> {
+        self.check()?;
+        let mut resources = Resources::default();
+        let mut rest = &self.passes[..];
+        while !rest.is_empty() {
+            let (together, after) = rest.split_at(independent(rest));
+            rest = after;
+            if let [pass] = together {
+                run_pass(pass.as_ref(), context, &mut resources, progress)?;
+                continue;
+            }
+            let mut own: Vec<Resources> = together
+                .iter()
+                .map(|pass| {
+                    let mut own = Resources::default();
+                    for name in pass.inputs() {
+                        if let Some(resource) = resources.named.remove(name) {
+                            own.insert(name, resource);
+                        }
+                    }
+                    own
+                })
+                .collect();
+            let done: Vec<Result<()>> = std::thread::scope(|scope| {
+                let threads: Vec<_> = together
+                    .iter()
+                    .zip(&mut own)
+                    .map(|(pass, own)| {
+                        scope.spawn(move || run_pass(pass.as_ref(), context, own, progress))
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|thread| {
+                        thread
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                    })
+                    .collect()
+            });
+            for own in own {
+                resources.named.extend(own.named);
+            }
+            done.into_iter().collect::<Result<()>>()?;
+        }
+        Ok(resources)
+    }
+}
+
+fn run_pass(
+    pass: &dyn Pass,
+    context: &Context,
+    resources: &mut Resources,
+    progress: &(dyn Fn(&'static str, usize, usize) + Sync),
+) -> Result<()> {
+    let name = pass.name();
+    let _pass = tracing::debug_span!("pass", name).entered();
+    pass.run(context, resources, &|done, total| {
+        progress(name, done, total)
+    })
+}
+
+// how many passes from the first read and write resources none of the others do, only the
+// first where there are no threads
+fn independent(passes: &[Box<dyn Pass + '_>]) -> usize {
+    if cfg!(target_arch = "wasm32") {
+        return 1;
+    }
+    let mut used = HashSet::new();
+    passes
+        .iter()
+        .take_while(|pass| {
+            let names: Vec<_> = pass.inputs().into_iter().chain(pass.outputs()).collect();
+            let free = names.iter().all(|name| !used.contains(name));
+            used.extend(names);
+            free
+        })
+        .count()
+        .max(1)
+}
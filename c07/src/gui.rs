@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Result};
+use cgmath::{InnerSpace, Vector3};
+use eframe::egui;
+use image::RgbImage;
+
+use super::analysis;
+use super::camera::{Camera, Projection};
+use super::light::{Light, Source};
+use super::our_gl::Framebuffer;
+use super::texture::{linear_to_srgb, srgb_to_linear};
+use super::viewer;
+
+// the blur a whole depth buffer away from focus that new settings start with
+pub const APERTURE: f32 = 0.01;
+
+// what the panel can change, the frame is rendered again whenever any of it does
+#[derive(Clone)]
+pub struct Settings {
+    pub camera: Camera,
+    pub lights: Vec<Light>,
+    // stops, applied to the finished frame in linear light
+    pub exposure: f32,
+    // the depth buffer value depth of field keeps sharp, none until the frame is clicked
+    pub focus: Option<f32>,
+    // the blur a whole depth buffer away from focus, as a part of the frame's width
+    pub aperture: f32,
+}
+
+struct App<F> {
+    settings: Settings,
+    render: F,
+    frame: Option<Framebuffer>,
+    texture: Option<egui::TextureHandle>,
+    refine: viewer::Refine,
+    error: Option<anyhow::Error>,
+}
+
+// Like viewer::run() but with a side panel of sliders for the lights, exposure, depth of
+// field and the camera's field of view. Dragging the frame orbits and scrolling over it
+// zooms. Clicking it focuses on what is under the pointer and right clicking meters the
+// exposure there. render draws the settings' camera viewport into a frame of the size it
+// is given, with (0,0) at the top left of both its colour and depth.
+pub fn run<F: FnMut(&Settings, u32, u32) -> Result<Framebuffer>>(
+    settings: Settings,
+    width: u32,
+    height: u32,
+    render: F,
+) -> Result<()> {
+    let mut app = App {
+        settings,
+        render,
+        frame: None,
+        texture: None,
+        refine: viewer::Refine::new(width, height),
+        error: None,
+    };
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([width as f32 + 300.0, height as f32 + 20.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "tinyrenderer",
+        options,
+        Box::new(|_| Ok(Box::new(&mut app))),
+    )
+    .map_err(|e| anyhow!("{}", e))?;
+    match app.error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+impl<F: FnMut(&Settings, u32, u32) -> Result<Framebuffer>> eframe::App for &mut App<F> {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::left("settings")
+            .resizable(false)
+            .show(ctx, |ui| {
+                if settings_ui(ui, &mut self.settings) {
+                    self.refine.restart();
+                }
+            });
+
+        // the settings as they are but for the camera refine() moves about the pixel
+        let mut settings = self.settings.clone();
+        let render = &mut self.render;
+        let step = self
+            .refine
+            .step(&self.settings.camera, |camera, width, height| {
+                settings.camera = *camera;
+                render(&settings, width, height)
+            });
+        match step {
+            Ok(Some(frame)) => {
+                self.frame = Some(frame);
+                self.texture = None;
+            }
+            Ok(None) => {}
+            Err(error) => {
+                self.error = Some(error);
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                return;
+            }
+        }
+        if self.texture.is_none() {
+            if let Some(frame) = &self.frame {
+                let image = expose(&frame.color, self.settings.exposure);
+                self.texture = Some(ctx.load_texture("frame", image, Default::default()));
+            }
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if let (Some(texture), Some(frame)) = (&self.texture, &self.frame) {
+                let response =
+                    ui.add(egui::Image::new(texture).sense(egui::Sense::click_and_drag()));
+                let clicked = response
+                    .interact_pointer_pos()
+                    .and_then(|pos| pixel_at(frame, response.rect, pos));
+                if let Some((x, y)) = clicked {
+                    if response.clicked() {
+                        self.settings.focus = Some(frame.depth.get_pixel(x, y)[0] as f32);
+                        self.refine.restart();
+                    } else if response.secondary_clicked() {
+                        // what is clicked comes out middle grey
+                        let luminance = analysis::linear_luminance(frame.color.get_pixel(x, y));
+                        self.settings.exposure = (-analysis::stops(luminance)).clamp(-4.0, 4.0);
+                        self.texture = None;
+                        ctx.request_repaint();
+                    }
+                }
+                let drag = response.drag_delta();
+                if drag != egui::Vec2::ZERO {
+                    viewer::orbit(
+                        &mut self.settings.camera,
+                        drag.x * viewer::ORBIT_SPEED,
+                        drag.y * viewer::ORBIT_SPEED,
+                    );
+                    self.refine.restart();
+                }
+                if response.hovered() {
+                    let scroll = ui.input(|input| input.raw_scroll_delta.y);
+                    if scroll != 0.0 {
+                        // scrolling up zooms in
+                        viewer::zoom(
+                            &mut self.settings.camera,
+                            viewer::ZOOM_STEP.powf(scroll.signum()),
+                        );
+                        self.refine.restart();
+                    }
+                }
+            }
+        });
+        if !self.refine.done() {
+            ctx.request_repaint();
+        }
+    }
+}
+
+// the panel, true if anything changed
+fn settings_ui(ui: &mut egui::Ui, settings: &mut Settings) -> bool {
+    let mut changed = false;
+
+    ui.heading("Camera");
+    let mut perspective = matches!(settings.camera.projection, Projection::Perspective { .. });
+    if ui.checkbox(&mut perspective, "perspective").changed() {
+        // keeping roughly the same framing at the target
+        settings.camera.projection = if perspective {
+            Projection::perspective((settings.camera.eye - settings.camera.target).magnitude())
+        } else {
+            Projection::Orthographic {
+                size: settings.camera.half_height(),
+            }
+        };
+        changed = true;
+    }
+    if let Projection::Perspective { fov, .. } = &mut settings.camera.projection {
+        changed |= ui
+            .add(egui::Slider::new(fov, 10.0..=120.0).text("fov"))
+            .changed();
+    }
+
+    ui.heading("Exposure");
+    changed |= ui
+        .add(egui::Slider::new(&mut settings.exposure, -4.0..=4.0).text("stops"))
+        .changed();
+    ui.label("right click the frame to meter");
+
+    ui.heading("Depth of field");
+    changed |= ui
+        .add(egui::Slider::new(&mut settings.aperture, 0.0..=0.05).text("aperture"))
+        .changed();
+    match settings.focus {
+        Some(focus) => {
+            ui.label(format!("focused at depth {}", focus));
+            if ui.button("sharp").clicked() {
+                settings.focus = None;
+                changed = true;
+            }
+        }
+        None => {
+            ui.label("click the frame to focus");
+        }
+    }
+
+    for (i, light) in settings.lights.iter_mut().enumerate() {
+        ui.heading(format!("Light {}", i));
+        match &mut light.source {
+            Source::Directional { dir } => changed |= direction_ui(ui, dir),
+            Source::Point { position, .. } | Source::Spot { position, .. } => {
+                ui.horizontal(|ui| {
+                    for axis in 0..3 {
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut position[axis]).speed(0.05))
+                            .changed();
+                    }
+                });
+            }
+        }
+        if let Source::Spot { dir, .. } = &mut light.source {
+            changed |= direction_ui(ui, dir);
+        }
+        let mut color: [f32; 3] = light.color.into();
+        if ui.color_edit_button_rgb(&mut color).changed() {
+            light.color = color.into();
+            changed = true;
+        }
+        for (value, name) in [
+            (&mut light.ambient, "ambient"),
+            (&mut light.diffuse, "diffuse"),
+            (&mut light.specular, "specular"),
+        ] {
+            changed |= ui
+                .add(egui::Slider::new(value, 0.0..=2.0).text(name))
+                .changed();
+        }
+    }
+    changed
+}
+
+// azimuth around y and elevation above the xz plane, degrees
+fn direction_ui(ui: &mut egui::Ui, dir: &mut Vector3<f32>) -> bool {
+    let d = dir.normalize();
+    let mut azimuth = d.x.atan2(d.z).to_degrees();
+    let mut elevation = d.y.clamp(-1.0, 1.0).asin().to_degrees();
+    let changed = ui
+        .add(egui::Slider::new(&mut azimuth, -180.0..=180.0).text("azimuth"))
+        .changed()
+        | ui.add(egui::Slider::new(&mut elevation, -89.0..=89.0).text("elevation"))
+            .changed();
+    if changed {
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        *dir = Vector3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+    }
+    changed
+}
+
+// the pixel of frame under pos when it is shown over rect
+fn pixel_at(frame: &Framebuffer, rect: egui::Rect, pos: egui::Pos2) -> Option<(u32, u32)> {
+    let (width, height) = frame.color.dimensions();
+    let (u, v) = ((pos - rect.min) / rect.size()).into();
+    let (x, y) = ((u * width as f32) as u32, (v * height as f32) as u32);
+    (rect.contains(pos) && x < width && y < height).then_some((x, y))
+}
+
+// scales the frame by 2^stops in linear light
+fn expose(frame: &RgbImage, stops: f32) -> egui::ColorImage {
+    let scale = 2f32.powf(stops);
+    let pixels: Vec<u8> = frame
+        .as_raw()
+        .iter()
+        .map(|&c| linear_to_srgb(srgb_to_linear(c) * scale))
+        .collect();
+    egui::ColorImage::from_rgb([frame.width() as usize, frame.height() as usize], &pixels)
+}
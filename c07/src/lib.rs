@@ -0,0 +1,32 @@
+// The rasterizer, its shaders and the passes a frame is drawn in, shared by the renderer,
+// the benches and the browser build.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bake;
+pub mod bvh;
+pub mod camera;
+pub mod camera_path;
+pub mod cubemap;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fixtures;
+pub mod font;
+pub mod geometry;
+pub mod gltf;
+pub mod graph;
+pub mod light;
+pub mod material;
+pub mod mesh;
+pub mod model;
+pub mod our_gl;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pathtrace;
+pub mod pool;
+pub mod postprocess;
+pub mod probes;
+pub mod renderer;
+pub mod scene;
+pub mod shaders;
+pub mod shadowmap;
+pub mod texture;
+pub mod tiled;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Context, Result};
+use cgmath::{InnerSpace, Vector3};
+
+// everything is in world space
+#[derive(Debug, Clone, Copy)]
+pub enum Source {
+    // dir points from the surface towards the light
+    Directional {
+        dir: Vector3<f32>,
+    },
+    // attenuation is k in 1 / (1 + k * distance^2)
+    Point {
+        position: Vector3<f32>,
+        attenuation: f32,
+    },
+    // full intensity inside the cone of half angle `inner` (radians) around dir,
+    // fading smoothly to nothing at `outer`
+    Spot {
+        position: Vector3<f32>,
+        dir: Vector3<f32>,
+        inner: f32,
+        outer: f32,
+        attenuation: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub source: Source,
+    pub color: Vector3<f32>,
+    // how strongly the colour feeds each term of the phong model
+    pub ambient: f32,
+    pub diffuse: f32,
+    pub specular: f32,
+}
+
+impl Light {
+    // a white light that only contributes diffuse and specular, like the original LIGHT_DIR
+    pub fn white(source: Source) -> Light {
+        Light {
+            source,
+            color: Vector3::new(1.0, 1.0, 1.0),
+            ambient: 0.0,
+            diffuse: 1.0,
+            specular: 1.0,
+        }
+    }
+
+    // where a shadow map of a sphere around the scene should be rendered from
+    pub fn eye(&self, center: Vector3<f32>, radius: f32) -> Vector3<f32> {
+        match self.source {
+            Source::Directional { dir } => center + dir.normalize() * radius,
+            Source::Point { position, .. } | Source::Spot { position, .. } => position,
+        }
+    }
+
+    // normalized direction from p towards the light and how much of the light reaches p
+    pub fn incident(&self, p: Vector3<f32>) -> (Vector3<f32>, f32) {
+        match self.source {
+            Source::Directional { dir } => (dir.normalize(), 1.0),
+            Source::Point {
+                position,
+                attenuation,
+            } => {
+                let to_light = position - p;
+                (
+                    to_light.normalize(),
+                    1.0 / (1.0 + attenuation * to_light.magnitude2()),
+                )
+            }
+            Source::Spot {
+                position,
+                dir,
+                inner,
+                outer,
+                attenuation,
+            } => {
+                let to_light = position - p;
+                let l = to_light.normalize();
+                let cone = smoothstep(outer.cos(), inner.cos(), (-l).dot(dir.normalize()));
+                (l, cone / (1.0 + attenuation * to_light.magnitude2()))
+            }
+        }
+    }
+
+    // how far a shadow ray from p has to go to reach the light
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        match self.source {
+            Source::Directional { .. } => f32::INFINITY,
+            Source::Point { position, .. } | Source::Spot { position, .. } => {
+                (position - p).magnitude()
+            }
+        }
+    }
+}
+
+// 0 below edge0, 1 above edge1 and a smooth hermite curve between
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub fn parse_vector(s: &str) -> Result<Vector3<f32>> {
+    let v = s
+        .split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .with_context(|| format!("'{}' is not a vector", s))?;
+    if v.len() != 3 {
+        return Err(anyhow!("'{}' should have 3 components", s));
+    }
+    Ok(Vector3::new(v[0], v[1], v[2]))
+}
+
+// dir:x,y,z  point:x,y,z[:attenuation]  spot:x,y,z:dx,dy,dz:inner:outer[:attenuation]
+// with the spot cone angles in degrees
+impl std::str::FromStr for Source {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Source> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let attenuation = |i: usize| -> Result<f32> {
+            Ok(match parts.get(i) {
+                Some(a) => a.parse()?,
+                None => 0.0,
+            })
+        };
+        match parts[0] {
+            "dir" if parts.len() == 2 => Ok(Source::Directional {
+                dir: parse_vector(parts[1])?.normalize(),
+            }),
+            "point" if parts.len() >= 2 => Ok(Source::Point {
+                position: parse_vector(parts[1])?,
+                attenuation: attenuation(2)?,
+            }),
+            "spot" if parts.len() >= 5 => Ok(Source::Spot {
+                position: parse_vector(parts[1])?,
+                dir: parse_vector(parts[2])?.normalize(),
+                inner: parts[3].parse::<f32>()?.to_radians(),
+                outer: parts[4].parse::<f32>()?.to_radians(),
+                attenuation: attenuation(5)?,
+            }),
+            _ => Err(anyhow!("malformed light '{}'", s)),
+        }
+    }
+}
+
+// source[@r,g,b[@ambient,diffuse,specular]], e.g. dir:-1,-1,2@1,0.9,0.7@0.1,1,0.5
+impl std::str::FromStr for Light {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Light> {
+        let mut parts = s.split('@');
+        let mut light = Light::white(parts.next().unwrap_or_default().parse()?);
+        if let Some(color) = parts.next() {
+            light.color = parse_vector(color)?;
+        }
+        if let Some(intensities) = parts.next() {
+            let intensities = parse_vector(intensities)?;
+            light.ambient = intensities.x;
+            light.diffuse = intensities.y;
+            light.specular = intensities.z;
+        }
+        if parts.next().is_some() {
+            return Err(anyhow!("malformed light '{}'", s));
+        }
+        Ok(light)
+    }
+}
@@ -1,169 +1,1628 @@
-mod model;
-mod our_gl;
-mod shaders;
+mod analysis;
+mod animation;
+#[cfg(feature = "gui")]
+mod gui;
+mod output;
+mod passes;
+mod pointcloud;
+mod preview;
+mod profile;
+mod progress;
+mod registry;
+#[cfg(feature = "script")]
+mod script;
+mod sidecar;
+mod stream;
+#[cfg(feature = "viewer")]
+mod viewer;
 
-use anyhow::Result;
-use cgmath::{InnerSpace, Transform, Vector3, Vector4};
+use anyhow::{anyhow, Context, Result};
+use cgmath::{Matrix4, Vector3};
 use image::io::Reader as ImageReader;
-use image::{imageops, GrayImage, ImageBuffer, RgbImage};
-use our_gl::Shader;
+use image::{imageops, ImageBuffer, Luma, Rgb, RgbImage};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tinyrenderer::renderer;
+use tinyrenderer::{
+    bake, bvh, camera, camera_path, cubemap, fixtures, font, geometry, gltf, graph, light,
+    material, mesh, model, our_gl, pathtrace, postprocess, probes, scene, shaders, texture,
+};
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 800;
-const EYE: Vector3<f32> = Vector3 {
-    x: 1.0,
-    y: 0.0,
-    z: 2.0,
-};
-const CENTER: Vector3<f32> = Vector3 {
-    x: 0.0,
-    y: 0.0,
-    z: 0.0,
-};
-const UP: Vector3<f32> = Vector3 {
-    x: 0.0,
-    y: 1.0,
-    z: 0.0,
-};
 
-const LIGHT_DIR: Vector3<f32> = Vector3 {
-    x: -1.0,
-    y: -1.0,
-    z: 2.0,
-};
+// size in pixels of --points that don't give their own
+const POINT_SIZE: f32 = 2.0;
+
+// cel shading for --toon
+const TOON_BANDS: u32 = 4;
+
+// rays cast from every light probe while baking
+const PROBE_RAYS: usize = 256;
+
+// side of the occlusion textures baked for objects without a diffuse map to match
+const OCCLUSION_SIZE: u32 = 1024;
+
+// frames a second of a --camera-path flythrough
+const CAMERA_PATH_FPS: f32 = 24.0;
 
 fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    let path = if args.len() == 2 {
-        &args[1]
+    let start = std::time::Instant::now();
+    let mut path = String::from("obj/african_head/african_head");
+    let (mut width, mut height) = (WIDTH, HEIGHT);
+    let mut camera = camera::Camera::new(
+        Vector3::new(1.0, 0.0, 2.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        WIDTH,
+        HEIGHT,
+    );
+    let mut stream_addr: Option<String> = None;
+    let mut edge_aa = false;
+    // rasterize the main pass into tiled buffers
+    let mut tiled = false;
+    // skip blocks of the main pass behind what is already drawn
+    let mut hiz = false;
+    // draw the faces of the main pass on every core at once, with --tiled the tiles
+    let mut parallel = false;
+    let mut msaa: Option<u32> = None;
+    // layers of order independent transparency in place of hashed alpha
+    let mut peel: Option<u32> = None;
+    let mut capture: Option<(u32, u32)> = None;
+    // pixels of the saved image to report the face under
+    let mut picks: Vec<(u32, u32)> = Vec::new();
+    let mut wrap = texture::WrapMode::Repeat;
+    // which of the model's normal maps is read and how its texels are decoded
+    let mut normal_space = texture::NormalSpace::Tangent;
+    // which sides of faces are lit where their material doesn't say
+    let mut sides = material::Sides::One;
+    // lighting on raw texel values was the original behaviour, kept for comparison
+    let mut srgb = true;
+    let mut preview_matrix = false;
+    let mut lights: Vec<light::Light> = Vec::new();
+    // meshes to test for visibility against the finished depth buffer
+    let mut queries: Vec<String> = Vec::new();
+    let mut decals: Vec<String> = Vec::new();
+    // point clouds drawn with the model
+    let mut point_clouds: Vec<String> = Vec::new();
+    let mut point_size = POINT_SIZE;
+    let mut point_shape = our_gl::PointShape::Square;
+    // the main pass only lands where it passes the stencil written by this mesh
+    let mut stencil_mask: Option<(String, our_gl::StencilCompare, our_gl::StencilOp)> = None;
+    // drawn along with the model, each with its own transform and maps
+    let mut objects: Vec<scene::Object> = Vec::new();
+    // copies of the whole model drawn in the same passes, without any the model is drawn once
+    let mut instances: Vec<our_gl::Instance> = Vec::new();
+    let mut grid: Option<(usize, usize)> = None;
+    // in MiB, of the render targets the passes take from the pool
+    let mut memory_budget = renderer::MEMORY_BUDGET / (1024 * 1024);
+    let mut envmap: Option<String> = None;
+    let mut toon = false;
+    let mut gbuffer = false;
+    let mut output = output::Output::default();
+    // the zbuffer at full precision, for compositing or training
+    let mut depth_out: Option<String> = None;
+    // describe the frame in a .json next to it
+    let mut sidecar = false;
+    let mut view = false;
+    let mut gui = false;
+    // skip what can't be read in the model instead of failing
+    let mut tolerant = false;
+    let mut ssao = false;
+    // run over the finished frame in order, after ambient occlusion
+    let mut post = postprocess::Pipeline::default();
+    // parallel projection for technical drawings
+    let mut orthographic = false;
+    // point the camera at the model wherever and however big it is
+    let mut auto_frame = true;
+    let mut camera_specs: Vec<String> = Vec::new();
+    // a flythrough, rendered a frame at a time or only at --path-time seconds into it
+    let mut camera_path: Option<String> = None;
+    let mut path_time: Option<f32> = None;
+    let mut fps = CAMERA_PATH_FPS;
+    // luminance, exposure zone and N.L images next to the frame
+    let mut analysis = false;
+    // how many fragments were shaded at every pixel, as a false colour image
+    let mut overdraw = false;
+    // the depth in false colour, spread from the nearest to the farthest pixel and optionally
+    // made linear in the distance from the eye first
+    let mut depth_view: Option<(analysis::Colormap, bool)> = None;
+    let mut stats = false;
+    // where the time went and how much was drawn, printed once the frame is saved
+    let mut profile: Option<profile::Format> = None;
+    // a bar with an eta on stderr for each pass
+    let mut show_progress = false;
+    // how much of what was culled, clipped or skipped, and why, goes to stderr
+    let mut log_level = tracing::Level::WARN;
+    let mut wireframe: Option<Rgb<u8>> = None;
+    // the world axes from the origin, red, green and blue for x, y and z
+    let mut axes = false;
+    // in pixels, for the wireframe and axes
+    let mut line_width: f32 = 1.0;
+    let mut stencil_outline: Option<Rgb<u8>> = None;
+    // lines of text stamped over the frame
+    let mut labels: Vec<String> = Vec::new();
+    // the shading model of the main pass
+    let mut main_shader = registry::SHADERS[0].1;
+    // an attribute of the model drawn in place of --shader and --group-shader
+    let mut debug_view: Option<shaders::DebugView> = None;
+    // groups of the model's faces drawn by another shader than the main one, or not at all
+    let mut group_shaders: Vec<(String, registry::Constructor)> = Vec::new();
+    let mut hidden_groups: Vec<String> = Vec::new();
+    // triangles to simplify the model down to, and coarser levels of detail to pick from
+    let mut simplify: Option<usize> = None;
+    let mut lod_levels = 0;
+    // between the vertex and raster stages of the main pass and the shadows
+    let mut geometry: Option<geometry::Effect> = None;
+    // a Rhai file whose fn fragment(v) shades the main pass
+    let mut fragment_script: Option<String> = None;
+    let mut pcf_kernel = renderer::PCF_KERNEL;
+    // the rasterizer, or the same scene path traced to compare it with
+    let mut backend = pathtrace::Backend::default();
+    let mut samples = pathtrace::SAMPLES;
+    let mut bounces = pathtrace::BOUNCES;
+    // shadows from rays cast through a bvh of the scene instead of the shadow buffer
+    let mut ray_shadows = false;
+    let mut shadow_size = renderer::SHADOW_SIZE;
+    let mut shadow_offset = our_gl::DepthOffset {
+        bias: renderer::SHADOW_BIAS,
+        slope_bias: renderer::SHADOW_SLOPE_BIAS,
+    };
+    let mut probe_dims: Option<[usize; 3]> = None;
+    // directions to bake ambient occlusion into <model>_occlusion.tga from, instead of rendering
+    let mut bake_ao: Option<usize> = None;
+    let mut vat: Option<(String, f32)> = None;
+    let mut displacement: Option<(String, f32, f32)> = None;
+    let mut frame = 0;
+    // what skinned glTF models are posed in, their rest pose when neither is given
+    let mut pose = Pose {
+        animation: None,
+        time: None,
+    };
+    // everything around the eye as an equirectangular image, stitched from the cube faces
+    // this renderer is run again for
+    let mut panorama = false;
+    // the six faces seen from a point, lined up with the world's axes
+    let mut cube_map: Option<Vector3<f32>> = None;
+    // which of them this run renders
+    let mut cube_face: Option<usize> = None;
+    // two sets of extra arguments to render and show split-screen, and where to split
+    let mut compare: Option<(String, String)> = None;
+    let mut divider = 0.5;
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    let mut args = argv.iter().cloned();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stream" => {
+                stream_addr = Some(args.next().context("--stream expects host:port")?);
+            }
+            "--edge-aa" => edge_aa = true,
+            "--camera" => camera_specs.push(args.next().context(
+                "--camera expects fields such as eye=x,y,z;target=x,y,z;projection=perspective:fov:near:far",
+            )?),
+            "--camera-path" => {
+                camera_path = Some(
+                    args.next()
+                        .context("--camera-path expects a keyframe file")?,
+                )
+            }
+            "--path-time" => {
+                path_time = Some(
+                    args.next()
+                        .context("--path-time expects a time in seconds")?
+                        .parse()?,
+                )
+            }
+            "--fps" => {
+                fps = args
+                    .next()
+                    .context("--fps expects a number of frames a second")?
+                    .parse()?;
+                if !fps.is_finite() || fps <= 0.0 {
+                    return Err(anyhow!("--fps expects a number above 0"));
+                }
+            }
+            "--no-frame" => auto_frame = false,
+            "--panorama" => panorama = true,
+            "--cube-map" => {
+                cube_map = Some(light::parse_vector(
+                    &args.next().context("--cube-map expects a point x,y,z")?,
+                )?);
+            }
+            "--cube-face" => {
+                cube_face = Some(cubemap::face(
+                    &args
+                        .next()
+                        .context("--cube-face expects px, nx, py, ny, pz or nz")?,
+                )?);
+            }
+            "--orthographic" => orthographic = true,
+            "--size" => {
+                // resets the viewport, so comes before any --camera viewport=
+                let size = args.next().context("--size expects widthxheight")?;
+                let (w, h) = size.split_once('x').context("--size expects widthxheight")?;
+                (width, height) = (w.parse()?, h.parse()?);
+                camera.viewport = camera::Viewport::full(width, height);
+            }
+            "--tiled" => tiled = true,
+            "--capture" => {
+                // x,y in the saved image
+                let pixel = args.next().context("--capture expects x,y")?;
+                let (x, y) = pixel
+                    .split_once(',')
+                    .context("--capture expects x,y")?;
+                capture = Some((x.parse()?, y.parse()?));
+            }
+            "--pick" => {
+                // x,y in the saved image
+                let pixel = args.next().context("--pick expects x,y")?;
+                let (x, y) = pixel.split_once(',').context("--pick expects x,y")?;
+                picks.push((x.parse()?, y.parse()?));
+            }
+            "--msaa" => {
+                msaa = Some(
+                    args.next()
+                        .context("--msaa expects a sample count of 2, 4 or 8")?
+                        .parse()?,
+                );
+            }
+            "--peel" => {
+                peel = Some(
+                    args.next()
+                        .context("--peel expects a number of layers")?
+                        .parse()?,
+                );
+            }
+            "--compare" => {
+                let usage = "--compare expects two quoted argument lists, e.g. \"--pcf 1\" \"--pcf 5\"";
+                compare = Some((args.next().context(usage)?, args.next().context(usage)?));
+            }
+            "--divider" => {
+                divider = args
+                    .next()
+                    .context("--divider expects a fraction of the width")?
+                    .parse()?;
+            }
+            "--toon" => toon = true,
+            "--gbuffer" => gbuffer = true,
+            "--shader" => {
+                main_shader = registry::lookup(&args.next().context(
+                    "--shader expects shadow, gouraud, toon, normalmap or specular",
+                )?)?
+            }
+            "--debug-view" => {
+                debug_view = Some(
+                    args.next()
+                        .context("--debug-view expects normals, uvs, depth or barycentric")?
+                        .parse()?,
+                )
+            }
+            "--group-shader" => {
+                let usage = "--group-shader expects a group and a shader, e.g. eyes=specular";
+                let (group, shader) = args
+                    .next()
+                    .context(usage)?
+                    .split_once('=')
+                    .map(|(g, s)| (String::from(g), String::from(s)))
+                    .context(usage)?;
+                group_shaders.push((group, registry::lookup(&shader)?));
+            }
+            "--hide" => hidden_groups.push(args.next().context("--hide expects a group")?),
+            "--simplify" => {
+                simplify = Some(
+                    args.next()
+                        .context("--simplify expects a number of triangles")?
+                        .parse()?,
+                )
+            }
+            "--lods" => {
+                lod_levels = args
+                    .next()
+                    .context("--lods expects a number of levels")?
+                    .parse()?
+            }
+            "--geometry" => {
+                geometry = Some(
+                    args.next()
+                        .context("--geometry expects explode=distance or shrink=factor")?
+                        .parse()?,
+                )
+            }
+            "--fragment-script" => {
+                fragment_script = Some(
+                    args.next()
+                        .context("--fragment-script expects a .rhai file")?,
+                )
+            }
+            "--sidecar" => sidecar = true,
+            "--depth-out" => {
+                depth_out = Some(
+                    args.next()
+                        .context("--depth-out expects a .png, .f32 or .raw path")?,
+                );
+            }
+            "--output" | "--format" | "--quality" => {
+                let value = args.next().with_context(|| {
+                    format!(
+                        "{} expects a path, one of tga, png, jpeg or bmp, or 1 to 100",
+                        arg
+                    )
+                })?;
+                output.set(&arg, &value)?;
+            }
+            "--view" => view = true,
+            "--gui" => gui = true,
+            "--tolerant" => tolerant = true,
+            "--check-loader" => {
+                let iterations = args
+                    .next()
+                    .context("--check-loader expects a number of fixtures")?
+                    .parse()?;
+                fixtures::check_loader(iterations, fixtures::SEED)?;
+                println!("{} fixtures loaded and round tripped", iterations);
+                return Ok(());
+            }
+            "--ssao" => ssao = true,
+            "--fxaa" => post.push(Box::new(postprocess::Fxaa)),
+            "--post" => post.push(postprocess::parse_effect(&args.next().context(
+                "--post expects fxaa, tonemap[=stops], bloom[=threshold,radius], \
+                 vignette[=strength], dof=focus[,radius] or lut=file.cube",
+            )?)?),
+            "--analysis" => analysis = true,
+            "--overdraw" => overdraw = true,
+            "--depth-view" => {
+                let spec = args
+                    .next()
+                    .context("--depth-view expects viridis or turbo, optionally with :linear")?;
+                depth_view = Some(match spec.split_once(':') {
+                    Some((colormap, "linear")) => (colormap.parse()?, true),
+                    Some(_) => {
+                        return Err(anyhow!(
+                            "--depth-view expects viridis or turbo, optionally with :linear"
+                        ))
+                    }
+                    None => (spec.parse()?, false),
+                });
+            }
+            "--stats" => stats = true,
+            "--hiz" => hiz = true,
+            "--parallel" => parallel = true,
+            "--progress" => show_progress = true,
+            "--log" => {
+                log_level = args
+                    .next()
+                    .context("--log expects error, warn, info, debug or trace")?
+                    .parse()
+                    .context("--log expects error, warn, info, debug or trace")?;
+            }
+            "--profile" => {
+                profile = Some(
+                    args.next()
+                        .context("--profile expects table or json")?
+                        .parse()?,
+                );
+            }
+            "--wireframe" => {
+                let color = args.next().context("--wireframe expects a colour r,g,b")?;
+                wireframe = Some(parse_rgb("--wireframe", &color)?);
+            }
+            "--axes" => axes = true,
+            "--label" => labels.push(args.next().context(
+                "--label expects text, where {frame}, {ms} and {args} are replaced by the frame \
+                 number, milliseconds so far and the other arguments",
+            )?),
+            "--line-width" => {
+                line_width = args
+                    .next()
+                    .context("--line-width expects a width in pixels")?
+                    .parse()?;
+            }
+            "--stencil-outline" => {
+                let color = args
+                    .next()
+                    .context("--stencil-outline expects a colour r,g,b")?;
+                stencil_outline = Some(parse_rgb("--stencil-outline", &color)?);
+            }
+            "--probes" => {
+                let dims = args.next().context("--probes expects nx,ny,nz")?;
+                let dims = dims
+                    .split(',')
+                    .map(|d| d.trim().parse::<usize>())
+                    .collect::<Result<Vec<usize>, _>>()?;
+                probe_dims = Some(
+                    dims.try_into()
+                        .map_err(|_| anyhow!("--probes expects three sizes"))?,
+                );
+            }
+            "--bake-ao" => {
+                bake_ao = Some(
+                    args.next()
+                        .context("--bake-ao expects a number of directions")?
+                        .parse()?,
+                );
+            }
+            "--shadow-size" => {
+                shadow_size = args
+                    .next()
+                    .context("--shadow-size expects a width in pixels")?
+                    .parse()?;
+            }
+            "--shadow-bias" => {
+                shadow_offset.bias = args
+                    .next()
+                    .context("--shadow-bias expects a depth such as 1")?
+                    .parse()?;
+            }
+            "--shadow-slope-bias" => {
+                shadow_offset.slope_bias = args
+                    .next()
+                    .context("--shadow-slope-bias expects a factor such as 2")?
+                    .parse()?;
+            }
+            "--pcf" => {
+                pcf_kernel = args
+                    .next()
+                    .context("--pcf expects a kernel width such as 1, 3 or 5")?
+                    .parse()?;
+            }
+            "--ray-shadows" => ray_shadows = true,
+            "--backend" => {
+                backend = args
+                    .next()
+                    .context("--backend expects raster or pathtrace")?
+                    .parse()?
+            }
+            "--samples" => {
+                samples = args
+                    .next()
+                    .context("--samples expects a number of paths per pixel")?
+                    .parse()?
+            }
+            "--bounces" => {
+                bounces = args
+                    .next()
+                    .context("--bounces expects a number of bounces")?
+                    .parse()?
+            }
+            "--vat" => {
+                // image[:scale]
+                let spec = args.next().context("--vat expects image[:scale]")?;
+                vat = Some(match spec.rsplit_once(':') {
+                    Some((image, scale)) => (String::from(image), scale.parse()?),
+                    None => (spec, 1.0),
+                });
+            }
+            "--displace" => {
+                // height[:scale[:bias]], texels in [0, 1] move scale * texel + bias along
+                // the normal
+                let spec = args
+                    .next()
+                    .context("--displace expects height[:scale[:bias]]")?;
+                let mut fields = spec.splitn(3, ':');
+                let height = String::from(fields.next().unwrap_or_default());
+                let scale = fields.next().map_or(Ok(1.0), str::parse)?;
+                let bias = fields.next().map_or(Ok(0.0), str::parse)?;
+                displacement = Some((height, scale, bias));
+            }
+            "--animation" => {
+                let name = args
+                    .next()
+                    .context("--animation expects the name of one of the model's animations")?;
+                pose.animation = Some(name);
+            }
+            "--animation-time" => {
+                pose.time = Some(
+                    args.next()
+                        .context("--animation-time expects a time in seconds")?
+                        .parse()?,
+                )
+            }
+            "--frame" => {
+                frame = args
+                    .next()
+                    .context("--frame expects a frame number")?
+                    .parse()?;
+            }
+            "--no-srgb" => srgb = false,
+            "--preview-matrix" => preview_matrix = true,
+            "--light" => lights.push(
+                args.next()
+                    .context(
+                        "--light expects dir:x,y,z, point:x,y,z or spot:x,y,z:dx,dy,dz:inner:outer, \
+                         optionally followed by @r,g,b and @ambient,diffuse,specular",
+                    )?
+                    .parse()?,
+            ),
+            "--object" => objects.push(
+                args.next()
+                    .context("--object expects path;translate=x,y,z;rotate=x,y,z;scale=s")?
+                    .parse()?,
+            ),
+            "--instance" => instances.push(our_gl::Instance::new(scene::parse_transform(
+                &args
+                    .next()
+                    .context("--instance expects translate=x,y,z;rotate=x,y,z;scale=s")?,
+            )?)),
+            "--grid" => {
+                let size = args.next().context("--grid expects columnsxrows")?;
+                let (columns, rows) = size.split_once('x').context("--grid expects columnsxrows")?;
+                grid = Some((columns.parse()?, rows.parse()?));
+            }
+            "--decal" => decals.push(args.next().context("--decal expects the path of an obj file")?),
+            "--points" => point_clouds.push(
+                args.next()
+                    .context("--points expects the path of an x y z [r g b [size]] file")?,
+            ),
+            "--point-size" => {
+                point_size = args
+                    .next()
+                    .context("--point-size expects a size in pixels")?
+                    .parse()?;
+            }
+            "--point-shape" => {
+                point_shape = args
+                    .next()
+                    .context("--point-shape expects square or disc")?
+                    .parse()?;
+            }
+            "--stencil-mask" => {
+                // mask.obj[:compare[:op]], the main pass draws where the stencil compares to 1
+                let spec = args
+                    .next()
+                    .context("--stencil-mask expects mask.obj[:compare[:op]]")?;
+                let mut parts = spec.split(':');
+                let path = String::from(parts.next().unwrap_or_default());
+                let compare = match parts.next() {
+                    Some(compare) => compare.parse()?,
+                    None => our_gl::StencilCompare::Equal,
+                };
+                let op = match parts.next() {
+                    Some(op) => op.parse()?,
+                    None => our_gl::StencilOp::Replace,
+                };
+                stencil_mask = Some((path, compare, op));
+            }
+            "--occlusion-query" => queries.push(
+                args.next()
+                    .context("--occlusion-query expects the path of an obj file")?,
+            ),
+            "--envmap" => {
+                envmap = Some(
+                    args.next()
+                        .context("--envmap expects a cross image or a face file prefix")?,
+                );
+            }
+            "--memory-budget" => {
+                memory_budget = args
+                    .next()
+                    .context("--memory-budget expects a size in MiB")?
+                    .parse()?;
+            }
+            "--wrap" => {
+                wrap = args
+                    .next()
+                    .context("--wrap expects repeat, clamp or mirror")?
+                    .parse()?;
+            }
+            "--normal-space" => {
+                normal_space = args
+                    .next()
+                    .context("--normal-space expects tangent or object")?
+                    .parse()?;
+            }
+            "--sides" => {
+                sides = args
+                    .next()
+                    .context("--sides expects one, flip or two")?
+                    .parse()?;
+            }
+            _ => path = arg,
+        }
+    }
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(std::io::stderr)
+        .init();
+    if let Some((a, b)) = compare {
+        let base = without_compare(&argv);
+        let scratch = Scratch::new("compare")?;
+        let left = (
+            label(&a),
+            render_variant(&base, &split_args(&a)?, &scratch.0.join("a.tga"))?,
+        );
+        let right = (
+            label(&b),
+            render_variant(&base, &split_args(&b)?, &scratch.0.join("b.tga"))?,
+        );
+        output.save(&preview::split(&left, &right, divider))?;
+        return Ok(());
+    }
+    if let (Some(keyframes), None) = (&camera_path, path_time) {
+        return render_camera_path(&argv, keyframes, fps, &output.path);
+    }
+    if panorama {
+        return render_panorama(&argv, width, height, &output);
+    }
+    if let Some(point) = cube_map {
+        return render_cube_map(&argv, point, width.min(height), &output);
+    }
+    if lights.is_empty() {
+        lights.push(renderer::default_light());
+    }
+    let frame_stream = match stream_addr {
+        Some(addr) => Some(stream::FrameStream::connect(addr)?),
+        None => None,
+    };
+    let diffuse_space = if srgb {
+        texture::ColorSpace::Srgb
     } else {
-        "obj/african_head/african_head"
+        texture::ColorSpace::Linear
     };
-    let model = model::file_to_model(format!("{}.obj", path).as_str())?;
-    let mut texture = ImageReader::open(format!("{}_diffuse.tga", path).as_str())?
-        .decode()?
-        .to_rgb8();
-    imageops::flip_vertical_in_place(&mut texture);
-
-    let mut normal_map = ImageReader::open(format!("{}_nm_tangent.tga", path).as_str())?
-        .decode()?
-        .to_rgb8();
-    imageops::flip_vertical_in_place(&mut normal_map);
-
-    let mut specular_map = ImageReader::open(format!("{}_spec.tga", path).as_str())?
-        .decode()?
-        .to_luma8();
-    imageops::flip_vertical_in_place(&mut specular_map);
-
-    let mut image: RgbImage = ImageBuffer::new(WIDTH, HEIGHT);
-    let mut zbuffer: GrayImage = ImageBuffer::new(WIDTH, HEIGHT);
-
-    let mut shadow_buffer: GrayImage = ImageBuffer::new(WIDTH, HEIGHT);
-    let m = {
-        // rendering the shadow buffer
-        let mut depth: RgbImage = ImageBuffer::new(WIDTH, HEIGHT);
-
-        let model_view = our_gl::lookat(LIGHT_DIR, CENTER, UP);
-        let viewport = our_gl::viewport(
-            (WIDTH / 8) as f32,
-            (HEIGHT / 8) as f32,
-            (WIDTH * 3 / 4) as f32,
-            (HEIGHT * 3 / 4) as f32,
+    // the model on the command line can be placed like any other object
+    let primary: scene::Object = path.parse()?;
+    let mut model = load_model(&primary.path, tolerant, &pose)?;
+    if let Some((filename, scale)) = vat {
+        // baked before any pass so the shadow map moves with the mesh
+        let animation = animation::VertexAnimation::load(&filename, scale)?;
+        model.displace(|v| animation.offset(v, frame));
+    }
+    let mut textures = texture::TextureCache::default();
+    if let Some((filename, scale, bias)) = displacement {
+        // in the model's own space, before it is placed
+        let height = textures
+            .gray(&texture::ImageSource::File(filename.clone()), wrap)?
+            .ok_or_else(|| anyhow!("--displace could not find {}", filename))?;
+        model.displace_by_height(|uv| height.sample(uv)[0] as f32 / 255.0 * scale + bias);
+    }
+    model.transform(primary.matrix);
+    // every pass draws the objects together as one model, the maps follow its objects
+    let mut maps = vec![texture::load_maps(
+        &primary.path,
+        model.get_materials(),
+        wrap,
+        diffuse_space,
+        normal_space,
+        &mut textures,
+    )?];
+    for object in &objects {
+        let mut mesh = load_model(&object.path, tolerant, &pose)?;
+        mesh.transform(object.matrix);
+        maps.push(texture::load_maps(
+            &object.path,
+            mesh.get_materials(),
+            wrap,
+            diffuse_space,
+            normal_space,
+            &mut textures,
+        )?);
+        model.append(mesh);
+    }
+    for name in &hidden_groups {
+        let groups: Vec<usize> = model.groups_named(name).collect();
+        if groups.is_empty() {
+            return Err(anyhow!(
+                "the model has no group '{}', it has {}",
+                name,
+                model.group_names().join(", ")
+            ));
+        }
+        let faces: Vec<usize> = (0..model.get_faces().len())
+            .map(|i| model.get_group(i))
+            .collect();
+        model.retain_faces(|i| !groups.contains(&faces[i]));
+    }
+    if let Some(triangles) = simplify {
+        model = mesh::simplify(&model, triangles);
+    }
+    if let Some(samples) = bake_ao {
+        // the size of the diffuse maps, so the shaders find the same texel in both
+        let sizes: Vec<(u32, u32)> = maps
+            .iter()
+            .map(|m| {
+                m.diffuse
+                    .as_ref()
+                    .map_or((OCCLUSION_SIZE, OCCLUSION_SIZE), |t| t.dimensions())
+            })
+            .collect();
+        let bar = show_progress.then(progress::Bar::default);
+        let baked = bake::ambient_occlusion(&model, &maps, &sizes, samples, &|done, total| {
+            if let Some(bar) = &bar {
+                bar.update("bake", done, total);
+            }
+        });
+        let paths = std::iter::once(&primary.path).chain(objects.iter().map(|o| &o.path));
+        for (path, occlusion) in paths.zip(baked) {
+            let filename = format!("{}_occlusion.tga", path);
+            occlusion.save(&filename)?;
+            tracing::info!(file = filename, "baked ambient occlusion");
+        }
+        return Ok(());
+    }
+    if let Some((columns, rows)) = grid {
+        // on the ground around the model's place, a model apart so none of them touch
+        let spacing = 2.0 * model.bounding_sphere().1;
+        for row in 0..rows {
+            for column in 0..columns {
+                let offset = Vector3::new(
+                    column as f32 - (columns - 1) as f32 / 2.0,
+                    0.0,
+                    row as f32 - (rows - 1) as f32 / 2.0,
+                ) * spacing;
+                instances.push(our_gl::Instance::new(Matrix4::from_translation(offset)));
+            }
+        }
+    }
+    if instances.is_empty() {
+        instances.push(our_gl::Instance::default());
+    }
+    let (center, radius) = our_gl::instanced_bounding_sphere(&model, &instances);
+    if auto_frame {
+        camera.frame(center, radius);
+    }
+    if let Some(time) = path_time {
+        let keyframes = camera_path
+            .as_deref()
+            .context("--path-time needs --camera-path")?;
+        camera_path::CameraPath::load(keyframes)?
+            .at(time)
+            .apply(&mut camera);
+        camera.fit_depth(center, radius);
+    }
+    // after framing so the fields given still hold
+    for spec in &camera_specs {
+        camera.apply(spec)?;
+    }
+    if orthographic {
+        // framed like the perspective camera at the target
+        camera.projection = camera::Projection::Orthographic {
+            size: camera.half_height(),
+        };
+    }
+    if let Some(face) = cube_face {
+        camera.cube_face(face);
+        camera.fit_depth(center, radius);
+    }
+    if lod_levels > 0 {
+        // each instance by what it covers of the frame, every other pass draws the same
+        let mut levels = mesh::lods(model, lod_levels);
+        let lod = renderer::instance_lods(
+            &levels.iter().collect::<Vec<_>>(),
+            &maps,
+            &instances,
+            &camera,
+        );
+        let count = levels.len();
+        match lod {
+            renderer::Lod::Level(level) => {
+                model = levels.swap_remove(level);
+            }
+            renderer::Lod::Copies(copies, copied_maps) => {
+                (model, maps) = (*copies, copied_maps);
+                instances = vec![our_gl::Instance::default()];
+            }
+        }
+        tracing::info!(
+            levels = count,
+            triangles = model.triangle_count(),
+            instances = instances.len(),
+            "levels of detail picked"
+        );
+    }
+    if stats {
+        let (min, max) = our_gl::instanced_bounds(&model, &instances);
+        let centroid = model.centroid();
+        println!(
+            "{} objects, {} vertices, {} triangles",
+            model.object_count(),
+            model.get_verts().len(),
+            model.triangle_count()
+        );
+        println!(
+            "bounds {},{},{} to {},{},{}",
+            min.x, min.y, min.z, max.x, max.y, max.z
         );
-        let projection = our_gl::projection(0.0);
-        let mat = viewport * projection * model_view;
-
-        let mut depth_shader = shaders::DepthShader::new();
-        for i in 0..model.get_faces().len() {
-            let mut screen_coords: [Vector4<f32>; 3] = [Vector4 {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 0.0,
-            }; 3];
-            for j in 0..3usize {
-                screen_coords[j] = depth_shader.vertex(&model, i, j, mat);
-            }
-            our_gl::triangle(
-                &screen_coords,
-                &depth_shader,
-                &mut depth,
-                &mut shadow_buffer,
+        println!(
+            "bounding sphere {},{},{} radius {}",
+            center.x, center.y, center.z, radius
+        );
+        println!("centroid {},{},{}", centroid.x, centroid.y, centroid.z);
+        println!("surface area {}", model.surface_area());
+        println!("groups {}", model.group_names().join(", "));
+        if instances.len() > 1 {
+            println!(
+                "drawn {} times, bounds are of every instance",
+                instances.len()
             );
         }
+        return Ok(());
+    }
+    let environment = match envmap {
+        Some(path) => Some(cubemap::CubeMap::load(&path)?),
+        None => None,
+    };
 
-        imageops::flip_vertical_in_place(&mut depth);
-        depth.save("depth.tga")?;
+    let bvh = ray_shadows.then(|| Arc::new(bvh::Bvh::new(&model, &instances)));
 
-        // imageops::flip_vertical_in_place(&mut shadow_buffer);
-        // shadow_buffer.save("shadow_buffer.tga")?;
-        mat
+    let light_probes = probe_dims.map(|dims| {
+        // a little past the model so the outermost probes are not on its surface
+        let (min, max) = our_gl::instanced_bounds(&model, &instances);
+        let margin = (max - min) * 0.1;
+        probes::ProbeGrid::bake(
+            &model,
+            &instances,
+            &lights,
+            environment.as_ref(),
+            (min - margin, max + margin),
+            dims,
+            PROBE_RAYS,
+        )
+    });
+
+    // only the main pass, with the specular shader to keep it interactive, and depth of field
+    // for the gui
+    #[cfg(feature = "viewer")]
+    let preview = |camera: &camera::Camera,
+                   lights: &[light::Light],
+                   dof: Option<postprocess::DepthOfField>,
+                   width,
+                   height| {
+        let mut framebuffer = our_gl::Framebuffer::new(
+            RgbImage::new(width, height),
+            image::GrayImage::new(width, height),
+        );
+        renderer::rasterize(
+            &model,
+            &instances,
+            &mut shaders::SpecularShader::new(lights, &maps),
+            &our_gl::Uniforms {
+                sides,
+                ..camera.uniforms()
+            },
+            &mut framebuffer,
+        );
+        if let Some(dof) = dof {
+            postprocess::PostEffect::apply(&dof, &mut framebuffer);
+        }
+        imageops::flip_vertical_in_place(&mut framebuffer.color);
+        imageops::flip_vertical_in_place(&mut framebuffer.depth);
+        framebuffer
     };
+    if gui {
+        #[cfg(feature = "gui")]
+        return gui::run(
+            gui::Settings {
+                camera,
+                lights: lights.clone(),
+                exposure: 0.0,
+                focus: None,
+                aperture: gui::APERTURE,
+            },
+            width,
+            height,
+            |settings, width, height| {
+                let dof = settings.focus.map(|focus| postprocess::DepthOfField {
+                    focus,
+                    radius: settings.aperture * width as f32,
+                });
+                Ok(preview(
+                    &settings.camera,
+                    &settings.lights,
+                    dof,
+                    width,
+                    height,
+                ))
+            },
+        );
+        #[cfg(not(feature = "gui"))]
+        return Err(anyhow!(
+            "--gui needs the gui feature, build with --features gui"
+        ));
+    }
+    if view {
+        #[cfg(feature = "viewer")]
+        return viewer::run(camera, width, height, |camera, width, height| {
+            Ok(preview(camera, &lights, None, width, height))
+        });
+        #[cfg(not(feature = "viewer"))]
+        return Err(anyhow!(
+            "--view needs the viewer feature, build with --features viewer"
+        ));
+    }
 
-    {
-        // ambient occlusion
-        let model_view = our_gl::lookat(EYE, CENTER, UP);
-        let viewport = our_gl::viewport(
-            (WIDTH / 8) as f32,
-            (HEIGHT / 8) as f32,
-            (WIDTH * 3 / 4) as f32,
-            (HEIGHT * 3 / 4) as f32,
+    let bar = show_progress.then(progress::Bar::default);
+    if backend == pathtrace::Backend::PathTrace {
+        let tracer = pathtrace::PathTracer::new(
+            &model,
+            &maps,
+            &instances,
+            &lights,
+            environment.as_ref(),
+            sides,
+            bounces,
+        );
+        let image = path_trace(
+            &tracer,
+            &camera,
+            (width, height),
+            samples,
+            diffuse_space,
+            &|done, total| {
+                if let Some(bar) = &bar {
+                    bar.update("trace", done, total);
+                }
+            },
         );
-        let projection = our_gl::projection(-1.0 / (EYE - CENTER).magnitude());
-        let mat = viewport * projection * model_view;
+        output.save(&image)?;
+        return Ok(());
+    }
 
-        let mut z_shader = shaders::ZShader::new();
-        for i in 0..model.get_faces().len() {
-            for j in 0..3usize {
-                z_shader.vertex(&model, i, j, mat);
+    #[cfg(not(feature = "script"))]
+    if fragment_script.is_some() {
+        return Err(anyhow!(
+            "--fragment-script needs the script feature, build with --features script"
+        ));
+    }
+    if stencil_mask.is_some() && (peel.is_some() || msaa.is_some() || edge_aa || tiled) {
+        return Err(anyhow!(
+            "--stencil-mask can't be used with --peel, --msaa, --edge-aa or --tiled"
+        ));
+    }
+    if parallel && (peel.is_some() || msaa.is_some() || edge_aa || hiz || stencil_mask.is_some()) {
+        return Err(anyhow!(
+            "--parallel can't be used with --peel, --msaa, --edge-aa, --hiz or --stencil-mask"
+        ));
+    }
+    if (overdraw || capture.is_some()) && (peel.is_some() || parallel || msaa.is_some() || edge_aa)
+    {
+        return Err(anyhow!(
+            "--overdraw and --capture can't be used with --peel, --parallel, --msaa or --edge-aa"
+        ));
+    }
+    if geometry.is_some() && (peel.is_some() || parallel) {
+        return Err(anyhow!(
+            "--geometry can't be used with --peel or --parallel"
+        ));
+    }
+
+    let scene = renderer::Scene {
+        model,
+        lods: Vec::new(),
+        maps,
+        instances,
+        lights,
+        camera,
+        width,
+        height,
+        sides,
+    };
+    let overdraw_counts = overdraw.then(|| {
+        Arc::new(Mutex::new(ImageBuffer::<Luma<u16>, Vec<u16>>::new(
+            width, height,
+        )))
+    });
+    // report every fragment the main pass writes to the captured pixel and count them for
+    // --overdraw
+    let hook: Option<Arc<our_gl::FragmentHook>> = (capture.is_some() || overdraw).then(|| {
+        let counts = overdraw_counts.clone();
+        Arc::new(move |fragment: our_gl::Fragment| {
+            if capture == Some((fragment.x, height - 1 - fragment.y)) {
+                println!(
+                    "bar ({:.3}, {:.3}, {:.3}) depth {:.2} colour {:?}",
+                    fragment.bar.x,
+                    fragment.bar.y,
+                    fragment.bar.z,
+                    fragment.depth,
+                    fragment.color.0
+                );
+            }
+            if let Some(counts) = &counts {
+                let mut counts = counts.lock().unwrap();
+                let count = &mut counts.get_pixel_mut(fragment.x, fragment.y)[0];
+                *count = count.saturating_add(1);
+            }
+            true
+        }) as Arc<our_gl::FragmentHook>
+    });
+    let renderer = renderer::Renderer {
+        hook,
+        progress: bar.map(|bar| -> renderer::PassProgress {
+            Box::new(move |pass, done, total| bar.update(pass, done, total))
+        }),
+        memory_budget: memory_budget * 1024 * 1024,
+        ..renderer::Renderer::default()
+    };
+    #[cfg(feature = "script")]
+    let script = fragment_script
+        .as_deref()
+        .map(|path| script::Script::load(path, &scene.lights, &scene.maps))
+        .transpose()?
+        .map(Arc::new);
+    let stencil_mask = match stencil_mask {
+        Some((path, compare, op)) => Some((model::file_to_model(&path)?, compare, op)),
+        None => None,
+    };
+    let decals = decals
+        .iter()
+        .map(|path| model::file_to_model(path))
+        .collect::<Result<Vec<_>>>()?;
+    let clouds = point_clouds
+        .iter()
+        .map(|path| pointcloud::load(path, point_size))
+        .collect::<Result<Vec<_>>>()?;
+    let frame_stream = frame_stream.map(Mutex::new);
+    let report = Mutex::new(profile::Report::default());
+
+    let mut resources = {
+        // each flag adds a pass or configures one, in the order they run
+        let mut order: Vec<Box<dyn graph::Pass + '_>> = vec![Box::new(renderer::ShadowPass {
+            size: shadow_size,
+            offset: shadow_offset,
+            geometry,
+        })];
+        if ssao {
+            order.push(Box::new(renderer::AmbientOcclusionPass));
+        }
+        if preview_matrix {
+            order.push(Box::new(passes::PreviewMatrixPass {
+                pcf_kernel,
+                probes: &light_probes,
+                environment: environment.as_ref(),
+            }));
+        }
+        if toon {
+            order.push(Box::new(passes::ToonPass));
+        }
+        if gbuffer {
+            order.push(Box::new(passes::GBufferPass));
+        }
+        if let Some(environment) = &environment {
+            order.push(Box::new(passes::ReflectionPass { environment }));
+        }
+        order.push(Box::new(passes::MainPass {
+            shader: main_shader,
+            group_shaders: &group_shaders,
+            debug_view,
+            #[cfg(feature = "script")]
+            script,
+            pcf_kernel,
+            probes: &light_probes,
+            traced: &bvh,
+            geometry,
+            msaa,
+            edge_aa,
+            tiled,
+            hiz,
+            parallel,
+            peel,
+            stencil_mask,
+            decals,
+            hook: renderer.hook.clone(),
+            stream: frame_stream.as_ref(),
+            report: profile.is_some().then_some(&report),
+        }));
+        if !clouds.is_empty() {
+            order.push(Box::new(passes::PointsPass {
+                clouds,
+                shape: point_shape,
+            }));
+        }
+        if let Some(color) = stencil_outline {
+            order.push(Box::new(passes::OutlinePass { color }));
+        }
+        if ssao {
+            order.push(Box::new(renderer::OcclusionPass));
+        }
+        order.push(Box::new(renderer::PostPass { post: &post }));
+        if let Some(color) = wireframe {
+            order.push(Box::new(passes::WireframePass {
+                color,
+                width: line_width,
+            }));
+        }
+        if axes {
+            order.push(Box::new(passes::AxesPass { width: line_width }));
+        }
+        if !labels.is_empty() {
+            // what the frame was rendered with, other than the labels themselves
+            let mut settings: Vec<&str> = Vec::new();
+            let mut rest = argv.iter();
+            while let Some(arg) = rest.next() {
+                if arg == "--label" {
+                    rest.next();
+                } else {
+                    settings.push(arg);
+                }
             }
-            // first argument is not used
-            //our_gl::triangle(&z_shader.varying_tri, &z_shader, &mut image, &mut zbuffer);
+            let text = labels
+                .join("\n")
+                .replace("{frame}", &frame.to_string())
+                .replace("{args}", &settings.join(" "));
+            order.push(Box::new(passes::LabelPass { text, start }));
+        }
+        if analysis {
+            order.push(Box::new(passes::NdotLPass));
+        }
+        if depth_out.is_some() || depth_view.is_some() {
+            order.push(Box::new(passes::DepthPass));
+        }
+        let mut graph = graph::Graph::default();
+        for pass in order {
+            graph.add(Box::new(passes::Timed {
+                pass,
+                report: &report,
+            }));
+        }
+        renderer.run_graph(&graph, &scene)?
+    };
+    let mut report = report.into_inner().unwrap();
+
+    if let Some(depth) = resources.shadow("shadow")?.image() {
+        depth.save("depth.tga")?;
+    }
+    if preview_matrix {
+        resources
+            .take_color("preview_matrix")?
+            .save("preview_matrix.tga")?;
+    }
+    if toon {
+        resources.take_color("toon")?.save("toon.tga")?;
+    }
+    if gbuffer {
+        let targets = resources.take_frame("gbuffer")?;
+        for (name, target) in [
+            ("albedo", Some(targets.color)),
+            ("normal", targets.normal),
+            ("position", targets.position),
+        ] {
+            let Some(target) = target else { continue };
+            imageops::flip_vertical(&target).save(format!("gbuffer_{}.tga", name))?;
         }
+        imageops::flip_vertical(&targets.depth).save("gbuffer_depth.tga")?;
+    }
+    if environment.is_some() {
+        resources.take_color("reflection")?.save("reflection.tga")?;
     }
 
-    {
-        // rendering the frame buffer
-        let model_view = our_gl::lookat(EYE, CENTER, UP);
-        let viewport = our_gl::viewport(
-            (WIDTH / 8) as f32,
-            (HEIGHT / 8) as f32,
-            (WIDTH * 3 / 4) as f32,
-            (HEIGHT * 3 / 4) as f32,
+    let framebuffer = resources.take_frame("frame")?;
+    let (mut image, zbuffer) = (framebuffer.color, framebuffer.depth);
+    let uniforms = scene.uniforms();
+
+    if analysis {
+        let (mut zones, counts) = analysis::zones(&image, &zbuffer);
+        let covered: usize = counts.iter().sum();
+        for (zone, count) in counts.iter().enumerate() {
+            println!(
+                "zone {:2} from {:5.1} stops: {:5.1}% of covered pixels",
+                zone,
+                analysis::zone_floor(zone),
+                100.0 * *count as f32 / covered.max(1) as f32
+            );
+        }
+        imageops::flip_vertical_in_place(&mut zones);
+        zones.save("zones.tga")?;
+        imageops::flip_vertical(&analysis::luminance(&image)).save("luminance.tga")?;
+        resources.take_color("ndotl")?.save("ndotl.tga")?;
+    }
+
+    if let Some(counts) = overdraw_counts {
+        let counts = counts.lock().unwrap();
+        let covered = counts.pixels().filter(|count| count[0] > 0).count();
+        let fragments: u64 = counts.pixels().map(|count| count[0] as u64).sum();
+        println!(
+            "overdraw: {} fragments over {} pixels, {:.2} per pixel, at most {}",
+            fragments,
+            covered,
+            fragments as f32 / covered.max(1) as f32,
+            counts.pixels().map(|count| count[0]).max().unwrap_or(0)
         );
-        let projection = our_gl::projection(-1.0 / (EYE - CENTER).magnitude());
-
-        let mat = viewport * projection * model_view;
-
-        let mut shader = shaders::ShadowShader::new(
-            LIGHT_DIR.normalize(),
-            texture,
-            normal_map,
-            specular_map,
-            projection * model_view,
-            m * mat.inverse_transform().expect("mat has not inverse"),
-            shadow_buffer,
+        imageops::flip_vertical(&analysis::overdraw(&counts)).save("overdraw.tga")?;
+    }
+
+    for query in &queries {
+        let mesh = model::file_to_model(query)?;
+        let passed = our_gl::occlusion_query(
+            &mesh,
+            &mut shaders::DepthShader::new(&[]),
+            &uniforms,
+            &zbuffer,
         );
+        println!("{}: {} pixels visible", query, passed);
+    }
 
-        for i in 0..model.get_faces().len() {
-            let mut screen_coords: [Vector4<f32>; 3] = [Vector4 {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 0.0,
-            }; 3];
-            for j in 0..3usize {
-                screen_coords[j] = shader.vertex(&model, i, j, mat);
-            }
-            our_gl::triangle(&screen_coords, &shader, &mut image, &mut zbuffer);
+    if !picks.is_empty() {
+        let model = &scene.model;
+        // rays cast through the picked pixels, which pass through cut out holes like the
+        // frame shows
+        let bvh = match &bvh {
+            Some(bvh) => bvh.clone(),
+            None => Arc::new(bvh::Bvh::new(model, &scene.instances)),
+        };
+        for &(x, y) in &picks {
+            let hit = height.checked_sub(y + 1).and_then(|flipped| {
+                let (origin, dir) = scene.camera.ray(x as f32, flipped as f32);
+                bvh.closest_where(origin, dir, |object, uv| {
+                    !shaders::cut_out(shaders::object_maps(&scene.maps, object), uv)
+                })
+            });
+            let Some(hit) = hit.filter(|_| x < width) else {
+                println!("{},{}: nothing", x, y);
+                continue;
+            };
+            let id = our_gl::Id {
+                face: hit.face,
+                object: model.get_object(hit.face),
+                instance: hit.instance,
+                bar: Vector3::new(1.0 - hit.bar.x - hit.bar.y, hit.bar.x, hit.bar.y),
+            };
+            let face = &model.get_faces()[id.face];
+            let uv = model.get_uvs()[face[0]] * id.bar.x
+                + model.get_uvs()[face[1]] * id.bar.y
+                + model.get_uvs()[face[2]] * id.bar.z;
+            println!(
+                "{},{}: face {} object {} group {} instance {} bar ({:.3}, {:.3}, {:.3}) \
+                 uv ({:.4}, {:.4})",
+                x,
+                y,
+                id.face,
+                id.object,
+                model.group_names()[model.get_group(id.face)],
+                id.instance,
+                id.bar.x,
+                id.bar.y,
+                id.bar.z,
+                uv.x,
+                uv.y
+            );
         }
+    }
 
-        // (0,0) is the bottom left
-        imageops::flip_vertical_in_place(&mut image);
-        image.save("output.tga")?;
-        // imageops::flip_vertical_in_place(&mut zbuffer);
-        // zbuffer.save("debug.tga")?;
+    // (0,0) is the bottom left
+    imageops::flip_vertical_in_place(&mut image);
+    if let Some(frame_stream) = &frame_stream {
+        frame_stream.lock().unwrap().send_frame(&image)?;
+    }
+    output.save(&image)?;
+    if depth_out.is_some() || depth_view.is_some() {
+        let depth = resources.take_depth("depth")?;
+        if let Some(path) = &depth_out {
+            output::save_depth(&depth, path)?;
+        }
+        if let Some((colormap, linear)) = depth_view {
+            let (view, (farthest, nearest)) = analysis::depth_view(&depth, colormap, |depth| {
+                if linear {
+                    scene.camera.linear_depth(depth)
+                } else {
+                    depth
+                }
+            });
+            println!(
+                "depth view from {:.2} at the farthest to {:.2} at the nearest, of {}",
+                farthest,
+                nearest,
+                our_gl::DEPTH
+            );
+            imageops::flip_vertical(&view).save("depth_view.tga")?;
+        }
+    }
+    if sidecar {
+        let stats = sidecar::Stats {
+            frame,
+            faces: scene.model.get_faces().len() * scene.instances.len(),
+            covered: zbuffer.pixels().filter(|depth| depth[0] > 0).count(),
+            milliseconds: start.elapsed().as_millis(),
+        };
+        sidecar::write(
+            &Path::new(&output.path)
+                .with_extension("json")
+                .to_string_lossy(),
+            &output.path,
+            &image,
+            &scene.camera,
+            &scene.lights,
+            &stats,
+        )?;
     }
+    report.pass("total", start);
+    match profile {
+        Some(profile::Format::Table) => println!("{}", report.table()),
+        Some(profile::Format::Json) => println!("{}", report.json()),
+        None => {}
+    }
+    // imageops::flip_vertical_in_place(&mut zbuffer);
+    // zbuffer.save("debug.tga")?;
+
+    Ok(())
+}
+
+// The path tracer's frame, its rows split between the cores. Each row draws its own random
+// numbers seeded by where it is, so the same scene always gives the same image. Encoded in
+// space, like the rasterizer's frame, and the right way up to be saved.
+fn path_trace(
+    tracer: &pathtrace::PathTracer,
+    camera: &camera::Camera,
+    (width, height): (u32, u32),
+    samples: usize,
+    space: texture::ColorSpace,
+    progress: renderer::Progress,
+) -> RgbImage {
+    let done = AtomicUsize::new(0);
+    let rows: Vec<Vec<Rgb<u8>>> = (0..height)
+        .into_par_iter()
+        .map(|y| {
+            let mut rng = StdRng::seed_from_u64(y as u64);
+            let row = (0..width)
+                .map(|x| texture::encode(tracer.pixel(camera, (x, y), samples, &mut rng), space))
+                .collect();
+            progress(done.fetch_add(1, Ordering::Relaxed) + 1, height as usize);
+            row
+        })
+        .collect();
+    // (0,0) is the bottom left
+    RgbImage::from_fn(width, height, |x, y| {
+        rows[(height - 1 - y) as usize][x as usize]
+    })
+}
 
+// Renders every frame of a flythrough by running this renderer again at its time, into
+// numbered files named after output, e.g. output_0000.tga, output_0001.tga and on. The
+// frame number goes to --frame too, for labels and vertex animations.
+fn render_camera_path(argv: &[String], keyframes: &str, fps: f32, output: &str) -> Result<()> {
+    let path = camera_path::CameraPath::load(keyframes)?;
+    let frames = (path.duration() * fps).floor() as u32 + 1;
+    let mut base = Vec::new();
+    let mut args = argv.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" | "--frame" => {
+                args.next();
+            }
+            _ => base.push(arg.clone()),
+        }
+    }
+    let output = Path::new(output);
+    let stem = output
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("output");
+    let extension = output.extension().and_then(|e| e.to_str());
+    for frame in 0..frames {
+        let name = match extension {
+            Some(extension) => format!("{}_{:04}.{}", stem, frame, extension),
+            None => format!("{}_{:04}", stem, frame),
+        };
+        let filename = output.with_file_name(name);
+        let time = frame as f32 / fps;
+        let status = std::process::Command::new(std::env::current_exe()?)
+            .args(&base)
+            .args(["--path-time", &time.to_string()])
+            .args(["--frame", &frame.to_string()])
+            .arg("--output")
+            .arg(&filename)
+            .status()?;
+        if !status.success() {
+            return Err(anyhow!("rendering frame {} failed: {}", frame, status));
+        }
+        tracing::info!(frame, time, file = %filename.display(), "rendered");
+    }
     Ok(())
 }
+
+// Renders an equirectangular panorama of everything around the eye. The faces of a cube
+// map that turns with the camera are rendered first, a quarter of the width across so the
+// panorama's equator doesn't lose detail, then unwrapped into a width x height image.
+fn render_panorama(
+    argv: &[String],
+    width: u32,
+    height: u32,
+    output: &output::Output,
+) -> Result<()> {
+    let size = (width / 4).max(height / 2).max(1);
+    let scratch = Scratch::new("panorama")?;
+    let faces = render_cube_faces(&without_cube_flags(argv), size, &scratch.0.join("face"))?;
+    output.save(&faces.equirectangular(width, height))
+}
+
+// Renders size x size faces seen from point with the world's axes, e.g. to reflect with
+// --envmap or to see what a light at point sees. They are saved as a cross, and each on its
+// own as output_px.tga to output_nz.tga next to output, which --envmap output reads back.
+fn render_cube_map(
+    argv: &[String],
+    point: Vector3<f32>,
+    size: u32,
+    output: &output::Output,
+) -> Result<()> {
+    let mut base = without_cube_flags(argv);
+    base.push(String::from("--camera"));
+    base.push(format!(
+        "eye={},{},{};target={},{},{};up=0,1,0",
+        point.x,
+        point.y,
+        point.z,
+        point.x,
+        point.y,
+        point.z - 1.0
+    ));
+    let path = Path::new(&output.path);
+    let prefix = path.with_file_name(
+        path.file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("output"),
+    );
+    let faces = render_cube_faces(&base, size, &prefix)?;
+    output.save(&faces.to_cross())
+}
+
+// Runs this renderer again for each face of a cube map around the camera's eye, into
+// prefix_px.tga to prefix_nz.tga, and reads them back.
+fn render_cube_faces(base: &[String], size: u32, prefix: &Path) -> Result<cubemap::CubeMap> {
+    for face in cubemap::FACES {
+        let filename = format!("{}_{}.tga", prefix.display(), face);
+        let status = std::process::Command::new(std::env::current_exe()?)
+            .args(base)
+            .args(["--size", &format!("{}x{}", size, size)])
+            .args(["--cube-face", face])
+            .args(["--output", &filename])
+            .status()?;
+        if !status.success() {
+            return Err(anyhow!("rendering cube face {} failed: {}", face, status));
+        }
+        tracing::info!(face, file = filename, "rendered");
+    }
+    cubemap::CubeMap::load(&prefix.to_string_lossy())
+}
+
+// the command line minus what picks the panorama or cube map and where it is saved
+fn without_cube_flags(argv: &[String]) -> Vec<String> {
+    let mut base = Vec::new();
+    let mut args = argv.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--panorama" => {}
+            "--cube-map" | "--cube-face" | "--output" | "--format" | "--quality" | "--size" => {
+                args.next();
+            }
+            _ => base.push(arg.clone()),
+        }
+    }
+    base
+}
+
+// runs this renderer again with the extra arguments appended and reads back what it saved
+// to output
+fn render_variant(base: &[String], extra: &[String], output: &Path) -> Result<RgbImage> {
+    let status = std::process::Command::new(std::env::current_exe()?)
+        .args(base)
+        .args(extra)
+        .arg("--output")
+        .arg(output)
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("rendering with {:?} failed: {}", extra, status));
+    }
+    Ok(ImageReader::open(output)?.decode()?.to_rgb8())
+}
+
+// An argument list given as one argument, split at whitespace outside of quotes, e.g.
+// "--label 'soft shadows' --pcf 5"
+fn split_args(list: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for c in list.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => arg.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(arg.take()),
+            (None, c) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow!("unterminated quote in '{}'", list));
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+// A directory of its own under the system's temporary one, for what a run renders only to
+// read back. It is removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Result<Scratch> {
+        let dir =
+            std::env::temp_dir().join(format!("tinyrenderer-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        Ok(Scratch(dir))
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// The command line minus --compare and --divider, shared by both sides of a comparison.
+// Each side is saved somewhere of its own, so where the split goes is left out too.
+fn without_compare(argv: &[String]) -> Vec<String> {
+    let mut base = Vec::new();
+    let mut args = argv.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--compare" => {
+                args.nth(1);
+            }
+            "--divider" | "--output" | "--format" | "--quality" => {
+                args.next();
+            }
+            _ => base.push(arg.clone()),
+        }
+    }
+    base
+}
+
+fn label(extra: &str) -> String {
+    if extra.trim().is_empty() {
+        String::from("default")
+    } else {
+        String::from(extra.trim())
+    }
+}
+
+// which of a glTF model's animations and when, seconds from its start
+struct Pose {
+    animation: Option<String>,
+    time: Option<f32>,
+}
+
+// path.gltf or path.glb posed and skinned, or else path.obj
+fn load_model(path: &str, tolerant: bool, pose: &Pose) -> Result<model::Model> {
+    for extension in ["gltf", "glb"] {
+        let filename = format!("{}.{}", path, extension);
+        if !Path::new(&filename).is_file() {
+            continue;
+        }
+        let (mut model, rig) = gltf::file_to_model(&filename)?;
+        let animation = if pose.animation.is_some() || pose.time.is_some() {
+            Some(rig.animation(pose.animation.as_deref())?)
+        } else {
+            None
+        };
+        // baked before any pass like a vertex animation, so the shadow map moves with it
+        model.skin(&rig.joint_matrices(animation, pose.time.unwrap_or(0.0)));
+        return Ok(model);
+    }
+    let filename = format!("{}.obj", path);
+    if !tolerant {
+        return model::file_to_model(&filename);
+    }
+    let (model, skipped) = model::file_to_model_tolerant(&filename)?;
+    if skipped > 0 {
+        tracing::warn!(
+            file = filename,
+            skipped,
+            "skipped malformed lines or faces, --log debug lists them"
+        );
+    }
+    Ok(model)
+}
+
+// a colour given on the command line as r,g,b from 0 to 255
+fn parse_rgb(flag: &str, spec: &str) -> Result<Rgb<u8>> {
+    let channels = spec
+        .split(',')
+        .map(|c| c.parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()
+        .with_context(|| format!("{} expects a colour r,g,b from 0 to 255", flag))?;
+    Ok(Rgb(channels
+        .try_into()
+        .map_err(|_| anyhow!("{} expects three channels", flag))?))
+}
@@ -0,0 +1,176 @@
+use anyhow::Result;
+use cgmath::Vector3;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::io::{Error, ErrorKind};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use super::texture::ImageSource;
+
+// Which sides of a surface are lit. Meshes that are only one face thick or wound
+// inconsistently show their back, which One leaves black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sides {
+    #[default]
+    One,
+    // normals facing away from the camera are turned around, both sides look the same
+    Flip,
+    // as Flip and light reaching the back shows through, like leaves or paper
+    Two,
+}
+
+impl std::str::FromStr for Sides {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Sides> {
+        match s {
+            "one" => Ok(Sides::One),
+            "flip" => Ok(Sides::Flip),
+            "two" => Ok(Sides::Two),
+            _ => Err(anyhow::anyhow!("unknown sides '{}'", s)),
+        }
+    }
+}
+
+// the subset of a wavefront .mtl material the shaders understand
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub kd: Vector3<f32>, // diffuse colour, used when there is no diffuse texture
+    pub ks: Vector3<f32>, // specular colour
+    pub ns: f32,          // specular exponent, used when there is no specular map
+    pub d: f32,           // opacity ("dissolve"), drawn as a dither pattern when below 1
+    // textures, see texture::load_maps()
+    pub map_kd: Option<ImageSource>,
+    pub map_ks: Option<ImageSource>,
+    pub map_ns: Option<ImageSource>,
+    pub map_d: Option<ImageSource>,
+    pub map_bump: Option<ImageSource>, // a tangent space normal map, not a height map
+    // not part of the format, "sides one|flip|two" lines. None leaves it to --sides
+    pub sides: Option<Sides>,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: String::from(name),
+            kd: Vector3::new(0.8, 0.8, 0.8),
+            ks: Vector3::new(0.0, 0.0, 0.0),
+            ns: 1.0,
+            d: 1.0,
+            map_kd: None,
+            map_ks: None,
+            map_ns: None,
+            map_d: None,
+            map_bump: None,
+            sides: None,
+        }
+    }
+}
+
+fn parse_color<'a>(mut iter: impl Iterator<Item = &'a str>) -> Result<Vector3<f32>> {
+    let mut next = || -> Result<f32> {
+        Ok(iter
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "mtl file colour line malformed"))?
+            .parse::<f32>()?)
+    };
+    Ok(Vector3::new(next()?, next()?, next()?))
+}
+
+fn parse_scalar<'a>(mut iter: impl Iterator<Item = &'a str>, keyword: &str) -> Result<f32> {
+    Ok(iter
+        .next()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("mtl file '{}' line malformed", keyword),
+            )
+        })?
+        .parse::<f32>()?)
+}
+
+// textures are named relative to the mtl file
+#[cfg(not(target_arch = "wasm32"))]
+pub fn file_to_materials(filename: &str) -> Result<Vec<Material>> {
+    let mut materials = mtl_to_materials(&fs::read_to_string(filename)?)?;
+    let resolve = |map: &mut Option<ImageSource>| {
+        if let Some(ImageSource::File(name)) = map {
+            *name = Path::new(filename)
+                .with_file_name(&*name)
+                .to_string_lossy()
+                .into_owned();
+        }
+    };
+    for m in materials.iter_mut() {
+        for map in [
+            &mut m.map_kd,
+            &mut m.map_ks,
+            &mut m.map_ns,
+            &mut m.map_d,
+            &mut m.map_bump,
+        ] {
+            resolve(map);
+        }
+    }
+    Ok(materials)
+}
+
+// there are no files in the browser, models there come without their materials
+#[cfg(target_arch = "wasm32")]
+pub fn file_to_materials(_filename: &str) -> Result<Vec<Material>> {
+    Ok(Vec::new())
+}
+
+pub fn mtl_to_materials(mtl: &str) -> Result<Vec<Material>> {
+    let mut materials: Vec<Material> = Vec::new();
+
+    for l in mtl.lines() {
+        let mut iter = l.split_ascii_whitespace();
+        let keyword = match iter.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = iter.next().ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, "mtl file 'newmtl' line malformed")
+            })?;
+            materials.push(Material::new(name));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue, // properties before any newmtl have nothing to attach to
+        };
+        match keyword {
+            "Kd" => material.kd = parse_color(iter)?,
+            "Ks" => material.ks = parse_color(iter)?,
+            "Ns" => material.ns = parse_scalar(iter, "Ns")?,
+            "d" => material.d = parse_scalar(iter, "d")?,
+            // transparency, the inverse of d
+            "Tr" => material.d = 1.0 - parse_scalar(iter, "Tr")?,
+            // options such as -s come before the file name
+            "map_Kd" => material.map_kd = iter.last().map(ImageSource::file),
+            "map_Ks" => material.map_ks = iter.last().map(ImageSource::file),
+            "map_Ns" => material.map_ns = iter.last().map(ImageSource::file),
+            "map_d" => material.map_d = iter.last().map(ImageSource::file),
+            "map_Bump" | "map_bump" | "bump" | "norm" => {
+                material.map_bump = iter.last().map(ImageSource::file)
+            }
+            "sides" => {
+                material.sides = Some(
+                    iter.next()
+                        .ok_or_else(|| {
+                            Error::new(ErrorKind::InvalidData, "mtl file 'sides' line malformed")
+                        })?
+                        .parse()?,
+                )
+            }
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
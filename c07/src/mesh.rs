@@ -0,0 +1,459 @@
+use anyhow::Result;
+use cgmath::{InnerSpace, Matrix3, SquareMatrix, Vector3};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::f32::consts::PI;
+
+use super::model::{self, Model};
+
+// A unit sphere around the origin, an icosahedron with every triangle split in four
+// subdivisions times, so it has 20 * 4^subdivisions faces. Built as obj text and read back
+// like any other file so it gets the same welding and tangents.
+pub fn sphere(subdivisions: u32) -> Result<Model> {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut verts: Vec<Vector3<f32>> = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|&(x, y, z)| Vector3::new(x, y, z).normalize())
+    .collect();
+    let mut faces: Vec<[usize; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // edges are shared by two faces, both get the same midpoint
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                verts.push(((verts[a] + verts[b]) / 2.0).normalize());
+                verts.len() - 1
+            })
+        };
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut obj = String::new();
+    for v in &verts {
+        obj += &format!("v {} {} {}\n", v.x, v.y, v.z);
+    }
+    // longitude and latitude, on a unit sphere the normals are the positions
+    for v in &verts {
+        let u = 0.5 + v.z.atan2(v.x) / (2.0 * PI);
+        let w = 0.5 + v.y.asin() / PI;
+        obj += &format!("vt {} {}\n", u, w);
+    }
+    for v in &verts {
+        obj += &format!("vn {} {} {}\n", v.x, v.y, v.z);
+    }
+    for face in &faces {
+        obj += "f";
+        for i in face {
+            obj += &format!(" {0}/{0}/{0}", i + 1);
+        }
+        obj += "\n";
+    }
+    Ok(model::obj_to_model(&obj, "sphere", false)?.0)
+}
+
+// how much more moving a point off the border of an open mesh costs than off its surface,
+// so holes and the edges of planes keep their outline
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+// Garland and Heckbert's quadric error: the summed squared distances of a point to the planes
+// of the faces around it, kept as the ten distinct entries of the symmetric 4x4 matrix.
+// In f64 as the sums cancel a lot.
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // the squared distance to the plane through p with unit normal n, times weight
+    fn plane(n: Vector3<f64>, p: Vector3<f64>, weight: f64) -> Quadric {
+        let (a, b, c, d) = (n.x, n.y, n.z, -n.dot(p));
+        Quadric(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|q| q * weight),
+        )
+    }
+
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x
+            + q[4] * y * y
+            + q[7] * z * z
+            + 2.0 * (q[1] * x * y + q[2] * x * z + q[5] * y * z)
+            + 2.0 * (q[3] * x + q[6] * y + q[8] * z)
+            + q[9]
+    }
+
+    // where the error is least, None when that isn't a single point, e.g. on a flat patch
+    fn minimum(&self) -> Option<Vector3<f64>> {
+        let q = &self.0;
+        let m = Matrix3::new(q[0], q[1], q[2], q[1], q[4], q[5], q[2], q[5], q[7]);
+        // tiny against the size of the entries, the inverse would be all rounding
+        if m.determinant().abs() <= 1e-9 * m.trace().powi(3).abs() {
+            return None;
+        }
+        Some(m.invert()? * -Vector3::new(q[3], q[6], q[8]))
+    }
+}
+
+impl std::ops::Add for Quadric {
+    type Output = Quadric;
+
+    fn add(self, other: Quadric) -> Quadric {
+        let mut sum = self.0;
+        for (s, o) in sum.iter_mut().zip(other.0) {
+            *s += o;
+        }
+        Quadric(sum)
+    }
+}
+
+// an edge to collapse, the cheapest comes off the heap first
+struct Collapse {
+    cost: f64,
+    points: [usize; 2],
+    // of the points when it was costed, it is stale once either has changed
+    stamps: [u32; 2],
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+// The state of simplify(), faces are split into triangles and vertices gathered into
+// points by position
+struct Simplifier {
+    tris: Vec<[usize; 3]>, // of the model's vertices
+    alive: Vec<bool>,
+    point_of: Vec<Option<usize>>, // for each of the model's vertices, None when unused
+    point_verts: Vec<usize>,      // how many vertices each point was made from
+    pos: Vec<Vector3<f64>>,
+    quadrics: Vec<Quadric>,
+    point_tris: Vec<Vec<usize>>, // may still hold triangles that have collapsed
+    stamps: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(model: &Model, tris: Vec<[usize; 3]>) -> Simplifier {
+        let positions = model.get_positions();
+        let mut points: HashMap<usize, usize> = HashMap::new();
+        let mut point_of = vec![None; model.get_verts().len()];
+        let mut point_verts = Vec::new();
+        let mut pos = Vec::new();
+        for &v in tris.iter().flatten() {
+            if point_of[v].is_some() {
+                continue;
+            }
+            let point = *points.entry(positions[v]).or_insert_with(|| {
+                point_verts.push(0);
+                pos.push(
+                    model.get_verts()[v]
+                        .cast()
+                        .unwrap_or(Vector3::new(0.0, 0.0, 0.0)),
+                );
+                pos.len() - 1
+            });
+            point_of[v] = Some(point);
+            point_verts[point] += 1;
+        }
+        let mut simplifier = Simplifier {
+            alive: vec![true; tris.len()],
+            point_of,
+            point_verts,
+            quadrics: vec![Quadric::default(); pos.len()],
+            point_tris: vec![Vec::new(); pos.len()],
+            stamps: vec![0; pos.len()],
+            pos,
+            tris,
+            heap: BinaryHeap::new(),
+        };
+        simplifier.start();
+        simplifier
+    }
+
+    fn point(&self, v: usize) -> usize {
+        self.point_of[v].unwrap_or_default()
+    }
+
+    fn corners(&self, t: usize) -> [usize; 3] {
+        self.tris[t].map(|v| self.point(v))
+    }
+
+    // the quadrics of the faces and borders, and every edge ready to collapse
+    fn start(&mut self) {
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for t in 0..self.tris.len() {
+            let [a, b, c] = self.corners(t);
+            for p in [a, b, c] {
+                self.point_tris[p].push(t);
+            }
+            let n = (self.pos[b] - self.pos[a]).cross(self.pos[c] - self.pos[a]);
+            if n.magnitude2() > 0.0 {
+                // weighted by area
+                let plane = Quadric::plane(n.normalize(), self.pos[a], n.magnitude() / 2.0);
+                for p in [a, b, c] {
+                    self.quadrics[p] = self.quadrics[p] + plane;
+                }
+            }
+            for (p, q) in [(a, b), (b, c), (c, a)] {
+                *edges.entry((p.min(q), p.max(q))).or_default() += 1;
+            }
+        }
+        // a border edge is held by a plane standing on it across its face
+        for t in 0..self.tris.len() {
+            let [a, b, c] = self.corners(t);
+            let n = (self.pos[b] - self.pos[a]).cross(self.pos[c] - self.pos[a]);
+            for (p, q) in [(a, b), (b, c), (c, a)] {
+                let edge = self.pos[q] - self.pos[p];
+                let across = edge.cross(n);
+                if edges[&(p.min(q), p.max(q))] == 1 && across.magnitude2() > 0.0 {
+                    let weight = BOUNDARY_WEIGHT * edge.magnitude2();
+                    let plane = Quadric::plane(across.normalize(), self.pos[p], weight);
+                    self.quadrics[p] = self.quadrics[p] + plane;
+                    self.quadrics[q] = self.quadrics[q] + plane;
+                }
+            }
+        }
+        for (a, b) in edges.into_keys() {
+            self.push([a, b]);
+        }
+    }
+
+    // Which of a pair of points stays, where it goes and what that costs. The one removed
+    // has a single vertex, the one kept stays put when it has more.
+    fn plan(&self, [a, b]: [usize; 2]) -> Option<(usize, usize, Vector3<f64>, f64)> {
+        let (keep, remove) = match (self.point_verts[a], self.point_verts[b]) {
+            (_, 1) => (a, b),
+            (1, _) => (b, a),
+            _ => return None,
+        };
+        let q = self.quadrics[a] + self.quadrics[b];
+        let to = if self.point_verts[keep] > 1 {
+            self.pos[keep]
+        } else {
+            q.minimum().unwrap_or_else(|| {
+                let (pa, pb) = (self.pos[a], self.pos[b]);
+                [pa, pb, (pa + pb) / 2.0]
+                    .into_iter()
+                    .min_by(|x, y| q.error(*x).total_cmp(&q.error(*y)))
+                    .unwrap_or(pa)
+            })
+        };
+        Some((keep, remove, to, q.error(to).max(0.0)))
+    }
+
+    fn push(&mut self, points: [usize; 2]) {
+        if let Some((_, _, _, cost)) = self.plan(points) {
+            self.heap.push(Collapse {
+                cost,
+                points,
+                stamps: points.map(|p| self.stamps[p]),
+            });
+        }
+    }
+
+    fn neighbours(&self, p: usize) -> HashSet<usize> {
+        self.point_tris[p]
+            .iter()
+            .flat_map(|&t| self.corners(t))
+            .filter(|&n| n != p)
+            .collect()
+    }
+
+    // Merges the points unless that would pinch the surface or fold a face over, gives the
+    // number of triangles gone
+    fn collapse(&mut self, points: [usize; 2]) -> usize {
+        let Some((keep, remove, to, _)) = self.plan(points) else {
+            return 0;
+        };
+        let alive = &self.alive;
+        self.point_tris[keep].retain(|&t| alive[t]);
+        self.point_tris[remove].retain(|&t| alive[t]);
+        let shared: Vec<usize> = self.point_tris[remove]
+            .iter()
+            .copied()
+            .filter(|&t| self.corners(t).contains(&keep))
+            .collect();
+        // the points around both are the corners of the faces on the edge, or more than
+        // two faces would end up on one edge
+        let common = self
+            .neighbours(keep)
+            .intersection(&self.neighbours(remove))
+            .count();
+        if shared.is_empty() || common != shared.len() {
+            return 0;
+        }
+        let moved = |p: usize| {
+            if p == keep || p == remove {
+                to
+            } else {
+                self.pos[p]
+            }
+        };
+        let folds = self.point_tris[keep]
+            .iter()
+            .chain(&self.point_tris[remove])
+            .filter(|t| !shared.contains(t))
+            .any(|&t| {
+                let [a, b, c] = self.corners(t).map(|p| self.pos[p]);
+                let [a2, b2, c2] = self.corners(t).map(moved);
+                (b - a).cross(c - a).dot((b2 - a2).cross(c2 - a2)) <= 0.0
+            });
+        if folds {
+            return 0;
+        }
+
+        let kept_vertex = self.tris[shared[0]]
+            .into_iter()
+            .find(|&v| self.point(v) == keep)
+            .unwrap_or_default();
+        for &t in &shared {
+            self.alive[t] = false;
+        }
+        for t in std::mem::take(&mut self.point_tris[remove]) {
+            if self.alive[t] {
+                for c in 0..3 {
+                    if self.point(self.tris[t][c]) == remove {
+                        self.tris[t][c] = kept_vertex;
+                    }
+                }
+                self.point_tris[keep].push(t);
+            }
+        }
+        self.pos[keep] = to;
+        self.quadrics[keep] = self.quadrics[keep] + self.quadrics[remove];
+        self.stamps[keep] += 1;
+        self.stamps[remove] += 1;
+        for n in self.neighbours(keep) {
+            self.push([keep, n]);
+        }
+        shared.len()
+    }
+}
+
+// The model with edges collapsed one at a time, the one moving the surface least first,
+// until it has no more than target triangles or nothing is left that can go. Vertices welded
+// from the same position collapse as one point so uv seams stay closed. A point split
+// between several vertices, on a seam or a hard edge, is never moved or removed, others
+// collapse into it. Collapses that would fold a face over or pinch the surface are
+// skipped. Polygons are split into the fan of triangles they are drawn with, kept vertices
+// keep their normals and uvs.
+pub fn simplify(model: &Model, target: usize) -> Model {
+    let mut tris = Vec::new();
+    let mut sources = Vec::new();
+    for (i, face) in model.get_faces().iter().enumerate() {
+        for pair in face.get(1..).unwrap_or_default().windows(2) {
+            tris.push([face[0], pair[0], pair[1]]);
+            sources.push(i);
+        }
+    }
+    let mut simplifier = Simplifier::new(model, tris);
+    let mut remaining = simplifier.tris.len();
+    while remaining > target {
+        let Some(collapse) = simplifier.heap.pop() else {
+            break;
+        };
+        // stale once either point has changed since it was costed
+        if collapse.points.map(|p| simplifier.stamps[p]) == collapse.stamps {
+            remaining -= simplifier.collapse(collapse.points);
+        }
+    }
+
+    let mut verts = model.get_verts().clone();
+    for (vert, point) in verts.iter_mut().zip(&simplifier.point_of) {
+        if let Some(p) = point {
+            *vert = simplifier.pos[*p].cast().unwrap_or(*vert);
+        }
+    }
+    let faces = simplifier
+        .tris
+        .iter()
+        .zip(sources)
+        .zip(&simplifier.alive)
+        .filter(|(_, &alive)| alive)
+        .map(|((tri, source), _)| (source, tri.to_vec()))
+        .collect();
+    model.remeshed(verts, faces)
+}
+
+// The model and levels coarser versions of it, each with about half the triangles of the
+// one before. Fewer when simplify() can't take any more away.
+pub fn lods(model: Model, levels: usize) -> Vec<Model> {
+    let mut lods = vec![model];
+    for _ in 0..levels {
+        let last = &lods[lods.len() - 1];
+        let lod = simplify(last, last.triangle_count() / 2);
+        if lod.triangle_count() == last.triangle_count() {
+            break;
+        }
+        lods.push(lod);
+    }
+    lods
+}
@@ -1,27 +1,58 @@
+use super::material::{self, Material};
+use super::our_gl::Instance;
 use anyhow::Result;
-use cgmath::{InnerSpace, Vector2, Vector3};
+use cgmath::{InnerSpace, Matrix3, Matrix4, SquareMatrix, Vector2, Vector3, Vector4};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
 use std::fs;
-use std::io::{Error, ErrorKind};
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
 
-#[derive(Debug)]
-pub struct VertexInfo {
-    pub v: usize,
-    pub vt: usize,
+// how far from unit length a normal can be before it is normalized on load
+const NORMAL_TOLERANCE: f32 = 1e-6;
+
+// the group of faces listed before any 'o' or 'g' line, and of models that have none
+pub const DEFAULT_GROUP: &str = "default";
+
+// a face corner as the obj file gives it, vn is None when the face leaves it out
+struct VertexInfo {
+    v: usize,
+    vt: usize,
+    vn: Option<usize>,
 }
 
-#[derive(Debug)]
+// Every vertex has one of each attribute, faces index all of them at once.
+#[derive(Debug, Clone)]
 pub struct Model {
-    verts: Vec<Vector3<f32>>, // access specific norms via VertexInfo.v
-    norms: Vec<Vector3<f32>>, // access specific norms via VertexInfo.v
+    verts: Vec<Vector3<f32>>,
+    norms: Vec<Vector3<f32>>,
+    // like norms, w is the sign of the bitangent against cross(normal, tangent)
+    tangents: Vec<Vector4<f32>>,
     uvs: Vec<Vector2<f32>>,
-    faces: Vec<Vec<VertexInfo>>,
+    positions: Vec<usize>, // the obj file 'v' each vertex was welded from
+    // up to four joints moving each vertex and how much, empty for models without a skin.
+    // Joints index the skinning matrices of the file the model was read from.
+    joints: Vec<[usize; 4]>,
+    weights: Vec<[f32; 4]>,
+    faces: Vec<Vec<usize>>,
+    materials: Vec<Material>,
+    face_materials: Vec<Option<usize>>, // index into materials for each face
+    // which of the models appended together each face came from, counting from 0
+    face_objects: Vec<usize>,
+    objects: usize,
+    // how transform() has turned each object's normals since it was read, object space
+    // normal maps are turned the same way
+    object_normals: Vec<Matrix3<f32>>,
+    // the obj 'o' and 'g' names faces were listed under, in the order they first appear
+    groups: Vec<String>,
+    face_groups: Vec<usize>,
 }
 
 impl Model {
     pub fn get_verts(&self) -> &Vec<Vector3<f32>> {
         &self.verts
     }
-    pub fn get_faces(&self) -> &Vec<Vec<VertexInfo>> {
+    pub fn get_faces(&self) -> &Vec<Vec<usize>> {
         &self.faces
     }
     pub fn get_uvs(&self) -> &Vec<Vector2<f32>> {
@@ -30,111 +61,751 @@ impl Model {
     pub fn get_norms(&self) -> &Vec<Vector3<f32>> {
         &self.norms
     }
+    pub fn get_tangents(&self) -> &Vec<Vector4<f32>> {
+        &self.tangents
+    }
+    pub fn get_joints(&self) -> &Vec<[usize; 4]> {
+        &self.joints
+    }
+    pub fn get_weights(&self) -> &Vec<[f32; 4]> {
+        &self.weights
+    }
+    // vertices with the same one were welded from the same position, e.g. either side of a
+    // uv seam
+    pub fn get_positions(&self) -> &Vec<usize> {
+        &self.positions
+    }
+    pub fn get_material(&self, iface: usize) -> Option<&Material> {
+        self.face_materials[iface].map(|m| &self.materials[m])
+    }
+    pub fn get_materials(&self) -> &Vec<Material> {
+        &self.materials
+    }
+    pub fn get_object(&self, iface: usize) -> usize {
+        self.face_objects[iface]
+    }
+    pub fn object_count(&self) -> usize {
+        self.objects
+    }
+    pub fn get_object_normals(&self, object: usize) -> Matrix3<f32> {
+        self.object_normals[object]
+    }
+    pub fn get_group(&self, iface: usize) -> usize {
+        self.face_groups[iface]
+    }
+    pub fn group_names(&self) -> &Vec<String> {
+        &self.groups
+    }
+    // every group called name, objects appended together can each have one
+    pub fn groups_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+        (0..self.groups.len()).filter(move |&g| self.groups[g] == name)
+    }
+    // axis aligned bounding box of the vertices
+    pub fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for v in &self.verts {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(v[axis]);
+                max[axis] = max[axis].max(v[axis]);
+            }
+        }
+        (min, max)
+    }
+
+    // centre and radius of a sphere around every vertex, loose but cheap to test
+    pub fn bounding_sphere(&self) -> (Vector3<f32>, f32) {
+        let (min, max) = self.bounds();
+        ((min + max) / 2.0, (max - min).magnitude() / 2.0)
+    }
+    // polygons count as the fan of triangles they are drawn with
+    pub fn triangle_count(&self) -> usize {
+        self.faces.iter().map(|f| f.len().saturating_sub(2)).sum()
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        self.triangles()
+            .map(|[a, b, c]| triangle_area(a, b, c))
+            .sum()
+    }
+
+    // Centre of the surface, each triangle weighted by its area so welded seams and densely
+    // tessellated areas don't pull it around like they would an average of the vertices.
+    // Falls back to the centre of the bounds when there's no area.
+    pub fn centroid(&self) -> Vector3<f32> {
+        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+        let mut area = 0.0;
+        for [a, b, c] in self.triangles() {
+            let weight = triangle_area(a, b, c);
+            sum += (a + b + c) / 3.0 * weight;
+            area += weight;
+        }
+        if area.is_normal() {
+            sum / area
+        } else {
+            self.bounding_sphere().0
+        }
+    }
+
+    fn triangles(&self) -> impl Iterator<Item = [Vector3<f32>; 3]> + '_ {
+        self.faces.iter().flat_map(move |face| {
+            face[1..]
+                .windows(2)
+                .map(move |pair| [face[0], pair[0], pair[1]].map(|v| self.verts[v]))
+        })
+    }
+
+    // Bakes a model matrix into the vertices, they end up where an instance with it would
+    // draw them.
+    pub fn transform(&mut self, m: Matrix4<f32>) {
+        let instance = Instance::new(m);
+        for vert in self.verts.iter_mut() {
+            *vert = instance.position(*vert);
+        }
+        for n in self.norms.iter_mut() {
+            *n = instance.normal(*n);
+        }
+        for t in self.tangents.iter_mut() {
+            *t = instance.tangent(*t);
+        }
+        for m in self.object_normals.iter_mut() {
+            *m = instance.normal_matrix() * *m;
+        }
+    }
+
+    // Adds other's faces to this model as the next object, so everything can be drawn
+    // in one go.
+    pub fn append(&mut self, other: Model) {
+        let verts = self.verts.len();
+        let materials = self.materials.len();
+        // positions only mean something within the file they were read from
+        let positions = self.positions.iter().max().map_or(0, |p| p + 1);
+        // a model without a skin keeps its vertices where they are, weighted by nothing
+        if !self.weights.is_empty() || !other.weights.is_empty() {
+            self.joints.resize(verts, [0; 4]);
+            self.weights.resize(verts, [0.0; 4]);
+            self.joints.extend(other.joints);
+            self.weights.extend(other.weights);
+            self.joints.resize(verts + other.verts.len(), [0; 4]);
+            self.weights.resize(verts + other.verts.len(), [0.0; 4]);
+        }
+        self.verts.extend(other.verts);
+        self.norms.extend(other.norms);
+        self.tangents.extend(other.tangents);
+        self.uvs.extend(other.uvs);
+        self.positions
+            .extend(other.positions.iter().map(|p| p + positions));
+        self.faces.extend(
+            other
+                .faces
+                .into_iter()
+                .map(|face| face.into_iter().map(|v| v + verts).collect()),
+        );
+        self.materials.extend(other.materials);
+        self.face_materials.extend(
+            other
+                .face_materials
+                .iter()
+                .map(|m| m.map(|m| m + materials)),
+        );
+        self.face_objects
+            .extend(other.face_objects.iter().map(|o| o + self.objects));
+        self.objects += other.objects;
+        self.object_normals.extend(other.object_normals);
+        self.face_groups
+            .extend(other.face_groups.iter().map(|g| g + self.groups.len()));
+        self.groups.extend(other.groups);
+    }
+
+    // Drops the faces keep() turns down, given their index. The vertices stay, faces
+    // don't have to be renumbered.
+    pub fn retain_faces<F: FnMut(usize) -> bool>(&mut self, keep: F) {
+        let keep: Vec<bool> = (0..self.faces.len()).map(keep).collect();
+        retain_by(&mut self.faces, &keep);
+        retain_by(&mut self.face_materials, &keep);
+        retain_by(&mut self.face_objects, &keep);
+        retain_by(&mut self.face_groups, &keep);
+    }
+
+    // A copy with its vertices moved to verts and faces in place of its own, each made of
+    // this model's vertices and given with the face it takes its material, object and group
+    // from. Vertices no face uses anymore are kept. For simplified meshes.
+    pub fn remeshed(&self, verts: Vec<Vector3<f32>>, faces: Vec<(usize, Vec<usize>)>) -> Model {
+        let (sources, faces): (Vec<usize>, Vec<Vec<usize>>) = faces.into_iter().unzip();
+        Model {
+            verts,
+            norms: self.norms.clone(),
+            tangents: self.tangents.clone(),
+            uvs: self.uvs.clone(),
+            positions: self.positions.clone(),
+            joints: self.joints.clone(),
+            weights: self.weights.clone(),
+            faces,
+            materials: self.materials.clone(),
+            face_materials: sources.iter().map(|&f| self.face_materials[f]).collect(),
+            face_objects: sources.iter().map(|&f| self.face_objects[f]).collect(),
+            objects: self.objects,
+            object_normals: self.object_normals.clone(),
+            groups: self.groups.clone(),
+            face_groups: sources.iter().map(|&f| self.face_groups[f]).collect(),
+        }
+    }
+
+    // materials for models read without any, face_materials indexes them for each face
+    pub fn set_materials(&mut self, materials: Vec<Material>, face_materials: Vec<Option<usize>>) {
+        self.materials = materials;
+        self.face_materials = face_materials;
+    }
+
+    // Moves every vertex by offset(index of its 'v' in the obj file) so vertices welded
+    // from the same position move together. Normals are left as they are.
+    pub fn displace<F: Fn(usize) -> Vector3<f32>>(&mut self, offset: F) {
+        for (vert, &position) in self.verts.iter_mut().zip(&self.positions) {
+            *vert += offset(position);
+        }
+    }
+
+    // Moves every vertex along its normal by height(its uv), for terrain or detail carved
+    // from a height map. Vertices welded from the same position move together by the
+    // average of their offsets so seams in the uvs don't open cracks. Normals are made up
+    // again from the moved faces, vertices that shared one before still do.
+    pub fn displace_by_height<F: Fn(Vector2<f32>) -> f32>(&mut self, height: F) {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let positions = self.positions.iter().max().map_or(0, |&p| p + 1);
+        let mut offsets = vec![(zero, 0); positions];
+        for v in 0..self.verts.len() {
+            let (sum, count) = &mut offsets[self.positions[v]];
+            *sum += self.norms[v] * height(self.uvs[v]);
+            *count += 1;
+        }
+        for (vert, &p) in self.verts.iter_mut().zip(&self.positions) {
+            let (sum, count) = offsets[p];
+            *vert += sum / count as f32;
+        }
+
+        // vertices split only by their uvs sum their faces' normals together, hard edges
+        // stay hard
+        let mut shared: HashMap<(usize, [u32; 3]), usize> = HashMap::new();
+        let corners: Vec<usize> = (0..self.verts.len())
+            .map(|v| {
+                let n = self.norms[v];
+                let next = shared.len();
+                *shared
+                    .entry((
+                        self.positions[v],
+                        [n.x.to_bits(), n.y.to_bits(), n.z.to_bits()],
+                    ))
+                    .or_insert(next)
+            })
+            .collect();
+        let mut norms = vec![zero; shared.len()];
+        for face in &self.faces {
+            let origin = self.verts[face[0]];
+            for pair in face[1..].windows(2) {
+                // twice the area of the triangle, along its normal
+                let n = (self.verts[pair[0]] - origin).cross(self.verts[pair[1]] - origin);
+                for v in [face[0], pair[0], pair[1]] {
+                    norms[corners[v]] += n;
+                }
+            }
+        }
+        normalize_sums(&mut norms);
+        self.norms = corners.iter().map(|&c| norms[c]).collect();
+        self.tangents = vertex_tangents(self);
+    }
+
+    // Linear blend skinning: every vertex is taken along by its joints' matrices, mixed by
+    // its weights. Vertices without weights, or with joints past the end of
+    // joint_matrices, stay where they are.
+    pub fn skin(&mut self, joint_matrices: &[Matrix4<f32>]) {
+        for (v, (joints, weights)) in self.joints.iter().zip(&self.weights).enumerate() {
+            let total: f32 = weights.iter().sum();
+            if !total.is_normal() || joints.iter().any(|&j| j >= joint_matrices.len()) {
+                continue;
+            }
+            let m = joints
+                .iter()
+                .zip(weights)
+                .map(|(&j, &w)| joint_matrices[j] * (w / total))
+                .fold(Matrix4::from_scale(0.0), |sum, m| sum + m);
+            let instance = Instance::new(m);
+            self.verts[v] = instance.position(self.verts[v]);
+            self.norms[v] = instance.normal(self.norms[v]);
+            self.tangents[v] = instance.tangent(self.tangents[v]);
+        }
+    }
+}
+
+// keeps the items whose keep is true, a list of faces and what goes along with them
+fn retain_by<T>(items: &mut Vec<T>, keep: &[bool]) {
+    let mut kept = keep.iter();
+    items.retain(|_| *kept.next().unwrap_or(&false));
+}
+
+fn triangle_area(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> f32 {
+    (b - a).cross(c - a).magnitude() / 2.0
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub fn file_to_model(filename: &str) -> Result<Model> {
+    let obj = fs::read_to_string(filename)?;
+    Ok(obj_to_model(&obj, filename, false)?.0)
+}
+
+// Like file_to_model() but lines that can't be read and faces with bad indices are
+// skipped instead of failing the load, for real world files. Also gives the number skipped.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn file_to_model_tolerant(filename: &str) -> Result<(Model, usize)> {
+    let obj = fs::read_to_string(filename)?;
+    obj_to_model(&obj, filename, true)
+}
+
+fn malformed(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn parse_floats<'a, const N: usize>(
+    mut iter: impl Iterator<Item = &'a str>,
+    message: &str,
+) -> Result<[f32; N]> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        *value = iter
+            .next()
+            .ok_or_else(|| malformed(message))?
+            .parse::<f32>()?;
+    }
+    Ok(values)
+}
+
+// obj indices count from 1, negative ones count back from the last element defined so far
+fn parse_index(index: &str, defined: usize) -> Result<usize> {
+    let index = index.parse::<i64>()?;
+    let resolved = match index {
+        0 => None,
+        i if i > 0 => Some(i - 1),
+        i => Some(defined as i64 + i),
+    };
+    match resolved {
+        Some(i) if i >= 0 => Ok(i as usize),
+        _ => Err(malformed("obj file 'f' index out of range").into()),
+    }
+}
+
+// Reads obj text, filename is only used to find material libraries. In tolerant mode
+// every line or face that would be an error is skipped and counted instead.
+pub fn obj_to_model(obj: &str, filename: &str, tolerant: bool) -> Result<(Model, usize)> {
     let mut model = Model {
         verts: Vec::new(),
         norms: Vec::new(),
+        tangents: Vec::new(),
         faces: Vec::new(),
         uvs: Vec::new(),
+        positions: Vec::new(),
+        joints: Vec::new(),
+        weights: Vec::new(),
+        materials: Vec::new(),
+        face_materials: Vec::new(),
+        face_objects: Vec::new(),
+        objects: 1,
+        object_normals: vec![Matrix3::identity()],
+        groups: Vec::new(),
+        face_groups: Vec::new(),
     };
+    let mut faces: Vec<Vec<VertexInfo>> = Vec::new();
+    let mut current_material: Option<usize> = None;
+    // the smoothing group of each face, None where smoothing is off. Faces before any 's'
+    // line are smoothed together as if under a group no line can name.
+    let mut smoothing: Vec<Option<u32>> = Vec::new();
+    let mut current_smoothing: Option<u32> = Some(0);
+    // groups are only made once a face is listed under them
+    let mut group_name = String::from(DEFAULT_GROUP);
+    let mut current_group: Option<usize> = None;
+    let mut skipped = 0;
 
-    let obj = fs::read_to_string(filename)?;
-    for l in obj.lines() {
-        if l.starts_with("v ") {
-            let mut iter = l.split_ascii_whitespace();
-            iter.next(); // drop first character
-            let v = Vector3::new(
-                iter.next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'v' line malformed",
-                    ))?
-                    .parse::<f32>()?,
-                iter.next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'v' line malformed",
-                    ))?
-                    .parse::<f32>()?,
-                iter.next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'v' line malformed",
-                    ))?
-                    .parse::<f32>()?,
-            );
-            model.verts.push(v);
-        } else if l.starts_with("f ") {
-            let mut f: Vec<VertexInfo> = Vec::new();
-            let mut iter = l.split_ascii_whitespace();
-            iter.next(); // drop first character
-            for ss in iter {
-                let mut sss = ss.split('/');
-                let v = sss
-                    .next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'f' line malformed",
-                    ))?
-                    .parse::<usize>()?
-                    - 1;
-                let vt = sss
-                    .next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'f' line malformed",
-                    ))?
-                    .parse::<usize>()?
-                    - 1;
-                f.push(VertexInfo { v, vt });
+    for (number, l) in obj.lines().enumerate() {
+        let mut iter = l.split_ascii_whitespace();
+        let parsed = match iter.next() {
+            Some("v") => parse_floats::<3>(iter, "obj file 'v' line malformed")
+                .map(|[x, y, z]| model.verts.push(Vector3::new(x, y, z))),
+            Some("vt") => parse_floats::<2>(iter, "obj file 'vt' line malformed")
+                .map(|[u, v]| model.uvs.push(Vector2::new(u, v))),
+            Some("vn") => {
+                parse_floats::<3>(iter, "obj file 'vn' line malformed").map(|[x, y, z]| {
+                    let n = Vector3::new(x, y, z);
+                    // already unit normals are kept bit for bit so written models read back
+                    // the same, zero or non-finite ones can't be normalized so stay as well
+                    let length2 = n.magnitude2();
+                    let n = if length2.is_normal() && (length2 - 1.0).abs() > NORMAL_TOLERANCE {
+                        n.normalize()
+                    } else {
+                        n
+                    };
+                    model.norms.push(n);
+                })
             }
-            model.faces.push(f);
-        } else if l.starts_with("vt ") {
-            let mut iter = l.split_ascii_whitespace();
-            iter.next(); // drop first portion
-            let uv = Vector2::new(
-                iter.next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'v' line malformed",
-                    ))?
-                    .parse::<f32>()?,
-                iter.next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'v' line malformed",
-                    ))?
-                    .parse::<f32>()?,
-            );
-            model.uvs.push(uv);
-        } else if l.starts_with("vn ") {
-            let mut iter = l.split_ascii_whitespace();
-            iter.next(); // drop first character
-            let v = Vector3::new(
-                iter.next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'v' line malformed",
-                    ))?
-                    .parse::<f32>()?,
-                iter.next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'v' line malformed",
-                    ))?
-                    .parse::<f32>()?,
-                iter.next()
-                    .ok_or(Error::new(
-                        ErrorKind::InvalidData,
-                        "obj file 'v' line malformed",
-                    ))?
-                    .parse::<f32>()?,
+            Some("f") => (|| -> Result<()> {
+                let mut f: Vec<VertexInfo> = Vec::new();
+                for ss in iter {
+                    let mut sss = ss.split('/');
+                    let v = parse_index(
+                        sss.next()
+                            .ok_or_else(|| malformed("obj file 'f' line malformed"))?,
+                        model.verts.len(),
+                    )?;
+                    // untextured meshes leave vt out, they all share a placeholder uv
+                    let vt = match sss.next() {
+                        Some(vt) if !vt.is_empty() => parse_index(vt, model.uvs.len())?,
+                        _ => 0,
+                    };
+                    let vn = match sss.next() {
+                        Some(vn) if !vn.is_empty() => Some(parse_index(vn, model.norms.len())?),
+                        _ => None,
+                    };
+                    f.push(VertexInfo { v, vt, vn });
+                }
+                if f.len() < 3 {
+                    return Err(malformed("obj file 'f' line has fewer than 3 vertices").into());
+                }
+                faces.push(f);
+                smoothing.push(current_smoothing);
+                model.face_materials.push(current_material);
+                let group = *current_group.get_or_insert_with(|| {
+                    model
+                        .groups
+                        .iter()
+                        .position(|g| *g == group_name)
+                        .unwrap_or_else(|| {
+                            model.groups.push(group_name.clone());
+                            model.groups.len() - 1
+                        })
+                });
+                model.face_groups.push(group);
+                Ok(())
+            })(),
+            Some("mtllib") => (|| -> Result<()> {
+                // material libraries are relative to the obj file
+                let name = l.trim_start()["mtllib".len()..].trim();
+                let mtl = Path::new(filename).with_file_name(name);
+                model
+                    .materials
+                    .extend(material::file_to_materials(mtl.to_str().ok_or_else(
+                        || malformed("obj file 'mtllib' path is not utf-8"),
+                    )?)?);
+                Ok(())
+            })(),
+            // objects and groups are both sub-meshes that can be told apart, the names of a
+            // 'g' line listing several make up one
+            Some(keyword @ ("o" | "g")) => {
+                let name = l.trim_start()[keyword.len()..].trim();
+                group_name = String::from(if name.is_empty() { DEFAULT_GROUP } else { name });
+                current_group = None;
+                Ok(())
+            }
+            Some("s") => match iter.next() {
+                Some("off") | Some("0") => {
+                    current_smoothing = None;
+                    Ok(())
+                }
+                Some(group) => group.parse::<u32>().map_or_else(
+                    |_| Err(malformed("obj file 's' line malformed").into()),
+                    |group| {
+                        current_smoothing = Some(group);
+                        Ok(())
+                    },
+                ),
+                None => Err(malformed("obj file 's' line malformed").into()),
+            },
+            Some("usemtl") => {
+                let name = l.trim_start()["usemtl".len()..].trim();
+                current_material = model.materials.iter().position(|m| m.name == name);
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        match parsed {
+            Err(e) if tolerant => {
+                tracing::debug!(file = filename, line = number + 1, error = %e, "skipped");
+                skipped += 1;
+            }
+            parsed => parsed?,
+        }
+    }
+
+    if model.uvs.is_empty() {
+        model.uvs.push(Vector2::new(0.0, 0.0));
+    }
+
+    // indices can only be checked once everything is defined
+    let (verts, uvs, norms) = (model.verts.len(), model.uvs.len(), model.norms.len());
+    let in_range = |f: &Vec<VertexInfo>| {
+        f.iter()
+            .all(|vi| vi.v < verts && vi.vt < uvs && vi.vn.is_none_or(|vn| vn < norms))
+    };
+    if tolerant {
+        let before = faces.len();
+        let keep = faces.iter().map(in_range).collect::<Vec<bool>>();
+        retain_by(&mut model.face_materials, &keep);
+        retain_by(&mut model.face_groups, &keep);
+        retain_by(&mut smoothing, &keep);
+        faces.retain(in_range);
+        if faces.len() < before {
+            tracing::debug!(
+                file = filename,
+                faces = before - faces.len(),
+                "skipped faces with an index out of range"
             );
-            model.norms.push(v.normalize());
         }
+        skipped += before - faces.len();
+    } else if !faces.iter().all(in_range) {
+        return Err(malformed("obj file 'f' index out of range").into());
     }
 
-    Ok(model)
+    // corners without a normal index use the one listed alongside their vertex, when the
+    // file doesn't have one for each vertex all of them are made up instead
+    let mut normals = std::mem::take(&mut model.norms);
+    if norms < verts && faces.iter().flatten().any(|vi| vi.vn.is_none()) {
+        normals = smoothing_group_normals(&model.verts, &mut faces, &smoothing);
+    }
+    weld(&mut model, &faces, &normals);
+    model.face_objects = vec![0; model.faces.len()];
+    // so normal mapping doesn't have to work them out for every pixel
+    model.tangents = vertex_tangents(&model);
+
+    Ok((model, skipped))
+}
+
+// A model of triangles whose attributes already share one index, as glTF stores them.
+// Without normals they are made up from the faces like an obj file's. joints and weights
+// are left empty when the model has no skin.
+pub fn indexed(
+    verts: Vec<Vector3<f32>>,
+    norms: Option<Vec<Vector3<f32>>>,
+    uvs: Vec<Vector2<f32>>,
+    joints: Vec<[usize; 4]>,
+    weights: Vec<[f32; 4]>,
+    faces: Vec<Vec<usize>>,
+) -> Model {
+    let norms = norms.unwrap_or_else(|| {
+        let corners: Vec<Vec<VertexInfo>> = faces
+            .iter()
+            .map(|face| {
+                face.iter()
+                    .map(|&v| VertexInfo { v, vt: v, vn: None })
+                    .collect()
+            })
+            .collect();
+        smooth_normals(&verts, &corners)
+    });
+    let mut model = Model {
+        positions: (0..verts.len()).collect(),
+        verts,
+        norms,
+        tangents: Vec::new(),
+        uvs,
+        joints,
+        weights,
+        face_materials: vec![None; faces.len()],
+        face_objects: vec![0; faces.len()],
+        face_groups: vec![0; faces.len()],
+        faces,
+        materials: Vec::new(),
+        objects: 1,
+        object_normals: vec![Matrix3::identity()],
+        groups: vec![String::from(DEFAULT_GROUP)],
+    };
+    model.tangents = vertex_tangents(&model);
+    model
+}
+
+// Gives every distinct (v, vt, vn) corner its own vertex, numbered in the order faces first
+// use them, so the model's attributes can all share one index. Vertices no face uses are
+// dropped.
+fn weld(model: &mut Model, faces: &[Vec<VertexInfo>], normals: &[Vector3<f32>]) {
+    let (verts, uvs) = (
+        std::mem::take(&mut model.verts),
+        std::mem::take(&mut model.uvs),
+    );
+    let mut welded: HashMap<(usize, usize, usize), usize> = HashMap::new();
+    for face in faces {
+        let face = face
+            .iter()
+            .map(|vi| {
+                let vn = vi.vn.unwrap_or(vi.v);
+                *welded.entry((vi.v, vi.vt, vn)).or_insert_with(|| {
+                    model.verts.push(verts[vi.v]);
+                    model.uvs.push(uvs[vi.vt]);
+                    model.norms.push(normals[vn]);
+                    model.positions.push(vi.v);
+                    model.verts.len() - 1
+                })
+            })
+            .collect();
+        model.faces.push(face);
+    }
+}
+
+// Like smooth_normals() but only faces in the same smoothing group share normals, the
+// edges between groups and around faces with smoothing off stay hard. Every corner's vn
+// is set to its normal in the list returned.
+fn smoothing_group_normals(
+    verts: &[Vector3<f32>],
+    faces: &mut [Vec<VertexInfo>],
+    smoothing: &[Option<u32>],
+) -> Vec<Vector3<f32>> {
+    let mut norms: Vec<Vector3<f32>> = Vec::new();
+    let mut shared: HashMap<(usize, u32), usize> = HashMap::new();
+    for (face, &group) in faces.iter_mut().zip(smoothing) {
+        let mut add = || {
+            norms.push(Vector3::new(0.0, 0.0, 0.0));
+            norms.len() - 1
+        };
+        let mut flat = None;
+        for vi in face.iter_mut() {
+            vi.vn = Some(match group {
+                Some(group) => *shared.entry((vi.v, group)).or_insert_with(&mut add),
+                None => *flat.get_or_insert_with(&mut add),
+            });
+        }
+        let origin = verts[face[0].v];
+        for pair in face[1..].windows(2) {
+            // twice the area of the triangle, along its normal
+            let n = (verts[pair[0].v] - origin).cross(verts[pair[1].v] - origin);
+            for vi in [&face[0], &pair[0], &pair[1]] {
+                norms[vi.vn.unwrap_or_default()] += n;
+            }
+        }
+    }
+    normalize_sums(&mut norms);
+    norms
+}
+
+// Vertex normals as the sum of the normals of the faces around each vertex weighted by
+// their area, so small slivers don't bend the shading. Polygons are split into a fan of
+// triangles. Vertices no face uses, or only degenerate ones, are left as zero.
+fn smooth_normals(verts: &[Vector3<f32>], faces: &[Vec<VertexInfo>]) -> Vec<Vector3<f32>> {
+    let mut norms = vec![Vector3::new(0.0, 0.0, 0.0); verts.len()];
+    for face in faces {
+        let origin = verts[face[0].v];
+        for pair in face[1..].windows(2) {
+            // twice the area of the triangle, along its normal
+            let n = (verts[pair[0].v] - origin).cross(verts[pair[1].v] - origin);
+            for vi in [&face[0], &pair[0], &pair[1]] {
+                norms[vi.v] += n;
+            }
+        }
+    }
+    normalize_sums(&mut norms);
+    norms
+}
+
+fn normalize_sums(norms: &mut [Vector3<f32>]) {
+    for n in norms.iter_mut() {
+        // overflowing sums would otherwise leave infinities or NaNs
+        *n = if n.magnitude2().is_normal() {
+            n.normalize()
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        };
+    }
+}
+
+// Lengyel's tangents: the directions u and v increase in across each face summed at its
+// vertices, then the u direction is made perpendicular to the vertex normal and the v
+// direction kept only as a handedness, like MikkTSpace stores them. Vertices without
+// usable uvs get any tangent perpendicular to their normal.
+fn vertex_tangents(model: &Model) -> Vec<Vector4<f32>> {
+    let zero = Vector3::new(0.0, 0.0, 0.0);
+    let mut tangents = vec![zero; model.verts.len()];
+    let mut bitangents = vec![zero; model.verts.len()];
+    for face in &model.faces {
+        for pair in face[1..].windows(2) {
+            let corners = [face[0], pair[0], pair[1]];
+            let [v0, v1, v2] = corners;
+            let e1 = model.verts[v1] - model.verts[v0];
+            let e2 = model.verts[v2] - model.verts[v0];
+            let d1 = model.uvs[v1] - model.uvs[v0];
+            let d2 = model.uvs[v2] - model.uvs[v0];
+            let r = d1.x * d2.y - d2.x * d1.y;
+            if !r.is_normal() {
+                continue; // the uvs don't span the face
+            }
+            let t = (e1 * d2.y - e2 * d1.y) / r;
+            let b = (e2 * d1.x - e1 * d2.x) / r;
+            for v in corners {
+                tangents[v] += t;
+                bitangents[v] += b;
+            }
+        }
+    }
+    model
+        .norms
+        .iter()
+        .zip(tangents.iter().zip(&bitangents))
+        .map(|(&n, (&t, &b))| {
+            let t = t - n * n.dot(t);
+            let t = if t.magnitude2().is_normal() {
+                t.normalize()
+            } else {
+                perpendicular(n)
+            };
+            let w = if n.cross(t).dot(b) < 0.0 { -1.0 } else { 1.0 };
+            t.extend(w)
+        })
+        .collect()
+}
+
+// some unit vector perpendicular to n, or along x if n has no direction
+fn perpendicular(n: Vector3<f32>) -> Vector3<f32> {
+    let axis = if n.x.abs() < 0.9 {
+        Vector3::new(1.0, 0.0, 0.0)
+    } else {
+        Vector3::new(0.0, 1.0, 0.0)
+    };
+    let p = n.cross(axis);
+    if p.magnitude2().is_normal() {
+        p.normalize()
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    }
+}
+
+impl Model {
+    // Wavefront obj text that obj_to_model() reads back to the same model. Faces keep
+    // their groups with g and their materials by name with usemtl, the material library
+    // isn't written.
+    pub fn write_obj<W: Write>(&self, out: &mut W) -> Result<()> {
+        for v in &self.verts {
+            writeln!(out, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        for uv in &self.uvs {
+            writeln!(out, "vt {} {}", uv.x, uv.y)?;
+        }
+        for n in &self.norms {
+            writeln!(out, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        let mut current_material = None;
+        let mut current_group = None;
+        let faces = self.faces.iter().zip(&self.face_materials);
+        for ((face, &material), &group) in faces.zip(&self.face_groups) {
+            // faces before any g line are in the default group already
+            let listed = current_group.is_some() || self.groups[group] != DEFAULT_GROUP;
+            if listed && current_group != Some(group) {
+                writeln!(out, "g {}", self.groups[group])?;
+                current_group = Some(group);
+            }
+            if material != current_material {
+                if let Some(m) = material {
+                    writeln!(out, "usemtl {}", self.materials[m].name)?;
+                }
+                current_material = material;
+            }
+            write!(out, "f")?;
+            for v in face {
+                write!(out, " {}/{}/{}", v + 1, v + 1, v + 1)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
 }
@@ -1,10 +1,28 @@
-use cgmath::{InnerSpace, Matrix, Matrix4, Vector2, Vector3, Vector4};
-use image::{GrayImage, Luma, Rgb, RgbImage};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector2, Vector3, Vector4};
+use image::{GrayImage, ImageBuffer, Luma, Pixel, Rgb, RgbImage};
 
+use std::mem;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::font;
+use super::material::Sides;
 use super::model;
+use super::tiled::{Tile, Tiled};
 
 pub const DEPTH: f32 = 255.0;
 const EPSILON: f32 = 1e-2;
+// depth difference under which two fragments are treated as the same surface
+const COVERAGE_DEPTH_TOLERANCE: u8 = 2;
+// side of the blocks triangle_hiz() tests before going down to pixels
+const HIZ_BLOCK: u32 = 8;
+// Kept between the pixels of a block and its corners' barycentric coordinates, and between
+// a triangle's fragments and its nearest vertex, so rounding can never reject a fragment
+// the plain rasterizer would have drawn.
+const HIZ_EDGE_MARGIN: f32 = 1e-3;
+const HIZ_DEPTH_MARGIN: f32 = 1e-2;
+// clip space w triangles are cut at, anything nearer the eye can't be divided by
+const NEAR_W: f32 = 1e-3;
 
 pub fn viewport(x: f32, y: f32, width: f32, height: f32) -> Matrix4<f32> {
     // translations to the centre of the desired rectangle
@@ -36,6 +54,37 @@ pub fn projection(coeff: f32) -> Matrix4<f32> {
     .transpose()
 }
 
+// A box reaching half_width, half_height and half_depth either side of the origin mapped
+// onto the unit cube, no foreshortening. Nearer points get the larger depth as with projection().
+pub fn orthographic(half_width: f32, half_height: f32, half_depth: f32) -> Matrix4<f32> {
+    Matrix4::from_nonuniform_scale(1.0 / half_width, 1.0 / half_height, 1.0 / half_depth)
+}
+
+// A frustum of vertical field of view fov (radians) from near to far. Unlike the usual GL
+// matrix nearer points get the larger depth, like everything else here.
+pub fn perspective(fov: f32, aspect: f32, near: f32, far: f32) -> Matrix4<f32> {
+    let f = 1.0 / (fov / 2.0).tan();
+    Matrix4::<f32>::new(
+        f / aspect,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        f,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        -(far + near) / (near - far),
+        -2.0 * far * near / (near - far),
+        0.0,
+        0.0,
+        -1.0,
+        0.0,
+    )
+    .transpose()
+}
+
 pub fn lookat(eye: Vector3<f32>, center: Vector3<f32>, up: Vector3<f32>) -> Matrix4<f32> {
     let z = (eye - center).normalize();
     let x = up.cross(z).normalize();
@@ -59,17 +108,461 @@ pub fn lookat(eye: Vector3<f32>, center: Vector3<f32>, up: Vector3<f32>) -> Matr
     minv * tr
 }
 
+// full precision depth, for when 8 bits causes acne (shadow maps)
+pub type DepthImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+// anything triangle() can depth test against
+pub trait DepthBuffer {
+    fn dimensions(&self) -> (u32, u32);
+    fn depth(&self, x: u32, y: u32) -> f32;
+    fn set_depth(&mut self, x: u32, y: u32, depth: f32);
+    // the depth as it would read back after being stored
+    fn quantize(&self, depth: f32) -> f32;
+}
+
+impl DepthBuffer for GrayImage {
+    fn dimensions(&self) -> (u32, u32) {
+        ImageBuffer::dimensions(self)
+    }
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.get_pixel(x, y)[0] as f32
+    }
+    fn set_depth(&mut self, x: u32, y: u32, depth: f32) {
+        self.put_pixel(x, y, Luma([depth as u8]));
+    }
+    fn quantize(&self, depth: f32) -> f32 {
+        (depth as u8) as f32
+    }
+}
+
+impl DepthBuffer for DepthImage {
+    fn dimensions(&self) -> (u32, u32) {
+        ImageBuffer::dimensions(self)
+    }
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.get_pixel(x, y)[0]
+    }
+    fn set_depth(&mut self, x: u32, y: u32, depth: f32) {
+        self.put_pixel(x, y, Luma([depth]));
+    }
+    fn quantize(&self, depth: f32) -> f32 {
+        depth
+    }
+}
+
+impl DepthBuffer for Tile<'_, Luma<u8>> {
+    fn dimensions(&self) -> (u32, u32) {
+        Tile::dimensions(self)
+    }
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.get_pixel(x, y)[0] as f32
+    }
+    fn set_depth(&mut self, x: u32, y: u32, depth: f32) {
+        self.put_pixel(x, y, Luma([depth as u8]));
+    }
+    fn quantize(&self, depth: f32) -> f32 {
+        (depth as u8) as f32
+    }
+}
+
+impl DepthBuffer for Tiled<Luma<u8>> {
+    fn dimensions(&self) -> (u32, u32) {
+        Tiled::dimensions(self)
+    }
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.get_pixel(x, y)[0] as f32
+    }
+    fn set_depth(&mut self, x: u32, y: u32, depth: f32) {
+        self.put_pixel(x, y, Luma([depth as u8]));
+    }
+    fn quantize(&self, depth: f32) -> f32 {
+        (depth as u8) as f32
+    }
+}
+
+// anything triangle() can draw into
+pub trait ColorBuffer {
+    fn dimensions(&self) -> (u32, u32);
+    fn set_color(&mut self, x: u32, y: u32, color: Rgb<u8>);
+}
+
+impl ColorBuffer for RgbImage {
+    fn dimensions(&self) -> (u32, u32) {
+        ImageBuffer::dimensions(self)
+    }
+    fn set_color(&mut self, x: u32, y: u32, color: Rgb<u8>) {
+        self.put_pixel(x, y, color);
+    }
+}
+
+impl ColorBuffer for Tiled<Rgb<u8>> {
+    fn dimensions(&self) -> (u32, u32) {
+        Tiled::dimensions(self)
+    }
+    fn set_color(&mut self, x: u32, y: u32, color: Rgb<u8>) {
+        self.put_pixel(x, y, color);
+    }
+}
+
+// only the pixels of its bounds can be drawn to, see triangle_scissor()
+impl ColorBuffer for Tile<'_, Rgb<u8>> {
+    fn dimensions(&self) -> (u32, u32) {
+        Tile::dimensions(self)
+    }
+    fn set_color(&mut self, x: u32, y: u32, color: Rgb<u8>) {
+        self.put_pixel(x, y, color);
+    }
+}
+
+// What a pass draws into: colour and depth plus the extra targets some passes fill from
+// the same fragments, which are made the first time a pass needs them.
+// (0,0) is the bottom left.
+pub struct Framebuffer<C = RgbImage, D = GrayImage> {
+    pub color: C,
+    pub depth: D,
+    pub normal: Option<RgbImage>,    // for triangle_gbuffer()
+    pub position: Option<RgbImage>,  // for triangle_gbuffer()
+    pub alpha: Option<GrayImage>,    // for triangle_peel()
+    pub coverage: Option<GrayImage>, // for triangle_coverage(), 255 is fully covered
+    pub stencil: Option<GrayImage>,  // for triangle_stencil()
+    pub hiz: Option<HiZ>,            // for triangle_hiz()
+    pub ids: Option<IdBuffer>,       // for triangle_id()
+    // sees every fragment of the triangles drawn into it, see Fragment
+    pub hook: Option<Arc<FragmentHook>>,
+}
+
+impl<C: ColorBuffer, D: DepthBuffer> Framebuffer<C, D> {
+    pub fn new(color: C, depth: D) -> Framebuffer<C, D> {
+        Framebuffer {
+            color,
+            depth,
+            normal: None,
+            position: None,
+            alpha: None,
+            coverage: None,
+            stencil: None,
+            hiz: None,
+            ids: None,
+            hook: None,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.color.dimensions()
+    }
+
+    // Stamps text over the colour with the embedded font, (x, y) being its top left
+    // counted from the top left of the saved image so it reads the right way up once the
+    // frame is flipped. Pixels off the frame are skipped.
+    pub fn draw_text(&mut self, x: u32, y: u32, scale: u32, text: &str, color: Rgb<u8>) {
+        let (width, height) = self.dimensions();
+        font::for_each_pixel(x, y, scale, text, |px, py| {
+            if px < width && py < height {
+                self.color.set_color(px, height - 1 - py, color);
+            }
+        });
+    }
+}
+
+// an attachment of a framebuffer, made cleared when it isn't there yet
+fn attachment<P: Pixel + 'static>(
+    target: &mut Option<ImageBuffer<P, Vec<P::Subpixel>>>,
+    (width, height): (u32, u32),
+) -> &mut ImageBuffer<P, Vec<P::Subpixel>> {
+    target.get_or_insert_with(|| ImageBuffer::new(width, height))
+}
+
+// One placement of a model in the scene. Shaders take the model's vertices through it
+// before anything else, so the same mesh can be drawn many times without copies.
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    pub transform: Matrix4<f32>,
+    linear: Matrix3<f32>,
+    normal_matrix: Matrix3<f32>,
+    handedness: f32,
+}
+
+impl Instance {
+    pub fn new(transform: Matrix4<f32>) -> Instance {
+        let linear = Matrix3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        Instance {
+            transform,
+            linear,
+            normal_matrix: linear.invert().unwrap_or(linear).transpose(),
+            // mirroring flips the handedness of the tangent frame
+            handedness: linear.determinant().signum(),
+        }
+    }
+
+    // the identity leaves everything bit for bit as it was
+    fn moves(&self) -> bool {
+        self.transform != Matrix4::identity()
+    }
+
+    pub fn position(&self, p: Vector3<f32>) -> Vector3<f32> {
+        if !self.moves() {
+            return p;
+        }
+        (self.transform * p.extend(1.0)).truncate()
+    }
+
+    // the inverse transpose normals are taken through, before they are normalized
+    pub fn normal_matrix(&self) -> Matrix3<f32> {
+        self.normal_matrix
+    }
+
+    pub fn normal(&self, n: Vector3<f32>) -> Vector3<f32> {
+        if !self.moves() {
+            return n;
+        }
+        unit(self.normal_matrix * n)
+    }
+
+    // w is the handedness of the tangent frame like in the model
+    pub fn tangent(&self, t: Vector4<f32>) -> Vector4<f32> {
+        if !self.moves() {
+            return t;
+        }
+        unit(self.linear * t.truncate()).extend(t.w * self.handedness)
+    }
+
+    // the model's bounding box placed by the instance, a box around its moved corners
+    pub fn bounds(&self, model: &model::Model) -> (Vector3<f32>, Vector3<f32>) {
+        let (min, max) = model.bounds();
+        if !self.moves() {
+            return (min, max);
+        }
+        let corners = (0..8).map(|i| {
+            self.position(Vector3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            ))
+        });
+        union(corners.map(|corner| (corner, corner)))
+    }
+}
+
+impl Default for Instance {
+    fn default() -> Instance {
+        Instance::new(Matrix4::identity())
+    }
+}
+
+// zero vectors stay zero rather than turning into NaNs
+fn unit(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2().is_normal() {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+fn union(
+    boxes: impl Iterator<Item = (Vector3<f32>, Vector3<f32>)>,
+) -> (Vector3<f32>, Vector3<f32>) {
+    boxes.fold(
+        (
+            Vector3::new(f32::MAX, f32::MAX, f32::MAX),
+            Vector3::new(f32::MIN, f32::MIN, f32::MIN),
+        ),
+        |(min, max), (lo, hi)| {
+            (
+                Vector3::new(min.x.min(lo.x), min.y.min(lo.y), min.z.min(lo.z)),
+                Vector3::new(max.x.max(hi.x), max.y.max(hi.y), max.z.max(hi.z)),
+            )
+        },
+    )
+}
+
+// box and bounding sphere around every instance of model, like Model::bounds() and
+// Model::bounding_sphere() are for the model alone
+pub fn instanced_bounds(
+    model: &model::Model,
+    instances: &[Instance],
+) -> (Vector3<f32>, Vector3<f32>) {
+    union(instances.iter().map(|instance| instance.bounds(model)))
+}
+
+pub fn instanced_bounding_sphere(
+    model: &model::Model,
+    instances: &[Instance],
+) -> (Vector3<f32>, f32) {
+    let (min, max) = instanced_bounds(model, instances);
+    ((min + max) / 2.0, (max - min).magnitude() / 2.0)
+}
+
+// What every vertex and fragment of a draw sees, set once for the draw and handed to both
+// stages. A shader that needs another matrix reads it from here instead of its constructor.
+#[derive(Clone, Copy)]
+pub struct Uniforms {
+    // takes the placed vertex to clip space, what vertex() gives back
+    pub mat: Matrix4<f32>,
+    // takes clip space to the screen, applied by the rasterizer and not the shaders
+    pub viewport: Matrix4<f32>,
+    // world to eye space
+    pub view: Matrix4<f32>,
+    // the space lights are shaded in, normals are taken there by shading_it
+    pub shading: Matrix4<f32>,
+    // inverse transposes of view and shading, for normals
+    pub view_it: Matrix4<f32>,
+    pub shading_it: Matrix4<f32>,
+    // takes points on the screen to the screen of the shadow buffer
+    pub shadow: Matrix4<f32>,
+    // world space
+    pub eye: Vector3<f32>,
+    // which sides of faces are lit when their material doesn't say
+    pub sides: Sides,
+}
+
+impl Uniforms {
+    // the inverse transposes are worked out from view and shading. The eye can't be, lookat()
+    // turns the scene about the target and leaves the distance to the projection.
+    pub fn new(
+        mat: Matrix4<f32>,
+        view: Matrix4<f32>,
+        shading: Matrix4<f32>,
+        eye: Vector3<f32>,
+    ) -> Uniforms {
+        let inverse_transpose =
+            |m: Matrix4<f32>| m.invert().expect("Could not find inverse").transpose();
+        Uniforms {
+            mat,
+            viewport: Matrix4::identity(),
+            view,
+            shading,
+            view_it: inverse_transpose(view),
+            shading_it: inverse_transpose(shading),
+            shadow: Matrix4::identity(),
+            eye,
+            sides: Sides::One,
+        }
+    }
+
+    // the whole way from the placed vertex to the screen, for culling
+    pub fn to_screen(&self) -> Matrix4<f32> {
+        self.viewport * self.mat
+    }
+
+    // mat takes straight to the screen, shading is done in world space seen from the origin
+    pub fn screen(mat: Matrix4<f32>) -> Uniforms {
+        Uniforms::new(
+            mat,
+            Matrix4::identity(),
+            Matrix4::identity(),
+            Vector3::new(0.0, 0.0, 0.0),
+        )
+    }
+}
+
 // create interface (pretty sure that isn't possible in rust)
 pub trait Shader {
+    // Gives the vertex in clip space, uniforms.mat takes it there once the instance has placed
+    // it in the scene. Dividing by w, the viewport and clipping are left to the rasterizer.
     fn vertex(
         &mut self,
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &Uniforms,
+        instance: &Instance,
     ) -> Vector4<f32>;
     // bar stands for barycentric coordinates
-    fn fragment(&self, bar: Vector3<f32>, color: &mut Rgb<u8>) -> bool;
+    fn fragment(&self, bar: Vector3<f32>, uniforms: &Uniforms, color: &mut Rgb<u8>) -> bool;
+}
+
+// A triangle a geometry stage hands on to the rasterizer: its corners in clip space and, for
+// each, the barycentric coordinates in the shaded triangle its varyings are taken at. The
+// rasterizer clips them into primitives of their own, with their corners on the screen.
+#[derive(Debug, Clone, Copy)]
+pub struct Primitive {
+    pub pts: [Vector4<f32>; 3],
+    pub bars: [Vector3<f32>; 3],
+    // cut by the rasterizer where it reaches behind the eye
+    clipped: bool,
+}
+
+impl Primitive {
+    // corners with the varyings of the shaded triangle's own, wherever they are moved to
+    pub fn new(pts: [Vector4<f32>; 3]) -> Primitive {
+        Primitive {
+            pts,
+            bars: [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()],
+            clipped: false,
+        }
+    }
+
+    // Barycentric coordinates in this primitive taken to the shaded triangle's. Those of a
+    // clipped one are made perspective correct first, the shaded triangle has a corner
+    // behind the eye so its varyings can't be spread over the screen like everyone else's.
+    fn bar(&self, c: Vector3<f32>) -> Vector3<f32> {
+        let c = if self.clipped {
+            let c = Vector3::new(
+                c.x / self.pts[0].w,
+                c.y / self.pts[1].w,
+                c.z / self.pts[2].w,
+            );
+            c / (c.x + c.y + c.z)
+        } else {
+            c
+        };
+        self.bars[0] * c.x + self.bars[1] * c.y + self.bars[2] * c.z
+    }
+}
+
+// An optional stage between Shader::vertex and the rasterizer. It sees every triangle once
+// its three vertices are shaded, pts as the vertex stage gave them, and emits any number
+// of triangles in its place: none discards it, more than one duplicates it, e.g. as shells
+// pushed out for fur.
+pub trait GeometryShader {
+    fn geometry(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        instance: &Instance,
+        uniforms: &Uniforms,
+        pts: &[Vector4<f32>; 3],
+        emit: &mut dyn FnMut(Primitive),
+    );
+}
+
+// A primitive drawn by the shader of the triangle it was emitted for, its fragments' barycentric
+// coordinates taken back into that triangle's
+pub struct Remapped<'a, T> {
+    shader: &'a mut T,
+    bars: Matrix3<f32>,
+}
+
+impl<'a, T: Shader> Remapped<'a, T> {
+    pub fn new(shader: &'a mut T, primitive: &Primitive) -> Remapped<'a, T> {
+        let [a, b, c] = primitive.bars;
+        Remapped {
+            shader,
+            bars: Matrix3::from_cols(a, b, c),
+        }
+    }
+}
+
+impl<T: Shader> Shader for Remapped<'_, T> {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &Uniforms,
+        instance: &Instance,
+    ) -> Vector4<f32> {
+        self.shader
+            .vertex(model, iface, nthvert, uniforms, instance)
+    }
+
+    fn fragment(&self, bar: Vector3<f32>, uniforms: &Uniforms, color: &mut Rgb<u8>) -> bool {
+        self.shader.fragment(self.bars * bar, uniforms, color)
+    }
 }
 
 fn barycentric(pts: &[Vector2<f32>; 3], p: Vector2<f32>) -> Vector3<f32> {
@@ -84,24 +577,619 @@ fn barycentric(pts: &[Vector2<f32>; 3], p: Vector2<f32>) -> Vector3<f32> {
     }
 }
 
-pub fn triangle<T: Shader>(
+// What is drawn of a triangle the vertex stage gave in clip space. The part of it in front
+// of the eye is kept, cut into a fan where it reaches behind (Sutherland-Hodgman against
+// w = NEAR_W), and taken to the screen by viewport.
+fn assemble(pts: &[Vector4<f32>; 3], viewport: Matrix4<f32>) -> Vec<Primitive> {
+    if pts.iter().all(|pt| pt.w >= NEAR_W) {
+        return vec![Primitive::new(pts.map(|pt| viewport * pt))];
+    }
+    let whole = Primitive::new(*pts);
+    let mut polygon = Vec::with_capacity(4);
+    for j in 0..3 {
+        let k = (j + 1) % 3;
+        let (a, b) = (pts[j], pts[k]);
+        if a.w >= NEAR_W {
+            polygon.push((a, whole.bars[j]));
+        }
+        if (a.w >= NEAR_W) != (b.w >= NEAR_W) {
+            let t = (NEAR_W - a.w) / (b.w - a.w);
+            let bar = whole.bars[j] + (whole.bars[k] - whole.bars[j]) * t;
+            polygon.push((a + (b - a) * t, bar));
+        }
+    }
+    if polygon.is_empty() {
+        tracing::trace!(reason = "behind the eye", "triangle clipped");
+    }
+    (2..polygon.len())
+        .map(|k| {
+            let corners = [polygon[0], polygon[k - 1], polygon[k]];
+            Primitive {
+                pts: corners.map(|(pt, _)| viewport * pt),
+                bars: corners.map(|(_, bar)| bar),
+                clipped: true,
+            }
+        })
+        .collect()
+}
+
+// The pixels of a width x height target under the screen box of a triangle grown by pad,
+// every rasterizer loops over this so none can index outside its buffers. None when
+// nothing is left on the target or the triangle can't be projected, i.e. it reaches
+// behind the eye (only ever left by assemble() for NaN w) or isn't finite.
+fn clipped_bbox(
+    pts: &[Vector4<f32>; 3],
+    pad: f32,
+    width: u32,
+    height: u32,
+) -> Option<(Vector2<u32>, Vector2<u32>)> {
+    if pts.iter().any(|pt| pt.w <= 0.0 || pt.w.is_nan()) {
+        tracing::trace!(reason = "behind the eye", "triangle clipped");
+        return None;
+    }
+    let pts_2d = pts.map(|pt| Vector2::new(pt.x / pt.w, pt.y / pt.w));
+    if pts_2d
+        .iter()
+        .any(|pt| !pt.x.is_finite() || !pt.y.is_finite())
+    {
+        tracing::trace!(reason = "not finite", "triangle clipped");
+        return None;
+    }
+    let min = pts_2d
+        .iter()
+        .fold(Vector2::new(f32::MAX, f32::MAX), |acc, pt| {
+            Vector2::new(acc.x.min(pt.x), acc.y.min(pt.y))
+        });
+    let max = pts_2d
+        .iter()
+        .fold(Vector2::new(f32::MIN, f32::MIN), |acc, pt| {
+            Vector2::new(acc.x.max(pt.x), acc.y.max(pt.y))
+        });
+    let xmin = (min.x - pad).floor().max(0.0);
+    let ymin = (min.y - pad).floor().max(0.0);
+    let xmax = (max.x + pad).floor().min(width as f32 - 1.0);
+    let ymax = (max.y + pad).floor().min(height as f32 - 1.0);
+    if xmin > xmax || ymin > ymax {
+        tracing::trace!(reason = "off the target", "triangle clipped");
+        return None;
+    }
+    Some((
+        Vector2::new(xmin as u32, ymin as u32),
+        Vector2::new(xmax as u32, ymax as u32),
+    ))
+}
+
+pub fn triangle<T: Shader, C: ColorBuffer, D: DepthBuffer>(
     pts: &[Vector4<f32>; 3], // TODO screen coords
     shader: &T,
-    image: &mut RgbImage,
-    zbuffer: &mut GrayImage,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer<C, D>,
+) {
+    triangle_biased(pts, shader, uniforms, frame, 0);
+}
+
+// Fragments at exactly the depth already in the zbuffer are rejected, so for co-planar
+// geometry whatever was submitted first wins, every run.
+// A positive bias lets a fragment win against surfaces up to that much in front of it,
+// e.g. 1 for decals drawn after the surface they sit on.
+pub fn triangle_biased<T: Shader, C: ColorBuffer, D: DepthBuffer>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer<C, D>,
+    bias: u8,
+) {
+    let tests = Tests {
+        bias,
+        hook: frame.hook.as_deref(),
+        ..Tests::default()
+    };
+    rasterize(
+        pts,
+        shader,
+        uniforms,
+        &mut frame.color,
+        &mut frame.depth,
+        tests,
+        &mut |_| true,
+    );
+}
+
+// what a fragment hook gets to see, (x, y) has (0,0) at the bottom left
+pub struct Fragment<'a> {
+    pub x: u32,
+    pub y: u32,
+    pub bar: Vector3<f32>,
+    pub depth: f32,
+    pub color: &'a mut Rgb<u8>,
+}
+
+// Every fragment the shader keeps is passed through the hook of the frame before it is
+// written. The hook can inspect it, change its colour or discard it by returning false, for
+// debug captures and effects without touching the rasterizer.
+pub type FragmentHook = dyn Fn(Fragment) -> bool + Send + Sync;
+
+// how the stencil value of a pixel is compared against the reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StencilCompare {
+    Never,
+    Less, // reference < stencil
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    Always,
+}
+
+impl std::str::FromStr for StencilCompare {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<StencilCompare> {
+        match s {
+            "never" => Ok(StencilCompare::Never),
+            "less" => Ok(StencilCompare::Less),
+            "lequal" => Ok(StencilCompare::LessEqual),
+            "greater" => Ok(StencilCompare::Greater),
+            "gequal" => Ok(StencilCompare::GreaterEqual),
+            "equal" => Ok(StencilCompare::Equal),
+            "notequal" => Ok(StencilCompare::NotEqual),
+            "always" => Ok(StencilCompare::Always),
+            _ => Err(anyhow::anyhow!("unknown stencil compare '{}'", s)),
+        }
+    }
+}
+
+// what happens to the stencil value of a pixel after the tests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace, // with the reference
+    Increment,
+    Decrement,
+    IncrementWrap,
+    DecrementWrap,
+    Invert,
+}
+
+impl std::str::FromStr for StencilOp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<StencilOp> {
+        match s {
+            "keep" => Ok(StencilOp::Keep),
+            "zero" => Ok(StencilOp::Zero),
+            "replace" => Ok(StencilOp::Replace),
+            "incr" => Ok(StencilOp::Increment),
+            "decr" => Ok(StencilOp::Decrement),
+            "incr_wrap" => Ok(StencilOp::IncrementWrap),
+            "decr_wrap" => Ok(StencilOp::DecrementWrap),
+            "invert" => Ok(StencilOp::Invert),
+            _ => Err(anyhow::anyhow!("unknown stencil op '{}'", s)),
+        }
+    }
+}
+
+// The stencil test of a pass. Both sides of the compare are masked with read_mask, and only
+// the bits in write_mask change. fail runs when the stencil test fails, depth_fail when it
+// passes but the depth test doesn't and pass when both do and the shader keeps the fragment.
+// With write off the pass only marks the stencil, colour and depth are left alone.
+#[derive(Debug, Clone, Copy)]
+pub struct StencilState {
+    pub compare: StencilCompare,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    pub fail: StencilOp,
+    pub depth_fail: StencilOp,
+    pub pass: StencilOp,
+    pub write: bool,
+}
+
+impl StencilState {
+    // passes where the stencil compares to reference, changing nothing
+    pub fn test(compare: StencilCompare, reference: u8) -> StencilState {
+        StencilState {
+            compare,
+            reference,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+            write: true,
+        }
+    }
+
+    // sets the stencil to reference wherever the triangle covers, in front or not, without
+    // drawing anything
+    pub fn mark(reference: u8) -> StencilState {
+        StencilState {
+            depth_fail: StencilOp::Replace,
+            pass: StencilOp::Replace,
+            write: false,
+            ..StencilState::test(StencilCompare::Always, reference)
+        }
+    }
+
+    fn passes(&self, stencil: u8) -> bool {
+        let reference = self.reference & self.read_mask;
+        let stencil = stencil & self.read_mask;
+        match self.compare {
+            StencilCompare::Never => false,
+            StencilCompare::Less => reference < stencil,
+            StencilCompare::LessEqual => reference <= stencil,
+            StencilCompare::Greater => reference > stencil,
+            StencilCompare::GreaterEqual => reference >= stencil,
+            StencilCompare::Equal => reference == stencil,
+            StencilCompare::NotEqual => reference != stencil,
+            StencilCompare::Always => true,
+        }
+    }
+
+    fn update(&self, op: StencilOp, stencil: &mut Luma<u8>) {
+        let old = stencil[0];
+        let new = match op {
+            StencilOp::Keep => old,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => self.reference,
+            StencilOp::Increment => old.saturating_add(1),
+            StencilOp::Decrement => old.saturating_sub(1),
+            StencilOp::IncrementWrap => old.wrapping_add(1),
+            StencilOp::DecrementWrap => old.wrapping_sub(1),
+            StencilOp::Invert => !old,
+        };
+        stencil[0] = (old & !self.write_mask) | (new & self.write_mask);
+    }
+}
+
+// How far the depth of a triangle's fragments is pushed away from the eye, like
+// glPolygonOffset: bias in depth units and slope_bias per unit its depth changes across a
+// pixel, so faces seen at a grazing angle are pushed further.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DepthOffset {
+    pub bias: f32,
+    pub slope_bias: f32,
+}
+
+impl DepthOffset {
+    // for the primitive on the screen at pts
+    fn depth(&self, pts: &[Vector4<f32>; 3]) -> f32 {
+        if self.slope_bias == 0.0 {
+            return self.bias;
+        }
+        let p = pts.map(|pt| pt.truncate() / pt.w);
+        let n = (p[1] - p[0]).cross(p[2] - p[0]);
+        // edge on, no pixel is under it
+        if n.z.abs() < f32::EPSILON {
+            return self.bias;
+        }
+        self.bias + self.slope_bias * n.x.abs().max(n.y.abs()) / n.z.abs()
+    }
+}
+
+// Like triangle() with the depth of every fragment pushed back by offset, for the depth
+// test and what is written. Keeps a shadow buffer's surfaces from shadowing themselves.
+pub fn triangle_offset<T: Shader, C: ColorBuffer, D: DepthBuffer>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer<C, D>,
+    offset: DepthOffset,
+) {
+    let tests = Tests {
+        offset,
+        hook: frame.hook.as_deref(),
+        ..Tests::default()
+    };
+    rasterize(
+        pts,
+        shader,
+        uniforms,
+        &mut frame.color,
+        &mut frame.depth,
+        tests,
+        &mut |_| true,
+    );
+}
+
+// Like triangle() with a stencil test in front of the depth test, against the stencil
+// attachment of the frame which starts out as zeros.
+pub fn triangle_stencil<T: Shader, C: ColorBuffer, D: DepthBuffer>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer<C, D>,
+    state: &StencilState,
+) {
+    let size = frame.dimensions();
+    let stencil = attachment(&mut frame.stencil, size);
+    let tests = Tests {
+        stencil: Some((stencil, state)),
+        hook: frame.hook.as_deref(),
+        ..Tests::default()
+    };
+    rasterize(
+        pts,
+        shader,
+        uniforms,
+        &mut frame.color,
+        &mut frame.depth,
+        tests,
+        &mut |_| true,
+    );
+}
+
+// Like triangle() but only the pixels from min to max (inclusive) are drawn, e.g. those of
+// one tile of the frame
+pub fn triangle_scissor<T: Shader, C: ColorBuffer, D: DepthBuffer>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer<C, D>,
+    scissor: (Vector2<u32>, Vector2<u32>),
+) {
+    let tests = Tests {
+        scissor: Some(scissor),
+        hook: frame.hook.as_deref(),
+        ..Tests::default()
+    };
+    rasterize(
+        pts,
+        shader,
+        uniforms,
+        &mut frame.color,
+        &mut frame.depth,
+        tests,
+        &mut |_| true,
+    );
+}
+
+// the first and last pixel of a width x height target triangle() can draw for pts, none
+// when it draws none
+pub fn triangle_bounds(
+    pts: &[Vector4<f32>; 3],
+    uniforms: &Uniforms,
+    width: u32,
+    height: u32,
+) -> Option<(Vector2<u32>, Vector2<u32>)> {
+    assemble(pts, uniforms.viewport)
+        .iter()
+        .filter_map(|primitive| clipped_bbox(&primitive.pts, 0.0, width, height))
+        .reduce(|(amin, amax), (bmin, bmax)| {
+            (
+                Vector2::new(amin.x.min(bmin.x), amin.y.min(bmin.y)),
+                Vector2::new(amax.x.max(bmax.x), amax.y.max(bmax.y)),
+            )
+        })
+}
+
+// the rasterizer behind triangle(), taking colour and depth on their own so hooks can write
+// to the other attachments of the same framebuffer
+fn rasterize<T: Shader, C: ColorBuffer, D: DepthBuffer, F: FnMut(Fragment) -> bool>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    image: &mut C,
+    zbuffer: &mut D,
+    mut tests: Tests,
+    hook: &mut F,
+) {
+    let (width, height) = image.dimensions();
+    for primitive in assemble(pts, uniforms.viewport) {
+        let Some((mut min, mut max)) = clipped_bbox(&primitive.pts, 0.0, width, height) else {
+            continue;
+        };
+        if let Some((smin, smax)) = tests.scissor {
+            (min, max) = (
+                Vector2::new(min.x.max(smin.x), min.y.max(smin.y)),
+                Vector2::new(max.x.min(smax.x), max.y.min(smax.y)),
+            );
+            if min.x > max.x || min.y > max.y {
+                continue;
+            }
+        }
+        rasterize_area(
+            &primitive,
+            (shader, uniforms),
+            image,
+            zbuffer,
+            &mut tests,
+            hook,
+            (min, max),
+        );
+    }
+}
+
+// A pyramid of the farthest depth under every block of HIZ_BLOCK x HIZ_BLOCK pixels, then
+// under every 2 x 2 of those and so on up to one cell for the whole target. Whatever is
+// no nearer than that depth can't be drawn there. Made from the depth buffer the first time
+// triangle_hiz() runs and kept up to date by it. Depth written any other way only leaves it
+// more cautious than it could be, as long as nothing is moved further away.
+pub struct HiZ {
+    // columns, rows and the cells of each level, the blocks first
+    levels: Vec<(u32, u32, Vec<f32>)>,
+    pub culled_triangles: usize,
+    pub culled_blocks: usize,
+}
+
+impl HiZ {
+    pub fn new<D: DepthBuffer>(zbuffer: &D, width: u32, height: u32) -> HiZ {
+        let (mut columns, mut rows) = (width.div_ceil(HIZ_BLOCK), height.div_ceil(HIZ_BLOCK));
+        let mut levels = vec![(columns, rows, vec![0.0; (columns * rows) as usize])];
+        while columns > 1 || rows > 1 {
+            (columns, rows) = (columns.div_ceil(2), rows.div_ceil(2));
+            levels.push((columns, rows, vec![0.0; (columns * rows) as usize]));
+        }
+        let mut hiz = HiZ {
+            levels,
+            culled_triangles: 0,
+            culled_blocks: 0,
+        };
+        for by in 0..height.div_ceil(HIZ_BLOCK) {
+            for bx in 0..width.div_ceil(HIZ_BLOCK) {
+                hiz.refresh(zbuffer, width, height, bx, by);
+            }
+        }
+        hiz
+    }
+
+    // the farthest depth under the cells from min to max (inclusive) of a level
+    fn farthest(&self, level: usize, min: Vector2<u32>, max: Vector2<u32>) -> f32 {
+        let (columns, _, cells) = &self.levels[level];
+        let mut farthest = f32::INFINITY;
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                farthest = farthest.min(cells[(y * columns + x) as usize]);
+            }
+        }
+        farthest
+    }
+
+    // true when nothing at depth or further can land on any pixel from min to max,
+    // checked at the first level where that is at most 2 x 2 cells
+    fn hides(&self, min: Vector2<u32>, max: Vector2<u32>, depth: f32) -> bool {
+        let (mut min, mut max) = (min / HIZ_BLOCK, max / HIZ_BLOCK);
+        let mut level = 0;
+        while max.x - min.x > 1 || max.y - min.y > 1 {
+            (min, max) = (min / 2, max / 2);
+            level += 1;
+        }
+        self.farthest(level, min, max) >= depth
+    }
+
+    // reads back one block after it was drawn to and carries it up the pyramid
+    fn refresh<D: DepthBuffer>(&mut self, zbuffer: &D, width: u32, height: u32, bx: u32, by: u32) {
+        let mut farthest = f32::INFINITY;
+        for y in by * HIZ_BLOCK..((by + 1) * HIZ_BLOCK).min(height) {
+            for x in bx * HIZ_BLOCK..((bx + 1) * HIZ_BLOCK).min(width) {
+                farthest = farthest.min(zbuffer.depth(x, y));
+            }
+        }
+        let (mut x, mut y) = (bx, by);
+        for level in 0..self.levels.len() {
+            if level > 0 {
+                // the children of this cell, some of which can be off the level below
+                let (columns, rows, _) = self.levels[level - 1];
+                let max = Vector2::new((x * 2 + 1).min(columns - 1), (y * 2 + 1).min(rows - 1));
+                farthest = self.farthest(level - 1, Vector2::new(x * 2, y * 2), max);
+            }
+            let (columns, _, cells) = &mut self.levels[level];
+            cells[(y * *columns + x) as usize] = farthest;
+            (x, y) = (x / 2, y / 2);
+        }
+    }
+}
+
+// Like triangle() but over blocks of HIZ_BLOCK x HIZ_BLOCK pixels, skipping whole blocks
+// that are outside one of the triangle's edges or behind everything drawn there before
+// according to the frame's hierarchical z. The triangle isn't looked at any closer when
+// all of its box is hidden. Draws exactly what triangle() would, faster when a lot of
+// what is drawn is hidden, in particular when the scene is drawn front to back.
+pub fn triangle_hiz<T: Shader, C: ColorBuffer, D: DepthBuffer>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer<C, D>,
 ) {
-    let mut bboxmin: Vector2<i32> = Vector2::new(i32::MAX, i32::MAX);
-    let mut bboxmax: Vector2<i32> = Vector2::new(-i32::MAX, -i32::MAX);
-    for i in 0..3 {
-        for j in 0..2 {
-            if pts[i][j].is_sign_negative() {
-                print!("Triangle outside bounds of canvas\n");
-                return;
+    let (width, height) = frame.dimensions();
+    let Framebuffer {
+        color,
+        depth,
+        hiz,
+        hook,
+        ..
+    } = frame;
+    let hiz = hiz.get_or_insert_with(|| HiZ::new(depth, width, height));
+    for primitive in assemble(pts, uniforms.viewport) {
+        let pts = &primitive.pts;
+        let Some((bboxmin, bboxmax)) = clipped_bbox(pts, 0.0, width, height) else {
+            continue;
+        };
+        // depth is a ratio of two things linear over the triangle, so it is largest at a vertex
+        let nearest = pts.iter().map(|pt| pt.z / pt.w).fold(f32::MIN, f32::max);
+        let nearest = depth.quantize((nearest + HIZ_DEPTH_MARGIN).clamp(0.0, DEPTH));
+        if hiz.hides(bboxmin, bboxmax, nearest) {
+            hiz.culled_triangles += 1;
+            continue;
+        }
+        let pts_2d = pts.map(|pt| Vector2::new(pt.x / pt.w, pt.y / pt.w));
+        for by in bboxmin.y / HIZ_BLOCK..=bboxmax.y / HIZ_BLOCK {
+            for bx in bboxmin.x / HIZ_BLOCK..=bboxmax.x / HIZ_BLOCK {
+                let min = Vector2::new(
+                    (bx * HIZ_BLOCK).max(bboxmin.x),
+                    (by * HIZ_BLOCK).max(bboxmin.y),
+                );
+                let max = Vector2::new(
+                    (bx * HIZ_BLOCK + HIZ_BLOCK - 1).min(bboxmax.x),
+                    (by * HIZ_BLOCK + HIZ_BLOCK - 1).min(bboxmax.y),
+                );
+                // barycentric coordinates are linear, so a block is outside an edge when all
+                // its corners are
+                let corners = [
+                    (min.x, min.y),
+                    (max.x, min.y),
+                    (min.x, max.y),
+                    (max.x, max.y),
+                ]
+                .map(|(x, y)| barycentric(&pts_2d, Vector2::new(x as f32, y as f32)));
+                let outside = (0..3).any(|k| corners.iter().all(|c| c[k] < -HIZ_EDGE_MARGIN));
+                if outside || hiz.farthest(0, Vector2::new(bx, by), Vector2::new(bx, by)) >= nearest
+                {
+                    hiz.culled_blocks += 1;
+                    continue;
+                }
+                let mut tests = Tests {
+                    hook: hook.as_deref(),
+                    ..Tests::default()
+                };
+                if rasterize_area(
+                    &primitive,
+                    (shader, uniforms),
+                    color,
+                    depth,
+                    &mut tests,
+                    &mut |_| true,
+                    (min, max),
+                ) {
+                    hiz.refresh(depth, width, height, bx, by);
+                }
             }
-            bboxmin[j] = bboxmin[j].min((pts[i][j] / pts[i].w) as i32);
-            bboxmax[j] = bboxmax[j].max((pts[i][j] / pts[i].w) as i32);
         }
     }
+}
+
+// what a fragment has to pass besides being inside the triangle, before it is shaded
+#[derive(Default)]
+struct Tests<'a> {
+    // depth is only written when it beats what is there by more than this
+    bias: u8,
+    stencil: Option<(&'a mut GrayImage, &'a StencilState)>,
+    // taken off the fragment's depth before it is tested
+    offset: DepthOffset,
+    // the frame's, after the one the rasterizer is given
+    hook: Option<&'a FragmentHook>,
+    // the first and last pixel that can be drawn
+    scissor: Option<(Vector2<u32>, Vector2<u32>)>,
+}
+
+// The pixels from min to max (inclusive) of rasterize(), which must be on the target and
+// under the primitive's bounding box. True when any of them was written.
+fn rasterize_area<T: Shader, C: ColorBuffer, D: DepthBuffer, F: FnMut(Fragment) -> bool>(
+    primitive: &Primitive,
+    (shader, uniforms): (&T, &Uniforms),
+    image: &mut C,
+    zbuffer: &mut D,
+    tests: &mut Tests,
+    hook: &mut F,
+    (bboxmin, bboxmax): (Vector2<u32>, Vector2<u32>),
+) -> bool {
+    let (bias, frame_hook) = (tests.bias, tests.hook);
+    let stencil = &mut tests.stencil;
+    let mut written = false;
+    let pts = &primitive.pts;
+    let offset = tests.offset.depth(pts);
     let pts_2d = pts.map(|pt| Vector2::new(pt.x / pt.w, pt.y / pt.w));
     for x in bboxmin.x..=bboxmax.x {
         for y in bboxmin.y..=bboxmax.y {
@@ -111,22 +1199,981 @@ pub fn triangle<T: Shader>(
             let z = pts[0].z * c.x + pts[1].z * c.y + pts[2].z * c.z;
             let w = pts[0].w * c.x + pts[1].w * c.y + pts[2].w * c.z;
 
-            let frag_depth = (z / w).clamp(0.0, 255.0) as u8;
-            if c.x < 0.0
-                || c.y < 0.0
-                || c.z < 0.0
-                || zbuffer.get_pixel(p.x as u32, p.y as u32)[0] >= frag_depth
-            {
+            let frag_depth = zbuffer.quantize((z / w - offset).clamp(0.0, DEPTH));
+            if c.x < 0.0 || c.y < 0.0 || c.z < 0.0 {
+                continue;
+            }
+            if let Some((buffer, state)) = stencil.as_mut() {
+                if !state.passes(buffer.get_pixel(x, y)[0]) {
+                    state.update(state.fail, buffer.get_pixel_mut(x, y));
+                    continue;
+                }
+            }
+            if zbuffer.depth(x, y) >= frag_depth + bias as f32 {
+                if let Some((buffer, state)) = stencil.as_mut() {
+                    state.update(state.depth_fail, buffer.get_pixel_mut(x, y));
+                }
                 continue;
             }
-            //print!("{} {} {}\n", pts[0].z, pts[1].z, pts[2].z);
 
+            let bar = primitive.bar(c);
             let mut color: Rgb<u8> = Rgb([0, 0, 0]);
-            let keep = shader.fragment(c, &mut color);
-            if keep {
-                zbuffer.put_pixel(p.x as u32, p.y as u32, Luma { 0: [frag_depth] });
-                image.put_pixel(p.x as u32, p.y as u32, color);
+            let keep = shader.fragment(bar, uniforms, &mut color)
+                && hook(Fragment {
+                    x,
+                    y,
+                    bar,
+                    depth: frag_depth,
+                    color: &mut color,
+                })
+                && frame_hook.is_none_or(|hook| {
+                    hook(Fragment {
+                        x,
+                        y,
+                        bar,
+                        depth: frag_depth,
+                        color: &mut color,
+                    })
+                });
+            if !keep {
+                continue;
+            }
+            if let Some((buffer, state)) = stencil.as_mut() {
+                state.update(state.pass, buffer.get_pixel_mut(x, y));
+                if !state.write {
+                    continue;
+                }
+            }
+            zbuffer.set_depth(x, y, frag_depth);
+            image.set_color(x, y, color);
+            written = true;
+        }
+    }
+    written
+}
+
+// Liang-Barsky, the part of the segment from a to b inside the rectangle from (0,0) to max
+fn clip_segment(
+    a: Vector2<f32>,
+    b: Vector2<f32>,
+    max: Vector2<f32>,
+) -> Option<(Vector2<f32>, Vector2<f32>)> {
+    let d = b - a;
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for (p, q) in [
+        (-d.x, a.x),
+        (d.x, max.x - a.x),
+        (-d.y, a.y),
+        (d.y, max.y - a.y),
+    ] {
+        if p == 0.0 {
+            // parallel to this edge, entirely inside or outside it
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t0 = t0.max(t);
+        } else {
+            t1 = t1.min(t);
+        }
+    }
+    (t0 <= t1).then(|| (a + d * t0, a + d * t1))
+}
+
+// Bresenham line from chapter 1, clipped to the image first so it never walks pixels
+// that can't be drawn
+pub fn line(x0: i32, y0: i32, x1: i32, y1: i32, image: &mut RgbImage, color: Rgb<u8>) {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let Some((a, b)) = clip_segment(
+        Vector2::new(x0 as f32, y0 as f32),
+        Vector2::new(x1 as f32, y1 as f32),
+        Vector2::new(width as f32 - 1.0, height as f32 - 1.0),
+    ) else {
+        return;
+    };
+    let (mut x0, mut y0) = (a.x.round() as i32, a.y.round() as i32);
+    let (mut x1, mut y1) = (b.x.round() as i32, b.y.round() as i32);
+
+    let steep = (x0 - x1).abs() < (y0 - y1).abs();
+    if steep {
+        mem::swap(&mut x0, &mut y0);
+        mem::swap(&mut x1, &mut y1);
+    }
+    if x0 > x1 {
+        mem::swap(&mut x0, &mut x1);
+        mem::swap(&mut y0, &mut y1);
+    }
+
+    let dx = x1 - x0;
+    let derror2 = (y1 - y0).abs() * 2;
+    let mut error2 = 0;
+    let mut y = y0;
+    for x in x0..=x1 {
+        let (px, py) = if steep { (y, x) } else { (x, y) };
+        if px >= 0 && py >= 0 && px < width as i32 && py < height as i32 {
+            image.put_pixel(px as u32, py as u32, color);
+        }
+        error2 += derror2;
+        if error2 > dx {
+            y += if y1 > y0 { 1 } else { -1 };
+            error2 -= dx * 2;
+        }
+    }
+}
+
+// how much of pixel p a line of the given width from a to b covers, from the distance of
+// its centre to the segment so the ends come out round
+fn segment_coverage(p: Vector2<f32>, a: Vector2<f32>, b: Vector2<f32>, width: f32) -> f32 {
+    let ab = b - a;
+    let length2 = ab.magnitude2();
+    let t = if length2 > 0.0 {
+        ((p - a).dot(ab) / length2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let distance = (p - (a + ab * t)).magnitude();
+    (width / 2.0 + 0.5 - distance).clamp(0.0, 1.0)
+}
+
+// An anti-aliased line width pixels wide through the points in order, with round joins and
+// ends. Every pixel is blended once with the most any segment covers it, so joins don't
+// come out darker than the segments.
+pub fn polyline(points: &[Vector2<f32>], width: f32, image: &mut RgbImage, color: Rgb<u8>) {
+    let (image_width, image_height) = image.dimensions();
+    if points.is_empty() || image_width == 0 || image_height == 0 {
+        return;
+    }
+    let reach = width / 2.0 + 1.0;
+    // the pixels the line can touch, clamped to the image
+    let bounds = |a: Vector2<f32>, b: Vector2<f32>| {
+        let x0 = (a.x.min(b.x) - reach).floor().max(0.0);
+        let y0 = (a.y.min(b.y) - reach).floor().max(0.0);
+        let x1 = (a.x.max(b.x) + reach).ceil().min(image_width as f32 - 1.0);
+        let y1 = (a.y.max(b.y) + reach).ceil().min(image_height as f32 - 1.0);
+        (x0 <= x1 && y0 <= y1).then_some((x0 as u32, y0 as u32, x1 as u32, y1 as u32))
+    };
+    let (min, max) = points.iter().fold((points[0], points[0]), |(min, max), p| {
+        (
+            Vector2::new(min.x.min(p.x), min.y.min(p.y)),
+            Vector2::new(max.x.max(p.x), max.y.max(p.y)),
+        )
+    });
+    let Some((left, bottom, right, top)) = bounds(min, max) else {
+        return;
+    };
+    let columns = (right - left + 1) as usize;
+    let mut coverage = vec![0.0f32; columns * (top - bottom + 1) as usize];
+
+    let segments: Vec<(Vector2<f32>, Vector2<f32>)> = if points.len() == 1 {
+        vec![(points[0], points[0])]
+    } else {
+        points.windows(2).map(|pair| (pair[0], pair[1])).collect()
+    };
+    for (a, b) in segments {
+        let Some((x0, y0, x1, y1)) = bounds(a, b) else {
+            continue;
+        };
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let covered = segment_coverage(Vector2::new(x as f32, y as f32), a, b, width);
+                let i = (y - bottom) as usize * columns + (x - left) as usize;
+                coverage[i] = coverage[i].max(covered);
+            }
+        }
+    }
+
+    for (i, covered) in coverage.into_iter().enumerate() {
+        if covered <= 0.0 {
+            continue;
+        }
+        let (x, y) = (left + (i % columns) as u32, bottom + (i / columns) as u32);
+        let pixel = image.get_pixel_mut(x, y);
+        for c in 0..3 {
+            pixel[c] =
+                (pixel[c] as f32 * (1.0 - covered) + color[c] as f32 * covered).round() as u8;
+        }
+    }
+}
+
+// polyline() of a single segment
+pub fn thick_line(
+    a: Vector2<f32>,
+    b: Vector2<f32>,
+    width: f32,
+    image: &mut RgbImage,
+    color: Rgb<u8>,
+) {
+    polyline(&[a, b], width, image, color);
+}
+
+// Draws the edges of every face over image, hidden ones included, for checking UV seams
+// and clipping. mat takes model space to the screen like Uniforms::to_screen().
+// Faces reaching behind the eye are left out. Edges more than a pixel wide are drawn
+// anti-aliased.
+pub fn wireframe(
+    model: &model::Model,
+    mat: Matrix4<f32>,
+    image: &mut RgbImage,
+    color: Rgb<u8>,
+    width: f32,
+) {
+    for face in model.get_faces() {
+        let clip = [0, 1, 2].map(|j| mat * model.get_verts()[face[j]].extend(1.0));
+        if clip.iter().any(|p| p.w <= 0.0 || p.w.is_nan()) {
+            continue;
+        }
+        if width > 1.0 {
+            let pts = clip.map(|p| Vector2::new(p.x / p.w, p.y / p.w));
+            polyline(&[pts[0], pts[1], pts[2], pts[0]], width, image, color);
+            continue;
+        }
+        // saturating, line() clips whatever lands far off the image
+        let pts = clip.map(|p| ((p.x / p.w) as i32, (p.y / p.w) as i32));
+        for j in 0..3 {
+            let (a, b) = (pts[j], pts[(j + 1) % 3]);
+            line(a.0, a.1, b.0, b.1, image, color);
+        }
+    }
+}
+
+// a point of a point cloud, drawn size pixels across whatever its distance
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+    pub position: Vector3<f32>,
+    pub size: f32,
+    pub color: Rgb<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointShape {
+    Square,
+    Disc,
+}
+
+impl std::str::FromStr for PointShape {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<PointShape> {
+        match s {
+            "square" => Ok(PointShape::Square),
+            "disc" => Ok(PointShape::Disc),
+            _ => Err(anyhow::anyhow!("unknown point shape '{}'", s)),
+        }
+    }
+}
+
+// Draws every point as a flat sprite facing the screen, depth tested and written at the
+// depth of its centre like any fragment of a triangle. mat takes the points to the screen
+// like Uniforms::to_screen(), points behind the eye are left out.
+pub fn points<C: ColorBuffer, D: DepthBuffer>(
+    points: &[Point],
+    mat: Matrix4<f32>,
+    shape: PointShape,
+    frame: &mut Framebuffer<C, D>,
+) {
+    let (width, height) = frame.dimensions();
+    for point in points {
+        let clip = mat * point.position.extend(1.0);
+        if clip.w <= 0.0 || clip.w.is_nan() {
+            continue;
+        }
+        let center = Vector2::new(clip.x / clip.w, clip.y / clip.w);
+        let depth = frame.depth.quantize((clip.z / clip.w).clamp(0.0, DEPTH));
+        let radius = point.size / 2.0;
+        // the pixels from center - radius up to but not including center + radius
+        let x0 = (center.x - radius).ceil().max(0.0) as u32;
+        let y0 = (center.y - radius).ceil().max(0.0) as u32;
+        let x1 = (center.x + radius).ceil().min(width as f32) as u32;
+        let y1 = (center.y + radius).ceil().min(height as f32) as u32;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let d = Vector2::new(x as f32, y as f32) - center;
+                if shape == PointShape::Disc && d.magnitude2() > radius * radius {
+                    continue;
+                }
+                if frame.depth.depth(x, y) >= depth {
+                    continue;
+                }
+                frame.depth.set_depth(x, y, depth);
+                frame.color.set_color(x, y, point.color);
+            }
+        }
+    }
+}
+
+// The planes of everything mat puts on a width x height target, in model space and facing
+// inwards. Only the sides and the eye are used as nothing is clipped against near and far,
+// the rasterizer clamps depth instead.
+pub struct Frustum {
+    planes: [Vector4<f32>; 5],
+}
+
+impl Frustum {
+    pub fn new(mat: Matrix4<f32>, width: u32, height: u32) -> Frustum {
+        // a screen point (x, y, z, w) is on the target when 0 <= x <= width * w,
+        // 0 <= y <= height * w and w > 0, each a plane through the rows of mat
+        let (x, y, w) = (mat.row(0), mat.row(1), mat.row(3));
+        Frustum {
+            planes: [x, w * width as f32 - x, y, w * height as f32 - y, w],
+        }
+    }
+
+    pub fn intersects_sphere(&self, center: Vector3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.dot(center.extend(1.0)) >= -radius * plane.truncate().magnitude())
+    }
+
+    // true when all of the triangle is behind one of the planes
+    pub fn culls(&self, pts: [Vector3<f32>; 3]) -> bool {
+        self.planes
+            .iter()
+            .any(|plane| pts.iter().all(|pt| plane.dot(pt.extend(1.0)) < 0.0))
+    }
+}
+
+// The faces of model that can land on a width x height target through mat, so the ones
+// that can't are never sent through the vertex shader. The whole model is skipped when
+// its bounding sphere misses, otherwise faces are tested one by one.
+pub fn visible_faces(
+    model: &model::Model,
+    mat: Matrix4<f32>,
+    width: u32,
+    height: u32,
+) -> impl Iterator<Item = usize> + '_ {
+    let frustum = Frustum::new(mat, width, height);
+    let (center, radius) = model.bounding_sphere();
+    let faces = if frustum.intersects_sphere(center, radius) {
+        model.get_faces().len()
+    } else {
+        tracing::debug!(
+            faces = model.get_faces().len(),
+            "bounding sphere is outside the frustum, every face culled"
+        );
+        0
+    };
+    (0..faces).filter(move |&i| {
+        let face = &model.get_faces()[i];
+        let culled = frustum.culls([0, 1, 2].map(|j| model.get_verts()[face[j]]));
+        if culled {
+            tracing::trace!(face = i, reason = "outside the frustum", "face culled");
+        }
+        !culled
+    })
+}
+
+// visible_faces() of every instance of model, each with the instance it is drawn through
+pub fn instanced_faces<'a>(
+    model: &'a model::Model,
+    instances: &'a [Instance],
+    mat: Matrix4<f32>,
+    width: u32,
+    height: u32,
+) -> impl Iterator<Item = (&'a Instance, usize)> + 'a {
+    instances.iter().flat_map(move |instance| {
+        visible_faces(model, mat * instance.transform, width, height).map(move |i| (instance, i))
+    })
+}
+
+// shaders that can fill a g-buffer, fragment() gives the albedo
+pub trait MultiTargetShader: Shader {
+    // the normal and position of the fragment encoded as colours
+    fn attributes(&self, bar: Vector3<f32>) -> (Rgb<u8>, Rgb<u8>);
+}
+
+// A deferred style pass, the albedo goes to the colour of the frame and the attributes to
+// its normal and position attachments.
+pub fn triangle_gbuffer<T: MultiTargetShader>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer,
+) {
+    let size = frame.dimensions();
+    let normal = attachment(&mut frame.normal, size);
+    let position = attachment(&mut frame.position, size);
+    rasterize(
+        pts,
+        shader,
+        uniforms,
+        &mut frame.color,
+        &mut frame.depth,
+        Tests {
+            hook: frame.hook.as_deref(),
+            ..Tests::default()
+        },
+        &mut |fragment| {
+            let (n, p) = shader.attributes(fragment.bar);
+            normal.put_pixel(fragment.x, fragment.y, n);
+            position.put_pixel(fragment.x, fragment.y, p);
+            true
+        },
+    );
+}
+
+// what is in front at a pixel of an id buffer, bar being where on the face like the
+// fragment shader got it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Id {
+    pub face: usize,
+    pub object: usize,
+    pub instance: usize,
+    pub bar: Vector3<f32>,
+}
+
+// Which face of which instance every pixel of a frame shows, for picking. (0,0) is the
+// bottom left.
+pub struct IdBuffer {
+    width: u32,
+    height: u32,
+    ids: Vec<Option<Id>>,
+}
+
+impl IdBuffer {
+    pub fn new(width: u32, height: u32) -> IdBuffer {
+        IdBuffer {
+            width,
+            height,
+            ids: vec![None; (width * height) as usize],
+        }
+    }
+
+    // none off the frame or where nothing was drawn
+    pub fn get(&self, x: u32, y: u32) -> Option<Id> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.ids[(y * self.width + x) as usize]
+    }
+
+    fn put(&mut self, x: u32, y: u32, id: Id) {
+        self.ids[(y * self.width + x) as usize] = Some(id);
+    }
+}
+
+// Like triangle() but the fragments that are kept also write the face, its object and the
+// instance being drawn to the frame's id buffer.
+pub fn triangle_id<T: Shader>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer,
+    (face, object, instance): (usize, usize, usize),
+) {
+    let (width, height) = frame.dimensions();
+    let ids = frame
+        .ids
+        .get_or_insert_with(|| IdBuffer::new(width, height));
+    rasterize(
+        pts,
+        shader,
+        uniforms,
+        &mut frame.color,
+        &mut frame.depth,
+        Tests {
+            hook: frame.hook.as_deref(),
+            ..Tests::default()
+        },
+        &mut |fragment| {
+            let id = Id {
+                face,
+                object,
+                instance,
+                bar: fragment.bar,
+            };
+            ids.put(fragment.x, fragment.y, id);
+            true
+        },
+    );
+}
+
+// shaders that can be depth peeled, fragment() gives the colour under any transparency
+pub trait TranslucentShader: Shader {
+    // how much of what is behind the fragment it hides, 0 to 1
+    fn opacity(&self, bar: Vector3<f32>) -> f32;
+}
+
+// Like triangle() into a layer of depth peeling, but only fragments behind the layer
+// peeled before at their pixel (front) can land. With a cleared layer the nearest of them
+// is kept, the next surface back. A front of infinity everywhere peels the nearest
+// surfaces. Depth is full precision so close layers stay apart, and the opacity of what
+// was kept goes to the alpha attachment.
+pub fn triangle_peel<T: TranslucentShader>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    layer: &mut Framebuffer<RgbImage, DepthImage>,
+    front: &DepthImage,
+) {
+    let size = layer.dimensions();
+    let alpha = attachment(&mut layer.alpha, size);
+    rasterize(
+        pts,
+        shader,
+        uniforms,
+        &mut layer.color,
+        &mut layer.depth,
+        Tests {
+            hook: layer.hook.as_deref(),
+            ..Tests::default()
+        },
+        &mut |fragment| {
+            if fragment.depth >= front.get_pixel(fragment.x, fragment.y)[0] {
+                return false;
             }
+            let opacity = shader.opacity(fragment.bar).clamp(0.0, 1.0);
+            alpha.put_pixel(fragment.x, fragment.y, Luma([(opacity * 255.0) as u8]));
+            true
+        },
+    );
+}
+
+// Counts the fragments of a triangle that would pass the depth test against zbuffer,
+// without shading them or writing anything. Like GL_SAMPLES_PASSED, overlapping triangles
+// of the same mesh are counted each time as they don't occlude one another.
+pub fn triangle_occlusion<D: DepthBuffer>(
+    pts: &[Vector4<f32>; 3],
+    uniforms: &Uniforms,
+    zbuffer: &D,
+) -> usize {
+    let (width, height) = zbuffer.dimensions();
+    let mut passed = 0;
+    rasterize(
+        pts,
+        &Unshaded,
+        uniforms,
+        &mut NoColor(width, height),
+        &mut ReadOnly(zbuffer),
+        Tests::default(),
+        &mut |_| {
+            passed += 1;
+            false
+        },
+    );
+    passed
+}
+
+// the fragment stage of a query, which keeps every fragment as it is
+struct Unshaded;
+
+impl Shader for Unshaded {
+    fn vertex(
+        &mut self,
+        _model: &model::Model,
+        _iface: usize,
+        _nthvert: usize,
+        _uniforms: &Uniforms,
+        _instance: &Instance,
+    ) -> Vector4<f32> {
+        unreachable!("queries are given their corners")
+    }
+
+    fn fragment(&self, _bar: Vector3<f32>, _uniforms: &Uniforms, _color: &mut Rgb<u8>) -> bool {
+        true
+    }
+}
+
+// the target of a query, of that size with nothing to write colour to
+struct NoColor(u32, u32);
+
+impl ColorBuffer for NoColor {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.0, self.1)
+    }
+    fn set_color(&mut self, _x: u32, _y: u32, _color: Rgb<u8>) {
+        unreachable!("queries discard every fragment")
+    }
+}
+
+// a depth buffer a query tests against but leaves alone
+struct ReadOnly<'a, D>(&'a D);
+
+impl<D: DepthBuffer> DepthBuffer for ReadOnly<'_, D> {
+    fn dimensions(&self) -> (u32, u32) {
+        self.0.dimensions()
+    }
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.0.depth(x, y)
+    }
+    fn set_depth(&mut self, _x: u32, _y: u32, _depth: f32) {
+        unreachable!("queries discard every fragment")
+    }
+    fn quantize(&self, depth: f32) -> f32 {
+        self.0.quantize(depth)
+    }
+}
+
+// Occlusion query for a whole mesh, only the vertex stage of the shader is run.
+// Zero means the mesh is completely hidden behind what is already in zbuffer.
+pub fn occlusion_query<T: Shader, D: DepthBuffer>(
+    model: &model::Model,
+    shader: &mut T,
+    uniforms: &Uniforms,
+    zbuffer: &D,
+) -> usize {
+    let mut passed = 0;
+    let (width, height) = zbuffer.dimensions();
+    for i in visible_faces(model, uniforms.to_screen(), width, height) {
+        let screen_coords =
+            [0, 1, 2].map(|j| shader.vertex(model, i, j, uniforms, &Instance::default()));
+        passed += triangle_occlusion(&screen_coords, uniforms, zbuffer);
+    }
+    passed
+}
+
+// Like triangle() but pixels straddling an edge are blended by how much of the pixel the
+// triangle covers, which smooths silhouettes without supersampling.
+// The coverage buffer holds how much of each pixel has been filled so far (255 is fully covered)
+// so that the two triangles either side of an interior edge add up to one opaque pixel
+// instead of each blending with the background. The colour must start cleared to black.
+pub fn triangle_coverage<T: Shader>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    frame: &mut Framebuffer,
+) {
+    let (width, height) = frame.dimensions();
+    let coverage_buffer = attachment(&mut frame.coverage, (width, height));
+    let (image, zbuffer) = (&mut frame.color, &mut frame.depth);
+    for primitive in assemble(pts, uniforms.viewport) {
+        let pts = &primitive.pts;
+        // one extra pixel around the box catches partially covered pixels
+        let Some((bboxmin, bboxmax)) = clipped_bbox(pts, 1.0, width, height) else {
+            continue;
+        };
+        let pts_2d = pts.map(|pt| Vector2::new(pt.x / pt.w, pt.y / pt.w));
+        let area2 = (pts_2d[1] - pts_2d[0]).perp_dot(pts_2d[2] - pts_2d[0]);
+        if area2.abs() < EPSILON {
+            continue;
         }
+
+        // signed distance in pixels from p to the edge opposite vertex i, positive inside
+        let edge_distance = |i: usize, p: Vector2<f32>| {
+            let a = pts_2d[(i + 1) % 3];
+            let e = pts_2d[(i + 2) % 3] - a;
+            e.perp_dot(p - a) * area2.signum() / e.magnitude()
+        };
+
+        for x in bboxmin.x..=bboxmax.x {
+            for y in bboxmin.y..=bboxmax.y {
+                let p: Vector2<f32> = Vector2::new(x as f32, y as f32);
+                let coverage = (0..3)
+                    .map(|i| (edge_distance(i, p) + 0.5).clamp(0.0, 1.0))
+                    .fold(1.0, f32::min);
+                if coverage <= 0.0 {
+                    continue;
+                }
+
+                // pixels just outside the triangle are shaded as if they were on its edge
+                let c = barycentric(&pts_2d, p).map(|e| e.max(0.0));
+                let c = c / (c.x + c.y + c.z);
+
+                let z = pts[0].z * c.x + pts[1].z * c.y + pts[2].z * c.z;
+                let w = pts[0].w * c.x + pts[1].w * c.y + pts[2].w * c.z;
+                let frag_depth = (z / w).clamp(0.0, 255.0) as u8;
+
+                let depth = zbuffer.get_pixel(x, y)[0];
+                let filled = coverage_buffer.get_pixel(x, y)[0] as f32 / 255.0;
+                let in_front = frag_depth > depth.saturating_add(COVERAGE_DEPTH_TOLERANCE);
+                let same_surface = frag_depth.abs_diff(depth) <= COVERAGE_DEPTH_TOLERANCE;
+                // anything further behind is hidden, even where the pixel isn't covered yet
+                if !in_front && (!same_surface || filled >= 1.0) {
+                    continue;
+                }
+
+                let mut color: Rgb<u8> = Rgb([0, 0, 0]);
+                if !shader.fragment(primitive.bar(c), uniforms, &mut color) {
+                    continue;
+                }
+
+                let dst = image.get_pixel_mut(x, y);
+                let filled = if in_front {
+                    // composite over whatever was behind
+                    zbuffer.put_pixel(x, y, Luma([frag_depth]));
+                    for k in 0..3 {
+                        dst[k] =
+                            (color[k] as f32 * coverage + dst[k] as f32 * (1.0 - coverage)) as u8;
+                    }
+                    coverage + filled * (1.0 - coverage)
+                } else {
+                    // the same surface filling the rest of the pixel
+                    let weight = coverage.min(1.0 - filled);
+                    for k in 0..3 {
+                        dst[k] = (dst[k] as f32 + color[k] as f32 * weight).min(255.0) as u8;
+                    }
+                    filled + weight
+                };
+                coverage_buffer.put_pixel(x, y, Luma([(filled * 255.0).round() as u8]));
+            }
+        }
+    }
+}
+
+// Multisampled colour and depth, every pixel holds `samples` of each.
+// Depth is kept at full precision, 0 is the far plane like the 8 bit zbuffer.
+pub struct MsaaTarget {
+    width: u32,
+    height: u32,
+    offsets: &'static [(f32, f32)],
+    color: Vec<Rgb<u8>>,
+    depth: Vec<f32>,
+}
+
+// standard sample positions, as offsets from the pixel centre
+const MSAA_1: [(f32, f32); 1] = [(0.0, 0.0)];
+const MSAA_2: [(f32, f32); 2] = [(0.25, 0.25), (-0.25, -0.25)];
+const MSAA_4: [(f32, f32); 4] = [
+    (-0.125, -0.375),
+    (0.375, -0.125),
+    (-0.375, 0.125),
+    (0.125, 0.375),
+];
+pub const MSAA_8: [(f32, f32); 8] = [
+    (0.0625, -0.1875),
+    (-0.0625, 0.1875),
+    (0.3125, 0.0625),
+    (-0.1875, -0.3125),
+    (-0.3125, 0.3125),
+    (-0.4375, -0.0625),
+    (0.1875, 0.4375),
+    (0.4375, -0.4375),
+];
+
+impl MsaaTarget {
+    // samples is rounded down to 1, 2, 4 or 8
+    pub fn new(width: u32, height: u32, samples: u32) -> MsaaTarget {
+        let offsets: &'static [(f32, f32)] = match samples {
+            0 | 1 => &MSAA_1,
+            2 | 3 => &MSAA_2,
+            4..=7 => &MSAA_4,
+            _ => &MSAA_8,
+        };
+        let len = (width * height) as usize * offsets.len();
+        MsaaTarget {
+            width,
+            height,
+            offsets,
+            color: vec![Rgb([0, 0, 0]); len],
+            depth: vec![0.0; len],
+        }
+    }
+
+    // averages the samples of every pixel into image and keeps the nearest sample of each
+    // in zbuffer, so later single sampled passes can still depth test against the result
+    pub fn resolve(&self, frame: &mut Framebuffer) {
+        let n = self.offsets.len();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let first = (y * self.width + x) as usize * n;
+                let mut sum = [0u32; 3];
+                for color in &self.color[first..first + n] {
+                    for k in 0..3 {
+                        sum[k] += color[k] as u32;
+                    }
+                }
+                frame
+                    .color
+                    .put_pixel(x, y, Rgb(sum.map(|c| (c / n as u32) as u8)));
+                let nearest = self.depth[first..first + n]
+                    .iter()
+                    .fold(0.0, |a: f32, &b| a.max(b));
+                frame.depth.put_pixel(x, y, Luma([nearest as u8]));
+            }
+        }
+    }
+}
+
+// Like triangle() but coverage and depth are tested at every sample position. The shader
+// still runs once per pixel, at the centre pulled into the triangle, and its colour goes
+// to all the samples that passed.
+pub fn triangle_msaa<T: Shader>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    target: &mut MsaaTarget,
+) {
+    for primitive in assemble(pts, uniforms.viewport) {
+        let pts = &primitive.pts;
+        // samples reach half a pixel either side of the centre
+        let Some((bboxmin, bboxmax)) = clipped_bbox(pts, 1.0, target.width, target.height) else {
+            continue;
+        };
+        let pts_2d = pts.map(|pt| Vector2::new(pt.x / pt.w, pt.y / pt.w));
+        let n = target.offsets.len();
+        let mut passed = [false; 8];
+        let mut depths = [0.0; 8];
+        for x in bboxmin.x..=bboxmax.x {
+            for y in bboxmin.y..=bboxmax.y {
+                let first = (y * target.width + x) as usize * n;
+                let mut any = false;
+                for (s, (dx, dy)) in target.offsets.iter().enumerate() {
+                    let c = barycentric(&pts_2d, Vector2::new(x as f32 + dx, y as f32 + dy));
+                    passed[s] = false;
+                    if c.x < 0.0 || c.y < 0.0 || c.z < 0.0 {
+                        continue;
+                    }
+                    let z = pts[0].z * c.x + pts[1].z * c.y + pts[2].z * c.z;
+                    let w = pts[0].w * c.x + pts[1].w * c.y + pts[2].w * c.z;
+                    let frag_depth = (z / w).clamp(0.0, DEPTH);
+                    if target.depth[first + s] >= frag_depth {
+                        continue;
+                    }
+                    depths[s] = frag_depth;
+                    passed[s] = true;
+                    any = true;
+                }
+                if !any {
+                    continue;
+                }
+
+                // pixels only partly covered are shaded as if they were on the triangle's edge
+                let c = barycentric(&pts_2d, Vector2::new(x as f32, y as f32)).map(|e| e.max(0.0));
+                let c = c / (c.x + c.y + c.z);
+                let mut color: Rgb<u8> = Rgb([0, 0, 0]);
+                if !shader.fragment(primitive.bar(c), uniforms, &mut color) {
+                    continue;
+                }
+                for s in 0..n {
+                    if passed[s] {
+                        target.color[first + s] = color;
+                        target.depth[first + s] = depths[s];
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Colour and depth that many threads can draw into at once, in two passes. The first keeps
+// the visible face of each pixel as one 64 bit atomic, the depth quantized to 8 bits like
+// the zbuffer above the face's place in the submission order, counted down. Keeping the
+// larger of two pixels keeps the nearer fragment, the earlier face on a tie, whatever
+// order the threads get there in. The second shades each pixel once, by the face that won.
+pub struct AtomicTarget {
+    width: u32,
+    height: u32,
+    pixels: Vec<AtomicU64>,
+    // the colour of shaded pixels with SHADED set, 0 for the rest
+    colors: Vec<AtomicU32>,
+}
+
+const SHADED: u32 = 1 << 24;
+
+impl AtomicTarget {
+    pub fn new(width: u32, height: u32) -> AtomicTarget {
+        AtomicTarget {
+            width,
+            height,
+            pixels: (0..width * height).map(|_| AtomicU64::new(0)).collect(),
+            colors: (0..width * height).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    fn pack(depth: u8, index: u32) -> u64 {
+        (depth as u64) << 32 | (u32::MAX - index) as u64
     }
+
+    fn unpack(pixel: u64) -> (u8, u32) {
+        ((pixel >> 32) as u8, u32::MAX - pixel as u32)
+    }
+
+    // copies the colour and depth of the pixels any face was kept at out to a frame of the
+    // same size
+    pub fn resolve(&self, frame: &mut Framebuffer) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = (y * self.width + x) as usize;
+                let color = self.colors[i].load(Ordering::Relaxed);
+                if color & SHADED == 0 {
+                    continue;
+                }
+                let (depth, _) = AtomicTarget::unpack(self.pixels[i].load(Ordering::Relaxed));
+                let [_, r, g, b] = color.to_be_bytes();
+                frame.color.put_pixel(x, y, Rgb([r, g, b]));
+                frame.depth.put_pixel(x, y, Luma([depth]));
+            }
+        }
+    }
+
+    // the pixels of the primitives of pts with their barycentric coordinates in each and
+    // their quantized depth, like rasterize() finds them
+    fn fragments(
+        &self,
+        pts: &[Vector4<f32>; 3],
+        uniforms: &Uniforms,
+        mut visit: impl FnMut(&Primitive, u32, u32, Vector3<f32>, u8),
+    ) {
+        for primitive in assemble(pts, uniforms.viewport) {
+            let pts = &primitive.pts;
+            let Some((bboxmin, bboxmax)) = clipped_bbox(pts, 0.0, self.width, self.height) else {
+                continue;
+            };
+            let pts_2d = pts.map(|pt| Vector2::new(pt.x / pt.w, pt.y / pt.w));
+            for x in bboxmin.x..=bboxmax.x {
+                for y in bboxmin.y..=bboxmax.y {
+                    let c = barycentric(&pts_2d, Vector2::new(x as f32, y as f32));
+                    if c.x < 0.0 || c.y < 0.0 || c.z < 0.0 {
+                        continue;
+                    }
+                    let z = pts[0].z * c.x + pts[1].z * c.y + pts[2].z * c.z;
+                    let w = pts[0].w * c.x + pts[1].w * c.y + pts[2].w * c.z;
+                    visit(&primitive, x, y, c, (z / w).clamp(0.0, DEPTH) as u8);
+                }
+            }
+        }
+    }
+}
+
+// The first pass of triangle() into a target shared between threads, index is the face's
+// place in the submission order. Fragments that are already behind what is there are thrown
+// away before the shader is asked whether it keeps them, the rest race to be kept. Like
+// against a cleared zbuffer the far plane never wins.
+pub fn triangle_atomic<T: Shader>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    target: &AtomicTarget,
+    index: u32,
+) {
+    target.fragments(pts, uniforms, |primitive, x, y, c, depth| {
+        let pixel = &target.pixels[(y * target.width + x) as usize];
+        let packed = AtomicTarget::pack(depth, index);
+        if depth == 0 || pixel.load(Ordering::Relaxed) >= packed {
+            return;
+        }
+        // only whether it is discarded, the colour is shaded once the winner is known
+        let mut color: Rgb<u8> = Rgb([0, 0, 0]);
+        if shader.fragment(primitive.bar(c), uniforms, &mut color) {
+            pixel.fetch_max(packed, Ordering::Relaxed);
+        }
+    });
+}
+
+// The second pass, once every face has been through triangle_atomic(): shades the pixels
+// the face at index was kept at. The first primitive of a clipped face to reach a pixel
+// shades it, like the zbuffer keeps it.
+pub fn shade_atomic<T: Shader>(
+    pts: &[Vector4<f32>; 3],
+    shader: &T,
+    uniforms: &Uniforms,
+    target: &AtomicTarget,
+    index: u32,
+) {
+    target.fragments(pts, uniforms, |primitive, x, y, c, _| {
+        let i = (y * target.width + x) as usize;
+        let (depth, winner) = AtomicTarget::unpack(target.pixels[i].load(Ordering::Relaxed));
+        if depth == 0 || winner != index || target.colors[i].load(Ordering::Relaxed) != 0 {
+            return;
+        }
+        let mut color: Rgb<u8> = Rgb([0, 0, 0]);
+        shader.fragment(primitive.bar(c), uniforms, &mut color);
+        let [r, g, b] = color.0;
+        target.colors[i].store(SHADED | u32::from_be_bytes([0, r, g, b]), Ordering::Relaxed);
+    });
 }
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::{imageops, ImageBuffer, ImageFormat, Luma, RgbImage};
+use std::fs::File;
+use std::io::BufWriter;
+use tinyrenderer::our_gl::{self, DepthImage};
+
+// from 1 to 100, for jpeg when --quality isn't given
+const JPEG_QUALITY: u8 = 90;
+
+// Where the frame is saved. The format follows the extension unless --format gives one,
+// --quality only matters for jpeg.
+pub struct Output {
+    pub path: String,
+    pub format: Option<ImageFormat>,
+    pub quality: u8,
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output {
+            path: String::from("output.tga"),
+            format: None,
+            quality: JPEG_QUALITY,
+        }
+    }
+}
+
+pub fn parse_format(name: &str) -> Result<ImageFormat> {
+    match name.to_ascii_lowercase().as_str() {
+        "tga" => Ok(ImageFormat::Tga),
+        "png" => Ok(ImageFormat::Png),
+        "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+        "bmp" => Ok(ImageFormat::Bmp),
+        _ => Err(anyhow!(
+            "unknown image format '{}', expected tga, png, jpeg or bmp",
+            name
+        )),
+    }
+}
+
+impl Output {
+    // --output, --format and --quality with their values
+    pub fn set(&mut self, flag: &str, value: &str) -> Result<()> {
+        match flag {
+            "--output" => self.path = String::from(value),
+            "--format" => self.format = Some(parse_format(value)?),
+            "--quality" => {
+                self.quality = value.parse()?;
+                if !(1..=100).contains(&self.quality) {
+                    return Err(anyhow!("--quality expects 1 to 100"));
+                }
+            }
+            _ => return Err(anyhow!("'{}' isn't an output flag", flag)),
+        }
+        Ok(())
+    }
+
+    pub fn format(&self) -> Result<ImageFormat> {
+        match self.format {
+            Some(format) => Ok(format),
+            None => parse_format(
+                std::path::Path::new(&self.path)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .with_context(|| format!("{} has no extension, give --format", self.path))?,
+            ),
+        }
+    }
+
+    pub fn save(&self, image: &RgbImage) -> Result<()> {
+        let format = self.format()?;
+        if format == ImageFormat::Jpeg {
+            let mut file = BufWriter::new(File::create(&self.path)?);
+            JpegEncoder::new_with_quality(&mut file, self.quality).encode_image(image)?;
+        } else {
+            image.save_with_format(&self.path, format)?;
+        }
+        Ok(())
+    }
+}
+
+// The zbuffer at full precision, from 0 where nothing was drawn up to 1 for the nearest.
+// .png gives 16 bit grey, .f32 or .raw little endian floats a row at a time from the top.
+pub fn save_depth(depth: &DepthImage, path: &str) -> Result<()> {
+    let depth = imageops::flip_vertical(depth);
+    let normalized = |x, y| (depth.get_pixel(x, y)[0] / our_gl::DEPTH).clamp(0.0, 1.0);
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => {
+            let image: ImageBuffer<Luma<u16>, Vec<u16>> =
+                ImageBuffer::from_fn(depth.width(), depth.height(), |x, y| {
+                    Luma([(normalized(x, y) * u16::MAX as f32).round() as u16])
+                });
+            image.save_with_format(path, ImageFormat::Png)?;
+        }
+        "f32" | "raw" => {
+            let mut bytes = Vec::with_capacity(depth.len() * 4);
+            for (x, y, _) in depth.enumerate_pixels() {
+                bytes.extend(normalized(x, y).to_le_bytes());
+            }
+            std::fs::write(path, bytes)?;
+        }
+        _ => {
+            return Err(anyhow!(
+                "--depth-out expects a .png, .f32 or .raw path, not {}",
+                path
+            ))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,1039 @@
+use anyhow::Result;
+use cgmath::{Vector2, Vector3, Vector4};
+use image::{imageops, ImageBuffer, Luma, Rgb, RgbImage, Rgba};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tinyrenderer::bvh::Bvh;
+use tinyrenderer::cubemap::CubeMap;
+use tinyrenderer::graph::{Context, Pass, Resource, Resources};
+use tinyrenderer::model::Model;
+use tinyrenderer::our_gl::{self, Point, Shader, TranslucentShader, Uniforms};
+use tinyrenderer::probes::ProbeGrid;
+use tinyrenderer::renderer::{self, rasterize, Progress};
+use tinyrenderer::{geometry, postprocess, shaders, tiled};
+
+use super::preview;
+use super::profile;
+use super::registry;
+#[cfg(feature = "script")]
+use super::script;
+use super::stream;
+
+// decals sit exactly on the surface they are drawn over, this lets them win the tie
+const DECAL_DEPTH_BIAS: u8 = 1;
+
+// --label text in the top left corner
+const LABEL_SCALE: u32 = 2;
+const LABEL_MARGIN: u32 = 8;
+
+// the outlines of --toon
+const OUTLINE_DEPTH_THRESHOLD: u8 = 4;
+const OUTLINE_WIDTH: u32 = 1;
+
+// --stencil-outline pushes a copy of the model out by this much along its normals, and
+// marks the model in this stencil bit so it can be used along with --stencil-mask
+const STENCIL_OUTLINE_WIDTH: f32 = 0.015;
+const STENCIL_OUTLINE_BIT: u8 = 0x80;
+
+// how many triangles to submit to the rasterizer between frames sent to a remote viewer
+const STREAM_INTERVAL: usize = 256;
+
+// a pass timed into the --profile report under its own name
+pub struct Timed<'a> {
+    pub pass: Box<dyn Pass + 'a>,
+    pub report: &'a Mutex<profile::Report>,
+}
+
+impl Pass for Timed<'_> {
+    fn name(&self) -> &'static str {
+        self.pass.name()
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        self.pass.inputs()
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        self.pass.outputs()
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, progress: Progress) -> Result<()> {
+        let start = Instant::now();
+        self.pass.run(context, resources, progress)?;
+        self.report.lock().unwrap().pass(self.pass.name(), start);
+        Ok(())
+    }
+}
+
+// The frame into "frame" through the shader --shader picks, the ones --group-shader gives
+// groups of the model or --debug-view's. The flags that change how it is rasterized pick
+// between the paths here, the caller checks the ones that can't be used together.
+pub struct MainPass<'a> {
+    pub shader: registry::Constructor,
+    pub group_shaders: &'a [(String, registry::Constructor)],
+    pub debug_view: Option<shaders::DebugView>,
+    #[cfg(feature = "script")]
+    pub script: Option<Arc<script::Script>>,
+    pub pcf_kernel: u32,
+    pub probes: &'a Option<ProbeGrid>,
+    pub traced: &'a Option<Arc<Bvh>>,
+    pub geometry: Option<geometry::Effect>,
+    pub msaa: Option<u32>,
+    pub edge_aa: bool,
+    pub tiled: bool,
+    pub hiz: bool,
+    pub parallel: bool,
+    pub peel: Option<u32>,
+    // the model only lands where it passes the stencil this mesh writes
+    pub stencil_mask: Option<(Model, our_gl::StencilCompare, our_gl::StencilOp)>,
+    // drawn over the model with its shader
+    pub decals: Vec<Model>,
+    // sees every fragment of the model, not of the decals
+    pub hook: Option<Arc<our_gl::FragmentHook>>,
+    // sent the frame as it is drawn
+    pub stream: Option<&'a Mutex<stream::FrameStream>>,
+    // told the shader's times and what was drawn, for --profile
+    pub report: Option<&'a Mutex<profile::Report>>,
+}
+
+impl Pass for MainPass<'_> {
+    fn name(&self) -> &'static str {
+        "main"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["shadow"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, progress: Progress) -> Result<()> {
+        let scene = context.scene;
+        let (model, instances) = (context.model, context.instances);
+        let (width, height) = (scene.width, scene.height);
+        let shadow = resources.shadow("shadow")?;
+        let uniforms = Uniforms {
+            shadow: shadow.matrix(&scene.camera),
+            ..scene.uniforms()
+        };
+        let mat = uniforms.to_screen();
+
+        let shader = match self.debug_view {
+            Some(view) => registry::Selected::Debug(shaders::DebugShader::new(view)),
+            None => registry::per_group(
+                self.shader,
+                self.group_shaders,
+                model,
+                &registry::Inputs {
+                    lights: &scene.lights,
+                    maps: context.maps,
+                    shadow,
+                    pcf_kernel: self.pcf_kernel,
+                    probes: self.probes,
+                    traced: self.traced,
+                },
+            )?,
+        };
+        #[cfg(feature = "script")]
+        let shader = script::Scripted::new(shader, self.script.clone());
+        let mut shader = profile::Profiled::new(shader, self.report.is_some());
+
+        let mut framebuffer: our_gl::Framebuffer = our_gl::Framebuffer::new(
+            context.acquire(width, height)?,
+            context.acquire(width, height)?,
+        );
+        if self.edge_aa {
+            framebuffer.coverage = Some(context.acquire(width, height)?);
+        }
+        let mut msaa_target = self
+            .msaa
+            .map(|samples| our_gl::MsaaTarget::new(width, height, samples));
+        let mut tiled_target = self.tiled.then(|| {
+            our_gl::Framebuffer::new(
+                tiled::Tiled::from_image(&framebuffer.color),
+                tiled::Tiled::from_image(&framebuffer.depth),
+            )
+        });
+        // mark the mask into the stencil, wherever it is on screen
+        let mut stencil_test = None;
+        if let Some((mesh, compare, op)) = &self.stencil_mask {
+            let mut mask_shader = shaders::DepthShader::new(&[]);
+            let mark = our_gl::StencilState {
+                depth_fail: *op,
+                pass: *op,
+                read_mask: !STENCIL_OUTLINE_BIT,
+                write_mask: !STENCIL_OUTLINE_BIT,
+                ..our_gl::StencilState::mark(1)
+            };
+            for i in our_gl::visible_faces(mesh, mat, width, height) {
+                let screen_coords = [0, 1, 2].map(|j| {
+                    mask_shader.vertex(mesh, i, j, &uniforms, &our_gl::Instance::default())
+                });
+                our_gl::triangle_stencil(
+                    &screen_coords,
+                    &mask_shader,
+                    &uniforms,
+                    &mut framebuffer,
+                    &mark,
+                );
+            }
+            stencil_test = Some(our_gl::StencilState {
+                read_mask: !STENCIL_OUTLINE_BIT,
+                write_mask: !STENCIL_OUTLINE_BIT,
+                ..our_gl::StencilState::test(*compare, 1)
+            });
+        }
+        framebuffer.hook = self.hook.clone();
+        if let Some(target) = tiled_target.as_mut() {
+            target.hook = self.hook.clone();
+        }
+        let submitted = Submitted {
+            stream: self.stream,
+            count: AtomicUsize::new(0),
+        };
+        let mut geometry = self.geometry;
+
+        let faces: Vec<_> = our_gl::instanced_faces(model, instances, mat, width, height).collect();
+        if faces.is_empty() {
+            tracing::warn!(
+                faces = model.get_faces().len(),
+                instances = instances.len(),
+                "no face is in front of the camera, the frame will be empty"
+            );
+        } else {
+            tracing::info!(
+                faces = model.get_faces().len() * instances.len(),
+                visible = faces.len(),
+                "faces left after culling"
+            );
+        }
+        if let Some(layers) = self.peel {
+            shader.set_hashed_alpha(false);
+            depth_peel(
+                model,
+                instances,
+                &mut shader,
+                &uniforms,
+                layers,
+                &mut framebuffer,
+                &submitted,
+            )?;
+        } else if let (true, Some(target)) = (self.parallel, tiled_target.as_mut()) {
+            rasterize_binned(model, instances, &shader, &uniforms, target, self.stream)?;
+        } else if self.parallel {
+            rasterize_parallel(
+                model,
+                instances,
+                &shader,
+                &uniforms,
+                &mut framebuffer,
+                &submitted,
+            )?;
+        } else {
+            let mut emitted = Vec::new();
+            for (n, &(instance, i)) in faces.iter().enumerate() {
+                if n % renderer::PROGRESS_INTERVAL == 0 {
+                    progress(n, faces.len());
+                }
+                let _face = tracing::trace_span!("face", index = i).entered();
+                let screen_coords =
+                    [0, 1, 2].map(|j| shader.vertex(model, i, j, &uniforms, instance));
+                renderer::primitives(
+                    geometry
+                        .as_mut()
+                        .map(|g| g as &mut dyn our_gl::GeometryShader),
+                    model,
+                    i,
+                    instance,
+                    &uniforms,
+                    screen_coords,
+                    &mut emitted,
+                );
+                for primitive in &emitted {
+                    let shader = our_gl::Remapped::new(&mut shader, primitive);
+                    if let Some(target) = msaa_target.as_mut() {
+                        our_gl::triangle_msaa(&primitive.pts, &shader, &uniforms, target);
+                    } else if self.edge_aa {
+                        our_gl::triangle_coverage(
+                            &primitive.pts,
+                            &shader,
+                            &uniforms,
+                            &mut framebuffer,
+                        );
+                    } else if let Some(target) = tiled_target.as_mut() {
+                        our_gl::triangle(&primitive.pts, &shader, &uniforms, target);
+                    } else if let Some(state) = &stencil_test {
+                        our_gl::triangle_stencil(
+                            &primitive.pts,
+                            &shader,
+                            &uniforms,
+                            &mut framebuffer,
+                            state,
+                        );
+                    } else if self.hiz {
+                        our_gl::triangle_hiz(&primitive.pts, &shader, &uniforms, &mut framebuffer);
+                    } else {
+                        our_gl::triangle(&primitive.pts, &shader, &uniforms, &mut framebuffer);
+                    }
+                    submitted.submit(|| {
+                        if let Some(target) = &msaa_target {
+                            target.resolve(&mut framebuffer);
+                        }
+                        if let Some(target) = &tiled_target {
+                            target.color.copy_to(&mut framebuffer.color);
+                        }
+                        framebuffer.color.clone()
+                    })?;
+                }
+            }
+        }
+        // peeling and the parallel path only say when they're done
+        progress(faces.len(), faces.len());
+        if let Some(target) = &msaa_target {
+            target.resolve(&mut framebuffer);
+        }
+        if let Some(hiz) = &framebuffer.hiz {
+            tracing::info!(
+                triangles = hiz.culled_triangles,
+                blocks = hiz.culled_blocks,
+                "hierarchical z culled"
+            );
+        }
+        // the passes after this one work on plain images
+        if let Some(target) = &tiled_target {
+            target.color.copy_to(&mut framebuffer.color);
+            target.depth.copy_to(&mut framebuffer.depth);
+        }
+        if let Some(report) = self.report {
+            let mut report = report.lock().unwrap();
+            report.vertex = shader.vertex_time();
+            report.fragment = shader.fragment_time();
+            report.fragments = shader.fragments();
+            report.count_triangles(model, instances, mat, width, height);
+            report.covered = framebuffer
+                .depth
+                .pixels()
+                .filter(|depth| depth[0] > 0)
+                .count();
+        }
+
+        framebuffer.hook = None;
+        for mesh in &self.decals {
+            for i in our_gl::visible_faces(mesh, mat, width, height) {
+                let screen_coords = [0, 1, 2]
+                    .map(|j| shader.vertex(mesh, i, j, &uniforms, &our_gl::Instance::default()));
+                our_gl::triangle_biased(
+                    &screen_coords,
+                    &shader,
+                    &uniforms,
+                    &mut framebuffer,
+                    DECAL_DEPTH_BIAS,
+                );
+            }
+        }
+        resources.insert("frame", Resource::Frame(Box::new(framebuffer)));
+        Ok(())
+    }
+}
+
+// the frame through every shader into "preview_matrix", the right way up, for eyeballing
+// regressions
+pub struct PreviewMatrixPass<'a> {
+    pub pcf_kernel: u32,
+    pub probes: &'a Option<ProbeGrid>,
+    pub environment: Option<&'a CubeMap>,
+}
+
+impl Pass for PreviewMatrixPass<'_> {
+    fn name(&self) -> &'static str {
+        "preview_matrix"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["shadow"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["preview_matrix"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, _progress: Progress) -> Result<()> {
+        let (lights, maps) = (&context.scene.lights, context.maps);
+        let shadow = resources.shadow("shadow")?;
+        let uniforms = Uniforms {
+            shadow: shadow.matrix(&context.scene.camera),
+            ..context.scene.uniforms()
+        };
+        let mut cells = vec![
+            (
+                String::from("gouraud"),
+                render(context, &mut shaders::GouraudShader::new(lights), &uniforms)?,
+            ),
+            (
+                String::from("funny"),
+                render(context, &mut shaders::FunnyShader::new(lights), &uniforms)?,
+            ),
+        ];
+        // these two have no fallback for untextured meshes, they only use the model's maps
+        if let Some(texture) = &maps[0].diffuse {
+            cells.push((
+                String::from("texture"),
+                render(
+                    context,
+                    &mut shaders::TextureShader::new(lights, texture.clone()),
+                    &uniforms,
+                )?,
+            ));
+            if let Some(normal_map) = &maps[0].normal {
+                cells.push((
+                    String::from("normal"),
+                    render(
+                        context,
+                        &mut shaders::NormalShader::new(
+                            lights,
+                            texture.clone(),
+                            normal_map.clone(),
+                            maps[0].normal_space,
+                        ),
+                        &uniforms,
+                    )?,
+                ));
+            }
+        }
+        cells.push((
+            String::from("specular"),
+            render(
+                context,
+                &mut shaders::SpecularShader::new(lights, maps),
+                &uniforms,
+            )?,
+        ));
+        cells.push((
+            String::from("shadow"),
+            render(
+                context,
+                &mut shadow.shader(lights, maps, self.pcf_kernel, self.probes.clone()),
+                &uniforms,
+            )?,
+        ));
+        if let Some(environment) = self.environment {
+            cells.push((
+                String::from("reflection"),
+                render(
+                    context,
+                    &mut shaders::ReflectionShader::new(environment),
+                    &uniforms,
+                )?,
+            ));
+        }
+        let grid = preview::grid(&cells);
+        for (_, cell) in cells {
+            context.release(cell);
+        }
+        resources.insert("preview_matrix", Resource::Color(grid));
+        Ok(())
+    }
+}
+
+// the frame cel shaded and outlined into "toon", the right way up
+pub struct ToonPass;
+
+impl Pass for ToonPass {
+    fn name(&self) -> &'static str {
+        "toon"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["toon"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, progress: Progress) -> Result<()> {
+        let scene = context.scene;
+        let mut frame = our_gl::Framebuffer::new(
+            context.acquire(scene.width, scene.height)?,
+            context.acquire(scene.width, scene.height)?,
+        );
+        renderer::rasterize_reporting(
+            context.model,
+            context.instances,
+            &mut shaders::ToonShader::new(&scene.lights, super::TOON_BANDS, context.maps),
+            &scene.uniforms(),
+            &mut frame,
+            progress,
+        );
+        postprocess::outline(
+            &mut frame.color,
+            &frame.depth,
+            OUTLINE_DEPTH_THRESHOLD,
+            OUTLINE_WIDTH,
+            Rgb([0, 0, 0]),
+        );
+        context.release(frame.depth);
+        imageops::flip_vertical_in_place(&mut frame.color);
+        resources.insert("toon", Resource::Color(frame.color));
+        Ok(())
+    }
+}
+
+// albedo, normals and positions of the frame into "gbuffer", (0,0) at the bottom left
+pub struct GBufferPass;
+
+impl Pass for GBufferPass {
+    fn name(&self) -> &'static str {
+        "gbuffer"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["gbuffer"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, _progress: Progress) -> Result<()> {
+        let scene = context.scene;
+        let (model, instances) = (context.model, context.instances);
+        let (width, height) = (scene.width, scene.height);
+        let uniforms = scene.uniforms();
+        let (min, max) = our_gl::instanced_bounds(model, instances);
+        let mut shader = shaders::GBufferShader::new(context.maps, min, max);
+        let mut targets = our_gl::Framebuffer::new(
+            context.acquire(width, height)?,
+            context.acquire(width, height)?,
+        );
+        targets.normal = Some(context.acquire(width, height)?);
+        targets.position = Some(context.acquire(width, height)?);
+        for (instance, i) in
+            our_gl::instanced_faces(model, instances, uniforms.to_screen(), width, height)
+        {
+            let screen_coords = [0, 1, 2].map(|j| shader.vertex(model, i, j, &uniforms, instance));
+            our_gl::triangle_gbuffer(&screen_coords, &shader, &uniforms, &mut targets);
+        }
+        resources.insert("gbuffer", Resource::Frame(Box::new(targets)));
+        Ok(())
+    }
+}
+
+// the frame as a mirror of the environment into "reflection", the right way up
+pub struct ReflectionPass<'a> {
+    pub environment: &'a CubeMap,
+}
+
+impl Pass for ReflectionPass<'_> {
+    fn name(&self) -> &'static str {
+        "reflection"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["reflection"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, _progress: Progress) -> Result<()> {
+        let reflection = render(
+            context,
+            &mut shaders::ReflectionShader::new(self.environment),
+            &context.scene.uniforms(),
+        )?;
+        resources.insert("reflection", Resource::Color(reflection));
+        Ok(())
+    }
+}
+
+// N.L of the first light over the frame into "ndotl", the right way up
+pub struct NdotLPass;
+
+impl Pass for NdotLPass {
+    fn name(&self) -> &'static str {
+        "ndotl"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["ndotl"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, _progress: Progress) -> Result<()> {
+        let n_dot_l = render(
+            context,
+            &mut shaders::NdotLShader::new(&context.scene.lights),
+            &context.scene.uniforms(),
+        )?;
+        resources.insert("ndotl", Resource::Color(n_dot_l));
+        Ok(())
+    }
+}
+
+// the frame's depth again at full precision into "depth", the one in "frame" only keeps
+// 8 bits
+pub struct DepthPass;
+
+impl Pass for DepthPass {
+    fn name(&self) -> &'static str {
+        "depth"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["depth"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, progress: Progress) -> Result<()> {
+        let scene = context.scene;
+        let mut frame = our_gl::Framebuffer::new(
+            context.acquire::<Rgb<u8>>(scene.width, scene.height)?,
+            our_gl::DepthImage::new(scene.width, scene.height),
+        );
+        renderer::rasterize_reporting(
+            context.model,
+            context.instances,
+            &mut shaders::ZShader::new(),
+            &scene.uniforms(),
+            &mut frame,
+            progress,
+        );
+        context.release(frame.color);
+        resources.insert("depth", Resource::Depth(frame.depth));
+        Ok(())
+    }
+}
+
+// point clouds drawn into "frame" with the model
+pub struct PointsPass {
+    pub clouds: Vec<Vec<Point>>,
+    pub shape: our_gl::PointShape,
+}
+
+impl Pass for PointsPass {
+    fn name(&self) -> &'static str {
+        "points"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, _progress: Progress) -> Result<()> {
+        let mat = context.scene.uniforms().to_screen();
+        let framebuffer = resources.frame("frame")?;
+        for points in &self.clouds {
+            our_gl::points(points, mat, self.shape, framebuffer);
+        }
+        Ok(())
+    }
+}
+
+// Everything the model covers is marked in the stencil of "frame", then a slightly bigger
+// copy is drawn where it isn't so only a rim around the silhouette is left.
+pub struct OutlinePass {
+    pub color: Rgb<u8>,
+}
+
+impl Pass for OutlinePass {
+    fn name(&self) -> &'static str {
+        "outline"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, _progress: Progress) -> Result<()> {
+        let (model, instances) = (context.model, context.instances);
+        let (width, height) = (context.scene.width, context.scene.height);
+        let uniforms = context.scene.uniforms();
+        let mat = uniforms.to_screen();
+        let framebuffer = resources.frame("frame")?;
+        let mut mark_shader = shaders::DepthShader::new(context.maps);
+        let mark = our_gl::StencilState {
+            read_mask: STENCIL_OUTLINE_BIT,
+            write_mask: STENCIL_OUTLINE_BIT,
+            ..our_gl::StencilState::mark(STENCIL_OUTLINE_BIT)
+        };
+        let mut hull = shaders::OutlineShader::new(self.color, STENCIL_OUTLINE_WIDTH);
+        let around = our_gl::StencilState {
+            read_mask: STENCIL_OUTLINE_BIT,
+            write_mask: STENCIL_OUTLINE_BIT,
+            ..our_gl::StencilState::test(our_gl::StencilCompare::NotEqual, STENCIL_OUTLINE_BIT)
+        };
+        for (instance, i) in our_gl::instanced_faces(model, instances, mat, width, height) {
+            let screen_coords =
+                [0, 1, 2].map(|j| mark_shader.vertex(model, i, j, &uniforms, instance));
+            our_gl::triangle_stencil(&screen_coords, &mark_shader, &uniforms, framebuffer, &mark);
+        }
+        for (instance, i) in our_gl::instanced_faces(model, instances, mat, width, height) {
+            let screen_coords = [0, 1, 2].map(|j| hull.vertex(model, i, j, &uniforms, instance));
+            our_gl::triangle_stencil(&screen_coords, &hull, &uniforms, framebuffer, &around);
+        }
+        Ok(())
+    }
+}
+
+// the edges of every face over "frame", after the post effects so the lines stay sharp
+pub struct WireframePass {
+    pub color: Rgb<u8>,
+    pub width: f32,
+}
+
+impl Pass for WireframePass {
+    fn name(&self) -> &'static str {
+        "wireframe"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, _progress: Progress) -> Result<()> {
+        let mat = context.scene.uniforms().to_screen();
+        let framebuffer = resources.frame("frame")?;
+        for instance in context.instances {
+            our_gl::wireframe(
+                context.model,
+                mat * instance.transform,
+                &mut framebuffer.color,
+                self.color,
+                self.width,
+            );
+        }
+        Ok(())
+    }
+}
+
+// the world axes over "frame", as long as the radius of the scene's bounding sphere
+pub struct AxesPass {
+    pub width: f32,
+}
+
+impl Pass for AxesPass {
+    fn name(&self) -> &'static str {
+        "axes"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, _progress: Progress) -> Result<()> {
+        let mat = context.scene.uniforms().to_screen();
+        let (_, radius) = our_gl::instanced_bounding_sphere(context.model, context.instances);
+        let framebuffer = resources.frame("frame")?;
+        let origin = mat * Vector4::new(0.0, 0.0, 0.0, 1.0);
+        for (axis, color) in [
+            (Vector3::unit_x(), Rgb([255, 0, 0])),
+            (Vector3::unit_y(), Rgb([0, 255, 0])),
+            (Vector3::unit_z(), Rgb([0, 0, 255])),
+        ] {
+            let end = mat * (axis * radius).extend(1.0);
+            if origin.w <= 0.0 || end.w <= 0.0 {
+                continue;
+            }
+            our_gl::thick_line(
+                Vector2::new(origin.x / origin.w, origin.y / origin.w),
+                Vector2::new(end.x / end.w, end.y / end.w),
+                self.width,
+                &mut framebuffer.color,
+                color,
+            );
+        }
+        Ok(())
+    }
+}
+
+// text stamped over "frame", {ms} in it is how long since start when it is
+pub struct LabelPass {
+    pub text: String,
+    pub start: Instant,
+}
+
+impl Pass for LabelPass {
+    fn name(&self) -> &'static str {
+        "label"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(
+        &self,
+        _context: &Context,
+        resources: &mut Resources,
+        _progress: Progress,
+    ) -> Result<()> {
+        let text = self
+            .text
+            .replace("{ms}", &self.start.elapsed().as_millis().to_string());
+        resources.frame("frame")?.draw_text(
+            LABEL_MARGIN,
+            LABEL_MARGIN,
+            LABEL_SCALE,
+            &text,
+            Rgb([255, 255, 255]),
+        );
+        Ok(())
+    }
+}
+
+// the scene through shader into a frame from the pool with (0,0) at the top left, ready to
+// save
+fn render<T: Shader>(context: &Context, shader: &mut T, uniforms: &Uniforms) -> Result<RgbImage> {
+    let (width, height) = (context.scene.width, context.scene.height);
+    let mut framebuffer = our_gl::Framebuffer::new(
+        context.acquire(width, height)?,
+        context.acquire(width, height)?,
+    );
+    rasterize(
+        context.model,
+        context.instances,
+        shader,
+        uniforms,
+        &mut framebuffer,
+    );
+    context.release(framebuffer.depth);
+    imageops::flip_vertical_in_place(&mut framebuffer.color);
+    Ok(framebuffer.color)
+}
+
+// Like rasterize() but the faces are split between the cores, each drawing its share with
+// its own copy of the shader into a target they share. Where two fragments are at the same
+// depth the one submitted earliest wins, like with the zbuffer, whatever order the threads
+// get there in. The faces are drawn twice, once to find what is visible at every pixel and
+// once to shade it.
+fn rasterize_parallel<T: Shader + Clone + Send + Sync>(
+    model: &Model,
+    instances: &[our_gl::Instance],
+    shader: &T,
+    uniforms: &Uniforms,
+    framebuffer: &mut our_gl::Framebuffer,
+    submitted: &Submitted,
+) -> Result<()> {
+    let (width, height) = framebuffer.dimensions();
+    let target = our_gl::AtomicTarget::new(width, height);
+    let faces: Vec<(&our_gl::Instance, usize)> =
+        our_gl::instanced_faces(model, instances, uniforms.to_screen(), width, height).collect();
+    let share = faces.len().div_ceil(rayon::current_num_threads()).max(1);
+    // only the second pass draws anything there is to see
+    let passes = [our_gl::triangle_atomic, our_gl::shade_atomic];
+    for (pass, shading) in passes.into_iter().zip([false, true]) {
+        faces
+            .par_chunks(share)
+            .enumerate()
+            .try_for_each(|(chunk, faces)| -> Result<()> {
+                let mut shader = shader.clone();
+                for (k, &(instance, i)) in faces.iter().enumerate() {
+                    let screen_coords =
+                        [0, 1, 2].map(|j| shader.vertex(model, i, j, uniforms, instance));
+                    pass(
+                        &screen_coords,
+                        &shader,
+                        uniforms,
+                        &target,
+                        (chunk * share + k) as u32,
+                    );
+                    if shading {
+                        submitted.submit(|| {
+                            let mut frame = our_gl::Framebuffer::new(
+                                RgbImage::new(width, height),
+                                image::GrayImage::new(width, height),
+                            );
+                            target.resolve(&mut frame);
+                            frame.color
+                        })?;
+                    }
+                }
+                Ok(())
+            })?;
+    }
+    target.resolve(framebuffer);
+    Ok(())
+}
+
+// --tiled with --parallel. The faces are binned by the tiles they land on, then each tile
+// draws its own faces on whichever thread is free. The tiles with the most faces go first,
+// so the ones the model covers don't hold up the end of the frame while those left empty
+// take no time. Each tile is sent to the viewer once drawn.
+fn rasterize_binned<T: Shader + Clone + Send + Sync>(
+    model: &Model,
+    instances: &[our_gl::Instance],
+    shader: &T,
+    uniforms: &Uniforms,
+    target: &mut our_gl::Framebuffer<tiled::Tiled<Rgb<u8>>, tiled::Tiled<Luma<u8>>>,
+    stream: Option<&Mutex<stream::FrameStream>>,
+) -> Result<()> {
+    let (width, height) = target.dimensions();
+    let faces: Vec<(&our_gl::Instance, usize)> =
+        our_gl::instanced_faces(model, instances, uniforms.to_screen(), width, height).collect();
+    let mut binning = shader.clone();
+    let bounds = faces.iter().map(|&(instance, i)| {
+        let screen_coords = [0, 1, 2].map(|j| binning.vertex(model, i, j, uniforms, instance));
+        our_gl::triangle_bounds(&screen_coords, uniforms, width, height)
+    });
+    let bins = tiled::bin(bounds, width, height);
+    let mut tiles: Vec<_> = target
+        .color
+        .tiles_mut()
+        .into_iter()
+        .zip(target.depth.tiles_mut())
+        .zip(bins)
+        .collect();
+    tiles.sort_by_key(|(_, bin)| std::cmp::Reverse(bin.len()));
+    tiles
+        .into_par_iter()
+        .with_max_len(1)
+        .try_for_each(|((color, depth), bin)| -> Result<()> {
+            let (min, max) = color.bounds();
+            let mut frame = our_gl::Framebuffer::new(color, depth);
+            let mut shader = shader.clone();
+            for &(instance, i) in bin.iter().map(|&n| &faces[n]) {
+                let screen_coords =
+                    [0, 1, 2].map(|j| shader.vertex(model, i, j, uniforms, instance));
+                our_gl::triangle_scissor(&screen_coords, &shader, uniforms, &mut frame, (min, max));
+            }
+            if let Some(stream) = stream {
+                // the viewer's rows go top down
+                let part = RgbImage::from_fn(max.x - min.x + 1, max.y - min.y + 1, |x, y| {
+                    *frame.color.get_pixel(min.x + x, max.y - y)
+                });
+                stream.lock().unwrap().send_part(
+                    (width, height),
+                    min.x,
+                    height - 1 - max.y,
+                    &part,
+                )?;
+            }
+            Ok(())
+        })
+}
+
+// Order independent transparency by depth peeling. Every pass draws the nearest surfaces
+// behind the ones peeled so far, and each layer is blended under those in front of it, in
+// the colour space the framebuffer is stored in. Opaque surfaces hide everything behind
+// them. Stops early when a pass draws nothing. The zbuffer ends up with the nearest layer.
+fn depth_peel<T: TranslucentShader>(
+    model: &Model,
+    instances: &[our_gl::Instance],
+    shader: &mut T,
+    uniforms: &Uniforms,
+    layers: u32,
+    framebuffer: &mut our_gl::Framebuffer,
+    submitted: &Submitted,
+) -> Result<()> {
+    let (width, height) = framebuffer.dimensions();
+    // premultiplied colour and opacity of the layers so far
+    let mut blended: ImageBuffer<Rgba<f32>, Vec<f32>> = ImageBuffer::new(width, height);
+    let mut front: our_gl::DepthImage =
+        ImageBuffer::from_pixel(width, height, Luma([f32::INFINITY]));
+    for layer in 0..layers {
+        let mut target = our_gl::Framebuffer::new(
+            RgbImage::new(width, height),
+            our_gl::DepthImage::new(width, height),
+        );
+        for (instance, i) in
+            our_gl::instanced_faces(model, instances, uniforms.to_screen(), width, height)
+        {
+            let screen_coords = [0, 1, 2].map(|j| shader.vertex(model, i, j, uniforms, instance));
+            our_gl::triangle_peel(&screen_coords, shader, uniforms, &mut target, &front);
+            submitted.submit(|| composite(&blended, &framebuffer.color))?;
+        }
+        let mut drawn = false;
+        for (x, y, pixel) in blended.enumerate_pixels_mut() {
+            let depth = target.depth.get_pixel(x, y)[0];
+            if depth <= 0.0 {
+                continue;
+            }
+            drawn = true;
+            let color = target.color.get_pixel(x, y);
+            let alpha = target
+                .alpha
+                .as_ref()
+                .map_or(255, |alpha| alpha.get_pixel(x, y)[0]) as f32
+                / 255.0;
+            let weight = (1.0 - pixel[3]) * alpha;
+            for c in 0..3 {
+                pixel[c] += weight * color[c] as f32;
+            }
+            pixel[3] += weight;
+            if layer == 0 {
+                framebuffer.depth.put_pixel(x, y, Luma([depth as u8]));
+            }
+        }
+        if !drawn {
+            break;
+        }
+        front = target.depth;
+    }
+    framebuffer.color = composite(&blended, &framebuffer.color);
+    Ok(())
+}
+
+// the premultiplied layers peeled so far over what is behind them
+fn composite(blended: &ImageBuffer<Rgba<f32>, Vec<f32>>, behind: &RgbImage) -> RgbImage {
+    let mut image = behind.clone();
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let layers = blended.get_pixel(x, y);
+        for c in 0..3 {
+            pixel[c] = (layers[c] + (1.0 - layers[3]) * pixel[c] as f32).round() as u8;
+        }
+    }
+    image
+}
+
+// Counts the triangles submitted to the rasterizer, whichever path draws them, to send the
+// frame to a remote viewer every STREAM_INTERVAL of them.
+struct Submitted<'a> {
+    stream: Option<&'a Mutex<stream::FrameStream>>,
+    count: AtomicUsize,
+}
+
+impl Submitted<'_> {
+    // one more triangle, frame gives what has been drawn so far when the viewer is due it
+    fn submit(&self, frame: impl FnOnce() -> RgbImage) -> Result<()> {
+        let Some(stream) = self.stream else {
+            return Ok(());
+        };
+        if (self.count.fetch_add(1, Ordering::Relaxed) + 1).is_multiple_of(STREAM_INTERVAL) {
+            let image = imageops::flip_vertical(&frame());
+            stream.lock().unwrap().send_frame(&image)?;
+        }
+        Ok(())
+    }
+}
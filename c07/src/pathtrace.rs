@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Result};
+use cgmath::{dot, ElementWise, InnerSpace, Matrix4, SquareMatrix, Transform, Vector2, Vector3};
+use rand::Rng;
+use std::f32::consts::PI;
+
+use super::bvh::{curved_position, Bvh};
+use super::camera::{primary_ray, Camera};
+use super::cubemap::CubeMap;
+use super::light::Light;
+use super::material::{Material, Sides};
+use super::model::Model;
+use super::our_gl::Instance;
+use super::shaders::{
+    cut_out, mapped_normal, object_frame, object_maps, reflect_light, tangent_frame, DIELECTRIC_KS,
+};
+use super::texture::{srgb_to_linear, Maps};
+
+// paths per pixel and how many times each is bounced off a surface after the first hit
+pub const SAMPLES: usize = 16;
+pub const BOUNCES: usize = 4;
+
+// how --backend draws the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Raster,
+    PathTrace,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Backend> {
+        match s {
+            "raster" => Ok(Backend::Raster),
+            "pathtrace" => Ok(Backend::PathTrace),
+            _ => Err(anyhow!("unknown backend '{}'", s)),
+        }
+    }
+}
+
+// a direction about n, more of them the closer they are to it
+fn cosine_sample<R: Rng>(n: Vector3<f32>, rng: &mut R) -> Vector3<f32> {
+    let (r1, r2): (f32, f32) = (rng.gen(), rng.gen());
+    let phi = 2.0 * PI * r1;
+    let r = r2.sqrt();
+    let a = if n.x.abs() > 0.9 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    let t = a.cross(n).normalize();
+    let b = n.cross(t);
+    (t * (phi.cos() * r) + b * (phi.sin() * r) + n * (1.0 - r2).sqrt()).normalize()
+}
+
+// The scene the rasterizer draws, path traced: the same model, maps, instances, lights and
+// environment seen through the same camera. Lights are reached by shadow rays and light
+// bounces diffusely between surfaces, paths that leave the scene see the environment map
+// or the lights' ambient light. Colours come out lit like the rasterizer's, a white surface
+// facing a light of 1 gives back 1, so the two can be compared side by side.
+pub struct PathTracer<'a> {
+    model: &'a Model,
+    maps: &'a [Maps],
+    instances: &'a [Instance],
+    lights: &'a [Light],
+    environment: Option<&'a CubeMap>,
+    sides: Sides,
+    bvh: Bvh,
+    bounces: usize,
+}
+
+impl<'a> PathTracer<'a> {
+    pub fn new(
+        model: &'a Model,
+        maps: &'a [Maps],
+        instances: &'a [Instance],
+        lights: &'a [Light],
+        environment: Option<&'a CubeMap>,
+        sides: Sides,
+        bounces: usize,
+    ) -> PathTracer<'a> {
+        PathTracer {
+            model,
+            maps,
+            instances,
+            lights,
+            environment,
+            sides,
+            bvh: Bvh::new(model, instances),
+            bounces,
+        }
+    }
+
+    // Linear colour of the screen pixel (x, y) of camera, (0,0) the bottom left like the
+    // framebuffer, averaged over samples paths through random spots in it.
+    pub fn pixel<R: Rng>(
+        &self,
+        camera: &Camera,
+        (x, y): (u32, u32),
+        samples: usize,
+        rng: &mut R,
+    ) -> Vector3<f32> {
+        let to_world = camera
+            .transform()
+            .inverse_transform()
+            .expect("the camera transform has no inverse");
+        let forward = (camera.target - camera.eye).normalize();
+        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            let (dx, dy): (f32, f32) = (rng.gen(), rng.gen());
+            let (origin, dir) =
+                primary_ray(to_world, camera.eye, forward, x as f32 + dx, y as f32 + dy);
+            sum += self.radiance(origin, dir, rng);
+        }
+        sum / samples.max(1) as f32
+    }
+
+    fn radiance<R: Rng>(
+        &self,
+        mut origin: Vector3<f32>,
+        mut dir: Vector3<f32>,
+        rng: &mut R,
+    ) -> Vector3<f32> {
+        let mut color = Vector3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut bounce = 0;
+        while bounce <= self.bounces {
+            let hit = match self.bvh.closest(origin, dir) {
+                Some(hit) => hit,
+                None => {
+                    // the background stays black like the rasterizer's
+                    if bounce > 0 {
+                        color += throughput.mul_element_wise(self.sky(dir));
+                    }
+                    break;
+                }
+            };
+            let p = origin + dir * hit.t;
+            let surface = match self.surface(hit.face, &self.instances[hit.instance], hit.bar, dir)
+            {
+                Some(surface) if rng.gen::<f32>() < surface.d => surface,
+                // holes and the uncovered part of see-through surfaces let the path carry on
+                _ => {
+                    origin = p + dir * self.bvh.epsilon();
+                    continue;
+                }
+            };
+
+            let kd = surface
+                .albedo
+                .mul_element_wise(Vector3::new(1.0, 1.0, 1.0) - surface.spec_color);
+            let mut diff = Vector3::new(0.0, 0.0, 0.0);
+            let mut spec = Vector3::new(0.0, 0.0, 0.0);
+            for light in self.lights {
+                let (l, attenuation) = light.incident(p);
+                let cos = dot(surface.n, l);
+                let lambert = match surface.sides {
+                    Sides::Two => cos.abs(),
+                    _ => cos.max(0.0),
+                };
+                if lambert <= 0.0 {
+                    continue;
+                }
+                let start =
+                    surface.curved + surface.ng * self.bvh.epsilon() * dot(surface.ng, l).signum();
+                if self
+                    .bvh
+                    .occluded(start, l, light.distance(start), |object, uv| {
+                        !cut_out(object_maps(self.maps, object), uv)
+                    })
+                {
+                    continue;
+                }
+                let r = surface.n * (2.0 * cos) - l;
+                let color = light.color * attenuation;
+                diff += color * (light.diffuse * lambert);
+                spec += color
+                    * (light.specular
+                        * dot(r, -dir).max(0.0).powf(surface.spec_pow)
+                        * cos.max(0.0));
+            }
+            color += throughput.mul_element_wise(reflect_light(
+                surface.albedo,
+                surface.spec_color,
+                surface.spec_pow,
+                diff,
+                spec,
+            ));
+
+            // cosine weighted bounces cancel the lambertian lobe's cosine and 1 / pi
+            throughput = throughput.mul_element_wise(kd);
+            if throughput.x.max(throughput.y).max(throughput.z) <= 0.0 {
+                break;
+            }
+            dir = cosine_sample(surface.n, rng);
+            if dot(dir, surface.ng) <= 0.0 {
+                break;
+            }
+            origin = p + surface.ng * self.bvh.epsilon();
+            bounce += 1;
+        }
+        color
+    }
+
+    // Linear radiance of the environment seen in direction dir. Without an environment map
+    // the lights' ambient terms light the scene evenly from every side instead, which comes
+    // to the rasterizer's ambient light on surfaces nothing shadows.
+    fn sky(&self, dir: Vector3<f32>) -> Vector3<f32> {
+        match self.environment {
+            Some(environment) => {
+                let texel = environment.sample(dir);
+                Vector3::new(
+                    srgb_to_linear(texel[0]),
+                    srgb_to_linear(texel[1]),
+                    srgb_to_linear(texel[2]),
+                )
+            }
+            None => self
+                .lights
+                .iter()
+                .map(|light| light.color * light.ambient)
+                .sum(),
+        }
+    }
+
+    // what the ray along dir finds where it hits the face, none where the face is cut out
+    fn surface(
+        &self,
+        iface: usize,
+        instance: &Instance,
+        bar: Vector2<f32>,
+        dir: Vector3<f32>,
+    ) -> Option<Surface> {
+        let model = self.model;
+        let face = &model.get_faces()[iface];
+        let bc = Vector3::new(1.0 - bar.x - bar.y, bar.x, bar.y);
+        let maps = object_maps(self.maps, model.get_object(iface));
+        let uv = model.get_uvs()[face[0]] * bc[0]
+            + model.get_uvs()[face[1]] * bc[1]
+            + model.get_uvs()[face[2]] * bc[2];
+        if cut_out(maps, uv) {
+            return None;
+        }
+        let default = Material::new("");
+        let material = model.get_material(iface).unwrap_or(&default);
+        let sides = material.sides.unwrap_or(self.sides);
+
+        let corners = [0, 1, 2].map(|j| instance.position(model.get_verts()[face[j]]));
+        let normals = [0, 1, 2].map(|j| instance.normal(model.get_norms()[face[j]]));
+        let ng = (corners[1] - corners[0])
+            .cross(corners[2] - corners[0])
+            .normalize();
+        let bn = (normals[0] * bc[0] + normals[1] * bc[1] + normals[2] * bc[2]).normalize();
+        let n = match &maps.normal {
+            Some(normal_map) => {
+                let frames = [0, 1, 2].map(|j| {
+                    tangent_frame(
+                        instance.tangent(model.get_tangents()[face[j]]),
+                        normals[j],
+                        Matrix4::identity(),
+                    )
+                });
+                let t = frames[0].0 * bc[0] + frames[1].0 * bc[1] + frames[2].0 * bc[2];
+                let b = frames[0].1 * bc[0] + frames[1].1 * bc[1] + frames[2].1 * bc[2];
+                mapped_normal(
+                    maps.normal_space,
+                    (t, b, bn),
+                    object_frame(model, iface, instance, Matrix4::identity()),
+                    normal_map.sample(uv),
+                )
+            }
+            None => bn,
+        };
+        // both normals face the ray, unless only the front is lit
+        let behind = sides != Sides::One && dot(bn, dir) > 0.0;
+        let flip = if behind { -1.0 } else { 1.0 };
+        let ng = if dot(ng, dir) > 0.0 { -ng } else { ng };
+
+        Some(Surface {
+            curved: curved_position(corners, normals, bc),
+            n: n * flip,
+            ng,
+            sides,
+            albedo: match &maps.diffuse {
+                Some(texture) => texture.sample_linear(uv),
+                None => material.kd,
+            },
+            spec_color: match (&maps.specular_color, model.get_material(iface)) {
+                (Some(specular_color_map), _) => specular_color_map.sample_linear(uv),
+                (None, Some(material)) => material.ks,
+                (None, None) => DIELECTRIC_KS,
+            }
+            .map(|c| c.clamp(0.0, 1.0)),
+            spec_pow: match &maps.specular {
+                Some(specular_map) => specular_map.sample(uv)[0] as f32,
+                None => material.ns,
+            },
+            d: material.d,
+        })
+    }
+}
+
+// what the path tracer shades a hit with, normals in world space
+struct Surface {
+    curved: Vector3<f32>, // where shadow rays start from, see curved_position()
+    n: Vector3<f32>,      // interpolated and normal mapped, for the lighting
+    ng: Vector3<f32>,     // of the flat face, towards the ray, for starting rays off it
+    sides: Sides,
+    albedo: Vector3<f32>,
+    spec_color: Vector3<f32>,
+    spec_pow: f32,
+    d: f32, // opacity
+}
@@ -0,0 +1,45 @@
+use anyhow::{anyhow, Context, Result};
+use cgmath::Vector3;
+use image::Rgb;
+use std::fs;
+
+use super::our_gl::Point;
+
+// Reads a point cloud in the plain text format most LiDAR tools export, one point a line
+// as x y z with optional r g b from 0 to 255 and a size in pixels after them. Points
+// without a colour are white and points without a size get size. Blank lines and lines
+// starting with # are skipped.
+pub fn load(filename: &str, size: f32) -> Result<Vec<Point>> {
+    let text = fs::read_to_string(filename)?;
+    let mut points = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line
+            .split_whitespace()
+            .map(|value| value.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .with_context(|| format!("{} line {}", filename, number + 1))?;
+        let channel = |c: f32| c.round().clamp(0.0, 255.0) as u8;
+        let (color, point_size) = match values[..] {
+            [_, _, _] => (Rgb([255, 255, 255]), size),
+            [_, _, _, r, g, b] => (Rgb([channel(r), channel(g), channel(b)]), size),
+            [_, _, _, r, g, b, s] => (Rgb([channel(r), channel(g), channel(b)]), s),
+            _ => {
+                return Err(anyhow!(
+                    "{} line {} should be x y z, x y z r g b or x y z r g b size",
+                    filename,
+                    number + 1
+                ))
+            }
+        };
+        points.push(Point {
+            position: Vector3::new(values[0], values[1], values[2]),
+            size: point_size,
+            color,
+        });
+    }
+    Ok(points)
+}
@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Result};
+use image::{ImageBuffer, Pixel};
+
+// Hands out cleared u8 images for intermediate passes (shadow maps, depth buffers, scratch
+// frames) and takes them back once a pass is done so the next pass can reuse the memory.
+// Everything handed out counts against the budget until it is released; images that are
+// kept (or dropped) instead of released stay counted.
+pub struct BufferPool {
+    budget: usize,
+    allocated: usize,
+    free: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    // budget is in bytes
+    pub fn new(budget: usize) -> BufferPool {
+        BufferPool {
+            budget,
+            allocated: 0,
+            free: Vec::new(),
+        }
+    }
+
+    pub fn acquire<P: Pixel<Subpixel = u8> + 'static>(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageBuffer<P, Vec<u8>>> {
+        let len = width as usize * height as usize * P::CHANNEL_COUNT as usize;
+
+        // the smallest free buffer that is big enough wastes the least
+        let reuse = self
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, buf)| buf.capacity() >= len)
+            .min_by_key(|(_, buf)| buf.capacity())
+            .map(|(i, _)| i);
+        let mut buf = match reuse {
+            Some(i) => self.free.swap_remove(i),
+            None => {
+                // make room by giving back free buffers that are too small to be useful
+                while self.allocated + len > self.budget && !self.free.is_empty() {
+                    let evicted = self.free.pop().unwrap();
+                    self.allocated = self.allocated.saturating_sub(evicted.capacity());
+                }
+                if self.allocated + len > self.budget {
+                    return Err(anyhow!(
+                        "{}x{} buffer would exceed the memory budget ({} of {} bytes in use)",
+                        width,
+                        height,
+                        self.allocated,
+                        self.budget
+                    ));
+                }
+                let buf = Vec::with_capacity(len);
+                self.allocated += buf.capacity();
+                buf
+            }
+        };
+        buf.clear();
+        buf.resize(len, 0);
+        Ok(ImageBuffer::from_raw(width, height, buf).expect("buffer sized for the image"))
+    }
+
+    // only images from acquire() should come back here
+    pub fn release<P: Pixel<Subpixel = u8> + 'static>(&mut self, image: ImageBuffer<P, Vec<u8>>) {
+        self.free.push(image.into_raw());
+    }
+}
@@ -0,0 +1,588 @@
+use anyhow::{anyhow, Context, Result};
+use image::{GrayImage, ImageBuffer, Luma, Pixel, Rgb, RgbImage};
+
+use super::our_gl::{Framebuffer, DEPTH};
+use super::texture::{linear_to_srgb, srgb_to_linear};
+
+// Inks silhouettes and creases by looking for jumps in the depth buffer.
+// A pixel is drawn in color when a neighbour within width pixels is more than threshold
+// further away, so the line sits on the nearer surface. Images must not be flipped yet
+// so they line up with the zbuffer.
+pub fn outline(
+    image: &mut RgbImage,
+    zbuffer: &GrayImage,
+    threshold: u8,
+    width: u32,
+    color: Rgb<u8>,
+) {
+    let (w, h) = zbuffer.dimensions();
+    let reach = width.max(1) as i64;
+    for y in 0..h {
+        for x in 0..w {
+            let depth = zbuffer.get_pixel(x, y)[0];
+            if depth == 0 {
+                continue; // background
+            }
+            let mut edge = false;
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= w as i64 || ny >= h as i64 {
+                        continue;
+                    }
+                    let neighbour = zbuffer.get_pixel(nx as u32, ny as u32)[0];
+                    // larger is nearer
+                    if depth > neighbour.saturating_add(threshold) {
+                        edge = true;
+                    }
+                }
+            }
+            if edge {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+const SSAO_DEPTH_BIAS: f32 = 2.0;
+
+// Screen space ambient occlusion from a depth buffer, 255 is fully open.
+// From every pixel rays are swept in 8 directions for up to radius pixels, keeping the
+// steepest rise of the depth buffer seen along each. depth_scale converts depth buffer
+// units into pixels so the angles are right.
+// Rises of a couple of units are ignored as they are just the 8 bit depth stepping.
+pub fn ambient_occlusion(zbuffer: &GrayImage, depth_scale: f32, radius: u32) -> GrayImage {
+    let (w, h) = zbuffer.dimensions();
+    let mut ao: GrayImage = ImageBuffer::from_pixel(w, h, Luma([255]));
+    let directions: Vec<(f32, f32)> = (0..8)
+        .map(|i| {
+            let angle = i as f32 * std::f32::consts::FRAC_PI_4;
+            (angle.cos(), angle.sin())
+        })
+        .collect();
+    for y in 0..h {
+        for x in 0..w {
+            let depth = zbuffer.get_pixel(x, y)[0] as f32;
+            if depth == 0.0 {
+                continue; // background
+            }
+            let mut total = 0.0;
+            for &(dx, dy) in &directions {
+                let mut max_elevation: f32 = 0.0;
+                for r in 1..=radius {
+                    let sx = (x as f32 + dx * r as f32).round();
+                    let sy = (y as f32 + dy * r as f32).round();
+                    if sx < 0.0 || sy < 0.0 || sx >= w as f32 || sy >= h as f32 {
+                        break;
+                    }
+                    let rise = (zbuffer.get_pixel(sx as u32, sy as u32)[0] as f32
+                        - depth
+                        - SSAO_DEPTH_BIAS)
+                        * depth_scale;
+                    max_elevation = max_elevation.max(rise.atan2(r as f32));
+                }
+                total += std::f32::consts::FRAC_PI_2 - max_elevation;
+            }
+            let visibility = total / (std::f32::consts::FRAC_PI_2 * directions.len() as f32);
+            ao.put_pixel(x, y, Luma([(visibility * 255.0) as u8]));
+        }
+    }
+    ao
+}
+
+// darkens image by an occlusion buffer from ambient_occlusion
+pub fn apply_occlusion(image: &mut RgbImage, ao: &GrayImage) {
+    for (pixel, occlusion) in image.pixels_mut().zip(ao.pixels()) {
+        let visibility = occlusion[0] as f32 / 255.0;
+        pixel.apply(|c| (c as f32 * visibility) as u8);
+    }
+}
+
+// FXAA tuning, the defaults of the "quality" preset
+const FXAA_EDGE_THRESHOLD: f32 = 0.125;
+const FXAA_EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const FXAA_SUBPIXEL: f32 = 0.75;
+// how far each step of the edge search moves along the edge, in pixels
+const FXAA_SEARCH_STEPS: [f32; 12] = [1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0];
+
+// Fast approximate anti-aliasing of a finished frame. Edges are found from jumps in luma,
+// followed along their length to both ends to work out where the pixel sits on the
+// stair step, and the pixel is then resampled that far across the edge. Needs nothing but
+// the colours so it works on any image, at the cost of some blur on fine texture.
+pub fn fxaa(image: &RgbImage) -> RgbImage {
+    let (w, h) = image.dimensions();
+    let luma: Vec<f32> = image
+        .pixels()
+        .map(|p| (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) / 255.0)
+        .collect();
+    // clamped to the image
+    let at = |x: i64, y: i64| -> f32 {
+        let x = x.clamp(0, w as i64 - 1);
+        let y = y.clamp(0, h as i64 - 1);
+        luma[(y * w as i64 + x) as usize]
+    };
+    let at_linear = |x: f32, y: f32| -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+        let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    };
+
+    let mut out = image.clone();
+    for y in 0..h {
+        for x in 0..w {
+            let (xi, yi) = (x as i64, y as i64);
+            let m = at(xi, yi);
+            let n = at(xi, yi - 1);
+            let s = at(xi, yi + 1);
+            let west = at(xi - 1, yi);
+            let east = at(xi + 1, yi);
+            let max = m.max(n).max(s).max(west).max(east);
+            let min = m.min(n).min(s).min(west).min(east);
+            let range = max - min;
+            if range < FXAA_EDGE_THRESHOLD_MIN.max(max * FXAA_EDGE_THRESHOLD) {
+                continue;
+            }
+            let nw = at(xi - 1, yi - 1);
+            let ne = at(xi + 1, yi - 1);
+            let sw = at(xi - 1, yi + 1);
+            let se = at(xi + 1, yi + 1);
+
+            // how much the pixel stands out from its neighbourhood, for edges thinner than
+            // a pixel that the search below can't follow
+            let average = (2.0 * (n + s + west + east) + nw + ne + sw + se) / 12.0;
+            let subpixel = ((average - m).abs() / range).clamp(0.0, 1.0);
+            let subpixel = (-2.0 * subpixel + 3.0) * subpixel * subpixel;
+            let subpixel = subpixel * subpixel * FXAA_SUBPIXEL;
+
+            let edge_horizontal = (nw - 2.0 * west + sw).abs()
+                + 2.0 * (n - 2.0 * m + s).abs()
+                + (ne - 2.0 * east + se).abs();
+            let edge_vertical = (nw - 2.0 * n + ne).abs()
+                + 2.0 * (west - 2.0 * m + east).abs()
+                + (sw - 2.0 * s + se).abs();
+            let horizontal = edge_horizontal >= edge_vertical;
+
+            // which side of the pixel the edge is on
+            let (luma1, luma2) = if horizontal { (n, s) } else { (west, east) };
+            let gradient1 = luma1 - m;
+            let gradient2 = luma2 - m;
+            let steepest1 = gradient1.abs() >= gradient2.abs();
+            let gradient = 0.25 * gradient1.abs().max(gradient2.abs());
+            let (step, local_average) = if steepest1 {
+                (-1.0, 0.5 * (luma1 + m))
+            } else {
+                (1.0, 0.5 * (luma2 + m))
+            };
+
+            // walk both ways along the edge, halfway between the pixel and its neighbour,
+            // until the luma leaves the local average
+            let (mut ex, mut ey) = (x as f32, y as f32);
+            let (dx, dy) = if horizontal {
+                ey += step * 0.5;
+                (1.0, 0.0)
+            } else {
+                ex += step * 0.5;
+                (0.0, 1.0)
+            };
+            let (mut x1, mut y1) = (ex - dx, ey - dy);
+            let (mut x2, mut y2) = (ex + dx, ey + dy);
+            let mut end1 = at_linear(x1, y1) - local_average;
+            let mut end2 = at_linear(x2, y2) - local_average;
+            let mut done1 = end1.abs() >= gradient;
+            let mut done2 = end2.abs() >= gradient;
+            for &distance in &FXAA_SEARCH_STEPS[1..] {
+                if done1 && done2 {
+                    break;
+                }
+                if !done1 {
+                    x1 -= dx * distance;
+                    y1 -= dy * distance;
+                    end1 = at_linear(x1, y1) - local_average;
+                    done1 = end1.abs() >= gradient;
+                }
+                if !done2 {
+                    x2 += dx * distance;
+                    y2 += dy * distance;
+                    end2 = at_linear(x2, y2) - local_average;
+                    done2 = end2.abs() >= gradient;
+                }
+            }
+
+            let (distance1, distance2) = if horizontal {
+                (x as f32 - x1, x2 - x as f32)
+            } else {
+                (y as f32 - y1, y2 - y as f32)
+            };
+            let (distance, end) = if distance1 < distance2 {
+                (distance1, end1)
+            } else {
+                (distance2, end2)
+            };
+            // only blend when the pixel is on the side of the step that the nearer end
+            // of the edge bends towards
+            let offset = if (end < 0.0) != (m < local_average) {
+                0.5 - distance / (distance1 + distance2)
+            } else {
+                0.0
+            };
+            let offset = offset.max(subpixel) * step;
+
+            let (sx, sy) = if horizontal {
+                (x as f32, y as f32 + offset)
+            } else {
+                (x as f32 + offset, y as f32)
+            };
+            out.put_pixel(x, y, sample_linear(image, sx, sy));
+        }
+    }
+    out
+}
+
+// bilinear filtered colour with (x, y) on a pixel centre giving that pixel, clamped
+fn sample_linear(image: &RgbImage, x: f32, y: f32) -> Rgb<u8> {
+    let (w, h) = image.dimensions();
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let pixel = |x: f32, y: f32| {
+        *image.get_pixel(
+            (x as i64).clamp(0, w as i64 - 1) as u32,
+            (y as i64).clamp(0, h as i64 - 1) as u32,
+        )
+    };
+    let corners = [
+        (pixel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+        (pixel(x0 + 1.0, y0), fx * (1.0 - fy)),
+        (pixel(x0, y0 + 1.0), (1.0 - fx) * fy),
+        (pixel(x0 + 1.0, y0 + 1.0), fx * fy),
+    ];
+    let mut color = [0.0f32; 3];
+    for (p, weight) in corners {
+        for c in 0..3 {
+            color[c] += p[c] as f32 * weight;
+        }
+    }
+    Rgb(color.map(|c| c.round().clamp(0.0, 255.0) as u8))
+}
+
+// One step of the post-processing chain, working on the colour of the frame with its depth
+// and any other attachments to go by. Frames are not flipped yet, and colours are sRGB
+// encoded like the rest of the output.
+pub trait PostEffect: Sync {
+    fn apply(&self, frame: &mut Framebuffer);
+}
+
+// the effects run on the finished frame, in the order they were pushed
+#[derive(Default)]
+pub struct Pipeline {
+    effects: Vec<Box<dyn PostEffect>>,
+}
+
+impl Pipeline {
+    pub fn push(&mut self, effect: Box<dyn PostEffect>) {
+        self.effects.push(effect);
+    }
+
+    pub fn apply(&self, frame: &mut Framebuffer) {
+        for effect in &self.effects {
+            effect.apply(frame);
+        }
+    }
+}
+
+// An effect as given on the command line, name=arguments with the arguments comma
+// separated, e.g. bloom=0.8,6 or lut=film.cube.
+pub fn parse_effect(spec: &str) -> Result<Box<dyn PostEffect>> {
+    let (name, args) = spec.split_once('=').unwrap_or((spec, ""));
+    let name = name.trim();
+    // the only one taking a file rather than numbers
+    if name == "lut" {
+        return Ok(Box::new(Lut::load(args.trim())?));
+    }
+    let numbers = args
+        .split(',')
+        .filter(|a| !a.trim().is_empty())
+        .map(|a| {
+            a.trim()
+                .parse::<f32>()
+                .with_context(|| format!("bad argument '{}' to {}", a, name))
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    Ok(match (name, numbers.as_slice()) {
+        ("fxaa", []) => Box::new(Fxaa),
+        ("tonemap", []) => Box::new(ToneMap { stops: 0.0 }),
+        ("tonemap", &[stops]) => Box::new(ToneMap { stops }),
+        ("bloom", []) => Box::new(Bloom {
+            threshold: BLOOM_THRESHOLD,
+            radius: BLOOM_RADIUS,
+        }),
+        ("bloom", &[threshold, radius]) => Box::new(Bloom {
+            threshold,
+            radius: radius as u32,
+        }),
+        ("vignette", []) => Box::new(Vignette {
+            strength: VIGNETTE_STRENGTH,
+        }),
+        ("vignette", &[strength]) => Box::new(Vignette { strength }),
+        ("dof", &[focus]) => Box::new(DepthOfField {
+            focus,
+            radius: DOF_RADIUS,
+        }),
+        ("dof", &[focus, radius]) => Box::new(DepthOfField { focus, radius }),
+        _ => {
+            return Err(anyhow!(
+                "unknown effect '{}', expected fxaa, tonemap[=stops], bloom[=threshold,radius], \
+                 vignette[=strength], dof=focus[,radius] or lut=file.cube",
+                spec
+            ))
+        }
+    })
+}
+
+pub struct Fxaa;
+
+impl PostEffect for Fxaa {
+    fn apply(&self, frame: &mut Framebuffer) {
+        frame.color = fxaa(&frame.color);
+    }
+}
+
+// Exposure in stops and a Reinhard curve that rolls highlights off instead of clipping,
+// with its white point moved with the exposure so white stays white.
+pub struct ToneMap {
+    pub stops: f32,
+}
+
+impl PostEffect for ToneMap {
+    fn apply(&self, frame: &mut Framebuffer) {
+        let image = &mut frame.color;
+        let white = 2f32.powf(self.stops);
+        for pixel in image.pixels_mut() {
+            pixel.apply(|c| {
+                let x = srgb_to_linear(c) * white;
+                linear_to_srgb(x * (1.0 + x / (white * white)) / (1.0 + x))
+            });
+        }
+    }
+}
+
+const BLOOM_THRESHOLD: f32 = 0.8;
+const BLOOM_RADIUS: u32 = 8;
+
+// Light above threshold (linear, 0 to 1) bleeds into its surroundings, spread by two box
+// blurs of radius pixels which come out close to a gaussian.
+pub struct Bloom {
+    pub threshold: f32,
+    pub radius: u32,
+}
+
+impl PostEffect for Bloom {
+    fn apply(&self, frame: &mut Framebuffer) {
+        let image = &mut frame.color;
+        let (w, h) = image.dimensions();
+        let mut glow: Vec<[f32; 3]> = image
+            .pixels()
+            .map(|p| [0, 1, 2].map(|c| (srgb_to_linear(p[c]) - self.threshold).max(0.0)))
+            .collect();
+        for _ in 0..2 {
+            glow = box_blur(&glow, w as usize, h as usize, self.radius as usize, true);
+            glow = box_blur(&glow, w as usize, h as usize, self.radius as usize, false);
+        }
+        for (pixel, glow) in image.pixels_mut().zip(glow) {
+            for c in 0..3 {
+                pixel[c] = linear_to_srgb(srgb_to_linear(pixel[c]) + glow[c]);
+            }
+        }
+    }
+}
+
+// mean over 2 * radius + 1 pixels along rows or columns, clamped to the image
+fn box_blur(
+    values: &[[f32; 3]],
+    w: usize,
+    h: usize,
+    radius: usize,
+    horizontal: bool,
+) -> Vec<[f32; 3]> {
+    let (lines, length) = if horizontal { (h, w) } else { (w, h) };
+    let index = |line: usize, i: usize| {
+        if horizontal {
+            line * w + i
+        } else {
+            i * w + line
+        }
+    };
+    let mut out = vec![[0.0; 3]; values.len()];
+    for line in 0..lines {
+        for i in 0..length {
+            let (lo, hi) = (i.saturating_sub(radius), (i + radius).min(length - 1));
+            let mut sum = [0.0; 3];
+            for j in lo..=hi {
+                let v = values[index(line, j)];
+                for c in 0..3 {
+                    sum[c] += v[c];
+                }
+            }
+            out[index(line, i)] = sum.map(|s| s / (2 * radius + 1) as f32);
+        }
+    }
+    out
+}
+
+const VIGNETTE_STRENGTH: f32 = 0.4;
+
+// darkens towards the corners, by strength of the light at the corners themselves
+pub struct Vignette {
+    pub strength: f32,
+}
+
+impl PostEffect for Vignette {
+    fn apply(&self, frame: &mut Framebuffer) {
+        let image = &mut frame.color;
+        let (w, h) = image.dimensions();
+        let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+        let corner2 = cx * cx + cy * cy;
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+            let falloff = 1.0 - self.strength * (dx * dx + dy * dy) / corner2;
+            pixel.apply(|c| linear_to_srgb(srgb_to_linear(c) * falloff));
+        }
+    }
+}
+
+const DOF_RADIUS: f32 = 8.0;
+
+// Blurs what is away from focus, a depth buffer value, by up to radius pixels for surfaces
+// a whole depth buffer away from it. A pixel averages the neighbours within its own blur
+// whose blur reaches it too, so sharp edges don't smear onto the blurred surfaces behind.
+pub struct DepthOfField {
+    pub focus: f32,
+    pub radius: f32,
+}
+
+impl DepthOfField {
+    // pixels of blur at a depth buffer value
+    pub fn blur(&self, depth: u8) -> f32 {
+        (depth as f32 - self.focus).abs() / DEPTH * self.radius
+    }
+}
+
+impl PostEffect for DepthOfField {
+    fn apply(&self, frame: &mut Framebuffer) {
+        let (w, h) = frame.color.dimensions();
+        let blur: Vec<f32> = frame.depth.pixels().map(|d| self.blur(d[0])).collect();
+        let linear: Vec<[f32; 3]> = frame
+            .color
+            .pixels()
+            .map(|p| [0, 1, 2].map(|c| srgb_to_linear(p[c])))
+            .collect();
+        for (x, y, pixel) in frame.color.enumerate_pixels_mut() {
+            let radius = blur[(y * w + x) as usize];
+            let reach = radius as i32;
+            let mut sum = [0.0; 3];
+            let mut count = 0.0;
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    if nx < 0 || ny < 0 || nx >= w as i32 || ny >= h as i32 {
+                        continue;
+                    }
+                    let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                    let n = (ny as u32 * w + nx as u32) as usize;
+                    if distance > radius || distance > blur[n] && distance > 0.0 {
+                        continue;
+                    }
+                    for c in 0..3 {
+                        sum[c] += linear[n][c];
+                    }
+                    count += 1.0;
+                }
+            }
+            for c in 0..3 {
+                pixel[c] = linear_to_srgb(sum[c] / count);
+            }
+        }
+    }
+}
+
+// A 3D colour lookup table from a .cube file, applied to the encoded colours with trilinear
+// interpolation like grading tools do.
+pub struct Lut {
+    size: usize,
+    table: Vec<[f32; 3]>, // red changes fastest, then green, then blue
+}
+
+impl Lut {
+    pub fn load(filename: &str) -> Result<Lut> {
+        let text = std::fs::read_to_string(filename)
+            .with_context(|| format!("can't read the lut '{}'", filename))?;
+        let mut size = 0;
+        let mut table = Vec::new();
+        for line in text.lines().map(str::trim) {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                None => {}
+                Some(word) if word.starts_with('#') || word == "TITLE" => {}
+                Some("DOMAIN_MIN") | Some("DOMAIN_MAX") => {}
+                Some("LUT_3D_SIZE") => {
+                    size = words
+                        .next()
+                        .context("LUT_3D_SIZE without a size")?
+                        .parse()?;
+                }
+                Some("LUT_1D_SIZE") => return Err(anyhow!("1D luts are not supported")),
+                Some(_) => {
+                    let rgb = line
+                        .split_whitespace()
+                        .map(str::parse::<f32>)
+                        .collect::<Result<Vec<f32>, _>>()
+                        .with_context(|| format!("bad lut entry '{}'", line))?;
+                    table.push(
+                        rgb.try_into()
+                            .map_err(|_| anyhow!("lut entry '{}' is not r g b", line))?,
+                    );
+                }
+            }
+        }
+        if size < 2 || table.len() != size * size * size {
+            return Err(anyhow!(
+                "lut '{}' has {} entries for a size of {}",
+                filename,
+                table.len(),
+                size
+            ));
+        }
+        Ok(Lut { size, table })
+    }
+
+    fn lookup(&self, color: &Rgb<u8>) -> Rgb<u8> {
+        let n = self.size - 1;
+        let scaled = [0, 1, 2].map(|c| color[c] as f32 / 255.0 * n as f32);
+        let lo = scaled.map(|s| (s.floor() as usize).min(n - 1));
+        let t = [0, 1, 2].map(|c| scaled[c] - lo[c] as f32);
+        let mut out = [0.0; 3];
+        for corner in 0..8 {
+            let pick = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let weight: f32 = (0..3)
+                .map(|c| if pick[c] == 1 { t[c] } else { 1.0 - t[c] })
+                .product();
+            let [r, g, b] = [0, 1, 2].map(|c| lo[c] + pick[c]);
+            let entry = self.table[r + self.size * (g + self.size * b)];
+            for c in 0..3 {
+                out[c] += weight * entry[c];
+            }
+        }
+        Rgb(out.map(|v| (v * 255.0).round().clamp(0.0, 255.0) as u8))
+    }
+}
+
+impl PostEffect for Lut {
+    fn apply(&self, frame: &mut Framebuffer) {
+        let image = &mut frame.color;
+        for pixel in image.pixels_mut() {
+            *pixel = self.lookup(pixel);
+        }
+    }
+}
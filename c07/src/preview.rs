@@ -0,0 +1,67 @@
+use super::font;
+use image::{imageops, ImageBuffer, Rgb, RgbImage};
+
+const LABEL_SCALE: u32 = 3;
+const LABEL_MARGIN: u32 = 8;
+const DIVIDER_WIDTH: u32 = 2;
+
+// tiles equally sized renders into a roughly square grid, each stamped with its label
+// in the top left corner
+pub fn grid(cells: &[(String, RgbImage)]) -> RgbImage {
+    if cells.is_empty() {
+        return ImageBuffer::new(0, 0);
+    }
+    let (cell_width, cell_height) = cells[0].1.dimensions();
+    let columns = (cells.len() as f32).sqrt().ceil() as u32;
+    let rows = (cells.len() as u32).div_ceil(columns);
+
+    let mut out: RgbImage = ImageBuffer::new(cell_width * columns, cell_height * rows);
+    for (n, (label, cell)) in cells.iter().enumerate() {
+        let x = (n as u32 % columns) * cell_width;
+        let y = (n as u32 / columns) * cell_height;
+        imageops::replace(&mut out, cell, x, y);
+        font::draw_text(
+            &mut out,
+            x + LABEL_MARGIN,
+            y + LABEL_MARGIN,
+            LABEL_SCALE,
+            label,
+            Rgb([255, 255, 255]),
+        );
+    }
+    out
+}
+
+// Two renders of the same frame side by side, left showing up to divider (a fraction of the
+// width) and right after it, with a line along the cut and each side labelled in its top corner.
+pub fn split(left: &(String, RgbImage), right: &(String, RgbImage), divider: f32) -> RgbImage {
+    let (width, height) = left.1.dimensions();
+    let cut = (divider.clamp(0.0, 1.0) * width as f32).round() as u32;
+    let mut out = ImageBuffer::from_fn(width, height, |x, y| {
+        if (cut..cut + DIVIDER_WIDTH).contains(&(x + DIVIDER_WIDTH / 2)) {
+            Rgb([255, 255, 255])
+        } else if x < cut {
+            *left.1.get_pixel(x, y)
+        } else {
+            *right.1.get_pixel(x, y)
+        }
+    });
+    font::draw_text(
+        &mut out,
+        LABEL_MARGIN,
+        LABEL_MARGIN,
+        LABEL_SCALE,
+        &left.0,
+        Rgb([255, 255, 255]),
+    );
+    let label_width = font::text_width(&right.0, LABEL_SCALE);
+    font::draw_text(
+        &mut out,
+        width.saturating_sub(label_width + LABEL_MARGIN),
+        LABEL_MARGIN,
+        LABEL_SCALE,
+        &right.0,
+        Rgb([255, 255, 255]),
+    );
+    out
+}
@@ -0,0 +1,211 @@
+use cgmath::{dot, ElementWise, InnerSpace, Vector3};
+use std::f32::consts::PI;
+
+use super::bvh::intersect;
+use super::cubemap::CubeMap;
+use super::light::Light;
+use super::model;
+use super::our_gl::Instance;
+use super::texture::srgb_to_linear;
+
+// radiance of rays that escape the scene when there is no environment map
+const SKY: Vector3<f32> = Vector3 {
+    x: 0.3,
+    y: 0.3,
+    z: 0.3,
+};
+
+// second order spherical harmonics, 9 RGB coefficients
+type Sh = [Vector3<f32>; 9];
+
+fn sh_basis(d: Vector3<f32>) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3.0 * d.z * d.z - 1.0),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+// cosine lobe convolution of each band, turns radiance into irradiance
+const SH_COSINE: [f32; 9] = [
+    PI,
+    2.0 * PI / 3.0,
+    2.0 * PI / 3.0,
+    2.0 * PI / 3.0,
+    PI / 4.0,
+    PI / 4.0,
+    PI / 4.0,
+    PI / 4.0,
+    PI / 4.0,
+];
+
+// evenly spread directions over the sphere
+fn fibonacci_sphere(n: usize) -> Vec<Vector3<f32>> {
+    let golden_angle = PI * (3.0 - 5f32.sqrt());
+    (0..n)
+        .map(|i| {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+            let r = (1.0 - y * y).sqrt();
+            let phi = i as f32 * golden_angle;
+            Vector3::new(phi.cos() * r, y, phi.sin() * r)
+        })
+        .collect()
+}
+
+// A regular grid of irradiance probes over a box, each baked by casting rays through the
+// scene. Shading reads the ambient light for any point and normal from the nearest 8
+// probes, so it works for objects that were not in the scene when it was baked.
+#[derive(Clone)]
+pub struct ProbeGrid {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    dims: [usize; 3],
+    probes: Vec<Sh>,
+}
+
+impl ProbeGrid {
+    // Rays that hit the model pick up the lights' direct diffuse light off the surface
+    // (unshadowed), rays that escape see the environment map or a grey sky.
+    pub fn bake(
+        model: &model::Model,
+        instances: &[Instance],
+        lights: &[Light],
+        environment: Option<&CubeMap>,
+        (min, max): (Vector3<f32>, Vector3<f32>),
+        dims: [usize; 3],
+        rays: usize,
+    ) -> ProbeGrid {
+        let dims = dims.map(|d| d.max(2));
+        let directions = fibonacci_sphere(rays);
+        let tris: Vec<[Vector3<f32>; 3]> = instances
+            .iter()
+            .flat_map(|instance| {
+                model.get_faces().iter().map(|face| {
+                    [face[0], face[1], face[2]].map(|v| instance.position(model.get_verts()[v]))
+                })
+            })
+            .collect();
+
+        let mut probes = Vec::with_capacity(dims[0] * dims[1] * dims[2]);
+        for k in 0..dims[2] {
+            for j in 0..dims[1] {
+                for i in 0..dims[0] {
+                    let t = Vector3::new(
+                        i as f32 / (dims[0] - 1) as f32,
+                        j as f32 / (dims[1] - 1) as f32,
+                        k as f32 / (dims[2] - 1) as f32,
+                    );
+                    let origin = min + (max - min).mul_element_wise(t);
+                    let mut sh: Sh = [Vector3::new(0.0, 0.0, 0.0); 9];
+                    for &dir in &directions {
+                        let radiance = Self::trace(model, &tris, lights, environment, origin, dir);
+                        for (c, y) in sh.iter_mut().zip(sh_basis(dir)) {
+                            *c += radiance * y;
+                        }
+                    }
+                    let weight = 4.0 * PI / rays as f32;
+                    probes.push(sh.map(|c| c * weight));
+                }
+            }
+        }
+        ProbeGrid {
+            min,
+            max,
+            dims,
+            probes,
+        }
+    }
+
+    fn trace(
+        model: &model::Model,
+        tris: &[[Vector3<f32>; 3]],
+        lights: &[Light],
+        environment: Option<&CubeMap>,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+    ) -> Vector3<f32> {
+        let nearest = tris
+            .iter()
+            .enumerate()
+            .filter_map(|(iface, tri)| intersect(origin, dir, *tri).map(|(t, _)| (iface, t)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let (iface, t) = match nearest {
+            Some(hit) => hit,
+            None => {
+                return match environment {
+                    Some(environment) => {
+                        let texel = environment.sample(dir);
+                        Vector3::new(
+                            srgb_to_linear(texel[0]),
+                            srgb_to_linear(texel[1]),
+                            srgb_to_linear(texel[2]),
+                        )
+                    }
+                    None => SKY,
+                }
+            }
+        };
+        let tri = tris[iface];
+        let n = (tri[1] - tri[0]).cross(tri[2] - tri[0]).normalize();
+        if dot(n, dir) > 0.0 {
+            // the inside of the mesh
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        let p = origin + dir * t;
+        let kd = model
+            .get_material(iface)
+            .map_or(Vector3::new(0.8, 0.8, 0.8), |m| m.kd);
+        let light: Vector3<f32> = lights
+            .iter()
+            .map(|light| {
+                let (l, attenuation) = light.incident(p);
+                light.color * attenuation * light.diffuse * dot(n, l).max(0.0)
+            })
+            .sum();
+        kd.mul_element_wise(light)
+    }
+
+    // light arriving at p over the hemisphere around the world space normal n
+    pub fn irradiance(&self, p: Vector3<f32>, n: Vector3<f32>) -> Vector3<f32> {
+        // trilinear weights of the 8 surrounding probes
+        let extent = self.max - self.min;
+        let mut base = [0usize; 3];
+        let mut frac = [0f32; 3];
+        for axis in 0..3 {
+            let cells = (self.dims[axis] - 1) as f32;
+            let g = ((p[axis] - self.min[axis]) / extent[axis] * cells).clamp(0.0, cells);
+            base[axis] = (g.floor() as usize).min(self.dims[axis] - 2);
+            frac[axis] = g - base[axis] as f32;
+        }
+        let mut sh: Sh = [Vector3::new(0.0, 0.0, 0.0); 9];
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0usize; 3];
+            for axis in 0..3 {
+                let step = (corner >> axis) & 1;
+                index[axis] = base[axis] + step;
+                weight *= if step == 1 {
+                    frac[axis]
+                } else {
+                    1.0 - frac[axis]
+                };
+            }
+            let probe =
+                &self.probes[index[0] + self.dims[0] * (index[1] + self.dims[1] * index[2])];
+            for (c, pc) in sh.iter_mut().zip(probe) {
+                *c += pc * weight;
+            }
+        }
+        let basis = sh_basis(n);
+        (0..9)
+            .map(|i| sh[i] * (SH_COSINE[i] * basis[i]))
+            .sum::<Vector3<f32>>()
+            .map(|c| c.max(0.0))
+    }
+}
@@ -0,0 +1,253 @@
+use cgmath::{Matrix4, Vector3, Vector4};
+use image::Rgb;
+use std::fmt::Write;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::model;
+use super::our_gl::{self, Instance, Shader, TranslucentShader, Uniforms};
+
+// how --profile prints its report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Format> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            _ => Err(anyhow::anyhow!("unknown report format '{}'", s)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    vertex_nanos: AtomicU64,
+    fragment_nanos: AtomicU64,
+    fragments: AtomicU64,
+}
+
+// A shader that times its vertex and fragment stages and counts the fragments it shades.
+// Clones share the counts so passes split between threads add up. When it is off it only
+// passes the calls through.
+#[derive(Clone)]
+pub struct Profiled<T> {
+    shader: T,
+    enabled: bool,
+    counters: Arc<Counters>,
+}
+
+impl<T> Profiled<T> {
+    pub fn new(shader: T, enabled: bool) -> Profiled<T> {
+        Profiled {
+            shader,
+            enabled,
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    pub fn vertex_time(&self) -> Duration {
+        Duration::from_nanos(self.counters.vertex_nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn fragment_time(&self) -> Duration {
+        Duration::from_nanos(self.counters.fragment_nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn fragments(&self) -> u64 {
+        self.counters.fragments.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Deref for Profiled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.shader
+    }
+}
+
+impl<T> DerefMut for Profiled<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.shader
+    }
+}
+
+impl<T: Shader> Shader for Profiled<T> {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &Uniforms,
+        instance: &Instance,
+    ) -> Vector4<f32> {
+        if !self.enabled {
+            return self
+                .shader
+                .vertex(model, iface, nthvert, uniforms, instance);
+        }
+        let start = Instant::now();
+        let position = self
+            .shader
+            .vertex(model, iface, nthvert, uniforms, instance);
+        let nanos = start.elapsed().as_nanos() as u64;
+        self.counters
+            .vertex_nanos
+            .fetch_add(nanos, Ordering::Relaxed);
+        position
+    }
+
+    fn fragment(&self, bar: Vector3<f32>, uniforms: &Uniforms, color: &mut Rgb<u8>) -> bool {
+        if !self.enabled {
+            return self.shader.fragment(bar, uniforms, color);
+        }
+        let start = Instant::now();
+        let keep = self.shader.fragment(bar, uniforms, color);
+        let nanos = start.elapsed().as_nanos() as u64;
+        self.counters
+            .fragment_nanos
+            .fetch_add(nanos, Ordering::Relaxed);
+        self.counters.fragments.fetch_add(1, Ordering::Relaxed);
+        keep
+    }
+}
+
+impl<T: TranslucentShader> TranslucentShader for Profiled<T> {
+    fn opacity(&self, bar: Vector3<f32>) -> f32 {
+        self.shader.opacity(bar)
+    }
+}
+
+// what a render did and how long each part of it took
+#[derive(Default)]
+pub struct Report {
+    // wall time of each pass in the order they ran
+    pub passes: Vec<(&'static str, Duration)>,
+    // of the main pass, summed over threads when it is split between them
+    pub vertex: Duration,
+    pub fragment: Duration,
+    // faces of every instance, those the frustum threw away and those reaching off the
+    // target or behind the eye that the rasterizer only draws part of
+    pub submitted: usize,
+    pub culled: usize,
+    pub clipped: usize,
+    pub fragments: u64,
+    // pixels the main pass drew to
+    pub covered: usize,
+}
+
+impl Report {
+    // adds the time since start as a pass
+    pub fn pass(&mut self, name: &'static str, start: Instant) {
+        self.passes.push((name, start.elapsed()));
+    }
+
+    // counts the faces the main pass is given, which reach the rasterizer and which of
+    // those are cut by the edges of the target
+    pub fn count_triangles(
+        &mut self,
+        model: &model::Model,
+        instances: &[Instance],
+        mat: Matrix4<f32>,
+        width: u32,
+        height: u32,
+    ) {
+        self.submitted = model.get_faces().len() * instances.len();
+        let mut visible = 0;
+        for (instance, i) in our_gl::instanced_faces(model, instances, mat, width, height) {
+            visible += 1;
+            let face = &model.get_faces()[i];
+            let clipped = (0..3).any(|j| {
+                let p = mat * instance.position(model.get_verts()[face[j]]).extend(1.0);
+                let (x, y) = (p.x / p.w, p.y / p.w);
+                p.w <= 0.0 || x < 0.0 || y < 0.0 || x > width as f32 || y > height as f32
+            });
+            if clipped {
+                self.clipped += 1;
+            }
+        }
+        self.culled = self.submitted - visible;
+    }
+
+    // the main pass less the time in its shaders
+    pub fn raster(&self) -> Duration {
+        let main = self
+            .passes
+            .iter()
+            .find(|(name, _)| *name == "main")
+            .map_or(Duration::ZERO, |pass| pass.1);
+        main.saturating_sub(self.vertex + self.fragment)
+    }
+
+    // fragments shaded for every pixel drawn
+    pub fn overdraw(&self) -> f32 {
+        self.fragments as f32 / self.covered.max(1) as f32
+    }
+
+    pub fn table(&self) -> String {
+        let milliseconds = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut table = String::new();
+        for (name, time) in &self.passes {
+            let _ = writeln!(table, "{:<10} {:>10.2} ms", name, milliseconds(*time));
+        }
+        let _ = writeln!(
+            table,
+            "{:<10} {:>10.2} ms",
+            "vertex",
+            milliseconds(self.vertex)
+        );
+        let _ = writeln!(
+            table,
+            "{:<10} {:>10.2} ms",
+            "raster",
+            milliseconds(self.raster())
+        );
+        let _ = writeln!(
+            table,
+            "{:<10} {:>10.2} ms",
+            "fragment",
+            milliseconds(self.fragment)
+        );
+        let _ = writeln!(table, "{:<10} {:>10}", "submitted", self.submitted);
+        let _ = writeln!(table, "{:<10} {:>10}", "culled", self.culled);
+        let _ = writeln!(table, "{:<10} {:>10}", "clipped", self.clipped);
+        let _ = writeln!(table, "{:<10} {:>10}", "fragments", self.fragments);
+        let _ = writeln!(table, "{:<10} {:>10}", "covered", self.covered);
+        let _ = write!(table, "{:<10} {:>10.2}", "overdraw", self.overdraw());
+        table
+    }
+
+    pub fn json(&self) -> String {
+        let milliseconds = |d: Duration| d.as_secs_f64() * 1000.0;
+        let passes = self
+            .passes
+            .iter()
+            .map(|(name, time)| format!("\"{}\": {}", name, milliseconds(*time)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{{\"passes\": {{{}}}, \"vertex\": {}, \"raster\": {}, \"fragment\": {}, \
+             \"submitted\": {}, \"culled\": {}, \"clipped\": {}, \"fragments\": {}, \
+             \"covered\": {}, \"overdraw\": {}}}",
+            passes,
+            milliseconds(self.vertex),
+            milliseconds(self.raster()),
+            milliseconds(self.fragment),
+            self.submitted,
+            self.culled,
+            self.clipped,
+            self.fragments,
+            self.covered,
+            self.overdraw()
+        )
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// characters across the bar
+const WIDTH: usize = 30;
+
+// A bar on stderr for the pass being drawn, redrawn in place with how long the pass has
+// left at the rate it has gone so far. Passes on other threads take turns on the line.
+#[derive(Default)]
+pub struct Bar {
+    started: Mutex<HashMap<&'static str, Instant>>,
+}
+
+impl Bar {
+    pub fn update(&self, pass: &'static str, done: usize, total: usize) {
+        let mut started = self.started.lock().unwrap();
+        let start = *started.entry(pass).or_insert_with(Instant::now);
+        let fraction = if total == 0 {
+            1.0
+        } else {
+            done as f32 / total as f32
+        };
+        let filled = ((fraction * WIDTH as f32) as usize).min(WIDTH);
+        let eta = if done == 0 {
+            String::from("?")
+        } else {
+            let elapsed = start.elapsed().as_secs_f32();
+            format!("{:.1}s", elapsed * (total - done) as f32 / done as f32)
+        };
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r{:<6} [{}{}] {:>3}% eta {:<8}",
+            pass,
+            "#".repeat(filled),
+            " ".repeat(WIDTH - filled),
+            (fraction * 100.0) as u32,
+            eta
+        );
+        if done >= total {
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{anyhow, Result};
+use cgmath::{Vector3, Vector4};
+use image::Rgb;
+use std::sync::Arc;
+use tinyrenderer::bvh::Bvh;
+use tinyrenderer::light::Light;
+use tinyrenderer::model;
+use tinyrenderer::our_gl::{Instance, Shader, TranslucentShader, Uniforms};
+use tinyrenderer::probes::ProbeGrid;
+use tinyrenderer::renderer::Shadow;
+use tinyrenderer::shaders;
+use tinyrenderer::texture::Maps;
+
+// everything the shaders of the main pass can be built from
+pub struct Inputs<'a> {
+    pub lights: &'a [Light],
+    pub maps: &'a [Maps],
+    pub shadow: &'a Shadow,
+    pub pcf_kernel: u32,
+    pub probes: &'a Option<ProbeGrid>,
+    pub traced: &'a Option<Arc<Bvh>>,
+}
+
+// one of the shaders --shader picks between
+#[derive(Clone)]
+pub enum Selected {
+    Gouraud(shaders::GouraudShader),
+    Toon(shaders::ToonShader),
+    NormalMap(shaders::NormalShader),
+    Specular(shaders::SpecularShader),
+    Shadow(Box<shaders::ShadowShader>), // by far the largest
+    // --debug-view's, in place of every other
+    Debug(shaders::DebugShader),
+    // one of shaders for each group of the model's faces, by_group indexes it
+    Groups {
+        shaders: Vec<Selected>,
+        by_group: Vec<usize>,
+        current: usize,
+    },
+}
+
+pub type Constructor = fn(&Inputs) -> Result<Selected>;
+
+// the shading models by name, the first is the default
+pub const SHADERS: [(&str, Constructor); 5] = [
+    ("shadow", |inputs| {
+        let mut shader = inputs.shadow.shader(
+            inputs.lights,
+            inputs.maps,
+            inputs.pcf_kernel,
+            inputs.probes.clone(),
+        );
+        shader.traced = inputs.traced.clone();
+        Ok(Selected::Shadow(Box::new(shader)))
+    }),
+    ("gouraud", |inputs| {
+        Ok(Selected::Gouraud(shaders::GouraudShader::new(
+            inputs.lights,
+        )))
+    }),
+    ("toon", |inputs| {
+        Ok(Selected::Toon(shaders::ToonShader::new(
+            inputs.lights,
+            crate::TOON_BANDS,
+            inputs.maps,
+        )))
+    }),
+    ("normalmap", |inputs| {
+        // only the model's own maps, there is nothing to fall back to
+        match (&inputs.maps[0].diffuse, &inputs.maps[0].normal) {
+            (Some(texture), Some(normal_map)) => {
+                Ok(Selected::NormalMap(shaders::NormalShader::new(
+                    inputs.lights,
+                    texture.clone(),
+                    normal_map.clone(),
+                    inputs.maps[0].normal_space,
+                )))
+            }
+            _ => Err(anyhow!(
+                "--shader normalmap needs the model's diffuse and normal maps"
+            )),
+        }
+    }),
+    ("specular", |inputs| {
+        Ok(Selected::Specular(shaders::SpecularShader::new(
+            inputs.lights,
+            inputs.maps,
+        )))
+    }),
+];
+
+pub fn lookup(name: &str) -> Result<Constructor> {
+    SHADERS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, constructor)| *constructor)
+        .ok_or_else(|| {
+            let names: Vec<&str> = SHADERS.iter().map(|(n, _)| *n).collect();
+            anyhow!(
+                "unknown shader '{}', expected one of {}",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+// The shader constructor builds, with the groups of model's faces named in overrides
+// drawn by their own instead.
+pub fn per_group(
+    constructor: Constructor,
+    overrides: &[(String, Constructor)],
+    model: &model::Model,
+    inputs: &Inputs,
+) -> Result<Selected> {
+    if overrides.is_empty() {
+        return constructor(inputs);
+    }
+    let mut shaders = vec![constructor(inputs)?];
+    let mut by_group = vec![0; model.group_names().len()];
+    for (name, constructor) in overrides {
+        let groups: Vec<usize> = model.groups_named(name).collect();
+        if groups.is_empty() {
+            return Err(anyhow!(
+                "the model has no group '{}', it has {}",
+                name,
+                model.group_names().join(", ")
+            ));
+        }
+        shaders.push(constructor(inputs)?);
+        for group in groups {
+            by_group[group] = shaders.len() - 1;
+        }
+    }
+    Ok(Selected::Groups {
+        shaders,
+        by_group,
+        current: 0,
+    })
+}
+
+impl Selected {
+    // off when depth peeling blends transparent surfaces instead, only shadow has it
+    pub fn set_hashed_alpha(&mut self, on: bool) {
+        match self {
+            Selected::Shadow(shader) => shader.hashed_alpha = on,
+            Selected::Groups { shaders, .. } => {
+                for shader in shaders {
+                    shader.set_hashed_alpha(on);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Shader for Selected {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &Uniforms,
+        instance: &Instance,
+    ) -> Vector4<f32> {
+        match self {
+            Selected::Gouraud(s) => s.vertex(model, iface, nthvert, uniforms, instance),
+            Selected::Toon(s) => s.vertex(model, iface, nthvert, uniforms, instance),
+            Selected::NormalMap(s) => s.vertex(model, iface, nthvert, uniforms, instance),
+            Selected::Specular(s) => s.vertex(model, iface, nthvert, uniforms, instance),
+            Selected::Shadow(s) => s.vertex(model, iface, nthvert, uniforms, instance),
+            Selected::Debug(s) => s.vertex(model, iface, nthvert, uniforms, instance),
+            Selected::Groups {
+                shaders,
+                by_group,
+                current,
+            } => {
+                *current = by_group[model.get_group(iface)];
+                shaders[*current].vertex(model, iface, nthvert, uniforms, instance)
+            }
+        }
+    }
+
+    fn fragment(&self, bar: Vector3<f32>, uniforms: &Uniforms, color: &mut Rgb<u8>) -> bool {
+        match self {
+            Selected::Gouraud(s) => s.fragment(bar, uniforms, color),
+            Selected::Toon(s) => s.fragment(bar, uniforms, color),
+            Selected::NormalMap(s) => s.fragment(bar, uniforms, color),
+            Selected::Specular(s) => s.fragment(bar, uniforms, color),
+            Selected::Shadow(s) => s.fragment(bar, uniforms, color),
+            Selected::Debug(s) => s.fragment(bar, uniforms, color),
+            Selected::Groups {
+                shaders, current, ..
+            } => shaders[*current].fragment(bar, uniforms, color),
+        }
+    }
+}
+
+// the others draw every surface opaque
+impl TranslucentShader for Selected {
+    fn opacity(&self, bar: Vector3<f32>) -> f32 {
+        match self {
+            Selected::Shadow(s) => s.opacity(bar),
+            Selected::Groups {
+                shaders, current, ..
+            } => shaders[*current].opacity(bar),
+            _ => 1.0,
+        }
+    }
+}
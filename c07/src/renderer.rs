@@ -0,0 +1,772 @@
+use anyhow::Result;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+use image::{imageops, GrayImage, Luma, Rgb, RgbImage};
+use std::sync::{Arc, Mutex};
+
+use super::camera::{self, Camera};
+use super::geometry::Effect;
+use super::graph::{Context, Graph, Pass, Resource, Resources};
+use super::light::{self, Light};
+use super::material::Sides;
+#[cfg(not(target_arch = "wasm32"))]
+use super::model;
+use super::model::Model;
+use super::our_gl::{
+    self, ColorBuffer, DepthBuffer, DepthImage, DepthOffset, FragmentHook, Framebuffer,
+    GeometryShader, Instance, Primitive, Remapped, Shader, Uniforms,
+};
+use super::pool::BufferPool;
+use super::postprocess::{self, Pipeline};
+use super::probes::ProbeGrid;
+use super::shaders;
+use super::shadowmap::CubeShadow;
+#[cfg(not(target_arch = "wasm32"))]
+use super::texture;
+use super::texture::Maps;
+
+pub const LIGHT_DIR: Vector3<f32> = Vector3 {
+    x: -1.0,
+    y: -1.0,
+    z: 2.0,
+};
+
+pub const DEFAULT_AMBIENT: f32 = 0.1;
+
+// the shadow map is square and independent of the frame size
+pub const SHADOW_SIZE: u32 = 2048;
+
+// width of the percentage-closer filter over the shadow buffer, 1 gives hard shadows
+pub const PCF_KERNEL: u32 = 3;
+
+// how far the shadow pass pushes depth away from the light, see our_gl::DepthOffset
+pub const SHADOW_BIAS: f32 = 1.0;
+pub const SHADOW_SLOPE_BIAS: f32 = 2.0;
+
+// how far in pixels ambient occlusion looks for occluders
+pub const SSAO_RADIUS: u32 = 40;
+
+// pixels of the frame a triangle facing the camera should cover at least, finer levels of
+// detail only add triangles smaller than that
+pub const LOD_PIXELS_PER_TRIANGLE: f32 = 16.0;
+
+// cap in bytes on the buffers the passes of a frame take from its pool
+pub const MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+
+// faces drawn between calls to a pass's progress
+pub const PROGRESS_INTERVAL: usize = 1024;
+
+// told how many of a pass's faces are drawn out of how many, every PROGRESS_INTERVAL and
+// once more when the pass is done
+pub type Progress<'a> = &'a (dyn Fn(usize, usize) + Sync);
+
+// a Progress for each pass of the renderer, told which one it is
+pub type PassProgress = Box<dyn Fn(&'static str, usize, usize) + Sync>;
+
+// What a frame is drawn from. Objects appended to the model keep their own maps.
+pub struct Scene {
+    pub model: Model,
+    // coarser versions of model, see mesh::lods(). Each instance is drawn with the one
+    // pick_lod() chooses for it
+    pub lods: Vec<Model>,
+    pub maps: Vec<Maps>,
+    pub instances: Vec<Instance>,
+    pub lights: Vec<Light>,
+    pub camera: Camera,
+    pub width: u32,
+    pub height: u32,
+    // which sides of faces are lit where their material doesn't say
+    pub sides: Sides,
+}
+
+impl Scene {
+    // The model at path (without .obj) and the maps next to it under the default light,
+    // framed by the camera like the renderer does on its own.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str, width: u32, height: u32) -> Result<Scene> {
+        let model = model::file_to_model(&format!("{}.obj", path))?;
+        let maps = texture::load_maps(
+            path,
+            model.get_materials(),
+            texture::WrapMode::Repeat,
+            texture::ColorSpace::Srgb,
+            texture::NormalSpace::Tangent,
+            &mut texture::TextureCache::default(),
+        )?;
+        let instances = vec![Instance::default()];
+        let mut camera = Camera::new(
+            Vector3::new(1.0, 0.0, 2.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            width,
+            height,
+        );
+        let (center, radius) = our_gl::instanced_bounding_sphere(&model, &instances);
+        camera.frame(center, radius);
+        Ok(Scene {
+            model,
+            lods: Vec::new(),
+            maps: vec![maps],
+            instances,
+            lights: vec![default_light()],
+            camera,
+            width,
+            height,
+            sides: Sides::default(),
+        })
+    }
+
+    // what the passes draw the scene with, the camera's matrices and the sides lit
+    pub fn uniforms(&self) -> Uniforms {
+        Uniforms {
+            sides: self.sides,
+            ..self.camera.uniforms()
+        }
+    }
+}
+
+// What the shadow pass leaves of the first light: the depth seen from a distant or spot light
+// along with the camera it was seen through, or the six around a point light.
+#[derive(Clone)]
+pub enum Shadow {
+    Map(DepthImage, Camera),
+    Cube(Arc<CubeShadow>),
+}
+
+impl Shadow {
+    // the shadow shader reading it
+    pub fn shader(
+        &self,
+        lights: &[Light],
+        maps: &[Maps],
+        pcf_kernel: u32,
+        probes: Option<ProbeGrid>,
+    ) -> shaders::ShadowShader {
+        match self {
+            Shadow::Map(buffer, _) => {
+                shaders::ShadowShader::new(lights, maps, buffer.clone(), pcf_kernel, probes)
+            }
+            Shadow::Cube(cube) => {
+                // the buffer is never read, the cube stands in for it
+                let mut shader = shaders::ShadowShader::new(
+                    lights,
+                    maps,
+                    DepthImage::new(1, 1),
+                    pcf_kernel,
+                    probes,
+                );
+                shader.cube_shadow = Some(cube.clone());
+                shader
+            }
+        }
+    }
+
+    // from the screen of camera into the shadow buffer's, the cube is looked up by position
+    pub fn matrix(&self, camera: &Camera) -> Matrix4<f32> {
+        match self {
+            Shadow::Map(_, light_camera) => camera.screen_to(light_camera),
+            Shadow::Cube(_) => Matrix4::identity(),
+        }
+    }
+
+    // the depth seen from the light as a grey image the right way up, like the lessons'
+    // depth.tga, none for a point light
+    pub fn image(&self) -> Option<GrayImage> {
+        let Shadow::Map(buffer, _) = self else {
+            return None;
+        };
+        let image = GrayImage::from_fn(buffer.width(), buffer.height(), |x, y| {
+            Luma([(255.0 * buffer.get_pixel(x, y)[0] / our_gl::DEPTH) as u8])
+        });
+        Some(imageops::flip_vertical(&image))
+    }
+}
+
+// Which of levels, each coarser than the one before, to draw an instance of a model with: the
+// coarsest that still gives it a triangle facing the camera every LOD_PIXELS_PER_TRIANGLE
+// pixels it covers on screen. About half of a closed model's triangles face it.
+pub fn pick_lod(levels: &[&Model], instance: &Instance, camera: &Camera) -> usize {
+    let Some(model) = levels.first() else {
+        return 0;
+    };
+    let (center, radius) = our_gl::instanced_bounding_sphere(model, &[*instance]);
+    let r = camera.projected_radius(center, radius);
+    let wanted = std::f32::consts::PI * r * r / LOD_PIXELS_PER_TRIANGLE;
+    levels
+        .iter()
+        .rposition(|level| level.triangle_count() as f32 / 2.0 >= wanted)
+        .unwrap_or(0)
+}
+
+// how the instances of a model are drawn at the levels pick_lod() gives each of them
+pub enum Lod {
+    // all of them picked this one, it is drawn through them as it is
+    Level(usize),
+    // every instance copied into one model at its own level, the maps repeated for the
+    // objects of each copy, to draw through a single identity instance
+    Copies(Box<Model>, Vec<Maps>),
+}
+
+pub fn instance_lods(
+    levels: &[&Model],
+    maps: &[Maps],
+    instances: &[Instance],
+    camera: &Camera,
+) -> Lod {
+    let picks: Vec<usize> = instances
+        .iter()
+        .map(|instance| pick_lod(levels, instance, camera))
+        .collect();
+    let first = picks.first().copied().unwrap_or(0);
+    if picks.iter().all(|&pick| pick == first) {
+        return Lod::Level(first);
+    }
+    let mut copies = instances.iter().zip(picks).map(|(instance, pick)| {
+        let mut copy = levels[pick].clone();
+        copy.transform(instance.transform);
+        copy
+    });
+    let mut model = copies.next().expect("more than one instance picked");
+    copies.for_each(|copy| model.append(copy));
+    let maps = maps
+        .iter()
+        .cycle()
+        .take(maps.len() * instances.len())
+        .cloned()
+        .collect();
+    Lod::Copies(Box::new(model), maps)
+}
+
+// a white light over the viewer's shoulder, dim ambient keeps the unlit side from going black
+pub fn default_light() -> Light {
+    let mut light = Light::white(light::Source::Directional {
+        dir: LIGHT_DIR.normalize(),
+    });
+    light.ambient = DEFAULT_AMBIENT;
+    light
+}
+
+// The plain pipeline, shadows from the first light, optional ambient occlusion and post
+// effects. Nothing is written to disk.
+pub struct Renderer {
+    pub shadow_size: u32,
+    pub shadow_offset: DepthOffset,
+    pub pcf_kernel: u32,
+    pub ssao: bool,
+    pub post: Pipeline,
+    // told the pass, "shadow", "ao" or "main", along with its progress
+    pub progress: Option<PassProgress>,
+    pub memory_budget: usize,
+    // sees every fragment of the main pass, see our_gl::Fragment
+    pub hook: Option<Arc<FragmentHook>>,
+}
+
+impl Default for Renderer {
+    fn default() -> Renderer {
+        Renderer {
+            shadow_size: SHADOW_SIZE,
+            shadow_offset: DepthOffset {
+                bias: SHADOW_BIAS,
+                slope_bias: SHADOW_SLOPE_BIAS,
+            },
+            pcf_kernel: PCF_KERNEL,
+            ssao: false,
+            post: Pipeline::default(),
+            progress: None,
+            memory_budget: MEMORY_BUDGET,
+            hook: None,
+        }
+    }
+}
+
+impl Renderer {
+    // the frame with (0,0) at the top left, ready to save or show
+    pub fn render(&self, scene: &Scene) -> Result<RgbImage> {
+        self.render_graph(&self.graph(), scene)
+    }
+
+    // The passes render() draws a frame with: "shadow" writes what the first light sees,
+    // "ao" the ambient occlusion, "main" the frame, "occlusion" darkens it by the ambient
+    // occlusion and "post" runs the post effects over it. Passes can be added before handing
+    // it to render_graph().
+    pub fn graph(&self) -> Graph<'_> {
+        let mut graph = Graph::default();
+        graph.add(Box::new(ShadowPass {
+            size: self.shadow_size,
+            offset: self.shadow_offset,
+            geometry: None,
+        }));
+        if self.ssao {
+            graph.add(Box::new(AmbientOcclusionPass));
+        }
+        graph.add(Box::new(MainPass {
+            pcf_kernel: self.pcf_kernel,
+            hook: self.hook.clone(),
+        }));
+        if self.ssao {
+            graph.add(Box::new(OcclusionPass));
+        }
+        graph.add(Box::new(PostPass { post: &self.post }));
+        graph
+    }
+
+    // the "frame" graph leaves, with (0,0) at the top left
+    pub fn render_graph(&self, graph: &Graph, scene: &Scene) -> Result<RgbImage> {
+        let mut framebuffer = self.run_graph(graph, scene)?.take_frame("frame")?;
+        imageops::flip_vertical_in_place(&mut framebuffer.color);
+        Ok(framebuffer.color)
+    }
+
+    // everything the passes of graph leave, for drawing more than the one frame
+    pub fn run_graph(&self, graph: &Graph, scene: &Scene) -> Result<Resources> {
+        let levels: Vec<&Model> = std::iter::once(&scene.model).chain(&scene.lods).collect();
+        let lod = instance_lods(&levels, &scene.maps, &scene.instances, &scene.camera);
+        let identity = [Instance::default()];
+        let pool = Mutex::new(BufferPool::new(self.memory_budget));
+        let context = match &lod {
+            Lod::Level(level) => Context {
+                scene,
+                model: levels[*level],
+                maps: &scene.maps,
+                instances: &scene.instances,
+                pool: &pool,
+            },
+            Lod::Copies(model, maps) => Context {
+                scene,
+                model,
+                maps,
+                instances: &identity,
+                pool: &pool,
+            },
+        };
+        let report = &self.progress;
+        graph.run(&context, &|pass, done, total| {
+            if let Some(report) = report {
+                report(pass, done, total);
+            }
+        })
+    }
+}
+
+// what the scene's first light sees into "shadow", through the geometry stage the main pass
+// is drawn with so shadows match
+pub struct ShadowPass {
+    pub size: u32,
+    pub offset: DepthOffset,
+    pub geometry: Option<Effect>,
+}
+
+impl Pass for ShadowPass {
+    fn name(&self) -> &'static str {
+        "shadow"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["shadow"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, progress: Progress) -> Result<()> {
+        let scene = context.scene;
+        let mut geometry = self.geometry;
+        let geometry = geometry.as_mut().map(|g| g as &mut dyn GeometryShader);
+        let shadow = match scene.lights[0].source {
+            light::Source::Point { position, .. } => Shadow::Cube(Arc::new(cube_shadow_pass(
+                context.model,
+                context.maps,
+                context.instances,
+                position,
+                self.size,
+                self.offset,
+                geometry,
+                progress,
+            ))),
+            _ => {
+                // full precision depth, the pool only deals in 8 bit buffers
+                let mut frame = Framebuffer::new(
+                    context.acquire::<Rgb<u8>>(self.size, self.size)?,
+                    DepthImage::new(self.size, self.size),
+                );
+                let light_camera = shadow_pass(
+                    context.model,
+                    context.maps,
+                    context.instances,
+                    &scene.lights[0],
+                    scene.camera.up,
+                    &mut frame,
+                    self.offset,
+                    geometry,
+                    progress,
+                );
+                context.release(frame.color);
+                Shadow::Map(frame.depth, light_camera)
+            }
+        };
+        resources.insert("shadow", Resource::Shadow(shadow));
+        Ok(())
+    }
+}
+
+// how much of the sky each pixel of the frame sees into "ao"
+pub struct AmbientOcclusionPass;
+
+impl Pass for AmbientOcclusionPass {
+    fn name(&self) -> &'static str {
+        "ao"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["ao"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, progress: Progress) -> Result<()> {
+        let scene = context.scene;
+        // only the depth is wanted, the colours are thrown away
+        let mut frame = Framebuffer::new(
+            context.acquire(scene.width, scene.height)?,
+            context.acquire(scene.width, scene.height)?,
+        );
+        let ao = ambient_occlusion_pass(
+            context.model,
+            context.instances,
+            &scene.camera,
+            &mut frame,
+            progress,
+        );
+        context.release(frame.color);
+        context.release(frame.depth);
+        resources.insert("ao", Resource::Gray(ao));
+        Ok(())
+    }
+}
+
+// the scene through the shadow shader into "frame"
+pub struct MainPass {
+    pub pcf_kernel: u32,
+    pub hook: Option<Arc<FragmentHook>>,
+}
+
+impl Pass for MainPass {
+    fn name(&self) -> &'static str {
+        "main"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["shadow"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(&self, context: &Context, resources: &mut Resources, progress: Progress) -> Result<()> {
+        let scene = context.scene;
+        let shadow = resources.shadow("shadow")?;
+        let mut shader = shadow.shader(&scene.lights, context.maps, self.pcf_kernel, None);
+        let uniforms = Uniforms {
+            shadow: shadow.matrix(&scene.camera),
+            ..scene.uniforms()
+        };
+        let mut frame = Framebuffer::new(
+            context.acquire(scene.width, scene.height)?,
+            context.acquire(scene.width, scene.height)?,
+        );
+        frame.hook = self.hook.clone();
+        rasterize_reporting(
+            context.model,
+            context.instances,
+            &mut shader,
+            &uniforms,
+            &mut frame,
+            progress,
+        );
+        // the passes after this one aren't hooked
+        frame.hook = None;
+        resources.insert("frame", Resource::Frame(Box::new(frame)));
+        Ok(())
+    }
+}
+
+// "frame" darkened by "ao"
+pub struct OcclusionPass;
+
+impl Pass for OcclusionPass {
+    fn name(&self) -> &'static str {
+        "occlusion"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["frame", "ao"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(
+        &self,
+        _context: &Context,
+        resources: &mut Resources,
+        _progress: Progress,
+    ) -> Result<()> {
+        let ao = resources.gray("ao")?.clone();
+        postprocess::apply_occlusion(&mut resources.frame("frame")?.color, &ao);
+        Ok(())
+    }
+}
+
+// the post effects over "frame"
+pub struct PostPass<'a> {
+    pub post: &'a Pipeline,
+}
+
+impl Pass for PostPass<'_> {
+    fn name(&self) -> &'static str {
+        "post"
+    }
+
+    fn inputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["frame"]
+    }
+
+    fn run(
+        &self,
+        _context: &Context,
+        resources: &mut Resources,
+        _progress: Progress,
+    ) -> Result<()> {
+        self.post.apply(resources.frame("frame")?);
+        Ok(())
+    }
+}
+
+// Depth of the scene seen from light into frame, for the shadow shader, through the
+// geometry stage the main pass is drawn with so shadows match. The depth is pushed away
+// from the light by offset so lit surfaces don't shadow themselves. Gives the camera it
+// was seen through.
+#[allow(clippy::too_many_arguments)]
+pub fn shadow_pass(
+    model: &Model,
+    maps: &[Maps],
+    instances: &[Instance],
+    light: &Light,
+    up: Vector3<f32>,
+    frame: &mut Framebuffer<RgbImage, DepthImage>,
+    offset: DepthOffset,
+    geometry: Option<&mut dyn GeometryShader>,
+    progress: Progress,
+) -> Camera {
+    let (center, radius) = our_gl::instanced_bounding_sphere(model, instances);
+    let (size, _) = frame.dimensions();
+    let mut light_camera = Camera::new(light.eye(center, radius), center, up, size, size);
+    light_camera.projection = camera::Projection::Orthographic { size: radius };
+    depth_pass(
+        model,
+        maps,
+        instances,
+        &light_camera,
+        frame,
+        offset,
+        geometry,
+        progress,
+    );
+    light_camera
+}
+
+// The shadow buffers of a point light at position, shadow_pass() for each face of a
+// size x size cube around it.
+#[allow(clippy::too_many_arguments)]
+pub fn cube_shadow_pass(
+    model: &Model,
+    maps: &[Maps],
+    instances: &[Instance],
+    position: Vector3<f32>,
+    size: u32,
+    offset: DepthOffset,
+    mut geometry: Option<&mut dyn GeometryShader>,
+    progress: Progress,
+) -> CubeShadow {
+    let sphere = our_gl::instanced_bounding_sphere(model, instances);
+    let faces = (0..6)
+        .map(|face| {
+            let light_camera = CubeShadow::face_camera(position, face, sphere, size);
+            let mut frame =
+                Framebuffer::new(RgbImage::new(size, size), DepthImage::new(size, size));
+            depth_pass(
+                model,
+                maps,
+                instances,
+                &light_camera,
+                &mut frame,
+                offset,
+                geometry
+                    .as_mut()
+                    .map(|g| &mut **g as &mut dyn GeometryShader),
+                &|done, total| progress(face * total + done, 6 * total),
+            );
+            (light_camera.transform(), frame.depth)
+        })
+        .collect();
+    CubeShadow::new(position, faces)
+}
+
+// the depth of the scene seen through light_camera into frame, pushed back by offset
+#[allow(clippy::too_many_arguments)]
+fn depth_pass(
+    model: &Model,
+    maps: &[Maps],
+    instances: &[Instance],
+    light_camera: &Camera,
+    frame: &mut Framebuffer<RgbImage, DepthImage>,
+    offset: DepthOffset,
+    mut geometry: Option<&mut dyn GeometryShader>,
+    progress: Progress,
+) {
+    let (size, _) = frame.dimensions();
+    let uniforms = light_camera.uniforms();
+
+    let mut depth_shader = shaders::DepthShader::new(maps);
+    let faces: Vec<_> =
+        our_gl::instanced_faces(model, instances, uniforms.to_screen(), size, size).collect();
+    let mut emitted = Vec::new();
+    for (n, &(instance, i)) in faces.iter().enumerate() {
+        if n % PROGRESS_INTERVAL == 0 {
+            progress(n, faces.len());
+        }
+        let _face = tracing::trace_span!("face", index = i).entered();
+        let screen_coords =
+            [0, 1, 2].map(|j| depth_shader.vertex(model, i, j, &uniforms, instance));
+        primitives(
+            geometry
+                .as_mut()
+                .map(|g| &mut **g as &mut dyn GeometryShader),
+            model,
+            i,
+            instance,
+            &uniforms,
+            screen_coords,
+            &mut emitted,
+        );
+        for primitive in &emitted {
+            let shader = Remapped::new(&mut depth_shader, primitive);
+            our_gl::triangle_offset(&primitive.pts, &shader, &uniforms, frame, offset);
+        }
+    }
+    progress(faces.len(), faces.len());
+}
+
+// The triangles geometry makes of face i once the shader has taken it to pts, or that one
+// alone without a geometry stage, into out. Each is drawn through Remapped.
+pub fn primitives(
+    geometry: Option<&mut dyn GeometryShader>,
+    model: &Model,
+    i: usize,
+    instance: &Instance,
+    uniforms: &Uniforms,
+    pts: [Vector4<f32>; 3],
+    out: &mut Vec<Primitive>,
+) {
+    out.clear();
+    match geometry {
+        Some(geometry) => {
+            geometry.geometry(model, i, instance, uniforms, &pts, &mut |p| out.push(p))
+        }
+        None => out.push(Primitive::new(pts)),
+    }
+}
+
+// how much of the sky each pixel of the camera's view sees, drawn through frame
+pub fn ambient_occlusion_pass(
+    model: &Model,
+    instances: &[Instance],
+    camera: &Camera,
+    frame: &mut Framebuffer,
+    progress: Progress,
+) -> GrayImage {
+    rasterize_reporting(
+        model,
+        instances,
+        &mut shaders::ZShader::new(),
+        &camera.uniforms(),
+        frame,
+        progress,
+    );
+    postprocess::ambient_occlusion(&frame.depth, camera.depth_scale(), SSAO_RADIUS)
+}
+
+// Which face of which instance the camera sees at each pixel, drawn with the depth shader so
+// cut out texels show what is behind them like in the frame
+pub fn id_pass(
+    model: &Model,
+    maps: &[Maps],
+    instances: &[Instance],
+    uniforms: &Uniforms,
+    (width, height): (u32, u32),
+) -> our_gl::IdBuffer {
+    let mut frame = Framebuffer::new(RgbImage::new(width, height), GrayImage::new(width, height));
+    let mut shader = shaders::DepthShader::new(maps);
+    for (n, instance) in instances.iter().enumerate() {
+        for i in our_gl::visible_faces(
+            model,
+            uniforms.to_screen() * instance.transform,
+            width,
+            height,
+        ) {
+            let pts = [0, 1, 2].map(|j| shader.vertex(model, i, j, uniforms, instance));
+            our_gl::triangle_id(
+                &pts,
+                &shader,
+                uniforms,
+                &mut frame,
+                (i, model.get_object(i), n),
+            );
+        }
+    }
+    frame
+        .ids
+        .unwrap_or_else(|| our_gl::IdBuffer::new(width, height))
+}
+
+// every face of every instance of the model through the shader, (0,0) is the bottom left
+pub fn rasterize<T: Shader, C: ColorBuffer, D: DepthBuffer>(
+    model: &Model,
+    instances: &[Instance],
+    shader: &mut T,
+    uniforms: &Uniforms,
+    framebuffer: &mut Framebuffer<C, D>,
+) {
+    rasterize_reporting(model, instances, shader, uniforms, framebuffer, &|_, _| {});
+}
+
+// rasterize() telling progress how far it has got
+pub fn rasterize_reporting<T: Shader, C: ColorBuffer, D: DepthBuffer>(
+    model: &Model,
+    instances: &[Instance],
+    shader: &mut T,
+    uniforms: &Uniforms,
+    framebuffer: &mut Framebuffer<C, D>,
+    progress: Progress,
+) {
+    let (width, height) = framebuffer.dimensions();
+    let faces: Vec<_> =
+        our_gl::instanced_faces(model, instances, uniforms.to_screen(), width, height).collect();
+    for (n, &(instance, i)) in faces.iter().enumerate() {
+        if n % PROGRESS_INTERVAL == 0 {
+            progress(n, faces.len());
+        }
+        let _face = tracing::trace_span!("face", index = i).entered();
+        let screen_coords = [0, 1, 2].map(|j| shader.vertex(model, i, j, uniforms, instance));
+        our_gl::triangle(&screen_coords, shader, uniforms, framebuffer);
+    }
+    progress(faces.len(), faces.len());
+}
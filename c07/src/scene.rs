@@ -0,0 +1,58 @@
+use anyhow::{anyhow, Result};
+use cgmath::{Deg, Matrix4, Vector3};
+
+use super::light::parse_vector;
+
+// A model and where it goes in the scene, written as a path (without .obj, like the
+// model on the command line) and optional ';' separated fields
+//   translate=x,y,z  rotate=x,y,z  scale=s or scale=x,y,z
+// The model is scaled, then rotated by the angles in degrees about x, y and z in turn,
+// then translated.
+pub struct Object {
+    pub path: String,
+    pub matrix: Matrix4<f32>,
+}
+
+impl std::str::FromStr for Object {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Object> {
+        let (path, fields) = spec.split_once(';').unwrap_or((spec, ""));
+        let path = path.trim();
+        if path.is_empty() {
+            return Err(anyhow!("object '{}' has no path", spec));
+        }
+        Ok(Object {
+            path: String::from(path),
+            matrix: parse_transform(fields)?,
+        })
+    }
+}
+
+// the fields of an object without its path, e.g. for instances of a model
+pub fn parse_transform(fields: &str) -> Result<Matrix4<f32>> {
+    let mut translate = Vector3::new(0.0, 0.0, 0.0);
+    let mut rotate = Vector3::new(0.0, 0.0, 0.0);
+    let mut scale = Vector3::new(1.0, 1.0, 1.0);
+    for field in fields.split(';').map(str::trim).filter(|f| !f.is_empty()) {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| anyhow!("transform field '{}' should be key=value", field))?;
+        match key.trim() {
+            "translate" => translate = parse_vector(value)?,
+            "rotate" => rotate = parse_vector(value)?,
+            "scale" => {
+                scale = match value.trim().parse::<f32>() {
+                    Ok(s) => Vector3::new(s, s, s),
+                    Err(_) => parse_vector(value)?,
+                }
+            }
+            _ => return Err(anyhow!("unknown transform field '{}'", key)),
+        }
+    }
+    Ok(Matrix4::from_translation(translate)
+        * Matrix4::from_angle_z(Deg(rotate.z))
+        * Matrix4::from_angle_y(Deg(rotate.y))
+        * Matrix4::from_angle_x(Deg(rotate.x))
+        * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z))
+}
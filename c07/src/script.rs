@@ -0,0 +1,323 @@
+use anyhow::{anyhow, Context, Result};
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4};
+use image::Rgb;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST, FLOAT};
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tinyrenderer::light::Light;
+use tinyrenderer::model;
+use tinyrenderer::our_gl::{Instance, Shader, TranslucentShader, Uniforms};
+use tinyrenderer::texture::{self, ColorSpace, GrayTexture, Maps, RgbTexture};
+
+// a texture handed to the script, sample(uv) gives linear colours as [r, g, b] and grey
+// maps as a number, all in [0, 1]
+#[derive(Clone)]
+enum Sampler {
+    Color(Arc<RgbTexture>),
+    Gray(Arc<GrayTexture>),
+}
+
+impl Sampler {
+    fn sample(&mut self, uv: Array) -> Dynamic {
+        let uv = Vector2::new(component(&uv, 0), component(&uv, 1));
+        match self {
+            Sampler::Color(texture) => array(texture.sample_linear(uv)).into(),
+            Sampler::Gray(texture) => ((texture.sample(uv)[0] as f32 / 255.0) as FLOAT).into(),
+        }
+    }
+}
+
+// vectors are arrays of numbers in the script, missing components are 0
+fn component(a: &Array, i: usize) -> f32 {
+    a.get(i).map_or(0.0, |c| {
+        c.as_float()
+            .map(|c| c as f32)
+            .or_else(|_| c.as_int().map(|c| c as f32))
+            .unwrap_or(0.0)
+    })
+}
+
+fn vector(a: &Array) -> Vector3<f32> {
+    Vector3::new(component(a, 0), component(a, 1), component(a, 2))
+}
+
+fn array(v: Vector3<f32>) -> Array {
+    vec![
+        (v.x as FLOAT).into(),
+        (v.y as FLOAT).into(),
+        (v.z as FLOAT).into(),
+    ]
+}
+
+fn map<const N: usize>(entries: [(&str, Dynamic); N]) -> Map {
+    entries
+        .into_iter()
+        .map(|(name, value)| (name.into(), value))
+        .collect()
+}
+
+// the maths shaders lean on, arrays add up by concatenating in Rhai
+fn register_vectors(engine: &mut Engine) {
+    engine
+        .register_fn("dot", |a: Array, b: Array| {
+            vector(&a).dot(vector(&b)) as FLOAT
+        })
+        .register_fn("cross", |a: Array, b: Array| {
+            array(vector(&a).cross(vector(&b)))
+        })
+        .register_fn("length", |a: Array| vector(&a).magnitude() as FLOAT)
+        .register_fn("normalize", |a: Array| array(vector(&a).normalize()))
+        .register_fn("add", |a: Array, b: Array| array(vector(&a) + vector(&b)))
+        .register_fn("sub", |a: Array, b: Array| array(vector(&a) - vector(&b)))
+        .register_fn("mul", |a: Array, b: Array| {
+            let (a, b) = (vector(&a), vector(&b));
+            array(Vector3::new(a.x * b.x, a.y * b.y, a.z * b.z))
+        })
+        .register_fn("scale", |a: Array, s: FLOAT| array(vector(&a) * s as f32))
+        .register_fn("mix", |a: Array, b: Array, t: FLOAT| {
+            let (a, b) = (vector(&a), vector(&b));
+            array(a + (b - a) * t as f32)
+        })
+        .register_fn("reflect", |i: Array, n: Array| {
+            let (i, n) = (vector(&i), vector(&n));
+            array(i - n * 2.0 * n.dot(i))
+        });
+}
+
+// A fragment shader in a Rhai file. It defines fn fragment(v) and returns the colour as
+// linear [r, g, b], or () to discard the fragment. v holds the interpolated varyings
+// (uv, world space normal and position, object), base, the colour the built in shader
+// gave the fragment, the uniforms (eye and lights as seen from the fragment) and the
+// object's samplers (diffuse, normal_map, specular, specular_color, () when missing).
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    lights: Vec<Light>,
+    samplers: Vec<Map>,            // one for each object of the model
+    color_spaces: Vec<ColorSpace>, // of the framebuffer the object's base colour is in
+    failed: AtomicBool,            // only the first error is reported
+}
+
+impl Script {
+    pub fn load(path: &str, lights: &[Light], maps: &[Maps]) -> Result<Script> {
+        let mut engine = Engine::new();
+        engine
+            .register_type_with_name::<Sampler>("Sampler")
+            .register_fn("sample", Sampler::sample);
+        register_vectors(&mut engine);
+        let ast = engine
+            .compile_file(PathBuf::from(path))
+            .with_context(|| format!("Could not compile {}", path))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "fragment" && f.params.len() == 1)
+        {
+            return Err(anyhow!("{} does not define fn fragment(v)", path));
+        }
+
+        let color = |t: &Option<Arc<RgbTexture>>| {
+            t.clone()
+                .map_or(Dynamic::UNIT, |t| Dynamic::from(Sampler::Color(t)))
+        };
+        let gray = |t: &Option<Arc<GrayTexture>>| {
+            t.clone()
+                .map_or(Dynamic::UNIT, |t| Dynamic::from(Sampler::Gray(t)))
+        };
+        let samplers = maps
+            .iter()
+            .map(|m| {
+                map([
+                    ("diffuse", color(&m.diffuse)),
+                    ("normal_map", color(&m.normal)),
+                    ("specular", gray(&m.specular)),
+                    ("specular_color", color(&m.specular_color)),
+                ])
+            })
+            .collect();
+        // the built in shaders write in the colour space of the diffuse map
+        let color_spaces = maps
+            .iter()
+            .map(|m| {
+                m.diffuse
+                    .as_ref()
+                    .map_or(ColorSpace::Srgb, |texture| texture.color_space())
+            })
+            .collect();
+        Ok(Script {
+            engine,
+            ast,
+            lights: lights.to_vec(),
+            samplers,
+            color_spaces,
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    fn lights(&self, p: Vector3<f32>) -> Array {
+        self.lights
+            .iter()
+            .map(|light| {
+                let (dir, attenuation) = light.incident(p);
+                map([
+                    ("dir", array(dir).into()),
+                    ("attenuation", (attenuation as FLOAT).into()),
+                    ("color", array(light.color).into()),
+                    ("ambient", (light.ambient as FLOAT).into()),
+                    ("diffuse", (light.diffuse as FLOAT).into()),
+                    ("specular", (light.specular as FLOAT).into()),
+                ])
+                .into()
+            })
+            .collect()
+    }
+
+    fn report(&self, error: impl std::fmt::Display) {
+        if !self.failed.swap(true, Ordering::Relaxed) {
+            tracing::error!(%error, "fragment script failed, drawing the built in shading");
+        }
+    }
+}
+
+// Runs a script over what another shader drew. Without a script it is the other shader.
+#[derive(Clone)]
+pub struct Scripted<T> {
+    shader: T,
+    script: Option<Arc<Script>>,
+    varying_object: usize,
+    varying_uv: [Vector2<f32>; 3],
+    varying_norm: [Vector3<f32>; 3], // world space
+    varying_pos: [Vector3<f32>; 3],
+}
+
+impl<T> Scripted<T> {
+    pub fn new(shader: T, script: Option<Arc<Script>>) -> Scripted<T> {
+        Scripted {
+            shader,
+            script,
+            varying_object: 0,
+            varying_uv: [Vector2::new(0.0, 0.0); 3],
+            varying_norm: [Vector3::new(0.0, 0.0, 0.0); 3],
+            varying_pos: [Vector3::new(0.0, 0.0, 0.0); 3],
+        }
+    }
+}
+
+impl<T> Deref for Scripted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.shader
+    }
+}
+
+impl<T> DerefMut for Scripted<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.shader
+    }
+}
+
+impl<T: Shader> Shader for Scripted<T> {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &Uniforms,
+        instance: &Instance,
+    ) -> Vector4<f32> {
+        if self.script.is_some() {
+            let v = model.get_faces()[iface][nthvert];
+            self.varying_object = model.get_object(iface);
+            self.varying_uv[nthvert] = model.get_uvs()[v];
+            self.varying_norm[nthvert] = instance.normal(model.get_norms()[v]);
+            self.varying_pos[nthvert] = instance.position(model.get_verts()[v]);
+        }
+        self.shader
+            .vertex(model, iface, nthvert, uniforms, instance)
+    }
+
+    fn fragment(&self, bc: Vector3<f32>, uniforms: &Uniforms, color: &mut Rgb<u8>) -> bool {
+        let keep = self.shader.fragment(bc, uniforms, color);
+        let script = match &self.script {
+            Some(script) if keep => script,
+            _ => return keep,
+        };
+        let uv =
+            self.varying_uv[0] * bc[0] + self.varying_uv[1] * bc[1] + self.varying_uv[2] * bc[2];
+        let n = (self.varying_norm[0] * bc[0]
+            + self.varying_norm[1] * bc[1]
+            + self.varying_norm[2] * bc[2])
+            .normalize();
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let color_space = script
+            .color_spaces
+            .get(self.varying_object)
+            .copied()
+            .unwrap_or(ColorSpace::Srgb);
+        let decode = |c: u8| match color_space {
+            ColorSpace::Srgb => texture::srgb_to_linear(c),
+            ColorSpace::Linear => c as f32 / 255.0,
+        };
+
+        let mut v = script
+            .samplers
+            .get(self.varying_object)
+            .cloned()
+            .unwrap_or_default();
+        v.extend(map([
+            (
+                "uv",
+                vec![Dynamic::from(uv.x as FLOAT), Dynamic::from(uv.y as FLOAT)].into(),
+            ),
+            ("normal", array(n).into()),
+            ("position", array(p).into()),
+            ("object", (self.varying_object as rhai::INT).into()),
+            (
+                "base",
+                array(Vector3::new(
+                    decode(color[0]),
+                    decode(color[1]),
+                    decode(color[2]),
+                ))
+                .into(),
+            ),
+            ("eye", array(uniforms.eye).into()),
+            ("lights", script.lights(p).into()),
+        ]));
+
+        let result = script.engine.call_fn_with_options::<Dynamic>(
+            CallFnOptions::new().eval_ast(false),
+            &mut Scope::new(),
+            &script.ast,
+            "fragment",
+            (Dynamic::from_map(v),),
+        );
+        match result {
+            Ok(out) if out.is_unit() => false,
+            Ok(out) if out.is_array() => {
+                *color = texture::encode(vector(&out.cast::<Array>()), color_space);
+                true
+            }
+            Ok(out) => {
+                script.report(format!(
+                    "fragment returned {}, not [r, g, b] or ()",
+                    out.type_name()
+                ));
+                true
+            }
+            Err(error) => {
+                script.report(error);
+                true
+            }
+        }
+    }
+}
+
+impl<T: TranslucentShader> TranslucentShader for Scripted<T> {
+    fn opacity(&self, bar: Vector3<f32>) -> f32 {
+        self.shader.opacity(bar)
+    }
+}
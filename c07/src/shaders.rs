@@ -1,22 +1,283 @@
+use super::bvh::{curved_position, Bvh};
+use super::cubemap::CubeMap;
+use super::light::Light;
+use super::material::{Material, Sides};
 use super::model;
 use super::our_gl;
+use super::our_gl::DepthImage;
+use super::probes::ProbeGrid;
+use super::shadowmap::{pcf, CubeShadow};
+use super::texture::{encode, ColorSpace, Maps, NormalSpace, RgbTexture};
 use cgmath::{
-    dot, InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Transform, Vector2, Vector3, Vector4,
+    dot, ElementWise, InnerSpace, Matrix3, Matrix4, SquareMatrix, Vector2, Vector3, Vector4,
 };
-use image::{GrayImage, Rgb, RgbImage};
+use image::Rgb;
+use std::sync::Arc;
 
-const WIGGLE: f32 = 5.0; // magic number to avoid z-fighting
+// texels of an alpha map under this are holes in the surface
+const ALPHA_CUTOFF: u8 = 128;
 
+// specular colour of textured meshes without a material or specular colour map, about
+// what most non-metals reflect head on
+pub const DIELECTRIC_KS: Vector3<f32> = Vector3 {
+    x: 0.04,
+    y: 0.04,
+    z: 0.04,
+};
+
+// flat colours of the material on the face being drawn, used in place of missing maps
+#[derive(Debug, Clone, Copy)]
+struct FaceMaterial {
+    kd: Vector3<f32>,
+    ks: Vector3<f32>,
+    ns: f32,
+    d: f32,
+    sides: Option<Sides>,
+}
+
+impl Default for FaceMaterial {
+    fn default() -> FaceMaterial {
+        FaceMaterial {
+            kd: Vector3::new(0.8, 0.8, 0.8),
+            ks: Vector3::new(0.0, 0.0, 0.0),
+            ns: 1.0,
+            d: 1.0,
+            sides: None,
+        }
+    }
+}
+
+impl From<&Material> for FaceMaterial {
+    fn from(m: &Material) -> FaceMaterial {
+        FaceMaterial {
+            kd: m.kd,
+            ks: m.ks,
+            ns: m.ns,
+            d: m.d,
+            sides: m.sides,
+        }
+    }
+}
+
+// Hashed alpha: a fragment of opacity alpha survives with probability alpha, decided by a
+// hash of its pixel so the pattern is stable between runs. Kept fragments write depth like
+// any opaque one, so no sorting is needed.
+fn hashed_alpha(alpha: f32, screen: Vector2<f32>) -> bool {
+    if alpha >= 1.0 {
+        return true;
+    }
+    let mut h =
+        (screen.x as u32).wrapping_mul(0x8da6_b343) ^ (screen.y as u32).wrapping_mul(0xd816_3841);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    alpha > h as f32 / u32::MAX as f32
+}
+
+// Alpha test for cutout textures such as foliage or hair cards. Unlike hashed alpha the
+// edges stay where they are painted, whatever the pixel.
+pub fn cut_out(maps: &Maps, uv: Vector2<f32>) -> bool {
+    maps.alpha
+        .as_ref()
+        .is_some_and(|alpha| alpha.sample(uv)[0] < ALPHA_CUTOFF)
+}
+
+// how much of the sky a texel sees by a baked occlusion map, all of it without one
+fn occlusion(maps: &Maps, uv: Vector2<f32>) -> f32 {
+    maps.occlusion
+        .as_ref()
+        .map_or(1.0, |occlusion| occlusion.sample(uv)[0] as f32 / 255.0)
+}
+
+static NO_MAPS: Maps = Maps {
+    diffuse: None,
+    normal: None,
+    normal_space: NormalSpace::Tangent,
+    specular: None,
+    specular_color: None,
+    alpha: None,
+    occlusion: None,
+};
+
+// the maps of the object a face came from, objects nothing was loaded for get none
+pub fn object_maps(maps: &[Maps], object: usize) -> &Maps {
+    maps.get(object).unwrap_or(&NO_MAPS)
+}
+
+// untextured output has nothing to match so it takes the gamma correct path
+fn output_space(texture: &Option<Arc<RgbTexture>>) -> ColorSpace {
+    texture
+        .as_ref()
+        .map_or(ColorSpace::Srgb, |texture| texture.color_space())
+}
+
+// a vertex tangent and the bitangent it makes with normal n taken into clip space by m like
+// positions, for tangent_normal()
+pub fn tangent_frame(
+    t: Vector4<f32>,
+    n: Vector3<f32>,
+    m: Matrix4<f32>,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let b = n.cross(t.truncate()) * t.w;
+    (
+        (m * t.truncate().extend(0.0)).truncate(),
+        (m * b.extend(0.0)).truncate(),
+    )
+}
+
+// the direction a normal map texel holds, from [0, 255] to [-1, 1]
+fn texel_normal(n_info: Rgb<u8>) -> Vector3<f32> {
+    Vector3::new(
+        n_info[0] as f32 / 255.0 * 2.0 - 1.0,
+        n_info[1] as f32 / 255.0 * 2.0 - 1.0,
+        n_info[2] as f32 / 255.0 * 2.0 - 1.0,
+    )
+    .normalize()
+}
+
+// takes object space normal map texels of the face's object to where m_it takes normals,
+// turned like the model's normals were when it was placed
+pub fn object_frame(
+    model: &model::Model,
+    iface: usize,
+    instance: &our_gl::Instance,
+    m_it: Matrix4<f32>,
+) -> Matrix3<f32> {
+    let m_it = Matrix3::from_cols(m_it.x.truncate(), m_it.y.truncate(), m_it.z.truncate());
+    m_it * instance.normal_matrix() * model.get_object_normals(model.get_object(iface))
+}
+
+// the normal a normal map texel in space gives, in the space of the interpolated normal bn.
+// Tangent space goes through the tangent t and bitangent b, object space through the
+// frame from object_frame().
+pub fn mapped_normal(
+    space: NormalSpace,
+    (t, b, bn): (Vector3<f32>, Vector3<f32>, Vector3<f32>),
+    object: Matrix3<f32>,
+    n_info: Rgb<u8>,
+) -> Vector3<f32> {
+    match space {
+        NormalSpace::Tangent => tangent_normal(t, b, bn, n_info),
+        NormalSpace::Object => (object * texel_normal(n_info)).normalize(),
+    }
+}
+
+// perturbs the interpolated normal bn by a tangent space normal map texel, in the frame of
+// the interpolated tangent t and bitangent b from tangent_frame()
+fn tangent_normal(
+    t: Vector3<f32>,
+    b: Vector3<f32>,
+    bn: Vector3<f32>,
+    n_info: Rgb<u8>,
+) -> Vector3<f32> {
+    // interpolation and the projection bend the tangent off the normal
+    let t = t - bn * dot(bn, t);
+    if !t.magnitude2().is_normal() {
+        return bn;
+    }
+    let t = t.normalize();
+    let b = bn.cross(t) * if dot(bn.cross(t), b) < 0.0 { -1.0 } else { 1.0 };
+
+    Matrix3::<f32>::from_cols(t, b, bn) * texel_normal(n_info)
+}
+
+// the sides of a face lit by its material, or by the draw when it has none
+fn face_sides(material: Option<FaceMaterial>, uniforms: &our_gl::Uniforms) -> Sides {
+    material.and_then(|m| m.sides).unwrap_or(uniforms.sides)
+}
+
+// whether a surface with interpolated normal bn is seen from behind and should be lit
+// as if it faced the other way, view points to the viewer in the space of bn
+fn seen_from_behind(sides: Sides, bn: Vector3<f32>, view: Vector3<f32>) -> bool {
+    sides != Sides::One && dot(bn, view) < 0.0
+}
+
+// lights and positions are in world space, m takes the light direction into the space
+// the normal n is in
+fn incident(light: &Light, p: Vector3<f32>, m: Matrix4<f32>) -> (Vector3<f32>, f32) {
+    let (l, attenuation) = light.incident(p);
+    ((m * l.extend(0.0)).truncate().normalize(), attenuation)
+}
+
+// how much of a light coming in at cos to the normal is diffused, lights behind the
+// surface come through with two sides
+fn lambert(cos: f32, sides: Sides) -> f32 {
+    match sides {
+        Sides::Two => cos.abs(),
+        _ => cos.max(0.0),
+    }
+}
+
+// summed ambient and diffuse light of every light at p with normal n, per channel
+fn diffuse(
+    lights: &[Light],
+    p: Vector3<f32>,
+    n: Vector3<f32>,
+    m: Matrix4<f32>,
+    sides: Sides,
+) -> Vector3<f32> {
+    lights
+        .iter()
+        .map(|light| {
+            let (l, attenuation) = incident(light, p, m);
+            light.color * attenuation * (light.ambient + light.diffuse * lambert(dot(n, l), sides))
+        })
+        .sum()
+}
+
+// Energy conserving combination of the light reaching a surface (diff is ambient plus
+// diffuse, spec the specular term from phong()) with its material: a lambertian layer of
+// colour albedo (map_Kd or Kd) under a normalized phong lobe of colour spec_color (Ks) and
+// exponent spec_pow (Ns). The (n + 2) / 2 keeps the energy of the lobe constant as it
+// tightens and the diffuse layer only gets what the specular one didn't reflect, so a
+// white surface under light of 1 never gives back more than 1.
+pub fn reflect_light(
+    albedo: Vector3<f32>,
+    spec_color: Vector3<f32>,
+    spec_pow: f32,
+    diff: Vector3<f32>,
+    spec: Vector3<f32>,
+) -> Vector3<f32> {
+    let spec_color = spec_color.map(|c| c.clamp(0.0, 1.0));
+    let kd = albedo.mul_element_wise(Vector3::new(1.0, 1.0, 1.0) - spec_color);
+    kd.mul_element_wise(diff) + spec_color.mul_element_wise(spec) * ((spec_pow + 2.0) / 2.0)
+}
+
+// ambient, diffuse and specular light of one light at p with normal n, the viewer looking
+// down -z. Light through the back of two sided surfaces is only diffused.
+fn phong(
+    light: &Light,
+    p: Vector3<f32>,
+    n: Vector3<f32>,
+    spec_pow: f32,
+    m: Matrix4<f32>,
+    sides: Sides,
+) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let (l, attenuation) = incident(light, p, m);
+    let r = (n * (2.0 * dot(n, l)) - l).normalize();
+    let diff = lambert(dot(n, l), sides);
+    let spec = r.z.max(0.0).powf(spec_pow) * dot(n, l).max(0.0);
+    let color = light.color * attenuation;
+    (
+        color * light.ambient,
+        color * (light.diffuse * diff),
+        color * (light.specular * spec),
+    )
+}
+
+#[derive(Clone)]
 pub struct GouraudShader {
-    varying_intensity: Vector3<f32>,
-    light_dir: Vector3<f32>,
+    varying_intensity: [Vector3<f32>; 3],
+    lights: Vec<Light>,
 }
 
 impl GouraudShader {
-    pub const fn new(light_dir: Vector3<f32>) -> GouraudShader {
+    pub fn new(lights: &[Light]) -> GouraudShader {
         GouraudShader {
-            light_dir,
-            varying_intensity: Vector3::<f32>::new(0.0, 0.0, 0.0),
+            lights: lights.to_vec(),
+            varying_intensity: [Vector3::<f32>::new(0.0, 0.0, 0.0); 3],
         }
     }
 }
@@ -27,34 +288,44 @@ impl our_gl::Shader for GouraudShader {
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
     ) -> Vector4<f32> {
-        let v = model.get_faces()[iface][nthvert].v;
-        let n = model.get_norms()[v];
-        self.varying_intensity[nthvert] = dot(n, self.light_dir.normalize()).max(0.0);
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
+        self.varying_intensity[nthvert] =
+            diffuse(&self.lights, p, n, Matrix4::identity(), Sides::One);
 
-        let gl_vertex = model.get_verts()[v].extend(1.0);
-        mat * gl_vertex
+        let gl_vertex = p.extend(1.0);
+        uniforms.mat * gl_vertex
     }
 
-    fn fragment(&self, bc: Vector3<f32>, color: &mut Rgb<u8>) -> bool {
-        let intensity = dot(self.varying_intensity, bc);
-        color[0] = (255.0 * intensity) as u8;
-        color[1] = (255.0 * intensity) as u8;
-        color[2] = (255.0 * intensity) as u8;
+    fn fragment(
+        &self,
+        bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
+        let intensity = self.varying_intensity[0] * bc[0]
+            + self.varying_intensity[1] * bc[1]
+            + self.varying_intensity[2] * bc[2];
+        color[0] = (255.0 * intensity.x) as u8;
+        color[1] = (255.0 * intensity.y) as u8;
+        color[2] = (255.0 * intensity.z) as u8;
         true
     }
 }
 
 pub struct FunnyShader {
     varying_intensity: Vector3<f32>,
-    light_dir: Vector3<f32>,
+    lights: Vec<Light>,
 }
 
 impl FunnyShader {
-    pub const fn new(light_dir: Vector3<f32>) -> FunnyShader {
+    pub fn new(lights: &[Light]) -> FunnyShader {
         FunnyShader {
-            light_dir,
+            lights: lights.to_vec(),
             varying_intensity: Vector3::<f32>::new(0.0, 0.0, 0.0),
         }
     }
@@ -66,17 +337,28 @@ impl our_gl::Shader for FunnyShader {
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
     ) -> Vector4<f32> {
-        let v = model.get_faces()[iface][nthvert].v;
-        let n = model.get_norms()[v];
-        self.varying_intensity[nthvert] = dot(n, self.light_dir.normalize()).max(0.0);
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
+        self.varying_intensity[nthvert] = {
+            // banding works on brightness so coloured lights are averaged
+            let light = diffuse(&self.lights, p, n, Matrix4::identity(), Sides::One);
+            (light.x + light.y + light.z) / 3.0
+        };
 
-        let gl_vertex = model.get_verts()[v].extend(1.0);
-        mat * gl_vertex
+        let gl_vertex = p.extend(1.0);
+        uniforms.mat * gl_vertex
     }
 
-    fn fragment(&self, bc: Vector3<f32>, color: &mut Rgb<u8>) -> bool {
+    fn fragment(
+        &self,
+        bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
         let mut intensity = dot(self.varying_intensity, bc);
         if intensity > 0.85 {
             intensity = 1.00;
@@ -98,19 +380,116 @@ impl our_gl::Shader for FunnyShader {
     }
 }
 
+// cel shading, the diffuse light is quantized into flat bands per fragment
+#[derive(Clone)]
+pub struct ToonShader {
+    lights: Vec<Light>,
+    bands: f32,
+    maps: Vec<Maps>, // one for each object of the model
+    varying_object: usize,
+    varying_uv: [Vector2<f32>; 3],
+    varying_norm: [Vector3<f32>; 3],
+    varying_pos: [Vector3<f32>; 3],
+    varying_material: Option<FaceMaterial>,
+}
+
+impl ToonShader {
+    pub fn new(lights: &[Light], bands: u32, maps: &[Maps]) -> ToonShader {
+        ToonShader {
+            lights: lights.to_vec(),
+            bands: bands.max(1) as f32,
+            maps: maps.to_vec(),
+            varying_object: 0,
+            varying_uv: [Vector2 { x: 0.0, y: 0.0 }; 3],
+            varying_norm: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_pos: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_material: None,
+        }
+    }
+}
+
+impl our_gl::Shader for ToonShader {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
+    ) -> Vector4<f32> {
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
+
+        self.varying_uv[nthvert] = model.get_uvs()[v];
+        self.varying_material = model.get_material(iface).map(FaceMaterial::from);
+        self.varying_object = model.get_object(iface);
+        self.varying_norm[nthvert] = n;
+        self.varying_pos[nthvert] = p;
+        uniforms.mat * p.extend(1.0)
+    }
+
+    fn fragment(&self, bc: Vector3<f32>, uniforms: &our_gl::Uniforms, color: &mut Rgb<u8>) -> bool {
+        let maps = object_maps(&self.maps, self.varying_object);
+        let n = (self.varying_norm[0] * bc[0]
+            + self.varying_norm[1] * bc[1]
+            + self.varying_norm[2] * bc[2])
+            .normalize();
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let uv =
+            self.varying_uv[0] * bc[0] + self.varying_uv[1] * bc[1] + self.varying_uv[2] * bc[2];
+        if cut_out(maps, uv) {
+            return false;
+        }
+        let albedo = match &maps.diffuse {
+            Some(texture) => texture.sample_linear(uv),
+            None => self.varying_material.unwrap_or_default().kd,
+        } * occlusion(maps, uv);
+
+        // lit in world space, where the viewer is at the eye
+        let sides = face_sides(self.varying_material, uniforms);
+        let n = if seen_from_behind(sides, n, uniforms.eye - p) {
+            -n
+        } else {
+            n
+        };
+
+        // quantize the brightness and keep the hue of the lights
+        let light = diffuse(&self.lights, p, n, Matrix4::identity(), sides);
+        let brightness = (light.x + light.y + light.z) / 3.0;
+        let light = if brightness > 0.0 {
+            let band = ((brightness * self.bands).ceil() / self.bands).min(1.0);
+            light * (band / brightness)
+        } else {
+            light
+        };
+        *color = encode(albedo.mul_element_wise(light), output_space(&maps.diffuse));
+        true
+    }
+}
+
 pub struct TextureShader {
-    light_dir: Vector3<f32>,
-    texture: RgbImage,
-    varying_intensity: Vector3<f32>,
+    lights: Vec<Light>,
+    texture: Arc<RgbTexture>,
+    varying_intensity: [Vector3<f32>; 3],
     varying_uv: [Vector2<f32>; 3],
 }
 
 impl TextureShader {
-    pub const fn new(light_dir: Vector3<f32>, texture: RgbImage) -> TextureShader {
+    pub fn new(lights: &[Light], texture: Arc<RgbTexture>) -> TextureShader {
         TextureShader {
-            light_dir,
+            lights: lights.to_vec(),
             texture,
-            varying_intensity: Vector3::<f32>::new(0.0, 0.0, 0.0),
+            varying_intensity: [Vector3::<f32>::new(0.0, 0.0, 0.0); 3],
             varying_uv: [Vector2 { x: 0.0, y: 0.0 }; 3],
         }
     }
@@ -122,80 +501,88 @@ impl our_gl::Shader for TextureShader {
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
     ) -> Vector4<f32> {
-        let v = model.get_faces()[iface][nthvert].v;
-        let vt = model.get_faces()[iface][nthvert].vt;
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
 
-        let n = model.get_norms()[v];
-        self.varying_intensity[nthvert] = dot(n, self.light_dir.normalize()).max(0.0);
+        self.varying_intensity[nthvert] =
+            diffuse(&self.lights, p, n, Matrix4::identity(), Sides::One);
 
-        self.varying_uv[nthvert] = model.get_uvs()[vt];
+        self.varying_uv[nthvert] = model.get_uvs()[v];
 
-        let gl_vertex = model.get_verts()[v].extend(1.0);
-        mat * gl_vertex
+        let gl_vertex = p.extend(1.0);
+        uniforms.mat * gl_vertex
     }
 
-    fn fragment(&self, bc: Vector3<f32>, color: &mut Rgb<u8>) -> bool {
-        let mut uv =
+    fn fragment(
+        &self,
+        bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
+        let uv =
             self.varying_uv[0] * bc[0] + self.varying_uv[1] * bc[1] + self.varying_uv[2] * bc[2];
-        uv.x *= self.texture.width() as f32;
-        uv.y *= self.texture.height() as f32;
-        *color = self.texture.get_pixel(uv.x as u32, uv.y as u32).clone();
-
-        let intensity = dot(self.varying_intensity, bc);
-        color[0] = (color[0] as f32 * intensity) as u8;
-        color[1] = (color[1] as f32 * intensity) as u8;
-        color[2] = (color[2] as f32 * intensity) as u8;
+        let albedo = self.texture.sample_linear(uv);
+
+        let intensity = self.varying_intensity[0] * bc[0]
+            + self.varying_intensity[1] * bc[1]
+            + self.varying_intensity[2] * bc[2];
+        *color = self.texture.encode(albedo.mul_element_wise(intensity));
         true
     }
 }
 
+#[derive(Clone)]
 pub struct NormalShader {
-    light_dir: Vector3<f32>,
-    texture: RgbImage,
-    normal_map: RgbImage,
+    lights: Vec<Light>,
+    texture: Arc<RgbTexture>,
+    normal_map: Arc<RgbTexture>,
+    normal_space: NormalSpace,
     varying_uv: [Vector2<f32>; 3],
-    varying_tri: [Vector4<f32>; 3],
-    ndc_tri: [Vector3<f32>; 3], // normalized version of above
+    varying_tangent: [Vector3<f32>; 3],
+    varying_bitangent: [Vector3<f32>; 3],
+    varying_object_frame: Matrix3<f32>, // for object space normal maps
     varying_norm: [Vector3<f32>; 3],
-    uniform_m: Matrix4<f32>,
-    uniform_mit: Matrix4<f32>, // invert_transpose of m
+    varying_pos: [Vector3<f32>; 3], // world space, for positional lights
 }
 
 impl NormalShader {
     pub fn new(
-        light_dir: Vector3<f32>,
-        texture: RgbImage,
-        normal_map: RgbImage,
-        uniform_m: Matrix4<f32>, // projection * model_view
+        lights: &[Light],
+        texture: Arc<RgbTexture>,
+        normal_map: Arc<RgbTexture>,
+        normal_space: NormalSpace,
     ) -> NormalShader {
         NormalShader {
-            light_dir: (uniform_m * light_dir.extend(0.0)).truncate().normalize(),
+            lights: lights.to_vec(),
             texture,
             normal_map,
+            normal_space,
             varying_uv: [Vector2 { x: 0.0, y: 0.0 }; 3],
-            varying_tri: [Vector4 {
+            varying_tangent: [Vector3 {
                 x: 0.0,
                 y: 0.0,
                 z: 0.0,
-                w: 0.0,
             }; 3],
-            ndc_tri: [Vector3 {
+            varying_bitangent: [Vector3 {
                 x: 0.0,
                 y: 0.0,
                 z: 0.0,
             }; 3],
+            varying_object_frame: Matrix3::identity(),
             varying_norm: [Vector3 {
                 x: 0.0,
                 y: 0.0,
                 z: 0.0,
             }; 3],
-            uniform_m,
-            uniform_mit: uniform_m
-                .inverse_transform()
-                .expect("Could not find inverse")
-                .transpose(),
+            varying_pos: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
         }
     }
 }
@@ -206,123 +593,112 @@ impl our_gl::Shader for NormalShader {
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
     ) -> Vector4<f32> {
-        let v = model.get_faces()[iface][nthvert].v;
-        let vt = model.get_faces()[iface][nthvert].vt;
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
 
-        self.varying_uv[nthvert] = model.get_uvs()[vt];
-        self.varying_norm[nthvert] =
-            (self.uniform_mit * model.get_norms()[v].extend(0.0)).truncate();
+        self.varying_uv[nthvert] = model.get_uvs()[v];
+        self.varying_norm[nthvert] = (uniforms.shading_it * n.extend(0.0)).truncate();
+        self.varying_pos[nthvert] = p;
 
-        let gl_vertex = model.get_verts()[v].extend(1.0);
-        self.varying_tri[nthvert] = gl_vertex;
-        self.ndc_tri[nthvert] = gl_vertex.truncate() / gl_vertex.w;
-        mat * gl_vertex
+        (
+            self.varying_tangent[nthvert],
+            self.varying_bitangent[nthvert],
+        ) = tangent_frame(
+            instance.tangent(model.get_tangents()[v]),
+            n,
+            uniforms.shading,
+        );
+        self.varying_object_frame = object_frame(model, iface, instance, uniforms.shading_it);
+
+        let gl_vertex = p.extend(1.0);
+        uniforms.mat * gl_vertex
     }
 
-    fn fragment(&self, bc: Vector3<f32>, color: &mut Rgb<u8>) -> bool {
+    fn fragment(&self, bc: Vector3<f32>, uniforms: &our_gl::Uniforms, color: &mut Rgb<u8>) -> bool {
         let bn = (self.varying_norm[0] * bc[0]
             + self.varying_norm[1] * bc[1]
             + self.varying_norm[2] * bc[2])
             .normalize();
         let uv =
             self.varying_uv[0] * bc[0] + self.varying_uv[1] * bc[1] + self.varying_uv[2] * bc[2];
-        *color = self
-            .texture
-            .get_pixel(
-                (uv.x * self.texture.width() as f32) as u32,
-                (uv.y * self.texture.height() as f32) as u32,
-            )
-            .clone();
+        let albedo = self.texture.sample_linear(uv);
 
-        let a = Matrix3::<f32>::from_cols(
-            self.ndc_tri[1] - self.ndc_tri[0],
-            self.ndc_tri[2] - self.ndc_tri[0],
-            bn,
-        )
-        .transpose();
-        let ai = a.invert().expect("Matrix A does not have an inverse");
-
-        let i = ai
-            * Vector3::<f32>::new(
-                self.varying_uv[1].x - self.varying_uv[0].x,
-                self.varying_uv[2].x - self.varying_uv[0].x,
-                0.0,
-            );
-        let j = ai
-            * Vector3::<f32>::new(
-                self.varying_uv[1].y - self.varying_uv[0].y,
-                self.varying_uv[2].y - self.varying_uv[0].y,
-                0.0,
-            );
-
-        let b = Matrix3::<f32>::from_cols(i.normalize(), j.normalize(), bn);
-
-        let n_info = self.normal_map.get_pixel(
-            (uv.x * self.normal_map.width() as f32) as u32,
-            (uv.y * self.normal_map.height() as f32) as u32,
-        );
-        let n = b * Vector3::<f32>::new(
-            n_info[0] as f32 / 255.0 * 2.0 - 1.0,
-            n_info[1] as f32 / 255.0 * 2.0 - 1.0,
-            n_info[2] as f32 / 255.0 * 2.0 - 1.0,
-        )
-        .normalize();
-        let intensity = f32::max(0.0, dot(n, self.light_dir));
-        color[0] = (color[0] as f32 * intensity) as u8;
-        color[1] = (color[1] as f32 * intensity) as u8;
-        color[2] = (color[2] as f32 * intensity) as u8;
+        let n = {
+            let t = self.varying_tangent[0] * bc[0]
+                + self.varying_tangent[1] * bc[1]
+                + self.varying_tangent[2] * bc[2];
+            let b = self.varying_bitangent[0] * bc[0]
+                + self.varying_bitangent[1] * bc[1]
+                + self.varying_bitangent[2] * bc[2];
+            mapped_normal(
+                self.normal_space,
+                (t, b, bn),
+                self.varying_object_frame,
+                self.normal_map.sample(uv),
+            )
+        };
+        let n = if seen_from_behind(uniforms.sides, bn, Vector3::unit_z()) {
+            -n
+        } else {
+            n
+        };
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let intensity = diffuse(&self.lights, p, n, uniforms.shading, uniforms.sides);
+        *color = self.texture.encode(albedo.mul_element_wise(intensity));
         true
     }
 }
 
+#[derive(Clone)]
 pub struct SpecularShader {
-    light_dir: Vector3<f32>,
-    texture: RgbImage,
-    normal_map: RgbImage,
-    specular_map: GrayImage,
+    lights: Vec<Light>,
+    maps: Vec<Maps>, // one for each object of the model
+    varying_object: usize,
+    varying_screen: [Vector2<f32>; 3], // for the hashed alpha pattern
+    varying_material: Option<FaceMaterial>,
     varying_uv: [Vector2<f32>; 3],
-    varying_tri: [Vector4<f32>; 3],
-    ndc_tri: [Vector3<f32>; 3], // normalized version of above
+    varying_tangent: [Vector3<f32>; 3],
+    varying_bitangent: [Vector3<f32>; 3],
+    varying_object_frame: Matrix3<f32>, // for object space normal maps
     varying_norm: [Vector3<f32>; 3],
-    uniform_mit: Matrix4<f32>, // invert_transpose of m
+    varying_pos: [Vector3<f32>; 3], // world space, for positional lights
 }
 
 impl SpecularShader {
-    pub fn new(
-        light_dir: Vector3<f32>,
-        texture: RgbImage,
-        normal_map: RgbImage,
-        specular_map: GrayImage,
-        uniform_m: Matrix4<f32>, // projection * model_view
-    ) -> SpecularShader {
+    pub fn new(lights: &[Light], maps: &[Maps]) -> SpecularShader {
         SpecularShader {
-            light_dir: (uniform_m * light_dir.extend(0.0)).truncate().normalize(),
-            texture,
-            normal_map,
-            specular_map,
+            lights: lights.to_vec(),
+            maps: maps.to_vec(),
+            varying_object: 0,
+            varying_material: None,
+            varying_screen: [Vector2 { x: 0.0, y: 0.0 }; 3],
             varying_uv: [Vector2 { x: 0.0, y: 0.0 }; 3],
-            varying_tri: [Vector4 {
+            varying_tangent: [Vector3 {
                 x: 0.0,
                 y: 0.0,
                 z: 0.0,
-                w: 0.0,
             }; 3],
-            ndc_tri: [Vector3 {
+            varying_bitangent: [Vector3 {
                 x: 0.0,
                 y: 0.0,
                 z: 0.0,
             }; 3],
+            varying_object_frame: Matrix3::identity(),
             varying_norm: [Vector3 {
                 x: 0.0,
                 y: 0.0,
                 z: 0.0,
             }; 3],
-            uniform_mit: uniform_m
-                .inverse_transform()
-                .expect("Could not find inverse")
-                .transpose(),
+            varying_pos: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
         }
     }
 }
@@ -333,98 +709,182 @@ impl our_gl::Shader for SpecularShader {
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
     ) -> Vector4<f32> {
-        let v = model.get_faces()[iface][nthvert].v;
-        let vt = model.get_faces()[iface][nthvert].vt;
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
 
-        self.varying_uv[nthvert] = model.get_uvs()[vt];
-        self.varying_norm[nthvert] =
-            (self.uniform_mit * model.get_norms()[v].extend(0.0)).truncate();
+        self.varying_uv[nthvert] = model.get_uvs()[v];
+        self.varying_material = model.get_material(iface).map(FaceMaterial::from);
+        self.varying_object = model.get_object(iface);
+        self.varying_norm[nthvert] = (uniforms.shading_it * n.extend(0.0)).truncate();
+        self.varying_pos[nthvert] = p;
 
-        let gl_vertex = model.get_verts()[v].extend(1.0);
-        self.varying_tri[nthvert] = gl_vertex;
-        self.ndc_tri[nthvert] = gl_vertex.truncate() / gl_vertex.w;
-        mat * gl_vertex
+        (
+            self.varying_tangent[nthvert],
+            self.varying_bitangent[nthvert],
+        ) = tangent_frame(
+            instance.tangent(model.get_tangents()[v]),
+            n,
+            uniforms.shading,
+        );
+        self.varying_object_frame = object_frame(model, iface, instance, uniforms.shading_it);
+
+        let gl_vertex = p.extend(1.0);
+        let gl_vertex = uniforms.mat * gl_vertex;
+        let screen = uniforms.viewport * gl_vertex;
+        self.varying_screen[nthvert] = screen.truncate().truncate() / screen.w;
+        gl_vertex
     }
 
-    fn fragment(&self, bc: Vector3<f32>, color: &mut Rgb<u8>) -> bool {
+    fn fragment(&self, bc: Vector3<f32>, uniforms: &our_gl::Uniforms, color: &mut Rgb<u8>) -> bool {
+        let maps = object_maps(&self.maps, self.varying_object);
         let bn = (self.varying_norm[0] * bc[0]
             + self.varying_norm[1] * bc[1]
             + self.varying_norm[2] * bc[2])
             .normalize();
         let uv =
             self.varying_uv[0] * bc[0] + self.varying_uv[1] * bc[1] + self.varying_uv[2] * bc[2];
-        *color = self
-            .texture
-            .get_pixel(
-                (uv.x * self.texture.width() as f32) as u32,
-                (uv.y * self.texture.height() as f32) as u32,
-            )
-            .clone();
+        if cut_out(maps, uv) {
+            return false;
+        }
+        let material = self.varying_material.unwrap_or_default();
+        let screen = self.varying_screen[0] * bc[0]
+            + self.varying_screen[1] * bc[1]
+            + self.varying_screen[2] * bc[2];
+        if !hashed_alpha(material.d, screen) {
+            return false;
+        }
+        let albedo = match &maps.diffuse {
+            Some(texture) => texture.sample_linear(uv),
+            None => material.kd,
+        } * occlusion(maps, uv);
+        let spec_color = match (&maps.specular_color, self.varying_material) {
+            (Some(specular_color_map), _) => specular_color_map.sample_linear(uv),
+            (None, Some(material)) => material.ks,
+            (None, None) => DIELECTRIC_KS,
+        };
 
-        let a = Matrix3::<f32>::from_cols(
-            self.ndc_tri[1] - self.ndc_tri[0],
-            self.ndc_tri[2] - self.ndc_tri[0],
-            bn,
-        )
-        .transpose();
-        let ai = a.invert().expect("Matrix A does not have an inverse");
-
-        let i = ai
-            * Vector3::<f32>::new(
-                self.varying_uv[1].x - self.varying_uv[0].x,
-                self.varying_uv[2].x - self.varying_uv[0].x,
-                0.0,
-            );
-        let j = ai
-            * Vector3::<f32>::new(
-                self.varying_uv[1].y - self.varying_uv[0].y,
-                self.varying_uv[2].y - self.varying_uv[0].y,
-                0.0,
-            );
-
-        let b = Matrix3::<f32>::from_cols(i.normalize(), j.normalize(), bn);
-
-        let n_info = self.normal_map.get_pixel(
-            (uv.x * self.normal_map.width() as f32) as u32,
-            (uv.y * self.normal_map.height() as f32) as u32,
-        );
-        let n = b * Vector3::<f32>::new(
-            n_info[0] as f32 / 255.0 * 2.0 - 1.0,
-            n_info[1] as f32 / 255.0 * 2.0 - 1.0,
-            n_info[2] as f32 / 255.0 * 2.0 - 1.0,
-        )
-        .normalize();
+        let n = match &maps.normal {
+            Some(normal_map) => {
+                let t = self.varying_tangent[0] * bc[0]
+                    + self.varying_tangent[1] * bc[1]
+                    + self.varying_tangent[2] * bc[2];
+                let b = self.varying_bitangent[0] * bc[0]
+                    + self.varying_bitangent[1] * bc[1]
+                    + self.varying_bitangent[2] * bc[2];
+                mapped_normal(
+                    maps.normal_space,
+                    (t, b, bn),
+                    self.varying_object_frame,
+                    normal_map.sample(uv),
+                )
+            }
+            None => bn,
+        };
+        let sides = face_sides(self.varying_material, uniforms);
+        let behind = seen_from_behind(sides, bn, Vector3::unit_z());
+        let n = if behind { -n } else { n };
 
         // since number is <= 1 raising to the power sends < 1 to 0
-        let spec_pow = self.specular_map.get_pixel(
-            (uv.x * self.specular_map.width() as f32) as u32,
-            (uv.y * self.specular_map.height() as f32) as u32,
-        )[0];
-
-        let r = (n * (2.0 * dot(n, self.light_dir)) - self.light_dir).normalize();
-        let spec = r.z.max(0.0).powf(spec_pow as f32);
-        let diff = f32::max(0.0, dot(n, self.light_dir));
-        color[0] = (5.0 + color[0] as f32 * (diff + 0.3 * spec)).min(255.0) as u8;
-        color[1] = (5.0 + color[1] as f32 * (diff + 0.3 * spec)).min(255.0) as u8;
-        color[2] = (5.0 + color[2] as f32 * (diff + 0.3 * spec)).min(255.0) as u8;
+        let spec_pow = match &maps.specular {
+            Some(specular_map) => specular_map.sample(uv)[0] as f32,
+            None => material.ns,
+        };
+
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let mut diff = Vector3::new(0.0, 0.0, 0.0);
+        let mut spec = Vector3::new(0.0, 0.0, 0.0);
+        for light in &self.lights {
+            let (a, d, s) = phong(light, p, n, spec_pow, uniforms.shading, sides);
+            diff += a + d;
+            spec += s;
+        }
+        *color = encode(
+            reflect_light(albedo, spec_color, spec_pow, diff, spec),
+            output_space(&maps.diffuse),
+        );
+        true
+    }
+}
+
+// mirror finish, the view ray bounced off the surface picks the colour out of the environment
+pub struct ReflectionShader<'a> {
+    environment: &'a CubeMap,
+    varying_norm: [Vector3<f32>; 3],
+    varying_pos: [Vector3<f32>; 3],
+}
+
+impl<'a> ReflectionShader<'a> {
+    pub fn new(environment: &'a CubeMap) -> ReflectionShader<'a> {
+        ReflectionShader {
+            environment,
+            varying_norm: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_pos: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+        }
+    }
+}
+
+impl our_gl::Shader for ReflectionShader<'_> {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
+    ) -> Vector4<f32> {
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
+        self.varying_norm[nthvert] = n;
+        self.varying_pos[nthvert] = p;
+        uniforms.mat * p.extend(1.0)
+    }
+
+    fn fragment(&self, bc: Vector3<f32>, uniforms: &our_gl::Uniforms, color: &mut Rgb<u8>) -> bool {
+        let n = (self.varying_norm[0] * bc[0]
+            + self.varying_norm[1] * bc[1]
+            + self.varying_norm[2] * bc[2])
+            .normalize();
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let view = (p - uniforms.eye).normalize();
+        let r = view - n * (2.0 * dot(view, n));
+        *color = self.environment.sample(r);
         true
     }
 }
 
 pub struct DepthShader {
+    maps: Vec<Maps>, // only for their alpha, so cutouts cast cutout shadows
     varying_tri: [Vector3<f32>; 3],
+    varying_uv: [Vector2<f32>; 3],
+    varying_object: usize,
 }
 
 impl DepthShader {
-    pub fn new() -> DepthShader {
+    pub fn new(maps: &[Maps]) -> DepthShader {
         DepthShader {
+            maps: maps.to_vec(),
             varying_tri: [Vector3 {
                 x: 0.0,
                 y: 0.0,
                 z: 0.0,
             }; 3],
+            varying_uv: [Vector2 { x: 0.0, y: 0.0 }; 3],
+            varying_object: 0,
         }
     }
 }
@@ -435,15 +895,30 @@ impl our_gl::Shader for DepthShader {
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
     ) -> Vector4<f32> {
-        let v = model.get_faces()[iface][nthvert].v;
-        let gl_vertex = mat * model.get_verts()[v].extend(1.0);
-        self.varying_tri[nthvert] = gl_vertex.truncate() / gl_vertex.w;
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        self.varying_uv[nthvert] = model.get_uvs()[v];
+        self.varying_object = model.get_object(iface);
+        let gl_vertex = uniforms.mat * p.extend(1.0);
+        let screen = uniforms.viewport * gl_vertex;
+        self.varying_tri[nthvert] = screen.truncate() / screen.w;
         gl_vertex
     }
 
-    fn fragment(&self, bc: Vector3<f32>, color: &mut Rgb<u8>) -> bool {
+    fn fragment(
+        &self,
+        bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
+        let uv =
+            self.varying_uv[0] * bc[0] + self.varying_uv[1] * bc[1] + self.varying_uv[2] * bc[2];
+        if cut_out(object_maps(&self.maps, self.varying_object), uv) {
+            return false;
+        }
         let p =
             self.varying_tri[0] * bc[0] + self.varying_tri[1] * bc[1] + self.varying_tri[2] * bc[2];
         let depth: u8 = (255.0 * p.z / our_gl::DEPTH) as u8;
@@ -454,36 +929,46 @@ impl our_gl::Shader for DepthShader {
     }
 }
 
+#[derive(Clone)]
 pub struct ShadowShader {
-    light_dir: Vector3<f32>,
-    texture: RgbImage,
-    normal_map: RgbImage,
-    specular_map: GrayImage,
+    lights: Vec<Light>,
+    maps: Vec<Maps>, // one for each object of the model
+    varying_object: usize,
+    varying_screen: [Vector2<f32>; 3], // for the hashed alpha pattern
+    varying_material: Option<FaceMaterial>,
     varying_uv: [Vector2<f32>; 3],
     varying_tri: [Vector4<f32>; 3],
-    ndc_tri: [Vector3<f32>; 3], // normalized version of above
+    ndc_tri: [Vector3<f32>; 3], // above on the screen, divided by w
+    varying_tangent: [Vector3<f32>; 3],
+    varying_bitangent: [Vector3<f32>; 3],
+    varying_object_frame: Matrix3<f32>, // for object space normal maps
     varying_norm: [Vector3<f32>; 3],
-    uniform_m: Matrix4<f32>,
-    uniform_mit: Matrix4<f32>, // invert_transpose of m
-    uniform_m_shadow: Matrix4<f32>,
-    shadow_buffer: GrayImage,
+    varying_pos: [Vector3<f32>; 3], // world space, for positional lights
+    shadow_buffer: DepthImage,
+    pcf_radius: i32, // 0 is a single hard comparison, 1 a 3x3 kernel, 2 a 5x5 ...
+    probes: Option<ProbeGrid>, // ambient light, none without probes
+    varying_world_norm: [Vector3<f32>; 3],
+    pub hashed_alpha: bool, // off when depth peeling blends transparent surfaces instead
+    // when set, every light casts shadows by rays through the scene and the buffer is unused
+    pub traced: Option<Arc<Bvh>>,
+    // when set, the first light is a point light and casts shadows through these instead
+    pub cube_shadow: Option<Arc<CubeShadow>>,
 }
 
 impl ShadowShader {
     pub fn new(
-        light_dir: Vector3<f32>,
-        texture: RgbImage,
-        normal_map: RgbImage,
-        specular_map: GrayImage,
-        uniform_m: Matrix4<f32>, // projection * model_view
-        uniform_m_shadow: Matrix4<f32>,
-        shadow_buffer: GrayImage,
+        lights: &[Light],
+        maps: &[Maps],
+        shadow_buffer: DepthImage,
+        pcf_kernel: u32, // width of the square filter kernel, odd
+        probes: Option<ProbeGrid>,
     ) -> ShadowShader {
         ShadowShader {
-            light_dir: (uniform_m * light_dir.extend(0.0)).truncate().normalize(),
-            texture,
-            normal_map,
-            specular_map,
+            lights: lights.to_vec(),
+            maps: maps.to_vec(),
+            varying_object: 0,
+            varying_material: None,
+            varying_screen: [Vector2 { x: 0.0, y: 0.0 }; 3],
             varying_uv: [Vector2 { x: 0.0, y: 0.0 }; 3],
             varying_tri: [Vector4 {
                 x: 0.0,
@@ -496,18 +981,68 @@ impl ShadowShader {
                 y: 0.0,
                 z: 0.0,
             }; 3],
+            varying_tangent: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_bitangent: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_object_frame: Matrix3::identity(),
             varying_norm: [Vector3 {
                 x: 0.0,
                 y: 0.0,
                 z: 0.0,
             }; 3],
-            uniform_m,
-            uniform_mit: uniform_m
-                .inverse_transform()
-                .expect("Could not find inverse")
-                .transpose(),
-            uniform_m_shadow,
+            varying_pos: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
             shadow_buffer,
+            pcf_radius: (pcf_kernel / 2) as i32,
+            probes,
+            varying_world_norm: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            hashed_alpha: true,
+            traced: None,
+            cube_shadow: None,
+        }
+    }
+
+    // How much of the first light reaches the fragment, percentage-closer filtered over the
+    // kernel around its spot in the shadow buffer. The shadow pass pushed the buffer's depth
+    // back, so the fragment's own surface is behind it.
+    fn shadow(&self, bc: Vector3<f32>, uniforms: &our_gl::Uniforms) -> f32 {
+        let sb_p4 = uniforms.shadow
+            * (self.ndc_tri[0] * bc[0] + self.ndc_tri[1] * bc[1] + self.ndc_tri[2] * bc[2])
+                .extend(1.0);
+        pcf(
+            &self.shadow_buffer,
+            sb_p4.truncate() / sb_p4.w,
+            self.pcf_radius,
+        )
+    }
+
+    // whether the light reaches the fragment with world space normal n, by a ray cast towards
+    // it that passes through cut out holes
+    fn traced_shadow(&self, bvh: &Bvh, light: &Light, bc: Vector3<f32>, n: Vector3<f32>) -> f32 {
+        let p = curved_position(self.varying_pos, self.varying_world_norm, bc);
+        let (l, _) = light.incident(p);
+        let origin = p + n * bvh.epsilon() * dot(n, l).signum();
+        let blocked = bvh.occluded(origin, l, light.distance(origin), |object, uv| {
+            !cut_out(object_maps(&self.maps, object), uv)
+        });
+        if blocked {
+            0.0
+        } else {
+            1.0
         }
     }
 }
@@ -518,94 +1053,309 @@ impl our_gl::Shader for ShadowShader {
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
     ) -> Vector4<f32> {
-        let v = model.get_faces()[iface][nthvert].v;
-        let vt = model.get_faces()[iface][nthvert].vt;
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
 
-        self.varying_uv[nthvert] = model.get_uvs()[vt];
-        self.varying_norm[nthvert] =
-            (self.uniform_mit * model.get_norms()[v].extend(0.0)).truncate();
+        self.varying_uv[nthvert] = model.get_uvs()[v];
+        self.varying_material = model.get_material(iface).map(FaceMaterial::from);
+        self.varying_object = model.get_object(iface);
+        self.varying_norm[nthvert] = (uniforms.shading_it * n.extend(0.0)).truncate();
+        self.varying_world_norm[nthvert] = n;
+        (
+            self.varying_tangent[nthvert],
+            self.varying_bitangent[nthvert],
+        ) = tangent_frame(
+            instance.tangent(model.get_tangents()[v]),
+            n,
+            uniforms.shading,
+        );
+        self.varying_object_frame = object_frame(model, iface, instance, uniforms.shading_it);
+        self.varying_pos[nthvert] = p;
 
-        let gl_vertex = mat * model.get_verts()[v].extend(1.0);
+        let gl_vertex = uniforms.mat * p.extend(1.0);
         self.varying_tri[nthvert] = gl_vertex;
-        self.ndc_tri[nthvert] = gl_vertex.truncate() / gl_vertex.w;
+        let screen = uniforms.viewport * gl_vertex;
+        self.ndc_tri[nthvert] = screen.truncate() / screen.w;
+        self.varying_screen[nthvert] = self.ndc_tri[nthvert].truncate();
         gl_vertex
     }
 
-    fn fragment(&self, bc: Vector3<f32>, color: &mut Rgb<u8>) -> bool {
-        let sb_p4 = self.uniform_m_shadow
-            * (self.ndc_tri[0] * bc[0] + self.ndc_tri[1] * bc[1] + self.ndc_tri[2] * bc[2])
-                .extend(1.0);
-        let sb_p = sb_p4.truncate() / sb_p4.w;
-        let shadow = if (self.shadow_buffer.get_pixel(sb_p.x as u32, sb_p.y as u32)[0] as f32)
-            .lt(&(sb_p.z + WIGGLE))
-        {
-            1.0
-        } else {
-            0.3
-        };
-
+    fn fragment(&self, bc: Vector3<f32>, uniforms: &our_gl::Uniforms, color: &mut Rgb<u8>) -> bool {
+        let maps = object_maps(&self.maps, self.varying_object);
         let bn = (self.varying_norm[0] * bc[0]
             + self.varying_norm[1] * bc[1]
             + self.varying_norm[2] * bc[2])
             .normalize();
         let uv =
             self.varying_uv[0] * bc[0] + self.varying_uv[1] * bc[1] + self.varying_uv[2] * bc[2];
-        *color = self
-            .texture
-            .get_pixel(
-                (uv.x * self.texture.width() as f32) as u32,
-                (uv.y * self.texture.height() as f32) as u32,
-            )
-            .clone();
+        if cut_out(maps, uv) {
+            return false;
+        }
+        let material = self.varying_material.unwrap_or_default();
+        let screen = self.varying_screen[0] * bc[0]
+            + self.varying_screen[1] * bc[1]
+            + self.varying_screen[2] * bc[2];
+        if self.hashed_alpha && !hashed_alpha(material.d, screen) {
+            return false;
+        }
+        let albedo = match &maps.diffuse {
+            Some(texture) => texture.sample_linear(uv),
+            None => material.kd,
+        } * occlusion(maps, uv);
+        let spec_color = match (&maps.specular_color, self.varying_material) {
+            (Some(specular_color_map), _) => specular_color_map.sample_linear(uv),
+            (None, Some(material)) => material.ks,
+            (None, None) => DIELECTRIC_KS,
+        };
 
-        let a = Matrix3::<f32>::from_cols(
-            self.ndc_tri[1] - self.ndc_tri[0],
-            self.ndc_tri[2] - self.ndc_tri[0],
-            bn,
-        )
-        .transpose();
-        let ai = a.invert().expect("Matrix A does not have an inverse");
-
-        let i = ai
-            * Vector3::<f32>::new(
-                self.varying_uv[1].x - self.varying_uv[0].x,
-                self.varying_uv[2].x - self.varying_uv[0].x,
-                0.0,
-            );
-        let j = ai
-            * Vector3::<f32>::new(
-                self.varying_uv[1].y - self.varying_uv[0].y,
-                self.varying_uv[2].y - self.varying_uv[0].y,
-                0.0,
-            );
-
-        let b = Matrix3::<f32>::from_cols(i.normalize(), j.normalize(), bn);
-
-        let n_info = self.normal_map.get_pixel(
-            (uv.x * self.normal_map.width() as f32) as u32,
-            (uv.y * self.normal_map.height() as f32) as u32,
-        );
-        let n = b * Vector3::<f32>::new(
-            n_info[0] as f32 / 255.0 * 2.0 - 1.0,
-            n_info[1] as f32 / 255.0 * 2.0 - 1.0,
-            n_info[2] as f32 / 255.0 * 2.0 - 1.0,
-        )
-        .normalize();
+        let n = match &maps.normal {
+            Some(normal_map) => {
+                let t = self.varying_tangent[0] * bc[0]
+                    + self.varying_tangent[1] * bc[1]
+                    + self.varying_tangent[2] * bc[2];
+                let b = self.varying_bitangent[0] * bc[0]
+                    + self.varying_bitangent[1] * bc[1]
+                    + self.varying_bitangent[2] * bc[2];
+                mapped_normal(
+                    maps.normal_space,
+                    (t, b, bn),
+                    self.varying_object_frame,
+                    normal_map.sample(uv),
+                )
+            }
+            None => bn,
+        };
+        let sides = face_sides(self.varying_material, uniforms);
+        let behind = seen_from_behind(sides, bn, Vector3::unit_z());
+        let n = if behind { -n } else { n };
 
         // since number is <= 1 raising to the power sends < 1 to 0
-        let spec_pow = self.specular_map.get_pixel(
-            (uv.x * self.specular_map.width() as f32) as u32,
-            (uv.y * self.specular_map.height() as f32) as u32,
-        )[0];
-
-        let r = (n * (2.0 * dot(n, self.light_dir)) - self.light_dir).normalize();
-        let spec = r.z.max(0.0).powf(spec_pow as f32);
-        let diff = f32::max(0.0, dot(n, self.light_dir));
-        color[0] = (20.0 + color[0] as f32 * shadow * (1.2 * diff + 0.6 * spec)).min(255.0) as u8;
-        color[1] = (20.0 + color[1] as f32 * shadow * (1.2 * diff + 0.6 * spec)).min(255.0) as u8;
-        color[2] = (20.0 + color[2] as f32 * shadow * (1.2 * diff + 0.6 * spec)).min(255.0) as u8;
+        let spec_pow = match &maps.specular {
+            Some(specular_map) => specular_map.sample(uv)[0] as f32,
+            None => material.ns,
+        };
+
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let world_n = (self.varying_world_norm[0] * bc[0]
+            + self.varying_world_norm[1] * bc[1]
+            + self.varying_world_norm[2] * bc[2])
+            .normalize()
+            * if behind { -1.0 } else { 1.0 };
+        let mut diff = Vector3::new(0.0, 0.0, 0.0);
+        let mut spec = Vector3::new(0.0, 0.0, 0.0);
+        for (k, light) in self.lights.iter().enumerate() {
+            let (a, d, s) = phong(light, p, n, spec_pow, uniforms.shading, sides);
+            let visibility = match &self.traced {
+                Some(bvh) => self.traced_shadow(bvh, light, bc, world_n),
+                // the shadow buffer is rendered from the first light only
+                None if k == 0 => match &self.cube_shadow {
+                    Some(cube_shadow) => cube_shadow.lit(p, self.pcf_radius),
+                    None => self.shadow(bc, uniforms),
+                },
+                None => 1.0,
+            };
+            diff += a + d * visibility;
+            spec += s * visibility;
+        }
+        if let Some(probes) = &self.probes {
+            // irradiance to outgoing radiance of a lambertian surface
+            diff += probes.irradiance(p, world_n) / std::f32::consts::PI;
+        }
+        *color = encode(
+            reflect_light(albedo, spec_color, spec_pow, diff, spec),
+            output_space(&maps.diffuse),
+        );
+        true
+    }
+}
+
+impl our_gl::TranslucentShader for ShadowShader {
+    fn opacity(&self, _bar: Vector3<f32>) -> f32 {
+        self.varying_material.unwrap_or_default().d
+    }
+}
+
+// The geometric term of the lighting on its own: the sum over the lights of how much of each
+// reaches the surface times n.l, in world space and ignoring the material. 0 to 1 is shown as
+// linear grey, more than 1 (only possible with several lights) in red and surfaces facing
+// away from every light in dark blue.
+pub struct NdotLShader {
+    lights: Vec<Light>,
+    varying_norm: [Vector3<f32>; 3],
+    varying_pos: [Vector3<f32>; 3],
+}
+
+impl NdotLShader {
+    pub fn new(lights: &[Light]) -> NdotLShader {
+        NdotLShader {
+            lights: lights.to_vec(),
+            varying_norm: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_pos: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+        }
+    }
+}
+
+impl our_gl::Shader for NdotLShader {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
+    ) -> Vector4<f32> {
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
+        self.varying_norm[nthvert] = n;
+        self.varying_pos[nthvert] = p;
+        uniforms.mat * p.extend(1.0)
+    }
+
+    fn fragment(
+        &self,
+        bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
+        let n = (self.varying_norm[0] * bc[0]
+            + self.varying_norm[1] * bc[1]
+            + self.varying_norm[2] * bc[2])
+            .normalize();
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let mut sum = 0.0;
+        let mut facing = false;
+        for light in &self.lights {
+            let (l, attenuation) = light.incident(p);
+            let n_dot_l = dot(n, l);
+            facing |= n_dot_l > 0.0;
+            sum += attenuation * n_dot_l.max(0.0);
+        }
+        *color = if !facing {
+            Rgb([0, 0, 64])
+        } else if sum > 1.0 {
+            Rgb([255, 0, 0])
+        } else {
+            let grey = (sum * 255.0).round() as u8;
+            Rgb([grey, grey, grey])
+        };
+        true
+    }
+}
+
+// what --debug-view draws in place of the shading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DebugView {
+    // world space, each axis mapped from [-1, 1]
+    Normals,
+    // the fraction of u in red and v in green, so tiled textures repeat
+    Uvs,
+    // grey as the depth buffer stores it, nearer is lighter
+    Depth,
+    // of the shaded triangle, one corner each in red, green and blue
+    Barycentric,
+}
+
+impl std::str::FromStr for DebugView {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<DebugView> {
+        match s {
+            "normals" => Ok(DebugView::Normals),
+            "uvs" => Ok(DebugView::Uvs),
+            "depth" => Ok(DebugView::Depth),
+            "barycentric" => Ok(DebugView::Barycentric),
+            _ => Err(anyhow::anyhow!("unknown debug view '{}'", s)),
+        }
+    }
+}
+
+// Draws one of the model's attributes as a colour with no lighting, to check them with.
+#[derive(Clone)]
+pub struct DebugShader {
+    view: DebugView,
+    varying_norm: [Vector3<f32>; 3],
+    varying_uv: [Vector2<f32>; 3],
+    varying_depth: [f32; 3],
+}
+
+impl DebugShader {
+    pub fn new(view: DebugView) -> DebugShader {
+        DebugShader {
+            view,
+            varying_norm: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_uv: [Vector2 { x: 0.0, y: 0.0 }; 3],
+            varying_depth: [0.0; 3],
+        }
+    }
+}
+
+impl our_gl::Shader for DebugShader {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
+    ) -> Vector4<f32> {
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let gl_vertex = uniforms.mat * p.extend(1.0);
+        self.varying_norm[nthvert] = instance.normal(model.get_norms()[v]);
+        self.varying_uv[nthvert] = model.get_uvs()[v];
+        self.varying_depth[nthvert] = (gl_vertex.z / gl_vertex.w + 1.0) / 2.0;
+        gl_vertex
+    }
+
+    fn fragment(
+        &self,
+        bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
+        let rgb = match self.view {
+            DebugView::Normals => {
+                let n = (self.varying_norm[0] * bc[0]
+                    + self.varying_norm[1] * bc[1]
+                    + self.varying_norm[2] * bc[2])
+                    .normalize();
+                (n + Vector3::new(1.0, 1.0, 1.0)) / 2.0
+            }
+            DebugView::Uvs => {
+                let uv = self.varying_uv[0] * bc[0]
+                    + self.varying_uv[1] * bc[1]
+                    + self.varying_uv[2] * bc[2];
+                Vector3::new(uv.x.rem_euclid(1.0), uv.y.rem_euclid(1.0), 0.0)
+            }
+            DebugView::Depth => {
+                let depth = self.varying_depth[0] * bc[0]
+                    + self.varying_depth[1] * bc[1]
+                    + self.varying_depth[2] * bc[2];
+                Vector3::new(depth, depth, depth)
+            }
+            DebugView::Barycentric => bc,
+        };
+        let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        *color = Rgb([to_u8(rgb.x), to_u8(rgb.y), to_u8(rgb.z)]);
         true
     }
 }
@@ -627,22 +1377,223 @@ impl ZShader {
     }
 }
 
+impl Default for ZShader {
+    fn default() -> ZShader {
+        ZShader::new()
+    }
+}
+
 impl our_gl::Shader for ZShader {
     fn vertex(
         &mut self,
         model: &model::Model,
         iface: usize,
         nthvert: usize,
-        mat: Matrix4<f32>,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
     ) -> Vector4<f32> {
-        let v = model.get_faces()[iface][nthvert].v;
-        let gl_vertex = mat * model.get_verts()[v].extend(1.0);
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let gl_vertex = uniforms.mat * p.extend(1.0);
         self.varying_tri[nthvert] = gl_vertex;
         gl_vertex
     }
 
-    fn fragment(&self, _bc: Vector3<f32>, color: &mut Rgb<u8>) -> bool {
+    fn fragment(
+        &self,
+        _bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
         *color = Rgb([0, 0, 0]);
         true
     }
 }
+
+// Draws faces unwrapped onto their texture, uniforms.mat taking uv to texels. Texels the
+// depth buffer of a shadow pass has in front are white and hidden ones black,
+// uniforms.shadow takes world space to that buffer.
+pub struct OcclusionBakeShader<'a> {
+    depth: &'a DepthImage,
+    varying_pos: [Vector3<f32>; 3],
+}
+
+impl OcclusionBakeShader<'_> {
+    pub fn new(depth: &DepthImage) -> OcclusionBakeShader<'_> {
+        OcclusionBakeShader {
+            depth,
+            varying_pos: [Vector3::new(0.0, 0.0, 0.0); 3],
+        }
+    }
+}
+
+impl our_gl::Shader for OcclusionBakeShader<'_> {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
+    ) -> Vector4<f32> {
+        let v = model.get_faces()[iface][nthvert];
+        self.varying_pos[nthvert] = instance.position(model.get_verts()[v]);
+        // always in front of the empty depth buffer, the unwrapped faces don't overlap
+        let uv = model.get_uvs()[v];
+        uniforms.mat * Vector4::new(uv.x, uv.y, our_gl::DEPTH, 1.0)
+    }
+
+    fn fragment(&self, bc: Vector3<f32>, uniforms: &our_gl::Uniforms, color: &mut Rgb<u8>) -> bool {
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let sb_p4 = uniforms.shadow * p.extend(1.0);
+        let sb_p = sb_p4.truncate() / sb_p4.w;
+        let (width, height) = self.depth.dimensions();
+        let x = (sb_p.x as i32).clamp(0, width as i32 - 1) as u32;
+        let y = (sb_p.y as i32).clamp(0, height as i32 - 1) as u32;
+        *color = if self.depth.get_pixel(x, y)[0] < sb_p.z {
+            Rgb([255, 255, 255])
+        } else {
+            Rgb([0, 0, 0])
+        };
+        true
+    }
+}
+
+// The model in one flat colour, pushed out along its normals by width in world units.
+// Drawn through the stencil where the model itself isn't, it leaves an outline.
+pub struct OutlineShader {
+    color: Rgb<u8>,
+    width: f32,
+}
+
+impl OutlineShader {
+    pub fn new(color: Rgb<u8>, width: f32) -> OutlineShader {
+        OutlineShader { color, width }
+    }
+}
+
+impl our_gl::Shader for OutlineShader {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
+    ) -> Vector4<f32> {
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
+        uniforms.mat * (p + n * self.width).extend(1.0)
+    }
+
+    fn fragment(
+        &self,
+        _bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
+        *color = self.color;
+        true
+    }
+}
+
+// Fills a G-buffer: the albedo as the colour, the normal in view space mapped from [-1, 1]
+// and the world space position mapped from the box min..max, both onto [0, 255].
+pub struct GBufferShader {
+    maps: Vec<Maps>, // one for each object of the model
+    varying_object: usize,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    varying_uv: [Vector2<f32>; 3],
+    varying_norm: [Vector3<f32>; 3],
+    varying_pos: [Vector3<f32>; 3],
+    varying_material: Option<FaceMaterial>,
+}
+
+impl GBufferShader {
+    pub fn new(maps: &[Maps], min: Vector3<f32>, max: Vector3<f32>) -> GBufferShader {
+        GBufferShader {
+            maps: maps.to_vec(),
+            varying_object: 0,
+            min,
+            max,
+            varying_uv: [Vector2 { x: 0.0, y: 0.0 }; 3],
+            varying_norm: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_pos: [Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            }; 3],
+            varying_material: None,
+        }
+    }
+}
+
+impl our_gl::Shader for GBufferShader {
+    fn vertex(
+        &mut self,
+        model: &model::Model,
+        iface: usize,
+        nthvert: usize,
+        uniforms: &our_gl::Uniforms,
+        instance: &our_gl::Instance,
+    ) -> Vector4<f32> {
+        let v = model.get_faces()[iface][nthvert];
+        let p = instance.position(model.get_verts()[v]);
+        let n = instance.normal(model.get_norms()[v]);
+
+        self.varying_uv[nthvert] = model.get_uvs()[v];
+        self.varying_material = model.get_material(iface).map(FaceMaterial::from);
+        self.varying_object = model.get_object(iface);
+        self.varying_norm[nthvert] = (uniforms.view_it * n.extend(0.0)).truncate();
+        self.varying_pos[nthvert] = p;
+        uniforms.mat * p.extend(1.0)
+    }
+
+    fn fragment(
+        &self,
+        bc: Vector3<f32>,
+        _uniforms: &our_gl::Uniforms,
+        color: &mut Rgb<u8>,
+    ) -> bool {
+        let maps = object_maps(&self.maps, self.varying_object);
+        let uv =
+            self.varying_uv[0] * bc[0] + self.varying_uv[1] * bc[1] + self.varying_uv[2] * bc[2];
+        if cut_out(maps, uv) {
+            return false;
+        }
+        let albedo = match &maps.diffuse {
+            Some(texture) => texture.sample_linear(uv),
+            None => self.varying_material.unwrap_or_default().kd,
+        };
+        *color = encode(albedo, output_space(&maps.diffuse));
+        true
+    }
+}
+
+impl our_gl::MultiTargetShader for GBufferShader {
+    fn attributes(&self, bc: Vector3<f32>) -> (Rgb<u8>, Rgb<u8>) {
+        let n = (self.varying_norm[0] * bc[0]
+            + self.varying_norm[1] * bc[1]
+            + self.varying_norm[2] * bc[2])
+            .normalize();
+        let p =
+            self.varying_pos[0] * bc[0] + self.varying_pos[1] * bc[1] + self.varying_pos[2] * bc[2];
+        let p = (p - self.min).div_element_wise(self.max - self.min);
+        let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        (
+            Rgb([
+                to_u8((n.x + 1.0) / 2.0),
+                to_u8((n.y + 1.0) / 2.0),
+                to_u8((n.z + 1.0) / 2.0),
+            ]),
+            Rgb([to_u8(p.x), to_u8(p.y), to_u8(p.z)]),
+        )
+    }
+}
@@ -0,0 +1,73 @@
+use cgmath::{dot, Matrix4, Vector3};
+
+use super::camera::Camera;
+use super::cubemap;
+use super::our_gl::DepthImage;
+
+// How much of the buffer around sb_p, a point on its screen, is behind sb_p's depth: 1 when
+// all of it, 0 when the point is in shadow. Percentage-closer filtered over radius texels
+// either side.
+pub fn pcf(buffer: &DepthImage, sb_p: Vector3<f32>, radius: i32) -> f32 {
+    let (width, height) = buffer.dimensions();
+    let mut lit = 0;
+    for dx in -radius..=radius {
+        for dy in -radius..=radius {
+            let x = (sb_p.x as i32 + dx).clamp(0, width as i32 - 1);
+            let y = (sb_p.y as i32 + dy).clamp(0, height as i32 - 1);
+            if buffer.get_pixel(x as u32, y as u32)[0] < sb_p.z {
+                lit += 1;
+            }
+        }
+    }
+    let samples = (2 * radius + 1).pow(2);
+    lit as f32 / samples as f32
+}
+
+// The shadow buffers of a point light, one for each face of a cube around it in
+// cubemap::FACES order, so it casts shadows in every direction.
+pub struct CubeShadow {
+    position: Vector3<f32>,
+    // world space to the screen of each face and the depth seen there
+    faces: Vec<(Matrix4<f32>, DepthImage)>,
+}
+
+impl CubeShadow {
+    pub fn new(position: Vector3<f32>, faces: Vec<(Matrix4<f32>, DepthImage)>) -> CubeShadow {
+        CubeShadow { position, faces }
+    }
+
+    // What face of a size x size cube around position sees, with near and far around the
+    // sphere at center. The light can be inside it.
+    pub fn face_camera(
+        position: Vector3<f32>,
+        face: usize,
+        (center, radius): (Vector3<f32>, f32),
+        size: u32,
+    ) -> Camera {
+        // looking down -z with +y up, so the faces turn to the world's axes
+        let mut camera = Camera::new(
+            position,
+            position - Vector3::unit_z(),
+            Vector3::unit_y(),
+            size,
+            size,
+        );
+        camera.fit_depth(center, radius);
+        camera.cube_face(face);
+        camera
+    }
+
+    // how much of the light reaches world space p, like pcf() in the face p is seen through
+    pub fn lit(&self, p: Vector3<f32>, radius: i32) -> f32 {
+        let d = p - self.position;
+        let face = (0..self.faces.len())
+            .max_by(|&a, &b| {
+                let along = |face| dot(cubemap::face_axes(face).0, d);
+                along(a).total_cmp(&along(b))
+            })
+            .unwrap_or(0);
+        let (transform, buffer) = &self.faces[face];
+        let sb_p4 = transform * p.extend(1.0);
+        pcf(buffer, sb_p4.truncate() / sb_p4.w, radius)
+    }
+}
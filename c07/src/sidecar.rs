@@ -0,0 +1,122 @@
+use anyhow::Result;
+use cgmath::{Matrix4, Vector3};
+use image::RgbImage;
+use std::fmt::Write;
+
+use super::camera::Camera;
+use super::light::{Light, Source};
+
+// what went into a frame besides the camera and lights
+pub struct Stats {
+    pub frame: u32,
+    pub faces: usize,
+    // pixels the main pass covered
+    pub covered: usize,
+    pub milliseconds: u128,
+}
+
+// 64 bit FNV-1a, stable across platforms and compiler versions unlike std's hasher
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn vector(v: Vector3<f32>) -> String {
+    format!("[{}, {}, {}]", v.x, v.y, v.z)
+}
+
+// column major, like OpenGL
+fn matrix(m: Matrix4<f32>) -> String {
+    let m: &[f32; 16] = m.as_ref();
+    format!(
+        "[{}]",
+        m.iter().map(f32::to_string).collect::<Vec<_>>().join(", ")
+    )
+}
+
+fn light(light: &Light) -> String {
+    let source = match light.source {
+        Source::Directional { dir } => format!("\"type\": \"dir\", \"dir\": {}", vector(dir)),
+        Source::Point {
+            position,
+            attenuation,
+        } => format!(
+            "\"type\": \"point\", \"position\": {}, \"attenuation\": {}",
+            vector(position),
+            attenuation
+        ),
+        Source::Spot {
+            position,
+            dir,
+            inner,
+            outer,
+            attenuation,
+        } => format!(
+            "\"type\": \"spot\", \"position\": {}, \"dir\": {}, \"inner\": {}, \"outer\": {}, \"attenuation\": {}",
+            vector(position),
+            vector(dir),
+            inner.to_degrees(),
+            outer.to_degrees(),
+            attenuation
+        ),
+    };
+    format!(
+        "{{{}, \"color\": {}, \"ambient\": {}, \"diffuse\": {}, \"specular\": {}}}",
+        source,
+        vector(light.color),
+        light.ambient,
+        light.diffuse,
+        light.specular
+    )
+}
+
+// Everything needed to reproduce or audit a saved frame, as JSON. image is the frame as
+// saved to image_path, the hash is over its raw RGB bytes so it doesn't depend on the file
+// format. Nothing in the renderer is random, so the camera, lights and model are the
+// whole input.
+pub fn write(
+    path: &str,
+    image_path: &str,
+    image: &RgbImage,
+    camera: &Camera,
+    lights: &[Light],
+    stats: &Stats,
+) -> Result<()> {
+    let mut json = String::new();
+    writeln!(json, "{{")?;
+    writeln!(json, "  \"image\": \"{}\",", image_path)?;
+    writeln!(
+        json,
+        "  \"hash\": \"{:016x}\",",
+        content_hash(image.as_raw())
+    )?;
+    writeln!(json, "  \"frame\": {},", stats.frame)?;
+    writeln!(json, "  \"camera\": {{")?;
+    writeln!(json, "    \"spec\": \"{}\",", camera)?;
+    writeln!(json, "    \"model_view\": {},", matrix(camera.model_view()))?;
+    writeln!(
+        json,
+        "    \"projection\": {},",
+        matrix(camera.projection_matrix())
+    )?;
+    writeln!(
+        json,
+        "    \"viewport\": {}",
+        matrix(camera.viewport_matrix())
+    )?;
+    writeln!(json, "  }},")?;
+    writeln!(
+        json,
+        "  \"lights\": [{}],",
+        lights.iter().map(light).collect::<Vec<_>>().join(", ")
+    )?;
+    writeln!(
+        json,
+        "  \"stats\": {{\"faces\": {}, \"covered\": {}, \"milliseconds\": {}}}",
+        stats.faces, stats.covered, stats.milliseconds
+    )?;
+    writeln!(json, "}}")?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use anyhow::Result;
+use image::RgbImage;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+
+// Wire format, one message per tile (a whole frame is just a tile covering the image).
+// Every integer is a big-endian u32:
+//   frame width, frame height, tile x, tile y, tile width, tile height, payload length
+// followed by the payload: tile width * tile height RGB triples, top row first.
+pub const HEADER_LEN: usize = 7 * 4;
+
+pub struct FrameStream {
+    stream: TcpStream,
+}
+
+impl FrameStream {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<FrameStream> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(FrameStream { stream })
+    }
+
+    pub fn send_frame(&mut self, image: &RgbImage) -> Result<()> {
+        self.send_tile(image, 0, 0, image.width(), image.height())
+    }
+
+    // (x, y) is the top left of the tile in image coordinates
+    pub fn send_tile(&mut self, image: &RgbImage, x: u32, y: u32, w: u32, h: u32) -> Result<()> {
+        let w = w.min(image.width().saturating_sub(x));
+        let h = h.min(image.height().saturating_sub(y));
+
+        let mut payload: Vec<u8> = Vec::with_capacity((w * h * 3) as usize);
+        for row in y..y + h {
+            for col in x..x + w {
+                payload.extend_from_slice(&image.get_pixel(col, row).0);
+            }
+        }
+        self.send(image.dimensions(), x, y, w, h, payload)
+    }
+
+    // a tile drawn on its own, with (x, y) its top left in a frame of the given size
+    pub fn send_part(&mut self, frame: (u32, u32), x: u32, y: u32, part: &RgbImage) -> Result<()> {
+        let (w, h) = part.dimensions();
+        self.send(frame, x, y, w, h, part.as_raw().clone())
+    }
+
+    fn send(
+        &mut self,
+        (width, height): (u32, u32),
+        x: u32,
+        y: u32,
+        w: u32,
+        h: u32,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        let mut message: Vec<u8> = Vec::with_capacity(HEADER_LEN + payload.len());
+        for field in [width, height, x, y, w, h, payload.len() as u32] {
+            message.extend_from_slice(&field.to_be_bytes());
+        }
+        message.extend_from_slice(&payload);
+
+        self.stream.write_all(&message)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+#[cfg(not(target_arch = "wasm32"))]
+use super::material::Material;
+use anyhow::Result;
+use cgmath::{Vector2, Vector3};
+use image::{io::Reader as ImageReader, DynamicImage, ImageFormat};
+use image::{ImageBuffer, Luma, Pixel, Rgb};
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::io::Cursor;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::sync::Arc;
+
+// what the textures next to a model can be saved as, tried in this order
+#[cfg(not(target_arch = "wasm32"))]
+const EXTENSIONS: [&str; 5] = ["tga", "png", "jpg", "jpeg", "bmp"];
+
+// where a texture a model refers to is kept
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ImageSource {
+    File(String),
+    // inside the model's own file, as glTF can keep them
+    Embedded(Vec<u8>),
+}
+
+impl ImageSource {
+    pub fn file(name: &str) -> ImageSource {
+        ImageSource::File(String::from(name))
+    }
+}
+
+// what to do with uv coordinates outside of [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl std::str::FromStr for WrapMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<WrapMode> {
+        match s {
+            "repeat" => Ok(WrapMode::Repeat),
+            "clamp" => Ok(WrapMode::Clamp),
+            "mirror" => Ok(WrapMode::Mirror),
+            _ => Err(anyhow::anyhow!("unknown wrap mode '{}'", s)),
+        }
+    }
+}
+
+impl WrapMode {
+    // maps a texture coordinate to a texel index in 0..size
+    fn texel(self, t: f32, size: u32) -> u32 {
+        let t = match self {
+            WrapMode::Repeat => t - t.floor(),
+            WrapMode::Clamp => t.clamp(0.0, 1.0),
+            WrapMode::Mirror => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 {
+                    2.0 - t
+                } else {
+                    t
+                }
+            }
+        };
+        ((t * size as f32) as u32).min(size - 1)
+    }
+}
+
+// what the directions stored in a normal map are relative to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalSpace {
+    // the surface's tangent frame, <model>_nm_tangent.tga
+    #[default]
+    Tangent,
+    // the model as it was read, <model>_nm.tga
+    Object,
+}
+
+impl std::str::FromStr for NormalSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<NormalSpace> {
+        match s {
+            "tangent" => Ok(NormalSpace::Tangent),
+            "object" => Ok(NormalSpace::Object),
+            _ => Err(anyhow::anyhow!("unknown normal map space '{}'", s)),
+        }
+    }
+}
+
+// how the stored texel values relate to light intensity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,   // gamma encoded, as painted colour maps usually are
+    Linear, // used as is, for data such as normal or specular maps
+}
+
+pub fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let c = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+#[derive(Clone)]
+pub struct Texture<P: Pixel + 'static> {
+    image: ImageBuffer<P, Vec<P::Subpixel>>,
+    wrap: WrapMode,
+    color_space: ColorSpace,
+}
+
+pub type RgbTexture = Texture<Rgb<u8>>;
+pub type GrayTexture = Texture<Luma<u8>>;
+
+// The textures one object is drawn with, shaders fall back to its materials for missing ones.
+// Shared with the TextureCache they came from and every shader, cloning copies no texels.
+#[derive(Clone, Default)]
+pub struct Maps {
+    pub diffuse: Option<Arc<RgbTexture>>,
+    pub normal: Option<Arc<RgbTexture>>,
+    pub normal_space: NormalSpace, // of normal
+    pub specular: Option<Arc<GrayTexture>>,
+    pub specular_color: Option<Arc<RgbTexture>>, // tints highlights, e.g. gold or copper
+    pub alpha: Option<Arc<GrayTexture>>,         // the diffuse map's alpha channel, for cutouts
+    pub occlusion: Option<Arc<GrayTexture>>,     // baked ambient occlusion, see bake.rs
+}
+
+// where a texture was loaded from and how it is sampled
+#[cfg(not(target_arch = "wasm32"))]
+type CacheKey = (ImageSource, WrapMode, ColorSpace);
+
+// Textures by the file or embedded image they were loaded from, so objects and materials
+// naming the same one share a single copy. One is kept for all the load_maps() of a scene.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub struct TextureCache {
+    color: HashMap<CacheKey, Arc<RgbTexture>>,
+    gray: HashMap<CacheKey, Arc<GrayTexture>>,
+    // the alpha channels of the colour textures, None for those without one
+    alpha: HashMap<CacheKey, Option<Arc<GrayTexture>>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TextureCache {
+    // None when the file isn't there, see load_image()
+    pub fn color(
+        &mut self,
+        source: &ImageSource,
+        wrap: WrapMode,
+        color_space: ColorSpace,
+    ) -> Result<Option<Arc<RgbTexture>>> {
+        let key = (source.clone(), wrap, color_space);
+        if let Some(texture) = self.color.get(&key) {
+            return Ok(Some(texture.clone()));
+        }
+        let image = match load_image(source)? {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+        // kept while the image is decoded, alpha() would have to load it again
+        let alpha = image.color().has_alpha().then(|| {
+            let rgba = image.to_rgba8();
+            let alpha = ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+                Luma([rgba.get_pixel(x, y)[3]])
+            });
+            Arc::new(Texture::new(alpha, wrap, ColorSpace::Linear))
+        });
+        self.alpha.insert(key.clone(), alpha);
+        let texture = Arc::new(Texture::new(image.to_rgb8(), wrap, color_space));
+        self.color.insert(key, texture.clone());
+        Ok(Some(texture))
+    }
+
+    // single channel data such as specular or occlusion, never gamma encoded
+    pub fn gray(
+        &mut self,
+        source: &ImageSource,
+        wrap: WrapMode,
+    ) -> Result<Option<Arc<GrayTexture>>> {
+        let key = (source.clone(), wrap, ColorSpace::Linear);
+        if let Some(texture) = self.gray.get(&key) {
+            return Ok(Some(texture.clone()));
+        }
+        let texture = load_image(source)?
+            .map(|image| Arc::new(Texture::new(image.to_luma8(), wrap, ColorSpace::Linear)));
+        if let Some(texture) = &texture {
+            self.gray.insert(key, texture.clone());
+        }
+        Ok(texture)
+    }
+
+    // the alpha channel of a texture color() loaded, None if it has none
+    pub fn alpha(
+        &self,
+        source: &ImageSource,
+        wrap: WrapMode,
+        color_space: ColorSpace,
+    ) -> Option<Arc<GrayTexture>> {
+        self.alpha
+            .get(&(source.clone(), wrap, color_space))
+            .cloned()
+            .flatten()
+    }
+}
+
+impl<P: Pixel + 'static> Texture<P> {
+    pub fn new(
+        image: ImageBuffer<P, Vec<P::Subpixel>>,
+        wrap: WrapMode,
+        color_space: ColorSpace,
+    ) -> Texture<P> {
+        Texture {
+            image,
+            wrap,
+            color_space,
+        }
+    }
+
+    pub fn sample(&self, uv: Vector2<f32>) -> P {
+        *self.image.get_pixel(
+            self.wrap.texel(uv.x, self.image.width()),
+            self.wrap.texel(uv.y, self.image.height()),
+        )
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+}
+
+impl RgbTexture {
+    // colour at uv in linear space with channels in [0, 1], ready for lighting
+    pub fn sample_linear(&self, uv: Vector2<f32>) -> Vector3<f32> {
+        let texel = self.sample(uv);
+        match self.color_space {
+            ColorSpace::Srgb => Vector3::new(
+                srgb_to_linear(texel[0]),
+                srgb_to_linear(texel[1]),
+                srgb_to_linear(texel[2]),
+            ),
+            ColorSpace::Linear => Vector3::new(
+                texel[0] as f32 / 255.0,
+                texel[1] as f32 / 255.0,
+                texel[2] as f32 / 255.0,
+            ),
+        }
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    // converts a lit linear colour back into the colour space the texture was authored in
+    pub fn encode(&self, color: Vector3<f32>) -> Rgb<u8> {
+        encode(color, self.color_space)
+    }
+}
+
+// a linear colour with channels in [0, 1] as a framebuffer pixel in the given colour space
+pub fn encode(color: Vector3<f32>, color_space: ColorSpace) -> Rgb<u8> {
+    match color_space {
+        ColorSpace::Srgb => Rgb([
+            linear_to_srgb(color.x),
+            linear_to_srgb(color.y),
+            linear_to_srgb(color.z),
+        ]),
+        ColorSpace::Linear => Rgb([
+            (color.x * 255.0).clamp(0.0, 255.0) as u8,
+            (color.y * 255.0).clamp(0.0, 255.0) as u8,
+            (color.z * 255.0).clamp(0.0, 255.0) as u8,
+        ]),
+    }
+}
+
+// The textures the materials of a model name, or else the ones next to its file called
+// <path>_diffuse, <path>_nm_tangent and so on in any of EXTENSIONS. Any of them can be
+// missing. The faces of a model share its maps, the first material naming one gives it.
+// Textures already in cache aren't loaded again.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_maps(
+    path: &str,
+    materials: &[Material],
+    wrap: WrapMode,
+    diffuse_space: ColorSpace,
+    normal_space: NormalSpace,
+    cache: &mut TextureCache,
+) -> Result<Maps> {
+    let linear = ColorSpace::Linear;
+    let find = |named: fn(&Material) -> Option<&ImageSource>, suffix: &str| {
+        materials
+            .iter()
+            .find_map(named)
+            .cloned()
+            .or_else(|| beside(path, suffix))
+    };
+    let diffuse_source = find(|m| m.map_kd.as_ref(), "diffuse");
+    let diffuse = diffuse_source
+        .as_ref()
+        .map(|source| cache.color(source, wrap, diffuse_space))
+        .transpose()?
+        .flatten();
+    // cutouts such as foliage or hair cards come in the diffuse map's alpha channel, or
+    // a map of their own
+    let alpha = match find(|m| m.map_d.as_ref(), "alpha") {
+        Some(source) => cache.gray(&source, wrap)?,
+        None => diffuse_source
+            .as_ref()
+            .and_then(|source| cache.alpha(source, wrap, diffuse_space)),
+    };
+    let normal = match normal_space {
+        NormalSpace::Tangent => find(|m| m.map_bump.as_ref(), "nm_tangent"),
+        // materials only name tangent space ones
+        NormalSpace::Object => find(|_| None, "nm"),
+    };
+    let mut color = |source: Option<ImageSource>, color_space| {
+        source
+            .map(|source| cache.color(&source, wrap, color_space))
+            .transpose()
+            .map(Option::flatten)
+    };
+    let normal = color(normal, linear)?;
+    // colour of the highlights, authored like the diffuse map
+    let specular_color = color(find(|m| m.map_ks.as_ref(), "spec_color"), diffuse_space)?;
+    let mut gray = |source: Option<ImageSource>| {
+        source
+            .map(|source| cache.gray(&source, wrap))
+            .transpose()
+            .map(Option::flatten)
+    };
+    Ok(Maps {
+        diffuse,
+        normal,
+        normal_space,
+        specular: gray(find(|m| m.map_ns.as_ref(), "spec"))?,
+        specular_color,
+        alpha,
+        occlusion: gray(find(|_| None, "occlusion"))?,
+    })
+}
+
+// <path>_<suffix> in the first of EXTENSIONS there is a file for
+#[cfg(not(target_arch = "wasm32"))]
+fn beside(path: &str, suffix: &str) -> Option<ImageSource> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| format!("{}_{}.{}", path, suffix, extension))
+        .find(|filename| Path::new(filename).is_file())
+        .map(ImageSource::File)
+}
+
+// None when a material names a file that isn't there, models often come without some
+#[cfg(not(target_arch = "wasm32"))]
+fn load_image(source: &ImageSource) -> Result<Option<DynamicImage>> {
+    match source {
+        ImageSource::File(filename) if !Path::new(filename).is_file() => {
+            tracing::warn!(file = filename, "texture not found");
+            Ok(None)
+        }
+        ImageSource::File(filename) => Ok(Some(decode_image(
+            &fs::read(filename)?,
+            ImageFormat::from_path(filename).ok(),
+        )?)),
+        ImageSource::Embedded(bytes) => Ok(Some(decode_image(bytes, None)?)),
+    }
+}
+
+// Any format the image crate reads, told apart by its content so misnamed files load too.
+// format is for the ones without a signature to tell them by, tga is tried last since it
+// has none at all. Every texture is flipped here so (0,0) is the bottom left like the
+// framebuffer and uvs.
+pub fn decode_image(bytes: &[u8], format: Option<ImageFormat>) -> Result<DynamicImage> {
+    let mut reader = ImageReader::new(Cursor::new(bytes));
+    if let Some(format) = format {
+        reader.set_format(format);
+    }
+    let image = match reader.with_guessed_format()?.decode() {
+        Ok(image) => image,
+        Err(e) => image::load_from_memory_with_format(bytes, ImageFormat::Tga).map_err(|_| e)?,
+    };
+    Ok(image.flipv())
+}
@@ -0,0 +1,159 @@
+use cgmath::Vector2;
+use image::{ImageBuffer, Pixel};
+
+// tiles are TILE_SIZE pixels square, a power of two so the morton index stays in the tile
+pub const TILE_SIZE: u32 = 32;
+
+// spreads the low 16 bits of v over the even bits
+fn part1by1(v: u32) -> u32 {
+    let mut v = v & 0x0000_ffff;
+    v = (v | (v << 8)) & 0x00ff_00ff;
+    v = (v | (v << 4)) & 0x0f0f_0f0f;
+    v = (v | (v << 2)) & 0x3333_3333;
+    (v | (v << 1)) & 0x5555_5555
+}
+
+// An image stored as TILE_SIZE x TILE_SIZE tiles, row by row, with the pixels of each tile
+// in Z (morton) order. Pixels that are close in 2D are close in memory whichever way a
+// triangle's bounding box is walked, where a scanline image jumps a whole row every step
+// down a column. The edge tiles are padded to the full size.
+pub struct Tiled<P: Pixel> {
+    width: u32,
+    height: u32,
+    tiles_x: u32,
+    data: Vec<P>,
+}
+
+impl<P: Pixel + 'static> Tiled<P> {
+    pub fn from_pixel(width: u32, height: u32, pixel: P) -> Tiled<P> {
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tiles_y = height.div_ceil(TILE_SIZE);
+        Tiled {
+            width,
+            height,
+            tiles_x,
+            data: vec![pixel; (tiles_x * tiles_y * TILE_SIZE * TILE_SIZE) as usize],
+        }
+    }
+
+    pub fn from_image(image: &ImageBuffer<P, Vec<P::Subpixel>>) -> Tiled<P> {
+        let (width, height) = image.dimensions();
+        let mut tiled = Tiled::from_pixel(width, height, *image.get_pixel(0, 0));
+        for (x, y, pixel) in image.enumerate_pixels() {
+            tiled.put_pixel(x, y, *pixel);
+        }
+        tiled
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        debug_assert!(x < self.width && y < self.height);
+        let tile = (y / TILE_SIZE) * self.tiles_x + x / TILE_SIZE;
+        let within = part1by1(x % TILE_SIZE) | (part1by1(y % TILE_SIZE) << 1);
+        (tile * TILE_SIZE * TILE_SIZE + within) as usize
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> &P {
+        &self.data[self.index(x, y)]
+    }
+
+    pub fn put_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        let i = self.index(x, y);
+        self.data[i] = pixel;
+    }
+
+    // back to scanline order in an image of the same size, for saving or anything else
+    // that wants a plain image
+    pub fn copy_to(&self, image: &mut ImageBuffer<P, Vec<P::Subpixel>>) {
+        assert_eq!(image.dimensions(), self.dimensions());
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            *pixel = *self.get_pixel(x, y);
+        }
+    }
+
+    // every tile on its own, row by row like bin() gives them
+    pub fn tiles_mut(&mut self) -> Vec<Tile<'_, P>> {
+        let (width, height, tiles_x) = (self.width, self.height, self.tiles_x);
+        self.data
+            .chunks_mut((TILE_SIZE * TILE_SIZE) as usize)
+            .enumerate()
+            .map(|(n, data)| Tile {
+                width,
+                height,
+                x: n as u32 % tiles_x * TILE_SIZE,
+                y: n as u32 / tiles_x * TILE_SIZE,
+                data,
+            })
+            .collect()
+    }
+}
+
+// One tile of a Tiled image borrowed on its own, so tiles can be drawn on threads of their
+// own. It is addressed like the whole image but only holds the pixels of bounds().
+pub struct Tile<'a, P: Pixel> {
+    width: u32,
+    height: u32,
+    // the bottom left of the tile
+    x: u32,
+    y: u32,
+    data: &'a mut [P],
+}
+
+impl<P: Pixel> Tile<'_, P> {
+    // of the whole image
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // the first and last pixel of the tile that are on the image
+    pub fn bounds(&self) -> (Vector2<u32>, Vector2<u32>) {
+        (
+            Vector2::new(self.x, self.y),
+            Vector2::new(
+                (self.x + TILE_SIZE).min(self.width) - 1,
+                (self.y + TILE_SIZE).min(self.height) - 1,
+            ),
+        )
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        debug_assert!((self.x..self.x + TILE_SIZE).contains(&x));
+        debug_assert!((self.y..self.y + TILE_SIZE).contains(&y));
+        (part1by1(x - self.x) | (part1by1(y - self.y) << 1)) as usize
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> &P {
+        &self.data[self.index(x, y)]
+    }
+
+    pub fn put_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        let i = self.index(x, y);
+        self.data[i] = pixel;
+    }
+}
+
+// Which triangles land on each tile of a width x height image, row by row, in the order
+// they are given. bounds are the first and last pixel each triangle covers, none when it
+// covers none.
+pub fn bin(
+    bounds: impl Iterator<Item = Option<(Vector2<u32>, Vector2<u32>)>>,
+    width: u32,
+    height: u32,
+) -> Vec<Vec<usize>> {
+    let tiles_x = width.div_ceil(TILE_SIZE);
+    let mut bins = vec![Vec::new(); (tiles_x * height.div_ceil(TILE_SIZE)) as usize];
+    for (n, bounds) in bounds.enumerate() {
+        let Some((min, max)) = bounds else {
+            continue;
+        };
+        for y in min.y / TILE_SIZE..=max.y / TILE_SIZE {
+            for x in min.x / TILE_SIZE..=max.x / TILE_SIZE {
+                bins[(y * tiles_x + x) as usize].push(n);
+            }
+        }
+    }
+    bins
+}
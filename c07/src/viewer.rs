@@ -0,0 +1,211 @@
+use anyhow::Result;
+use cgmath::{InnerSpace, Vector3};
+use image::{ImageBuffer, Pixel};
+use minifb::{Key, MouseButton, MouseMode, Window, WindowOptions};
+
+use super::camera::{Camera, Projection};
+use super::our_gl::{Framebuffer, MSAA_8};
+use super::texture::{linear_to_srgb, srgb_to_linear};
+
+// radians of orbit per pixel dragged
+pub const ORBIT_SPEED: f32 = 0.01;
+// how much one notch of the wheel zooms
+pub const ZOOM_STEP: f32 = 0.9;
+// keeps the orbit off the poles where up and the view direction line up
+const MAX_PITCH: f32 = 1.5;
+// times smaller on each side the frame is drawn while the camera moves
+pub const MOVING_SCALE: u32 = 4;
+
+// Shows the frames of render in a window and renders again whenever the camera moves,
+// small while it does and refined once it stops. Dragging with the left button orbits
+// around the target, the wheel zooms and Escape closes the window. render draws the
+// camera's viewport into a frame of the size it is given, with (0,0) at the top left of
+// both its colour and depth.
+pub fn run<F: FnMut(&Camera, u32, u32) -> Result<Framebuffer>>(
+    mut camera: Camera,
+    width: u32,
+    height: u32,
+    mut render: F,
+) -> Result<()> {
+    let mut window = Window::new(
+        "tinyrenderer",
+        width as usize,
+        height as usize,
+        WindowOptions::default(),
+    )?;
+    window.set_target_fps(60);
+
+    let mut refine = Refine::new(width, height);
+    let mut last_mouse: Option<(f32, f32)> = None;
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let mouse = window.get_mouse_pos(MouseMode::Discard);
+        if window.get_mouse_down(MouseButton::Left) {
+            if let (Some((x, y)), Some((last_x, last_y))) = (mouse, last_mouse) {
+                if (x, y) != (last_x, last_y) {
+                    orbit(
+                        &mut camera,
+                        (x - last_x) * ORBIT_SPEED,
+                        (y - last_y) * ORBIT_SPEED,
+                    );
+                    refine.restart();
+                }
+            }
+        }
+        last_mouse = mouse;
+        if let Some((_, scroll)) = window.get_scroll_wheel() {
+            if scroll != 0.0 {
+                zoom(&mut camera, ZOOM_STEP.powf(scroll.signum()));
+                refine.restart();
+            }
+        }
+
+        if let Some(frame) = refine.step(&camera, &mut render)? {
+            let buffer: Vec<u32> = frame
+                .color
+                .pixels()
+                .map(|p| (p[0] as u32) << 16 | (p[1] as u32) << 8 | p[2] as u32)
+                .collect();
+            window.update_with_buffer(&buffer, width as usize, height as usize)?;
+        } else {
+            window.update();
+        }
+    }
+    Ok(())
+}
+
+// Progressive refinement of a still camera. The frame is drawn MOVING_SCALE times smaller
+// first, then twice as large each step up to full size, then again with the viewport
+// jittered to each of the 8x MSAA sample positions and averaged in linear light.
+pub struct Refine {
+    width: u32,
+    height: u32,
+    scale: u32,
+    // the full size frames added up so far, linear
+    samples: usize,
+    sum: Vec<f32>,
+}
+
+impl Refine {
+    pub fn new(width: u32, height: u32) -> Refine {
+        Refine {
+            width,
+            height,
+            scale: MOVING_SCALE,
+            samples: 0,
+            sum: vec![0.0; (width * height * 3) as usize],
+        }
+    }
+
+    // the camera moved, back to the small frame
+    pub fn restart(&mut self) {
+        self.scale = MOVING_SCALE;
+        self.samples = 0;
+    }
+
+    // nothing left to refine until restart()
+    pub fn done(&self) -> bool {
+        self.samples == MSAA_8.len()
+    }
+
+    // The next frame to show, width x height whatever it was drawn at, or None when done.
+    // Its depth is that of the last frame drawn.
+    pub fn step<F: FnMut(&Camera, u32, u32) -> Result<Framebuffer>>(
+        &mut self,
+        camera: &Camera,
+        mut render: F,
+    ) -> Result<Option<Framebuffer>> {
+        if self.done() {
+            return Ok(None);
+        }
+        let mut camera = *camera;
+        if self.scale > 1 {
+            let scale = self.scale;
+            let viewport = &mut camera.viewport;
+            viewport.x /= scale as f32;
+            viewport.y /= scale as f32;
+            viewport.width /= scale as f32;
+            viewport.height /= scale as f32;
+            let small = render(
+                &camera,
+                self.width.div_ceil(scale),
+                self.height.div_ceil(scale),
+            )?;
+            self.scale /= 2;
+            return Ok(Some(Framebuffer::new(
+                enlarge(&small.color, scale, self.width, self.height),
+                enlarge(&small.depth, scale, self.width, self.height),
+            )));
+        }
+
+        let (dx, dy) = MSAA_8[self.samples];
+        camera.viewport.x += dx;
+        camera.viewport.y += dy;
+        let mut frame = render(&camera, self.width, self.height)?;
+        if self.samples == 0 {
+            self.sum.fill(0.0);
+        }
+        for (sum, &c) in self.sum.iter_mut().zip(frame.color.as_raw()) {
+            *sum += srgb_to_linear(c);
+        }
+        self.samples += 1;
+        let n = self.samples as f32;
+        for (c, &sum) in frame.color.iter_mut().zip(&self.sum) {
+            *c = linear_to_srgb(sum / n);
+        }
+        Ok(Some(frame))
+    }
+}
+
+// A frame drawn scale times smaller blown back up to width x height. Rows are matched from
+// the bottom like the viewport's, so they line up when height isn't a multiple of scale.
+fn enlarge<P: Pixel<Subpixel = u8> + 'static>(
+    small: &ImageBuffer<P, Vec<u8>>,
+    scale: u32,
+    width: u32,
+    height: u32,
+) -> ImageBuffer<P, Vec<u8>> {
+    let top = small.height() - 1;
+    ImageBuffer::from_fn(width, height, |x, y| {
+        *small.get_pixel(x / scale, top - (height - 1 - y) / scale)
+    })
+}
+
+// turns the eye around the target, yaw about up and pitch towards it
+pub fn orbit(camera: &mut Camera, yaw: f32, pitch: f32) {
+    let up = camera.up.normalize();
+    let offset = camera.eye - camera.target;
+    let distance = offset.magnitude();
+    let dir = offset / distance;
+    let height = dir.dot(up).clamp(-1.0, 1.0);
+    let current_pitch = height.asin();
+    // the direction flattened onto the plane under up
+    let flat = (dir - up * height).normalize();
+    let side = up.cross(flat);
+    let (sin, cos) = (-yaw).sin_cos();
+    let flat: Vector3<f32> = flat * cos + side * sin;
+    let pitch = (current_pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    camera.eye = camera.target + (flat * pitch.cos() + up * pitch.sin()) * distance;
+}
+
+// The simple projection always fits one unit around the target to the viewport, so that
+// zooms by growing the viewport about its centre instead of moving in. Moving an
+// orthographic camera changes nothing either, it shrinks the box it sees instead.
+pub fn zoom(camera: &mut Camera, factor: f32) {
+    match camera.projection {
+        Projection::Perspective { .. } => {
+            camera.eye = camera.target + (camera.eye - camera.target) * factor;
+        }
+        Projection::Orthographic { ref mut size } => *size *= factor,
+        Projection::Simple => {
+            let viewport = &mut camera.viewport;
+            let (cx, cy) = (
+                viewport.x + viewport.width / 2.0,
+                viewport.y + viewport.height / 2.0,
+            );
+            viewport.width /= factor;
+            viewport.height /= factor;
+            viewport.x = cx - viewport.width / 2.0;
+            viewport.y = cy - viewport.height / 2.0;
+        }
+    }
+}
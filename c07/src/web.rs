@@ -0,0 +1,101 @@
+use cgmath::{InnerSpace, Matrix3, Rad, Vector3};
+use image::{ImageFormat, Rgb};
+use std::sync::Arc;
+use wasm_bindgen::prelude::{wasm_bindgen, JsError};
+
+use super::camera::Camera;
+use super::light;
+use super::material::Sides;
+use super::model;
+use super::our_gl::{self, Instance};
+use super::renderer::{self, Renderer, Scene};
+use super::texture::{self, ColorSpace, Maps, Texture, WrapMode};
+
+// the browser has no files, so the head and its diffuse map are built in
+const OBJ: &str = include_str!("../obj/african_head/african_head.obj");
+const DIFFUSE: &[u8] = include_bytes!("../obj/african_head/african_head_diffuse.tga");
+// where the camera and the light are before they are turned about the head
+const EYE: Vector3<f32> = Vector3 {
+    x: 0.0,
+    y: 0.0,
+    z: 3.0,
+};
+const LIGHT_DIR: Vector3<f32> = Vector3 {
+    x: 1.0,
+    y: 1.0,
+    z: 1.0,
+};
+
+// The head with its diffuse map through the library's renderer, what a page draws into a
+// canvas. Build with wasm-pack build --target web, then new Canvas() and put render()'s
+// bytes in an ImageData.
+#[wasm_bindgen]
+pub struct Canvas {
+    scene: Scene,
+    renderer: Renderer,
+}
+
+#[wasm_bindgen]
+impl Canvas {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Canvas {
+        let model = model::obj_to_model(OBJ, "african_head.obj", false)
+            .expect("the bundled model is valid")
+            .0;
+        let diffuse = texture::decode_image(DIFFUSE, Some(ImageFormat::Tga))
+            .expect("the bundled diffuse map is valid")
+            .to_rgb8();
+        let maps = Maps {
+            diffuse: Some(Arc::new(Texture::new(
+                diffuse,
+                WrapMode::Repeat,
+                ColorSpace::Srgb,
+            ))),
+            ..Maps::default()
+        };
+        let scene = Scene {
+            model,
+            lods: Vec::new(),
+            maps: vec![maps],
+            instances: vec![Instance::default()],
+            lights: vec![renderer::default_light()],
+            camera: Camera::new(EYE, Vector3::new(0.0, 0.0, 0.0), Vector3::unit_y(), 1, 1),
+            width: 1,
+            height: 1,
+            sides: Sides::default(),
+        };
+        Canvas {
+            scene,
+            renderer: Renderer::default(),
+        }
+    }
+
+    // RGBA bytes from the top row down, the camera and light turned angle radians about the
+    // head
+    pub fn render(&mut self, width: u32, height: u32, angle: f32) -> Result<Vec<u8>, JsError> {
+        let turn = Matrix3::from_angle_y(Rad(angle));
+        let scene = &mut self.scene;
+        let (center, radius) = our_gl::instanced_bounding_sphere(&scene.model, &scene.instances);
+        (scene.width, scene.height) = (width, height);
+        scene.camera = Camera::new(turn * EYE, center, Vector3::unit_y(), width, height);
+        scene.camera.frame(center, radius);
+        scene.lights[0].source = light::Source::Directional {
+            dir: (turn * LIGHT_DIR).normalize(),
+        };
+
+        let image = self
+            .renderer
+            .render(scene)
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(image
+            .pixels()
+            .flat_map(|&Rgb([r, g, b])| [r, g, b, 255])
+            .collect())
+    }
+}
+
+impl Default for Canvas {
+    fn default() -> Canvas {
+        Canvas::new()
+    }
+}
@@ -0,0 +1,84 @@
+use cgmath::{Deg, InnerSpace, Matrix4, Vector3};
+use tinyrenderer::bvh::{intersect, Bvh};
+use tinyrenderer::model::{self, Model};
+use tinyrenderer::our_gl::Instance;
+
+// Rays cast at copies of the african head moved, turned and scaled about, checked against
+// every triangle of every copy one by one.
+
+const MODEL: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/obj/african_head/african_head.obj"
+);
+// rays across each side of the square they are cast through
+const RAYS: usize = 24;
+
+fn instances() -> Vec<Instance> {
+    vec![
+        Instance::default(),
+        Instance::new(Matrix4::from_translation(Vector3::new(2.5, 0.0, -1.0))),
+        Instance::new(
+            Matrix4::from_translation(Vector3::new(-2.0, 0.5, 0.0))
+                * Matrix4::from_angle_y(Deg(90.0)),
+        ),
+        Instance::new(
+            Matrix4::from_translation(Vector3::new(0.0, -2.0, 1.0)) * Matrix4::from_scale(0.5),
+        ),
+    ]
+}
+
+// the nearest hit along dir as (t, face, instance), the slow way
+fn brute_force(
+    model: &Model,
+    instances: &[Instance],
+    origin: Vector3<f32>,
+    dir: Vector3<f32>,
+) -> Option<(f32, usize, usize)> {
+    let mut nearest: Option<(f32, usize, usize)> = None;
+    for (n, instance) in instances.iter().enumerate() {
+        for (face, corners) in model.get_faces().iter().enumerate() {
+            let tri = [0, 1, 2].map(|j| instance.position(model.get_verts()[corners[j]]));
+            if let Some((t, _)) = intersect(origin, dir, tri) {
+                if nearest.is_none_or(|(nearest, _, _)| t < nearest) {
+                    nearest = Some((t, face, n));
+                }
+            }
+        }
+    }
+    nearest
+}
+
+#[test]
+fn instanced_hierarchy_finds_what_every_triangle_does() {
+    let model = model::file_to_model(MODEL).unwrap();
+    let instances = instances();
+    let bvh = Bvh::new(&model, &instances);
+    let origin = Vector3::new(0.3, 0.2, 6.0);
+    let mut hits = 0;
+    for i in 0..RAYS {
+        for j in 0..RAYS {
+            // through a square around all of the copies
+            let (x, y) = (
+                -3.5 + 7.0 * i as f32 / (RAYS - 1) as f32,
+                -3.0 + 6.0 * j as f32 / (RAYS - 1) as f32,
+            );
+            let dir = (Vector3::new(x, y, 0.0) - origin).normalize();
+            let expected = brute_force(&model, &instances, origin, dir);
+            let found = bvh.closest(origin, dir);
+            assert_eq!(
+                found.map(|hit| (hit.face, hit.instance)),
+                expected.map(|(_, face, instance)| (face, instance)),
+                "the ray towards ({}, {}) hit something else",
+                x,
+                y
+            );
+            if let (Some(hit), Some((t, _, _))) = (found, expected) {
+                assert!((hit.t - t).abs() < 1e-3, "{} is not {}", hit.t, t);
+                assert!(!bvh.occluded(origin, dir, hit.t * 0.999, |_, _| true));
+                assert!(bvh.occluded(origin, dir, hit.t * 1.001, |_, _| true));
+                hits += 1;
+            }
+        }
+    }
+    assert!(hits > RAYS * RAYS / 8, "only {} rays hit anything", hits);
+}
@@ -0,0 +1,85 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::process::Command;
+
+// White furnace test: a white lambertian sphere lit by nothing but an ambient light of 1
+// has to come out flat white, any shading term that adds or loses energy shows up as a
+// pixel off it.
+
+const SIZE: u32 = 64;
+const SLICES: usize = 16;
+const STACKS: usize = 8;
+// the most a covered pixel may be off white
+const TOLERANCE: u8 = 2;
+
+// a unit sphere of SLICES x STACKS, every vertex with its own uv and normal
+fn sphere() -> String {
+    let mut obj = String::from("mtllib furnace.mtl\nusemtl white\n");
+    for stack in 0..=STACKS {
+        for slice in 0..=SLICES {
+            let (u, v) = (
+                slice as f32 / SLICES as f32,
+                1.0 - stack as f32 / STACKS as f32,
+            );
+            let theta = u * std::f32::consts::TAU;
+            let phi = (1.0 - v) * std::f32::consts::PI;
+            let (x, y, z) = (phi.sin() * theta.cos(), phi.cos(), phi.sin() * theta.sin());
+            let _ = writeln!(
+                obj,
+                "v {} {} {}\nvt {} {}\nvn {} {} {}",
+                x, y, z, u, v, x, y, z
+            );
+        }
+    }
+    let index = |stack: usize, slice: usize| stack * (SLICES + 1) + slice + 1;
+    for stack in 0..STACKS {
+        for slice in 0..SLICES {
+            let (a, b) = (index(stack, slice), index(stack, slice + 1));
+            let (c, d) = (index(stack + 1, slice + 1), index(stack + 1, slice));
+            for [i, j, k] in [[a, d, c], [a, c, b]] {
+                let _ = writeln!(obj, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", i, j, k);
+            }
+        }
+    }
+    obj
+}
+
+#[test]
+fn white_sphere_under_ambient_light_is_flat_white() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("furnace");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("furnace.obj"), sphere()).unwrap();
+    std::fs::write(
+        dir.join("furnace.mtl"),
+        "newmtl white\nKd 1 1 1\nKs 0 0 0\nNs 1\n",
+    )
+    .unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_tinyrenderer"))
+        .current_dir(&dir)
+        .args([
+            "furnace",
+            "--size",
+            &format!("{}x{}", SIZE, SIZE),
+            "--light",
+            "dir:0,0,1@1,1,1@1,0,0",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success(), "the furnace failed to render");
+    let image = image::open(dir.join("output.tga")).unwrap().to_rgb8();
+
+    // the background is left black, everything else is the sphere
+    let covered: Vec<_> = image.pixels().filter(|p| p.0 != [0, 0, 0]).collect();
+    assert!(
+        covered.len() > (SIZE * SIZE / 8) as usize,
+        "the sphere only covers {} pixels",
+        covered.len()
+    );
+    for pixel in covered {
+        assert!(
+            pixel.0.iter().all(|&c| c >= 255 - TOLERANCE),
+            "{:?} isn't white",
+            pixel
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tinyrenderer::renderer::{Renderer, Scene};
+
+// Small renders of the african head compared against the references in tests/golden, so
+// changes to the rasterizer or shaders that move pixels don't slip through. Run with
+// GOLDEN_BLESS=1 to write the references again after a change that is meant to.
+
+const MODEL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/obj/african_head/african_head");
+const SIZE: (u32, u32) = (160, 160);
+const SHADOW_SIZE: u32 = 512;
+// mean structural similarity below which a render counts as changed, 1 is identical
+const THRESHOLD: f32 = 0.99;
+// side of the squares the similarity is measured over
+const WINDOW: u32 = 8;
+
+fn references() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn render(name: &str, args: &[&str]) -> RgbImage {
+    // each scene gets its own directory as the renderer writes its buffers next to the frame
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("golden")
+        .join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_tinyrenderer"))
+        .current_dir(&dir)
+        .args([
+            MODEL,
+            "--size",
+            &format!("{}x{}", SIZE.0, SIZE.1),
+            "--shadow-size",
+            &SHADOW_SIZE.to_string(),
+        ])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{} failed to render", name);
+    image::open(dir.join("output.tga")).unwrap().to_rgb8()
+}
+
+fn luminance(image: &RgbImage) -> GrayImage {
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let Rgb([r, g, b]) = *image.get_pixel(x, y);
+        Luma([(0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8])
+    })
+}
+
+// SSIM of the luminance averaged over WINDOW x WINDOW squares
+fn similarity(a: &RgbImage, b: &RgbImage) -> f32 {
+    let (a, b) = (luminance(a), luminance(b));
+    let c1 = (0.01f32 * 255.0).powi(2);
+    let c2 = (0.03f32 * 255.0).powi(2);
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y0 in (0..a.height()).step_by(WINDOW as usize) {
+        for x0 in (0..a.width()).step_by(WINDOW as usize) {
+            let pixels: Vec<(f32, f32)> = (y0..(y0 + WINDOW).min(a.height()))
+                .flat_map(|y| (x0..(x0 + WINDOW).min(a.width())).map(move |x| (x, y)))
+                .map(|(x, y)| (a.get_pixel(x, y)[0] as f32, b.get_pixel(x, y)[0] as f32))
+                .collect();
+            let n = pixels.len() as f32;
+            let mean_a = pixels.iter().map(|p| p.0).sum::<f32>() / n;
+            let mean_b = pixels.iter().map(|p| p.1).sum::<f32>() / n;
+            let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+            for (pa, pb) in &pixels {
+                var_a += (pa - mean_a).powi(2) / n;
+                var_b += (pb - mean_b).powi(2) / n;
+                cov += (pa - mean_a) * (pb - mean_b) / n;
+            }
+            total += (2.0 * mean_a * mean_b + c1) * (2.0 * cov + c2)
+                / ((mean_a.powi(2) + mean_b.powi(2) + c1) * (var_a + var_b + c2));
+            windows += 1;
+        }
+    }
+    total / windows as f32
+}
+
+// the absolute difference brightened so small changes show up
+fn difference(a: &RgbImage, b: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let (pa, pb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        Rgb([0, 1, 2].map(|c| (pa[c].abs_diff(pb[c]) as u32 * 4).min(255) as u8))
+    })
+}
+
+fn check(name: &str, args: &[&str]) {
+    compare(name, &render(name, args));
+}
+
+fn compare(name: &str, actual: &RgbImage) {
+    let reference = references().join(format!("{}.png", name));
+    if std::env::var_os("GOLDEN_BLESS").is_some() {
+        actual.save(&reference).unwrap();
+        return;
+    }
+    let expected = image::open(&reference)
+        .unwrap_or_else(|e| {
+            panic!(
+                "no reference for {} at {} ({}), run with GOLDEN_BLESS=1 to write it",
+                name,
+                reference.display(),
+                e
+            )
+        })
+        .to_rgb8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{} changed size",
+        name
+    );
+    let ssim = similarity(&expected, actual);
+    if ssim < THRESHOLD {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        let (actual_path, diff_path) = (
+            dir.join(format!("{}_actual.png", name)),
+            dir.join(format!("{}_diff.png", name)),
+        );
+        actual.save(&actual_path).unwrap();
+        difference(&expected, actual).save(&diff_path).unwrap();
+        panic!(
+            "{} differs from its reference, similarity {:.4} under {}, see {} and {}",
+            name,
+            ssim,
+            THRESHOLD,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+fn shaded() {
+    check("shaded", &[]);
+}
+
+// the library draws the same frame as the binary, without writing anything
+#[test]
+fn library() {
+    let scene = Scene::load(MODEL, SIZE.0, SIZE.1).unwrap();
+    let renderer = Renderer {
+        shadow_size: SHADOW_SIZE,
+        ..Renderer::default()
+    };
+    compare("shaded", &renderer.render(&scene).unwrap());
+}
+
+#[test]
+fn wireframe() {
+    check("wireframe", &["--wireframe", "255,255,255"]);
+}
+
+#[test]
+fn orthographic() {
+    check("orthographic", &["--orthographic"]);
+}
+
+#[test]
+fn ambient_occlusion() {
+    check("ambient_occlusion", &["--ssao"]);
+}
+
+#[test]
+fn msaa() {
+    check("msaa", &["--msaa", "4"]);
+}
+
+#[test]
+fn fxaa() {
+    check("fxaa", &["--fxaa"]);
+}
@@ -0,0 +1,30 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tinyrenderer::fixtures;
+
+// The loader over random obj fixtures from a fixed seed, --check-loader runs as many more
+// as asked for.
+
+const FIXTURES: usize = 500;
+
+#[test]
+fn valid_fixtures_load_and_round_trip() {
+    let mut rng = StdRng::seed_from_u64(fixtures::SEED);
+    for i in 0..FIXTURES {
+        let obj = fixtures::valid_obj(&mut rng);
+        if let Err(e) = fixtures::check_valid(&obj) {
+            panic!("fixture {}: {}\n{}", i, e, obj);
+        }
+    }
+}
+
+#[test]
+fn malformed_fixtures_load_tolerantly_and_round_trip() {
+    let mut rng = StdRng::seed_from_u64(fixtures::SEED);
+    for i in 0..FIXTURES {
+        let obj = fixtures::malformed_obj(&mut rng);
+        if let Err(e) = fixtures::check_malformed(&obj) {
+            panic!("fixture {}: {}\n{}", i, e, obj);
+        }
+    }
+}
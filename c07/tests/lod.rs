@@ -0,0 +1,71 @@
+use cgmath::{Matrix4, Vector3};
+use tinyrenderer::camera::Camera;
+use tinyrenderer::mesh;
+use tinyrenderer::model::{self, Model};
+use tinyrenderer::our_gl::Instance;
+use tinyrenderer::renderer::{self, Lod};
+use tinyrenderer::texture::Maps;
+
+// The african head framed up close by a camera looking down -z, with levels of detail of it.
+
+const MODEL: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/obj/african_head/african_head.obj"
+);
+const LEVELS: usize = 4;
+
+fn levels() -> Vec<Model> {
+    mesh::lods(model::file_to_model(MODEL).unwrap(), LEVELS)
+}
+
+fn camera() -> Camera {
+    Camera::new(
+        Vector3::new(0.0, 0.0, 3.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        400,
+        400,
+    )
+}
+
+// one instance in front of the camera and one far down its view
+fn near_and_far() -> [Instance; 2] {
+    [
+        Instance::default(),
+        Instance::new(Matrix4::from_translation(Vector3::new(0.0, 0.0, -500.0))),
+    ]
+}
+
+#[test]
+fn instances_at_one_distance_share_a_level() {
+    let levels = levels();
+    let levels: Vec<&Model> = levels.iter().collect();
+    let [near, _] = near_and_far();
+    let lod = renderer::instance_lods(&levels, &[Maps::default()], &[near, near], &camera());
+    assert!(
+        matches!(lod, Lod::Level(0)),
+        "the close up head isn't drawn in full"
+    );
+}
+
+#[test]
+fn instances_at_different_distances_get_their_own_levels() {
+    let levels = levels();
+    let levels: Vec<&Model> = levels.iter().collect();
+    let camera = camera();
+    let [near, far] = near_and_far();
+    assert_eq!(renderer::pick_lod(&levels, &near, &camera), 0);
+    let coarse = renderer::pick_lod(&levels, &far, &camera);
+    assert!(coarse > 0, "the far head isn't drawn any coarser");
+
+    let Lod::Copies(model, maps) =
+        renderer::instance_lods(&levels, &[Maps::default()], &[near, far], &camera)
+    else {
+        panic!("both heads are drawn with one level");
+    };
+    assert_eq!(
+        model.triangle_count(),
+        levels[0].triangle_count() + levels[coarse].triangle_count()
+    );
+    assert_eq!(maps.len(), model.object_count());
+}
@@ -0,0 +1,247 @@
+use cgmath::{Vector3, Vector4};
+use image::{GrayImage, Rgb, RgbImage};
+use std::sync::Arc;
+use tinyrenderer::model::Model;
+use tinyrenderer::our_gl::{self, Framebuffer, Instance, Shader, Uniforms};
+
+// Triangles given straight on the screen, uniforms with no viewport leave them there, drawn
+// into a small frame to look at single pixels.
+
+const SIZE: u32 = 20;
+
+// fills every fragment with one colour, the corners are handed to the rasterizer directly
+struct Flat(Rgb<u8>);
+
+impl Shader for Flat {
+    fn vertex(
+        &mut self,
+        _model: &Model,
+        _iface: usize,
+        _nthvert: usize,
+        _uniforms: &Uniforms,
+        _instance: &Instance,
+    ) -> Vector4<f32> {
+        unreachable!("the tests pass screen coordinates to the rasterizer")
+    }
+
+    fn fragment(&self, _bar: Vector3<f32>, _uniforms: &Uniforms, color: &mut Rgb<u8>) -> bool {
+        *color = self.0;
+        true
+    }
+}
+
+const GREEN: Rgb<u8> = Rgb([0, 255, 0]);
+const RED: Rgb<u8> = Rgb([255, 0, 0]);
+
+fn frame() -> Framebuffer {
+    Framebuffer::new(RgbImage::new(SIZE, SIZE), GrayImage::new(SIZE, SIZE))
+}
+
+fn uniforms() -> Uniforms {
+    Uniforms::screen(cgmath::SquareMatrix::identity())
+}
+
+fn corners(pts: [(f32, f32); 3], depth: f32) -> [Vector4<f32>; 3] {
+    pts.map(|(x, y)| Vector4::new(x, y, depth, 1.0))
+}
+
+// its right edge runs down the middle of the pixels at x = 10, covering half of each
+const LEFT: [(f32, f32); 3] = [(-50.0, -50.0), (10.0, -50.0), (10.0, 100.0)];
+// the other half of those pixels and everything right of them
+const RIGHT: [(f32, f32); 3] = [(10.0, -50.0), (80.0, -50.0), (10.0, 100.0)];
+
+#[test]
+fn coverage_hides_what_is_behind_a_partly_covered_edge() {
+    let (mut frame, uniforms) = (frame(), uniforms());
+    let behind = corners([(-50.0, -50.0), (80.0, -50.0), (-50.0, 100.0)], 50.0);
+    our_gl::triangle_coverage(&corners(LEFT, 200.0), &Flat(GREEN), &uniforms, &mut frame);
+    our_gl::triangle_coverage(&behind, &Flat(RED), &uniforms, &mut frame);
+    let edge = frame.color.get_pixel(10, 5);
+    assert_eq!(
+        edge[0], 0,
+        "the hidden triangle bled into the edge: {:?}",
+        edge
+    );
+    assert!(
+        edge[1] > 100 && edge[1] < 160,
+        "the edge isn't half covered: {:?}",
+        edge
+    );
+    assert_eq!(*frame.color.get_pixel(15, 5), RED);
+}
+
+#[test]
+fn coverage_fills_an_edge_shared_with_the_same_surface() {
+    let (mut frame, uniforms) = (frame(), uniforms());
+    our_gl::triangle_coverage(&corners(LEFT, 200.0), &Flat(GREEN), &uniforms, &mut frame);
+    our_gl::triangle_coverage(&corners(RIGHT, 200.0), &Flat(GREEN), &uniforms, &mut frame);
+    let edge = frame.color.get_pixel(10, 5);
+    assert!(edge[1] >= 250, "the shared edge isn't filled: {:?}", edge);
+}
+
+// two triangles making up the square from (2, 2) to (18, 18) at depth
+fn quad(depth: f32) -> [[Vector4<f32>; 3]; 2] {
+    [
+        corners([(2.0, 2.0), (18.0, 2.0), (18.0, 18.0)], depth),
+        corners([(2.0, 2.0), (18.0, 18.0), (2.0, 18.0)], depth),
+    ]
+}
+
+#[test]
+fn coplanar_quads_keep_the_first_submitted() {
+    let (mut frame, uniforms) = (frame(), uniforms());
+    for (color, depth) in [(GREEN, 100.0), (RED, 100.0)] {
+        for pts in quad(depth) {
+            our_gl::triangle(&pts, &Flat(color), &uniforms, &mut frame);
+        }
+    }
+    for (x, y) in [(3, 3), (10, 10), (16, 4), (4, 16)] {
+        assert_eq!(*frame.color.get_pixel(x, y), GREEN, "at ({}, {})", x, y);
+    }
+}
+
+#[test]
+fn coplanar_decal_wins_with_a_bias() {
+    let (mut frame, uniforms) = (frame(), uniforms());
+    for pts in quad(100.0) {
+        our_gl::triangle(&pts, &Flat(GREEN), &uniforms, &mut frame);
+    }
+    for pts in quad(100.0) {
+        our_gl::triangle_biased(&pts, &Flat(RED), &uniforms, &mut frame, 1);
+    }
+    for (x, y) in [(3, 3), (10, 10), (16, 4), (4, 16)] {
+        assert_eq!(*frame.color.get_pixel(x, y), RED, "at ({}, {})", x, y);
+    }
+}
+
+#[test]
+fn atomic_target_keeps_the_first_submitted_whatever_order_it_comes_in() {
+    let (mut frame, uniforms) = (frame(), uniforms());
+    let target = our_gl::AtomicTarget::new(SIZE, SIZE);
+    // the later face gets there first, as another thread might
+    let faces = [(1, RED), (0, GREEN)];
+    for pass in [our_gl::triangle_atomic, our_gl::shade_atomic] {
+        for (index, color) in faces {
+            for pts in quad(100.0) {
+                pass(&pts, &Flat(color), &uniforms, &target, index);
+            }
+        }
+    }
+    target.resolve(&mut frame);
+    for (x, y) in [(3, 3), (10, 10), (16, 4), (4, 16)] {
+        assert_eq!(*frame.color.get_pixel(x, y), GREEN, "at ({}, {})", x, y);
+    }
+    assert_eq!(frame.depth.get_pixel(10, 10)[0], 100);
+}
+
+#[test]
+fn hiz_culling_leaves_the_depth_unchanged() {
+    const SIZE: u32 = 64;
+    let uniforms = uniforms();
+    let frame = || Framebuffer::new(RgbImage::new(SIZE, SIZE), GrayImage::new(SIZE, SIZE));
+    // a wall over most of the frame first, then a fan of sloped triangles, some behind it,
+    // some through it and some in front
+    let mut triangles = vec![
+        (
+            [(0.0, 0.0), (60.0, 0.0), (60.0, 60.0)].map(|(x, y)| Vector4::new(x, y, 150.0, 1.0)),
+            GREEN,
+        ),
+        (
+            [(0.0, 0.0), (60.0, 60.0), (0.0, 60.0)].map(|(x, y)| Vector4::new(x, y, 150.0, 1.0)),
+            GREEN,
+        ),
+    ];
+    for i in 0..16 {
+        let (x, y) = ((i % 4) as f32 * 16.0, (i / 4) as f32 * 16.0);
+        let near = 40.0 + i as f32 * 14.0;
+        triangles.push((
+            [
+                Vector4::new(x, y, near, 1.0),
+                Vector4::new(x + 30.0, y + 4.0, near - 30.0, 1.0),
+                Vector4::new(x + 6.0, y + 28.0, near - 10.0, 1.0),
+            ],
+            RED,
+        ));
+    }
+    let (mut plain, mut culled) = (frame(), frame());
+    for (pts, color) in &triangles {
+        our_gl::triangle(pts, &Flat(*color), &uniforms, &mut plain);
+        our_gl::triangle_hiz(pts, &Flat(*color), &uniforms, &mut culled);
+    }
+    let hiz = culled
+        .hiz
+        .as_ref()
+        .expect("triangle_hiz() builds the pyramid");
+    assert!(hiz.culled_triangles > 0, "no triangle was culled");
+    assert!(hiz.culled_blocks > 0, "no block was culled");
+    assert_eq!(plain.depth, culled.depth);
+    assert_eq!(plain.color, culled.color);
+}
+
+#[test]
+fn occlusion_counts_what_the_depth_test_passes() {
+    let (mut frame, uniforms) = (frame(), uniforms());
+    for pts in quad(100.0) {
+        our_gl::triangle(&pts, &Flat(GREEN), &uniforms, &mut frame);
+    }
+    let [behind, _] = quad(50.0);
+    assert_eq!(
+        our_gl::triangle_occlusion(&behind, &uniforms, &frame.depth),
+        0
+    );
+    let [in_front, _] = quad(150.0);
+    let mut empty = self::frame();
+    our_gl::triangle(&in_front, &Flat(RED), &uniforms, &mut empty);
+    let drawn = empty.color.pixels().filter(|pixel| **pixel == RED).count();
+    assert!(drawn > 0);
+    assert_eq!(
+        our_gl::triangle_occlusion(&in_front, &uniforms, &frame.depth),
+        drawn
+    );
+}
+
+#[test]
+fn stencil_masked_draw_leaves_the_rest_untouched() {
+    let (mut frame, uniforms) = (frame(), uniforms());
+    for pts in quad(100.0) {
+        our_gl::triangle(&pts, &Flat(GREEN), &uniforms, &mut frame);
+    }
+    // the mask is the lower left half of the quad, marked without being drawn
+    let [_, mask] = quad(0.0);
+    let mark = our_gl::StencilState::mark(1);
+    our_gl::triangle_stencil(&mask, &Flat(RED), &uniforms, &mut frame, &mark);
+    assert_eq!(*frame.color.get_pixel(4, 16), GREEN, "marking drew");
+
+    let inside = our_gl::StencilState::test(our_gl::StencilCompare::Equal, 1);
+    for pts in quad(150.0) {
+        our_gl::triangle_stencil(&pts, &Flat(RED), &uniforms, &mut frame, &inside);
+    }
+    for (x, y) in [(4, 16), (3, 10), (8, 15)] {
+        assert_eq!(*frame.color.get_pixel(x, y), RED, "at ({}, {})", x, y);
+    }
+    for (x, y) in [(16, 4), (15, 10), (10, 3)] {
+        assert_eq!(*frame.color.get_pixel(x, y), GREEN, "at ({}, {})", x, y);
+    }
+    assert_eq!(*frame.color.get_pixel(0, 0), Rgb([0, 0, 0]));
+    assert_eq!(frame.depth.get_pixel(16, 4)[0], 100);
+}
+
+#[test]
+fn frame_hook_can_recolour_and_discard_fragments() {
+    let (mut frame, uniforms) = (frame(), uniforms());
+    // recolours the left half of the pixels and drops the rest
+    frame.hook = Some(Arc::new(|fragment: our_gl::Fragment| {
+        *fragment.color = RED;
+        fragment.x < SIZE / 2
+    }));
+    for pts in quad(100.0) {
+        our_gl::triangle(&pts, &Flat(GREEN), &uniforms, &mut frame);
+    }
+    assert_eq!(*frame.color.get_pixel(2, 5), RED);
+    assert_eq!(*frame.color.get_pixel(15, 5), Rgb([0, 0, 0]));
+    assert_eq!(
+        frame.depth.get_pixel(15, 5)[0],
+        0,
+        "a discarded fragment wrote depth"
+    );
+}
@@ -0,0 +1,52 @@
+#![cfg(feature = "script")]
+
+use image::{Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Small renders of the african head with a fragment script in place of the shading, run
+// with cargo test --features script.
+
+const MODEL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/obj/african_head/african_head");
+const BACKGROUND: Rgb<u8> = Rgb([0, 0, 0]);
+
+// the frame drawn with a script of its own, in a directory of its own
+fn render(name: &str, script: &str) -> RgbImage {
+    let dir: PathBuf = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("script")
+        .join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("shader.rhai"), script).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_tinyrenderer"))
+        .current_dir(&dir)
+        .args([
+            MODEL,
+            "--size",
+            "64x64",
+            "--shadow-size",
+            "128",
+            "--fragment-script",
+            "shader.rhai",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success(), "{} failed to render", name);
+    image::open(dir.join("output.tga")).unwrap().to_rgb8()
+}
+
+#[test]
+fn script_colours_every_fragment() {
+    let image = render("red", "fn fragment(v) { [1.0, 0.0, 0.0] }");
+    let drawn: Vec<_> = image.pixels().filter(|p| **p != BACKGROUND).collect();
+    assert!(!drawn.is_empty(), "nothing was drawn");
+    assert!(
+        drawn.iter().all(|p| **p == Rgb([255, 0, 0])),
+        "a fragment isn't the script's colour"
+    );
+}
+
+#[test]
+fn script_discards_fragments_it_returns_nothing_for() {
+    let image = render("discard", "fn fragment(v) { () }");
+    assert!(image.pixels().all(|p| *p == BACKGROUND));
+}
@@ -0,0 +1,113 @@
+use std::fmt::Write;
+use std::io::Read;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Command;
+
+// A flat grid facing the camera rendered with --stream, what the viewer is sent is read
+// back off the socket and counted.
+
+const SIZE: u32 = 64;
+const CELLS: usize = 20;
+// the binary sends a frame every this many triangles and the finished one at the end
+const STREAM_INTERVAL: usize = 256;
+const HEADER_LEN: usize = 7 * 4;
+
+// CELLS x CELLS squares of two triangles each, all of them on screen
+fn grid() -> String {
+    let mut obj = String::new();
+    for row in 0..=CELLS {
+        for column in 0..=CELLS {
+            let (u, v) = (column as f32 / CELLS as f32, row as f32 / CELLS as f32);
+            let _ = writeln!(obj, "v {} {} 0\nvt {} {}\nvn 0 0 1", u - 0.5, v - 0.5, u, v);
+        }
+    }
+    let index = |row: usize, column: usize| row * (CELLS + 1) + column + 1;
+    for row in 0..CELLS {
+        for column in 0..CELLS {
+            let (a, b) = (index(row, column), index(row, column + 1));
+            let (c, d) = (index(row + 1, column + 1), index(row + 1, column));
+            for [i, j, k] in [[a, b, c], [a, c, d]] {
+                let _ = writeln!(obj, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", i, j, k);
+            }
+        }
+    }
+    obj
+}
+
+// the frames of a stream, each checked to be one whole frame of the right size
+fn frames(mut bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    while !bytes.is_empty() {
+        let header: Vec<u32> = bytes[..HEADER_LEN]
+            .chunks(4)
+            .map(|field| u32::from_be_bytes(field.try_into().unwrap()))
+            .collect();
+        let [width, height, x, y, w, h, len] = header[..] else {
+            unreachable!()
+        };
+        assert_eq!([width, height, x, y, w, h], [SIZE, SIZE, 0, 0, SIZE, SIZE]);
+        assert_eq!(len, SIZE * SIZE * 3);
+        let end = HEADER_LEN + len as usize;
+        frames.push(bytes[HEADER_LEN..end].to_vec());
+        bytes = &bytes[end..];
+    }
+    frames
+}
+
+// triangles is how many the flags have the rasterizer draw
+fn stream(name: &str, flags: &[&str], triangles: usize) {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("grid.obj"), grid()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let viewer = std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        listener
+            .accept()
+            .unwrap()
+            .0
+            .read_to_end(&mut bytes)
+            .unwrap();
+        bytes
+    });
+    let status = Command::new(env!("CARGO_BIN_EXE_tinyrenderer"))
+        .current_dir(&dir)
+        .args(["grid", "--size", &format!("{}x{}", SIZE, SIZE)])
+        .args(["--stream", &addr])
+        .args(flags)
+        .status()
+        .unwrap();
+    assert!(status.success(), "the grid failed to render");
+
+    let frames = frames(&viewer.join().unwrap());
+    assert_eq!(
+        frames.len(),
+        triangles / STREAM_INTERVAL + 1,
+        "{} triangles sent the wrong number of frames",
+        triangles
+    );
+    let image = image::open(dir.join("output.tga")).unwrap().to_rgb8();
+    assert_eq!(
+        frames.last().unwrap(),
+        image.as_raw(),
+        "the last frame isn't the one saved"
+    );
+}
+
+#[test]
+fn stream_sends_a_frame_every_interval_of_triangles() {
+    stream("stream", &[], CELLS * CELLS * 2);
+}
+
+#[test]
+fn parallel_stream_sends_a_frame_every_interval_of_triangles() {
+    stream("stream_parallel", &["--parallel"], CELLS * CELLS * 2);
+}
+
+#[test]
+fn peeled_stream_sends_a_frame_every_interval_of_triangles() {
+    // the second layer is drawn but comes out empty
+    stream("stream_peel", &["--peel", "2"], CELLS * CELLS * 2 * 2);
+}
@@ -0,0 +1,108 @@
+use image::{GrayImage, RgbImage};
+use tinyrenderer::our_gl::{self, ColorBuffer, DepthBuffer, Framebuffer, Instance, Shader};
+use tinyrenderer::renderer::{self, Scene};
+use tinyrenderer::shaders::SpecularShader;
+use tinyrenderer::tiled::{self, Tiled};
+
+// The african head drawn into plain and tiled buffers of a size that isn't a whole number
+// of tiles, so the padded edge tiles are drawn to as well.
+
+const MODEL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/obj/african_head/african_head");
+const SIZE: (u32, u32) = (150, 110);
+
+fn draw<C: ColorBuffer, D: DepthBuffer>(scene: &Scene, framebuffer: &mut Framebuffer<C, D>) {
+    renderer::rasterize(
+        &scene.model,
+        &scene.instances,
+        &mut SpecularShader::new(&scene.lights, &scene.maps),
+        &scene.camera.uniforms(),
+        framebuffer,
+    );
+}
+
+#[test]
+fn tiled_buffers_match_plain_ones_pixel_for_pixel() {
+    let scene = Scene::load(MODEL, SIZE.0, SIZE.1).unwrap();
+    let mut plain = Framebuffer::new(
+        RgbImage::new(SIZE.0, SIZE.1),
+        GrayImage::new(SIZE.0, SIZE.1),
+    );
+    draw(&scene, &mut plain);
+    let mut tiled = Framebuffer::new(
+        Tiled::from_image(&RgbImage::new(SIZE.0, SIZE.1)),
+        Tiled::from_image(&GrayImage::new(SIZE.0, SIZE.1)),
+    );
+    draw(&scene, &mut tiled);
+
+    let (mut color, mut depth) = (
+        RgbImage::new(SIZE.0, SIZE.1),
+        GrayImage::new(SIZE.0, SIZE.1),
+    );
+    tiled.color.copy_to(&mut color);
+    tiled.depth.copy_to(&mut depth);
+    assert!(
+        plain.depth.pixels().any(|depth| depth[0] > 0),
+        "nothing was drawn"
+    );
+    assert_eq!(plain.depth, depth);
+    assert_eq!(plain.color, color);
+}
+
+#[test]
+fn tiles_drawn_on_their_own_match_the_whole_frame() {
+    let scene = Scene::load(MODEL, SIZE.0, SIZE.1).unwrap();
+    let mut plain = Framebuffer::new(
+        RgbImage::new(SIZE.0, SIZE.1),
+        GrayImage::new(SIZE.0, SIZE.1),
+    );
+    draw(&scene, &mut plain);
+
+    let uniforms = scene.camera.uniforms();
+    let mut shader = SpecularShader::new(&scene.lights, &scene.maps);
+    let faces: Vec<_> = our_gl::instanced_faces(
+        &scene.model,
+        &scene.instances,
+        uniforms.to_screen(),
+        SIZE.0,
+        SIZE.1,
+    )
+    .collect();
+    let corners = |shader: &mut SpecularShader, &(instance, i): &(&Instance, usize)| {
+        [0, 1, 2].map(|j| shader.vertex(&scene.model, i, j, &uniforms, instance))
+    };
+    let bounds: Vec<_> = faces
+        .iter()
+        .map(|face| {
+            let pts = corners(&mut shader, face);
+            our_gl::triangle_bounds(&pts, &uniforms, SIZE.0, SIZE.1)
+        })
+        .collect();
+    let bins = tiled::bin(bounds.into_iter(), SIZE.0, SIZE.1);
+    let mut color = Tiled::from_image(&RgbImage::new(SIZE.0, SIZE.1));
+    let mut depth = Tiled::from_image(&GrayImage::new(SIZE.0, SIZE.1));
+    // the busiest last, the order tiles are drawn in can't matter
+    let mut tiles: Vec<_> = color
+        .tiles_mut()
+        .into_iter()
+        .zip(depth.tiles_mut())
+        .zip(bins)
+        .collect();
+    tiles.sort_by_key(|(_, bin)| bin.len());
+    for ((color, depth), bin) in tiles {
+        let bounds = color.bounds();
+        let mut frame = Framebuffer::new(color, depth);
+        for face in bin.iter().map(|&n| &faces[n]) {
+            let pts = corners(&mut shader, face);
+            our_gl::triangle_scissor(&pts, &shader, &uniforms, &mut frame, bounds);
+        }
+    }
+
+    let (mut tiled_color, mut tiled_depth) = (
+        RgbImage::new(SIZE.0, SIZE.1),
+        GrayImage::new(SIZE.0, SIZE.1),
+    );
+    color.copy_to(&mut tiled_color);
+    depth.copy_to(&mut tiled_depth);
+    assert_eq!(plain.depth, tiled_depth);
+    assert_eq!(plain.color, tiled_color);
+}